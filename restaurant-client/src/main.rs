@@ -1,12 +1,16 @@
+#![allow(clippy::needless_return)]
+
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use chrono::{DateTime, Utc};
 use reqwest::header::CONTENT_TYPE;
 use serde_json::json;
 use std::time::SystemTime;
 
-const BASE_URL: &'static str = "http://localhost:9000";
+const BASE_URL: &str = "http://localhost:9000";
+// With a core each for the server and the client threads, 4 threads should manage well over this
+const DEFAULT_MIN_SPEEDUP: f64 = 1.5;
 
 fn current_time() -> String {
     let now = SystemTime::now();
@@ -67,20 +71,48 @@ fn get_order_items(thread_id: i32, table_id: i32, client: &reqwest::blocking::Cl
     println!("{}|thread[{}]:     response[{:?}]", current_time(), thread_id, resp);
 }
 
-fn get_order_item_details(thread_id: i32, table_id: i32, item_id: i32, client: &reqwest::blocking::Client) {
-    let url = format!("{}/v0/orders/{}/items/{}", BASE_URL, table_id, item_id);
+fn get_order_item_details(thread_id: i32, table_id: i32, line_id: i32, client: &reqwest::blocking::Client) {
+    let url = format!("{}/v0/orders/{}/items/{}", BASE_URL, table_id, line_id);
     println!("{}|thread[{}]: GET {}", current_time(), thread_id, url);
     let resp = client.get(url).send().unwrap().text().unwrap();
     println!("{}|thread[{}]:     response[{:?}]", current_time(), thread_id, resp);
 }
 
-fn delete_order_item(thread_id: i32, table_id: i32, item_id: i32, client: &reqwest::blocking::Client) {
-    let url = format!("{}/v0/orders/{}/items/{}", BASE_URL, table_id, item_id);
+fn delete_order_item(thread_id: i32, table_id: i32, line_id: i32, client: &reqwest::blocking::Client) {
+    let url = format!("{}/v0/orders/{}/items/{}", BASE_URL, table_id, line_id);
     println!("{}|thread[{}]: DELETE {}", current_time(), thread_id, url);
     let resp = client.delete(url).send().unwrap().text().unwrap();
     println!("{}|thread[{}]:     response[{:?}]", current_time(), thread_id, resp);
 }
 
+fn bill_order(thread_id: i32, table_id: i32, client: &reqwest::blocking::Client) {
+    let url = format!("{}/v0/orders/{}/bill", BASE_URL, table_id);
+    println!("{}|thread[{}]: POST {}", current_time(), thread_id, url);
+    let resp = client
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(json!({}).to_string())
+        .send()
+        .unwrap()
+        .text()
+        .unwrap();
+    println!("{}|thread[{}]:     response[{:?}]", current_time(), thread_id, resp);
+}
+
+fn pay_order(thread_id: i32, table_id: i32, client: &reqwest::blocking::Client) {
+    let url = format!("{}/v0/orders/{}/payments", BASE_URL, table_id);
+    println!("{}|thread[{}]: POST {}", current_time(), thread_id, url);
+    let resp = client
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(json!({ "method": "cash" }).to_string())
+        .send()
+        .unwrap()
+        .text()
+        .unwrap();
+    println!("{}|thread[{}]:     response[{:?}]", current_time(), thread_id, resp);
+}
+
 fn delete_order(thread_id: i32, table_id: i32, client: &reqwest::blocking::Client) {
     let url = format!("{}/v0/orders/{}", BASE_URL, table_id);
     println!("{}|thread[{}]: DELETE {}", current_time(), thread_id, url);
@@ -106,6 +138,7 @@ fn table_staff_thread(thread_id: i32) {
         thread::sleep(Duration::from_millis(1000));
         get_order_items(thread_id, table_id, &client);
         thread::sleep(Duration::from_millis(1000));
+        // The update left menu item 3 on line 3 and put 4 and 5 on lines 4 and 5
         get_order_item_details(thread_id, table_id, 3, &client);
         thread::sleep(Duration::from_millis(1000));
         get_order_item_details(thread_id, table_id, 5, &client);
@@ -114,6 +147,11 @@ fn table_staff_thread(thread_id: i32) {
         thread::sleep(Duration::from_millis(1000));
         get_order_items(thread_id, table_id, &client);
         thread::sleep(Duration::from_millis(1000));
+        // The table can only be closed once everything on it is billed and paid for
+        bill_order(thread_id, table_id, &client);
+        thread::sleep(Duration::from_millis(1000));
+        pay_order(thread_id, table_id, &client);
+        thread::sleep(Duration::from_millis(1000));
         delete_order(thread_id, table_id, &client);
         thread::sleep(Duration::from_millis(1000));
     }
@@ -127,7 +165,96 @@ fn dump_persistence(client: &reqwest::blocking::Client) {
     println!("persistence contents: {}", resp);
 }
 
+// Runs a full table lifecycle without any logging or sleeps, returning the number of requests sent
+fn run_table_lifecycle(table_id: i32, client: &reqwest::blocking::Client) -> u64 {
+    let order_url = format!("{}/v0/orders/{}", BASE_URL, table_id);
+    let order_body = json!({ "items": [{ "item_id": "1", "qty": 1 }, { "item_id": "2", "qty": 1 }, { "item_id": "3", "qty": 1 }] }).to_string();
+
+    // Resending the same items leaves lines 1 to 3 as they are
+    let responses = [
+        client.post(&order_url).header(CONTENT_TYPE, "application/json").body(order_body.clone()).send(),
+        client.put(&order_url).header(CONTENT_TYPE, "application/json").body(order_body).send(),
+        client.get(&order_url).send(),
+        client.get(format!("{}/items/{}", order_url, 2)).send(),
+        client.delete(format!("{}/items/{}", order_url, 3)).send(),
+        client
+            .post(format!("{}/bill", order_url))
+            .header(CONTENT_TYPE, "application/json")
+            .body(json!({}).to_string())
+            .send(),
+        client
+            .post(format!("{}/payments", order_url))
+            .header(CONTENT_TYPE, "application/json")
+            .body(json!({ "method": "cash" }).to_string())
+            .send(),
+        client.delete(&order_url).send(),
+    ];
+
+    for response in responses.iter() {
+        let status = response.as_ref().unwrap().status();
+        assert!(status.is_success(), "unexpected status {} for table {}", status, table_id);
+    }
+
+    return responses.len() as u64;
+}
+
+fn load_test_thread(thread_id: i32, duration: Duration) -> u64 {
+    // Every thread works on its own range of tables, so threads never touch the same table
+    let client = reqwest::blocking::Client::new();
+    let started = Instant::now();
+    let mut table_id = 1_000_000 + thread_id * 100_000;
+    let mut request_count = 0;
+
+    while started.elapsed() < duration {
+        request_count += run_table_lifecycle(table_id, &client);
+        table_id += 1;
+    }
+
+    return request_count;
+}
+
+// Measures requests/sec for increasing thread counts, and fails unless the best run is at least `min_speedup` times the single thread one.
+// Unrelated tables contending in the server would keep it near 1x however many threads there are.
+fn load_test(min_speedup: f64) -> Result<(), String> {
+    let duration = Duration::from_secs(5);
+    let mut single_thread_throughput = None;
+    let mut best_speedup: f64 = 1.0;
+
+    for thread_count in [1, 2, 4, 8, 16] {
+        let started = Instant::now();
+        let threads = (0..thread_count)
+            .map(|i| return thread::spawn(move || load_test_thread(i, duration)))
+            .collect::<Vec<_>>();
+        let request_count: u64 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+
+        let throughput = request_count as f64 / started.elapsed().as_secs_f64();
+        let speedup = throughput / *single_thread_throughput.get_or_insert(throughput);
+        best_speedup = best_speedup.max(speedup);
+        println!("threads[{:>2}]: {:>7} requests, {:>9.1} req/s, {:>5.2}x single thread", thread_count, request_count, throughput, speedup);
+    }
+
+    if best_speedup < min_speedup {
+        return Err(format!("best speedup {:.2}x is below the required {:.2}x", best_speedup, min_speedup));
+    }
+    println!("best speedup {:.2}x, required {:.2}x", best_speedup, min_speedup);
+    return Ok(());
+}
+
 fn main() {
+    // load-test [min speedup], e.g. `load-test 1.0` on a single core machine where nothing can scale
+    let args = std::env::args().collect::<Vec<String>>();
+    if args.get(1).map(String::as_str) == Some("load-test") {
+        let min_speedup = args
+            .get(2)
+            .map(|s| s.parse::<f64>().expect("min speedup should be a number"))
+            .unwrap_or(DEFAULT_MIN_SPEEDUP);
+        if let Err(err) = load_test(min_speedup) {
+            eprintln!("load test failed: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let thread_count = 10;
 
     let client = reqwest::blocking::Client::new();
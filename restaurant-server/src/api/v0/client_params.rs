@@ -1,12 +1,120 @@
-use crate::models::{
-    menu::{get_preparation_time, MenuItemId},
-    orders::{TableId, TableOrderItem},
+use std::collections::{BTreeSet, HashSet};
+
+use axum::http::Uri;
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+use crate::{
+    events::OrderEventType,
+    models::{
+        billing::{BillSplit, PayerShare},
+        kitchen::Station,
+        menu::{Menu, MenuItem, MenuItemId, ModifierId},
+        money::{Currency, Money},
+        orders::{LineId, OrderItemChanges, OrderItemStatus, TableId, TableOrderItem},
+        payments::{PaymentId, PaymentMethod},
+    },
+    webhooks::{DeadLetterId, WebhookHostAllowList, WebhookId},
 };
 
+// Anything larger than this is rejected before it is parsed, see create_app
+pub const MAX_REQUEST_BODY_BYTES: usize = 16 * 1024;
+pub const MAX_ITEMS_PER_ORDER: usize = 100;
+pub const MAX_NOTES_CHARS: usize = 200;
+pub const MAX_SPLIT_PARTS: usize = 50;
+// Per line. Far more than any table orders, and small enough that preparation times, quantity totals and line amounts can't overflow.
+pub const MAX_QUANTITY: i32 = 100;
+
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum ValidationError {
+    #[error("Table id '{0}' is not a valid number.")]
+    InvalidTableId(String),
+    #[error("Item id '{0}' is not a valid number.")]
+    InvalidItemId(String),
+    #[error("Line id '{0}' is not a valid number.")]
+    InvalidLineId(String),
+    #[error("Menu item id {0} does not exist.")]
+    UnknownMenuItem(String),
+    #[error("Quantity {1} for item id {0} must be between 1 and {MAX_QUANTITY}.")]
+    InvalidQuantity(String, i32),
+    #[error("Quantity {1} for line id {0} must be between 1 and {MAX_QUANTITY}.")]
+    InvalidLineQuantity(String, i32),
+    #[error("An order must contain at least one item.")]
+    EmptyItemList,
+    #[error("An order can contain at most {MAX_ITEMS_PER_ORDER} items, {0} were given.")]
+    TooManyItems(usize),
+    #[error("Modifier '{1}' is not available for menu item id {0}.")]
+    UnknownModifier(String, String),
+    #[error("Modifier '{1}' is listed more than once for item id {0}.")]
+    DuplicateModifier(String, String),
+    #[error("Only one {1} modifier can be chosen for item id {0}.")]
+    ConflictingModifiers(String, String),
+    #[error("Notes for item id {0} can be at most {MAX_NOTES_CHARS} characters.")]
+    NotesTooLong(String),
+    #[error("Notes for line id {0} can be at most {MAX_NOTES_CHARS} characters.")]
+    LineNotesTooLong(String),
+    #[error("Nothing to change on line id {0}, give a new qty, notes or seat.")]
+    NothingToChange(String),
+    #[error("Seat {1} for item id {0} must be greater than zero.")]
+    NonPositiveSeat(String, i32),
+    #[error("Seat {1} for line id {0} must be greater than zero.")]
+    NonPositiveLineSeat(String, i32),
+    #[error("Guests {0} must be greater than zero.")]
+    NonPositiveGuests(i32),
+    #[error("Group by '{0}' is not supported, the only grouping is seat.")]
+    UnknownGroupBy(String),
+    #[error("Give one of even, the number of ways to split the bill, payers, the lines each payer pays for, or by_seat.")]
+    InvalidSplit,
+    #[error("A bill can be split between 1 and {MAX_SPLIT_PARTS} ways, not {0}.")]
+    InvalidSplitCount(i64),
+    #[error("Share {1} of line id {0} must be greater than zero.")]
+    NonPositiveShare(String, i32),
+    #[error("Payment id '{0}' is not a valid number.")]
+    InvalidPaymentId(String),
+    #[error("Payment method '{0}' is not one of cash or card.")]
+    UnknownPaymentMethod(String),
+    #[error("A card payment needs the card_token from the card terminal.")]
+    MissingCardToken,
+    #[error("Amount '{0}' is not an amount greater than zero like 12.50.")]
+    InvalidAmount(String),
+    #[error("Status '{0}' is not one of ordered, preparing, ready or served.")]
+    UnknownStatus(String),
+    #[error("Station '{0}' does not exist, expected one of grill, fryer, cold or bar.")]
+    UnknownStation(String),
+    #[error("Last-Event-ID '{0}' is not a valid event id.")]
+    InvalidLastEventId(String),
+    #[error("Webhook id '{0}' is not a valid number.")]
+    InvalidWebhookId(String),
+    #[error("Webhook URL '{0}' must be an absolute http:// URL.")]
+    InvalidWebhookUrl(String),
+    #[error("Webhook URL '{0}' points at a loopback, private or link-local address.")]
+    WebhookUrlNotAllowed(String),
+    #[error("Dead letter id '{0}' is not a valid number.")]
+    InvalidDeadLetterId(String),
+    #[error("A webhook needs a secret to sign its payloads with.")]
+    MissingWebhookSecret,
+    #[error("Event type '{0}' is not one of order_created, order_updated, order_deleted, item_removed, item_ready, bill_issued, payment_recorded or refund_recorded.")]
+    UnknownEventType(String),
+}
+
 #[derive(serde::Deserialize)]
 pub struct ClientNewItem {
     pub item_id: String,
     pub qty: i32,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub modifiers: Vec<String>, // modifier ids from the menu item
+    #[serde(default)]
+    pub seat: Option<i32>, // from 1 up to the order's guests, shared by the table when not given
+}
+
+#[derive(serde::Deserialize)]
+pub struct CreateOrderParams {
+    #[serde(default)]
+    pub guests: Option<i32>, // needed before any line can have a seat
+    pub items: Vec<ClientNewItem>,
 }
 
 #[derive(serde::Deserialize)]
@@ -14,17 +122,322 @@ pub struct CreateOrUpdateOrderParams {
     pub items: Vec<ClientNewItem>,
 }
 
-pub fn from_client_table_id(table_id: &str) -> TableId {
-    return TableId(table_id.parse().unwrap());
+#[derive(serde::Deserialize)]
+pub struct UpdateOrderParams {
+    pub guests: i32,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ReadOrderParams {
+    pub group_by: Option<String>, // only "seat" for now
+}
+
+// Fields left out are unchanged, blank notes clear them
+#[derive(serde::Deserialize)]
+pub struct UpdateOrderItemParams {
+    pub qty: Option<i32>,
+    pub notes: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    pub seat: Option<Option<i32>>, // null makes the line shared by the table again
+}
+
+// With #[serde(default)], tells a field that is null (Some(None)) apart from one that is left out (None)
+fn deserialize_nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    return <Option<T> as serde::Deserialize>::deserialize(deserializer).map(Some);
+}
+
+#[derive(serde::Deserialize)]
+pub struct CreateBillParams {
+    pub guests: Option<i32>, // the party size, which decides whether a service charge is added
+}
+
+// Exactly one of the three
+#[derive(serde::Deserialize)]
+pub struct SplitBillParams {
+    pub even: Option<i64>,
+    pub payers: Option<Vec<ClientPayer>>,
+    #[serde(default)]
+    pub by_seat: bool,
+}
+
+// Amounts are in the menu's currency, so just the number
+#[derive(serde::Deserialize)]
+pub struct CreatePaymentParams {
+    pub method: String,             // cash or card
+    pub amount: Option<String>,     // everything left to pay when not given
+    pub card_token: Option<String>, // from the card terminal, only for card payments
+}
+
+#[derive(serde::Deserialize)]
+pub struct RefundPaymentParams {
+    pub amount: Option<String>, // everything not refunded yet when not given
+}
+
+#[derive(serde::Deserialize)]
+pub struct ClientPayer {
+    pub lines: Vec<ClientPayerLine>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ClientPayerLine {
+    pub line_id: String,
+    #[serde(default)]
+    pub share: Option<i32>, // 1 when not given, only needed for a line shared unequally
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateItemStatusParams {
+    pub status: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct KdsParams {
+    pub station: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct CreateWebhookParams {
+    pub url: String,
+    #[serde(default)]
+    pub events: Vec<String>, // every event type when empty
+    pub secret: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct OrderEventsParams {
+    pub tables: Option<String>, // comma separated table ids, every table when not given
+}
+
+pub fn from_client_table_id(table_id: &str) -> Result<TableId, ValidationError> {
+    return table_id.parse().map(TableId).map_err(|_| ValidationError::InvalidTableId(table_id.to_string()));
+}
+
+pub fn from_client_table_ids(table_ids: &str) -> Result<HashSet<TableId>, ValidationError> {
+    return table_ids.split(',').map(str::trim).filter(|id| !id.is_empty()).map(from_client_table_id).collect();
+}
+
+pub fn from_client_last_event_id(last_event_id: &str) -> Result<u64, ValidationError> {
+    return last_event_id
+        .trim()
+        .parse()
+        .map_err(|_| ValidationError::InvalidLastEventId(last_event_id.to_string()));
 }
 
-pub fn from_client_item_id(item_id: &str) -> MenuItemId {
-    return MenuItemId(item_id.parse().unwrap());
+pub fn from_client_webhook_id(webhook_id: &str) -> Result<WebhookId, ValidationError> {
+    return webhook_id.parse().map_err(|_| ValidationError::InvalidWebhookId(webhook_id.to_string()));
+}
+
+// Only plain http, the server has no TLS client. Receivers elsewhere are expected to sit behind a local proxy.
+// Hosts that aren't public are refused unless they are allowed, see WebhookHostAllowList.
+pub fn from_client_webhook_url(url: &str, allowed_hosts: &WebhookHostAllowList) -> Result<String, ValidationError> {
+    let host = match url.parse::<Uri>() {
+        Ok(uri) if uri.scheme_str() == Some("http") && uri.host().is_some() => uri.host().unwrap().to_string(),
+        _ => return Err(ValidationError::InvalidWebhookUrl(url.to_string())),
+    };
+    if !allowed_hosts.allows_host(&host) {
+        return Err(ValidationError::WebhookUrlNotAllowed(url.to_string()));
+    }
+
+    return Ok(url.to_string());
+}
+
+pub fn from_client_dead_letter_id(dead_letter_id: &str) -> Result<DeadLetterId, ValidationError> {
+    return dead_letter_id.parse().map_err(|_| ValidationError::InvalidDeadLetterId(dead_letter_id.to_string()));
+}
+
+pub fn from_client_webhook_secret(secret: &str) -> Result<String, ValidationError> {
+    if secret.is_empty() {
+        return Err(ValidationError::MissingWebhookSecret);
+    }
+
+    return Ok(secret.to_string());
+}
+
+pub fn from_client_event_types(event_types: &[String]) -> Result<BTreeSet<OrderEventType>, ValidationError> {
+    return event_types
+        .iter()
+        .map(|t| t.parse().map_err(|_| ValidationError::UnknownEventType(t.to_string())))
+        .collect();
+}
+
+pub fn from_client_guests(guests: Option<i32>) -> Result<Option<u32>, ValidationError> {
+    return match guests {
+        Some(g) if g <= 0 => Err(ValidationError::NonPositiveGuests(g)),
+        _ => Ok(guests.map(|g| g as u32)),
+    };
+}
+
+// Seats aren't checked against the guests here, that needs the order, see check_seats
+pub fn from_client_seat(item_id: &MenuItemId, seat: Option<i32>) -> Result<Option<u32>, ValidationError> {
+    return match seat {
+        Some(s) if s <= 0 => Err(ValidationError::NonPositiveSeat(item_id.to_string(), s)),
+        _ => Ok(seat.map(|s| s as u32)),
+    };
+}
+
+pub fn from_client_group_by_seat(group_by: Option<&str>) -> Result<bool, ValidationError> {
+    return match group_by {
+        None => Ok(false),
+        Some("seat") => Ok(true),
+        Some(other) => Err(ValidationError::UnknownGroupBy(other.to_string())),
+    };
+}
+
+pub fn from_client_bill_split(params: &SplitBillParams) -> Result<BillSplit, ValidationError> {
+    return match (params.even, &params.payers, params.by_seat) {
+        (Some(ways), None, false) if ways < 1 || ways > MAX_SPLIT_PARTS as i64 => Err(ValidationError::InvalidSplitCount(ways)),
+        (Some(ways), None, false) => Ok(BillSplit::Even(ways as usize)),
+        (None, Some(payers), false) if payers.is_empty() || payers.len() > MAX_SPLIT_PARTS => Err(ValidationError::InvalidSplitCount(payers.len() as i64)),
+        (None, Some(payers), false) => payers
+            .iter()
+            .map(|p| p.lines.iter().map(from_client_payer_share).collect::<Result<Vec<PayerShare>, ValidationError>>())
+            .collect::<Result<Vec<Vec<PayerShare>>, ValidationError>>()
+            .map(BillSplit::ByLine),
+        (None, None, true) => Ok(BillSplit::BySeat),
+        _ => Err(ValidationError::InvalidSplit),
+    };
+}
+
+fn from_client_payer_share(line: &ClientPayerLine) -> Result<PayerShare, ValidationError> {
+    let line_id = from_client_line_id(&line.line_id)?;
+    let share = line.share.unwrap_or(1);
+    if share <= 0 {
+        return Err(ValidationError::NonPositiveShare(line_id.to_string(), share));
+    }
+
+    return Ok(PayerShare { line_id: line_id, share: share as u32 });
+}
+
+pub fn from_client_payment_id(payment_id: &str) -> Result<PaymentId, ValidationError> {
+    return payment_id
+        .parse()
+        .map(PaymentId)
+        .map_err(|_| ValidationError::InvalidPaymentId(payment_id.to_string()));
+}
+
+pub fn from_client_payment_method(method: &str) -> Result<PaymentMethod, ValidationError> {
+    return method.parse().map_err(|_| ValidationError::UnknownPaymentMethod(method.to_string()));
+}
+
+// Only card payments have a token, one given for cash is ignored
+pub fn from_client_card_token(method: PaymentMethod, card_token: Option<&str>) -> Result<Option<String>, ValidationError> {
+    return match (method, card_token.map(str::trim).filter(|t| !t.is_empty())) {
+        (PaymentMethod::Card, Some(token)) => Ok(Some(token.to_string())),
+        (PaymentMethod::Card, None) => Err(ValidationError::MissingCardToken),
+        (PaymentMethod::Cash, _) => Ok(None),
+    };
+}
+
+pub fn from_client_amount(amount: &str, currency: Currency) -> Result<Money, ValidationError> {
+    return format!("{} {}", amount.trim(), currency)
+        .parse::<Money>()
+        .ok()
+        .filter(|m| m.minor_units > 0)
+        .ok_or_else(|| ValidationError::InvalidAmount(amount.to_string()));
+}
+
+pub fn from_client_item_id(item_id: &str) -> Result<MenuItemId, ValidationError> {
+    return item_id.parse().map(MenuItemId).map_err(|_| ValidationError::InvalidItemId(item_id.to_string()));
+}
+
+pub fn from_client_line_id(line_id: &str) -> Result<LineId, ValidationError> {
+    return line_id.parse().map(LineId).map_err(|_| ValidationError::InvalidLineId(line_id.to_string()));
+}
+
+pub fn from_client_status(status: &str) -> Result<OrderItemStatus, ValidationError> {
+    return status.parse().map_err(|_| ValidationError::UnknownStatus(status.to_string()));
+}
+
+pub fn from_client_station(station: &str) -> Result<Station, ValidationError> {
+    return station.parse().map_err(|_| ValidationError::UnknownStation(station.to_string()));
+}
+
+pub fn from_client_item(new_item: &ClientNewItem, menu: &Menu, ordered_at: DateTime<Utc>) -> Result<TableOrderItem, ValidationError> {
+    let item_id = from_client_item_id(&new_item.item_id)?;
+    let menu_item = match menu.find_item(&item_id) {
+        Ok(menu_item) => menu_item,
+        Err(_) => return Err(ValidationError::UnknownMenuItem(item_id.to_string())),
+    };
+    if !(1..=MAX_QUANTITY).contains(&new_item.qty) {
+        return Err(ValidationError::InvalidQuantity(item_id.to_string(), new_item.qty));
+    }
+
+    let notes = from_client_notes(&item_id, new_item.notes.as_deref())?;
+    let modifiers = from_client_modifiers(menu_item, &new_item.modifiers)?;
+    let seat = from_client_seat(&item_id, new_item.seat)?;
+
+    let preparation_time = menu.get_preparation_time(menu_item, new_item.qty);
+
+    return Ok(TableOrderItem::new(item_id, new_item.qty, preparation_time, ordered_at)
+        .with_notes(notes)
+        .with_modifiers(modifiers)
+        .with_seat(seat));
+}
+
+pub fn from_client_notes(item_id: &MenuItemId, notes: Option<&str>) -> Result<Option<String>, ValidationError> {
+    let notes = notes.and_then(trim_notes);
+    if notes.is_some_and(|n| n.chars().count() > MAX_NOTES_CHARS) {
+        return Err(ValidationError::NotesTooLong(item_id.to_string()));
+    }
+
+    return Ok(notes.map(str::to_string));
+}
+
+// Blank notes are the same as none
+fn trim_notes(notes: &str) -> Option<&str> {
+    return Some(notes.trim()).filter(|n| !n.is_empty());
+}
+
+pub fn from_client_item_changes(line_id: &LineId, params: &UpdateOrderItemParams) -> Result<OrderItemChanges, ValidationError> {
+    if params.qty.is_none() && params.notes.is_none() && params.seat.is_none() {
+        return Err(ValidationError::NothingToChange(line_id.to_string()));
+    }
+    if let Some(qty) = params.qty.filter(|q| !(1..=MAX_QUANTITY).contains(q)) {
+        return Err(ValidationError::InvalidLineQuantity(line_id.to_string(), qty));
+    }
+    if let Some(seat) = params.seat.flatten().filter(|s| *s <= 0) {
+        return Err(ValidationError::NonPositiveLineSeat(line_id.to_string(), seat));
+    }
+
+    let notes = params.notes.as_deref().map(trim_notes);
+    if notes.flatten().is_some_and(|n| n.chars().count() > MAX_NOTES_CHARS) {
+        return Err(ValidationError::LineNotesTooLong(line_id.to_string()));
+    }
+
+    return Ok(OrderItemChanges { quantity: params.qty, notes: notes.map(|n| n.map(str::to_string)), seat: params.seat.map(|s| s.map(|s| s as u32)) });
+}
+
+pub fn from_client_modifiers(menu_item: &MenuItem, modifiers: &[String]) -> Result<Vec<ModifierId>, ValidationError> {
+    let mut chosen: Vec<ModifierId> = vec![];
+    for client_modifier in modifiers {
+        let modifier = menu_item
+            .find_modifier(&ModifierId(client_modifier.clone()))
+            .ok_or_else(|| ValidationError::UnknownModifier(menu_item.id.to_string(), client_modifier.clone()))?;
+        if chosen.contains(&modifier.id) {
+            return Err(ValidationError::DuplicateModifier(menu_item.id.to_string(), client_modifier.clone()));
+        }
+        if let Some(group) = &modifier.group {
+            if chosen.iter().any(|id| menu_item.find_modifier(id).is_some_and(|m| m.group.as_ref() == Some(group))) {
+                return Err(ValidationError::ConflictingModifiers(menu_item.id.to_string(), group.clone()));
+            }
+        }
+        chosen.push(modifier.id.clone());
+    }
+
+    return Ok(chosen);
 }
 
-pub fn from_client_item(new_item: &ClientNewItem) -> TableOrderItem {
-    let item_id = from_client_item_id(&new_item.item_id);
-    let preparation_time = get_preparation_time(&item_id);
+pub fn from_client_items(new_items: &[ClientNewItem], menu: &Menu, ordered_at: DateTime<Utc>) -> Result<Vec<TableOrderItem>, ValidationError> {
+    if new_items.is_empty() {
+        return Err(ValidationError::EmptyItemList);
+    }
+    if new_items.len() > MAX_ITEMS_PER_ORDER {
+        return Err(ValidationError::TooManyItems(new_items.len()));
+    }
 
-    return TableOrderItem { item_id: item_id, quantity: new_item.qty, total_preparation_time_mins: preparation_time };
+    return new_items.iter().map(|i| from_client_item(i, menu, ordered_at)).collect();
 }
@@ -1,3 +1,7 @@
 pub mod client_params;
+pub mod problem_details;
 pub mod routes;
+pub mod server_sent_events;
 pub mod view_models;
+pub mod webhooks;
+pub mod websocket;
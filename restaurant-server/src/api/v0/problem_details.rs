@@ -0,0 +1,277 @@
+// Every error response is an RFC 7807 problem document, so clients can switch on `code` instead of matching on the text in `detail`.
+// Codes are part of the API contract: add new ones freely, but never rename or reuse an existing one.
+
+use axum::{
+    extract::rejection::JsonRejection,
+    http::{header, Response, StatusCode},
+    response::IntoResponse,
+};
+
+use crate::{
+    models::{
+        billing::{ReadBillError, SplitBillError},
+        menu::ReadMenuItemError,
+        orders::OrderChangeError,
+    },
+    payments::PaymentGatewayError,
+    persistence::persistence::{CreateOrderError, ModifyOrderError, ReadOrderError},
+    webhooks::WebhookError,
+};
+
+use super::client_params::ValidationError;
+
+pub const PROBLEM_JSON_CONTENT_TYPE: &str = "application/problem+json";
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    pub code: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub table_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub item_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub line_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub payment_id: Option<String>,
+}
+
+impl ProblemDetails {
+    pub fn new(status: StatusCode, code: &str, title: &str, detail: String) -> Self {
+        return Self {
+            problem_type: format!("urn:restaurant:problem:{}", code),
+            title: title.to_string(),
+            status: status.as_u16(),
+            detail: detail,
+            code: code.to_string(),
+            table_id: None,
+            item_id: None,
+            line_id: None,
+            payment_id: None,
+        };
+    }
+
+    pub fn with_table_id(mut self, table_id: String) -> Self {
+        self.table_id = Some(table_id);
+        return self;
+    }
+
+    pub fn with_item_id(mut self, item_id: String) -> Self {
+        self.item_id = Some(item_id);
+        return self;
+    }
+
+    pub fn with_line_id(mut self, line_id: String) -> Self {
+        self.line_id = Some(line_id);
+        return self;
+    }
+
+    pub fn with_payment_id(mut self, payment_id: String) -> Self {
+        self.payment_id = Some(payment_id);
+        return self;
+    }
+
+    // The underlying error is logged rather than returned, it is of no use to clients and may leak internals
+    fn storage_error(error: String) -> Self {
+        tracing::error!("storage error: {}", error);
+        return Self::new(StatusCode::INTERNAL_SERVER_ERROR, "storage_error", "Storage error", "The order could not be read or saved, try again later.".to_string());
+    }
+}
+
+impl IntoResponse for ProblemDetails {
+    fn into_response(self) -> Response<axum::body::Body> {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        return (status, [(header::CONTENT_TYPE, PROBLEM_JSON_CONTENT_TYPE)], serde_json::to_string(&self).unwrap()).into_response();
+    }
+}
+
+impl From<CreateOrderError> for ProblemDetails {
+    fn from(value: CreateOrderError) -> Self {
+        let detail = value.to_string();
+        return match value {
+            CreateOrderError::OrderAlreadyExistsForTable(table_id) => Self::new(StatusCode::CONFLICT, "order_already_exists", "Order already exists", detail).with_table_id(table_id),
+            CreateOrderError::Storage(error) => Self::storage_error(error),
+        };
+    }
+}
+
+impl From<ReadOrderError> for ProblemDetails {
+    fn from(value: ReadOrderError) -> Self {
+        let detail = value.to_string();
+        return match value {
+            ReadOrderError::OrderNotFound(table_id) => Self::new(StatusCode::NOT_FOUND, "order_not_found", "Order not found", detail).with_table_id(table_id),
+            ReadOrderError::Storage(error) => Self::storage_error(error),
+        };
+    }
+}
+
+impl From<OrderChangeError> for ProblemDetails {
+    fn from(value: OrderChangeError) -> Self {
+        let detail = value.to_string();
+        return match value {
+            OrderChangeError::OrderItemNotFound(table_id, line_id) => Self::new(StatusCode::NOT_FOUND, "order_item_not_found", "Order item not found", detail)
+                .with_table_id(table_id)
+                .with_line_id(line_id),
+            OrderChangeError::IllegalStatusTransition(table_id, line_id, _, _) => Self::new(StatusCode::CONFLICT, "illegal_status_transition", "Illegal status transition", detail)
+                .with_table_id(table_id)
+                .with_line_id(line_id),
+            OrderChangeError::NotBumped(table_id, line_id, _) => Self::new(StatusCode::CONFLICT, "item_not_bumped", "Item not bumped", detail)
+                .with_table_id(table_id)
+                .with_line_id(line_id),
+            OrderChangeError::RecallWindowExpired(table_id, line_id, _) => Self::new(StatusCode::CONFLICT, "recall_window_expired", "Recall window expired", detail)
+                .with_table_id(table_id)
+                .with_line_id(line_id),
+            OrderChangeError::TooManyItems(table_id, _) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "too_many_items", "Too many items", detail).with_table_id(table_id),
+            OrderChangeError::AlreadyStarted(table_id, line_id, _) => Self::new(StatusCode::CONFLICT, "item_already_started", "Item already started", detail)
+                .with_table_id(table_id)
+                .with_line_id(line_id),
+            OrderChangeError::UnpricedItem(table_id, line_id, item_id) => Self::new(StatusCode::CONFLICT, "unpriced_item", "Unpriced item", detail)
+                .with_table_id(table_id)
+                .with_line_id(line_id)
+                .with_item_id(item_id),
+            OrderChangeError::SeatOutOfRange(table_id, _, _) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "seat_out_of_range", "Seat out of range", detail).with_table_id(table_id),
+            OrderChangeError::UnbilledItems(table_id) => Self::new(StatusCode::CONFLICT, "unbilled_items", "Unbilled items", detail).with_table_id(table_id),
+            OrderChangeError::OutstandingBalance(table_id, _) => Self::new(StatusCode::CONFLICT, "outstanding_balance", "Outstanding balance", detail).with_table_id(table_id),
+            OrderChangeError::Overpaid(table_id, _) => Self::new(StatusCode::CONFLICT, "overpaid", "Overpaid", detail).with_table_id(table_id),
+            OrderChangeError::AlreadyPaid(table_id) => Self::new(StatusCode::CONFLICT, "already_paid", "Already paid", detail).with_table_id(table_id),
+            OrderChangeError::Overpayment(table_id, _, _) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "overpayment", "Overpayment", detail).with_table_id(table_id),
+            OrderChangeError::PaymentNotFound(table_id, payment_id) => Self::new(StatusCode::NOT_FOUND, "payment_not_found", "Payment not found", detail)
+                .with_table_id(table_id)
+                .with_payment_id(payment_id),
+            OrderChangeError::RefundExceedsPayment(table_id, payment_id, _) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "refund_exceeds_payment", "Refund exceeds payment", detail)
+                .with_table_id(table_id)
+                .with_payment_id(payment_id),
+            OrderChangeError::RefundPending(table_id, payment_id) => Self::new(StatusCode::CONFLICT, "refund_pending", "Refund pending", detail)
+                .with_table_id(table_id)
+                .with_payment_id(payment_id),
+        };
+    }
+}
+
+impl From<ModifyOrderError> for ProblemDetails {
+    fn from(value: ModifyOrderError) -> Self {
+        let detail = value.to_string();
+        return match value {
+            ModifyOrderError::OrderNotFound(table_id) => Self::new(StatusCode::NOT_FOUND, "order_not_found", "Order not found", detail).with_table_id(table_id),
+            ModifyOrderError::Rejected(error) => Self::from(error),
+            ModifyOrderError::Storage(error) => Self::storage_error(error),
+        };
+    }
+}
+
+impl From<PaymentGatewayError> for ProblemDetails {
+    fn from(value: PaymentGatewayError) -> Self {
+        let detail = value.to_string();
+        return match value {
+            PaymentGatewayError::Declined(_) => Self::new(StatusCode::PAYMENT_REQUIRED, "payment_declined", "Payment declined", detail),
+            // Logged rather than returned like a storage error, the gateway's own message is no use to the table
+            PaymentGatewayError::Unavailable(error) => {
+                tracing::error!("payment gateway error: {}", error);
+                Self::new(StatusCode::BAD_GATEWAY, "payment_gateway_error", "Payment gateway error", "The payment gateway could not be reached, try again later.".to_string())
+            }
+        };
+    }
+}
+
+impl From<WebhookError> for ProblemDetails {
+    fn from(value: WebhookError) -> Self {
+        let detail = value.to_string();
+        return match value {
+            WebhookError::WebhookNotFound(_) => Self::new(StatusCode::NOT_FOUND, "webhook_not_found", "Webhook not found", detail),
+            WebhookError::DeadLetterNotFound(_) => Self::new(StatusCode::NOT_FOUND, "dead_letter_not_found", "Dead letter not found", detail),
+        };
+    }
+}
+
+impl From<ReadMenuItemError> for ProblemDetails {
+    fn from(value: ReadMenuItemError) -> Self {
+        let detail = value.to_string();
+        return match value {
+            ReadMenuItemError::MenuItemNotFound(item_id) => Self::new(StatusCode::NOT_FOUND, "menu_item_not_found", "Menu item not found", detail).with_item_id(item_id),
+        };
+    }
+}
+
+impl From<ReadBillError> for ProblemDetails {
+    fn from(value: ReadBillError) -> Self {
+        let detail = value.to_string();
+        return match value {
+            ReadBillError::BillNotFound(table_id) => Self::new(StatusCode::NOT_FOUND, "bill_not_found", "Bill not found", detail).with_table_id(table_id),
+        };
+    }
+}
+
+impl From<SplitBillError> for ProblemDetails {
+    fn from(value: SplitBillError) -> Self {
+        let detail = value.to_string();
+        return match value {
+            SplitBillError::LineNotOnBill(table_id, _, line_id) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "line_not_on_bill", "Line not on bill", detail)
+                .with_table_id(table_id)
+                .with_line_id(line_id),
+            SplitBillError::UnassignedLine(table_id, _, line_id) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "unassigned_line", "Unassigned line", detail)
+                .with_table_id(table_id)
+                .with_line_id(line_id),
+            SplitBillError::NoSeats(table_id, _) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "no_seats", "No seats", detail).with_table_id(table_id),
+            SplitBillError::SharesTooLarge(table_id, _, line_id, _) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_share", "Invalid share", detail)
+                .with_table_id(table_id)
+                .with_line_id(line_id),
+        };
+    }
+}
+
+impl From<ValidationError> for ProblemDetails {
+    fn from(value: ValidationError) -> Self {
+        let detail = value.to_string();
+        return match value {
+            ValidationError::InvalidTableId(table_id) => Self::new(StatusCode::BAD_REQUEST, "invalid_table_id", "Invalid table id", detail).with_table_id(table_id),
+            ValidationError::InvalidItemId(item_id) => Self::new(StatusCode::BAD_REQUEST, "invalid_item_id", "Invalid item id", detail).with_item_id(item_id),
+            ValidationError::InvalidLineId(line_id) => Self::new(StatusCode::BAD_REQUEST, "invalid_line_id", "Invalid line id", detail).with_line_id(line_id),
+            ValidationError::UnknownMenuItem(item_id) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "unknown_menu_item", "Unknown menu item", detail).with_item_id(item_id),
+            ValidationError::InvalidQuantity(item_id, _) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_quantity", "Invalid quantity", detail).with_item_id(item_id),
+            ValidationError::InvalidLineQuantity(line_id, _) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_quantity", "Invalid quantity", detail).with_line_id(line_id),
+            ValidationError::EmptyItemList => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "empty_item_list", "Empty item list", detail),
+            ValidationError::TooManyItems(_) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "too_many_items", "Too many items", detail),
+            ValidationError::UnknownModifier(item_id, _) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "unknown_modifier", "Unknown modifier", detail).with_item_id(item_id),
+            ValidationError::DuplicateModifier(item_id, _) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "duplicate_modifier", "Duplicate modifier", detail).with_item_id(item_id),
+            ValidationError::ConflictingModifiers(item_id, _) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "conflicting_modifiers", "Conflicting modifiers", detail).with_item_id(item_id),
+            ValidationError::NotesTooLong(item_id) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "notes_too_long", "Notes too long", detail).with_item_id(item_id),
+            ValidationError::LineNotesTooLong(line_id) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "notes_too_long", "Notes too long", detail).with_line_id(line_id),
+            ValidationError::NothingToChange(line_id) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "nothing_to_change", "Nothing to change", detail).with_line_id(line_id),
+            ValidationError::NonPositiveSeat(item_id, _) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_seat", "Invalid seat", detail).with_item_id(item_id),
+            ValidationError::NonPositiveLineSeat(line_id, _) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_seat", "Invalid seat", detail).with_line_id(line_id),
+            ValidationError::NonPositiveGuests(_) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_guests", "Invalid guests", detail),
+            ValidationError::UnknownGroupBy(_) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_group_by", "Invalid group by", detail),
+            ValidationError::InvalidSplit => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_split", "Invalid split", detail),
+            ValidationError::InvalidSplitCount(_) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_split", "Invalid split", detail),
+            ValidationError::NonPositiveShare(line_id, _) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_share", "Invalid share", detail).with_line_id(line_id),
+            ValidationError::InvalidPaymentId(payment_id) => Self::new(StatusCode::BAD_REQUEST, "invalid_payment_id", "Invalid payment id", detail).with_payment_id(payment_id),
+            ValidationError::UnknownPaymentMethod(_) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "unknown_payment_method", "Unknown payment method", detail),
+            ValidationError::MissingCardToken => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "missing_card_token", "Missing card token", detail),
+            ValidationError::InvalidAmount(_) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_amount", "Invalid amount", detail),
+            ValidationError::UnknownStatus(_) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "unknown_status", "Unknown status", detail),
+            ValidationError::UnknownStation(_) => Self::new(StatusCode::NOT_FOUND, "station_not_found", "Station not found", detail),
+            ValidationError::InvalidLastEventId(_) => Self::new(StatusCode::BAD_REQUEST, "invalid_last_event_id", "Invalid Last-Event-ID", detail),
+            ValidationError::InvalidWebhookId(_) => Self::new(StatusCode::BAD_REQUEST, "invalid_webhook_id", "Invalid webhook id", detail),
+            ValidationError::InvalidWebhookUrl(_) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_webhook_url", "Invalid webhook URL", detail),
+            ValidationError::WebhookUrlNotAllowed(_) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "webhook_url_not_allowed", "Webhook URL not allowed", detail),
+            ValidationError::InvalidDeadLetterId(_) => Self::new(StatusCode::BAD_REQUEST, "invalid_dead_letter_id", "Invalid dead letter id", detail),
+            ValidationError::MissingWebhookSecret => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "missing_webhook_secret", "Missing webhook secret", detail),
+            ValidationError::UnknownEventType(_) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "unknown_event_type", "Unknown event type", detail),
+        };
+    }
+}
+
+// Bodies that aren't JSON, don't match the expected shape, or are over the size limit
+impl From<JsonRejection> for ProblemDetails {
+    fn from(value: JsonRejection) -> Self {
+        let status = value.status();
+        return match status {
+            StatusCode::PAYLOAD_TOO_LARGE => Self::new(status, "payload_too_large", "Payload too large", value.body_text()),
+            _ => Self::new(status, "invalid_body", "Invalid request body", value.body_text()),
+        };
+    }
+}
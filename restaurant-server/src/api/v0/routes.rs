@@ -1,135 +1,870 @@
+use std::future::Future;
+
 use crate::{
-    models::orders::TableOrderItem,
-    persistence::persistence::{CreateOrderError, Persistence, ReadOrderError, ReadOrderItemError},
-    state::SharedAppState,
+    events::OrderEventKind,
+    models::{
+        kitchen::{list_pending_items, list_recallable_items, schedule_kitchen_queue, ReadyEstimates},
+        money::Money,
+        orders::{check_seats, LineId, OrderChangeError, OrderItemStatus, TableId, TableOrder},
+        payments::{PaymentId, PaymentMethod},
+    },
+    persistence::persistence::Persistence,
+    state::{AppState, SharedAppState},
 };
 use axum::{
-    extract::{Path, State},
+    extract::{rejection::JsonRejection, Path, Query, State},
     http::{Response, StatusCode},
     response::IntoResponse,
-    routing::{delete, get, post, put},
+    routing::{delete, get, patch, post, put},
     Json, Router,
 };
+use chrono::{DateTime, Utc};
 
 use super::{
-    client_params::{from_client_item, from_client_item_id, from_client_table_id, CreateOrUpdateOrderParams},
-    view_models::{to_order_item_detail_view_model, to_order_view_model},
+    client_params::{
+        from_client_amount, from_client_bill_split, from_client_card_token, from_client_dead_letter_id, from_client_event_types, from_client_group_by_seat, from_client_guests,
+        from_client_item_changes, from_client_item_id, from_client_items, from_client_line_id, from_client_payment_id, from_client_payment_method, from_client_station, from_client_status,
+        from_client_table_id, from_client_webhook_id, from_client_webhook_secret, from_client_webhook_url, CreateBillParams, CreateOrUpdateOrderParams, CreateOrderParams, CreatePaymentParams,
+        CreateWebhookParams, KdsParams, ReadOrderParams, RefundPaymentParams, SplitBillParams, UpdateItemStatusParams, UpdateOrderItemParams, UpdateOrderParams, MAX_ITEMS_PER_ORDER,
+    },
+    problem_details::ProblemDetails,
+    server_sent_events::order_events_sse_handler,
+    view_models::{
+        to_bill_split_view_model, to_bill_view_model, to_dead_letters_view_model, to_kds_view_model, to_kitchen_queue_view_model, to_menu_item_view_model, to_menu_view_model,
+        to_order_by_seat_view_model, to_order_item_detail_view_model, to_order_view_model, to_payments_view_model, to_settlements_view_model, to_station_tickets_view_model,
+        to_updated_order_view_model, to_webhook_view_model, to_webhooks_view_model,
+    },
+    websocket::order_events_websocket_handler,
 };
 
-pub fn create_routes() -> Router<SharedAppState> {
-    return Router::<SharedAppState>::new()
-        .route("/v0/orders/:table_id", post(create_order_handler))
-        .route("/v0/orders/:table_id", get(read_order_handler))
-        .route("/v0/orders/:table_id", put(update_order_handler))
-        .route("/v0/orders/:table_id", delete(delete_order_handler))
-        .route("/v0/orders/:table_id/items/:item_id", get(read_order_item_handler))
-        .route("/v0/orders/:table_id/items/:item_id", delete(delete_order_item_handler))
-        .route("/debug/dump_persistence", get(debug_dump_persistence_handler));
+pub fn create_routes<P: Persistence + 'static>() -> Router<SharedAppState<P>> {
+    return Router::<SharedAppState<P>>::new()
+        .route("/v0/orders/:table_id", post(create_order_handler::<P>))
+        .route("/v0/orders/:table_id", get(read_order_handler::<P>))
+        .route("/v0/orders/:table_id", put(update_order_handler::<P>))
+        .route("/v0/orders/:table_id", patch(update_order_guests_handler::<P>))
+        .route("/v0/orders/:table_id", delete(delete_order_handler::<P>))
+        .route("/v0/orders/:table_id/items", post(add_order_items_handler::<P>))
+        .route("/v0/orders/:table_id/items/:line_id", get(read_order_item_handler::<P>))
+        .route("/v0/orders/:table_id/items/:line_id", patch(update_order_item_handler::<P>))
+        .route("/v0/orders/:table_id/items/:line_id", delete(delete_order_item_handler::<P>))
+        .route("/v0/orders/:table_id/items/:line_id/status", post(update_order_item_status_handler::<P>))
+        .route("/v0/orders/:table_id/bill", post(create_bill_handler::<P>))
+        .route("/v0/orders/:table_id/bill", get(read_bill_handler::<P>))
+        .route("/v0/orders/:table_id/bill/split", post(split_bill_handler::<P>))
+        .route("/v0/orders/:table_id/payments", post(create_payment_handler::<P>))
+        .route("/v0/orders/:table_id/payments", get(read_payments_handler::<P>))
+        .route("/v0/orders/:table_id/payments/:payment_id/refund", post(refund_payment_handler::<P>))
+        .route("/v0/tables/:table_id/settlements", get(read_settlements_handler::<P>))
+        .route("/v0/kitchen/queue", get(read_kitchen_queue_handler::<P>))
+        .route("/v0/stations/:station/tickets", get(read_station_tickets_handler::<P>))
+        .route("/v0/kds/items", get(read_kds_items_handler::<P>))
+        .route("/v0/kds/tables/:table_id/items/:line_id/bump", post(bump_kds_item_handler::<P>))
+        .route("/v0/kds/tables/:table_id/items/:line_id/recall", post(recall_kds_item_handler::<P>))
+        .route("/v0/ws", get(order_events_websocket_handler::<P>))
+        .route("/v0/events", get(order_events_sse_handler::<P>))
+        .route("/v0/webhooks", post(create_webhook_handler::<P>))
+        .route("/v0/webhooks", get(read_webhooks_handler::<P>))
+        .route("/v0/webhooks/dead_letters", get(read_webhook_dead_letters_handler::<P>))
+        .route("/v0/webhooks/dead_letters/:dead_letter_id/replay", post(replay_webhook_dead_letter_handler::<P>))
+        .route("/v0/webhooks/:webhook_id", delete(delete_webhook_handler::<P>))
+        .route("/v0/menu", get(read_menu_handler::<P>))
+        .route("/v0/menu/:item_id", get(read_menu_item_handler::<P>))
+        .route("/debug/dump_persistence", get(debug_dump_persistence_handler::<P>));
 }
 
-async fn create_order_handler(State(state): State<SharedAppState>, Path(client_table_id): Path<String>, Json(payload): Json<CreateOrUpdateOrderParams>) -> Response<axum::body::Body> {
-    let app_state = &mut state.write().await;
-    let persistence = &mut app_state.persistence;
-    let table_id = from_client_table_id(&client_table_id);
+async fn create_order_handler<P: Persistence>(
+    State(state): State<SharedAppState<P>>, Path(client_table_id): Path<String>, payload: Result<Json<CreateOrderParams>, JsonRejection>,
+) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return create_error_response(rejection),
+    };
+
+    let guests = match from_client_guests(payload.guests) {
+        Ok(guests) => guests,
+        Err(err) => return create_error_response(err),
+    };
+    let new_items = match from_client_items(&payload.items, &state.menu, now) {
+        Ok(items) => items,
+        Err(err) => return create_error_response(err),
+    };
+    if let Err(err) = check_seats(&table_id, guests, &new_items) {
+        return create_error_response(err);
+    }
 
-    let new_items = payload.items.iter().map(|i| from_client_item(i)).collect::<Vec<TableOrderItem>>();
+    let events = &state.events;
+    let order = match persistence
+        .create_order(&table_id, guests, &new_items, |o| events.publish(&table_id, now, OrderEventKind::OrderCreated(o.clone())))
+        .await
+    {
+        Ok(order) => order,
+        Err(err) => return create_error_response(err),
+    };
 
-    let order = persistence.create_order(&table_id, &new_items).await;
-    return order.map_or_else(|err| create_error_response(err), |o| (StatusCode::CREATED, axum::Json(to_order_view_model(o))).into_response());
+    let estimates = estimate_ready_times(&state, &order, now).await;
+    return (StatusCode::CREATED, axum::Json(to_order_view_model(&order, &state.menu, &estimates, now))).into_response();
 }
 
-async fn read_order_handler(State(state): State<SharedAppState>, Path(client_table_id): Path<String>) -> Response<axum::body::Body> {
-    let app_state = &state.read().await;
-    let persistence = &app_state.persistence;
-    let table_id = from_client_table_id(&client_table_id);
-    let order = persistence.find_order(&table_id).await;
+async fn read_order_handler<P: Persistence>(State(state): State<SharedAppState<P>>, Path(client_table_id): Path<String>, Query(params): Query<ReadOrderParams>) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let group_by_seat = match from_client_group_by_seat(params.group_by.as_deref()) {
+        Ok(group_by_seat) => group_by_seat,
+        Err(err) => return create_error_response(err),
+    };
+    let order = match persistence.find_order(&table_id).await {
+        Ok(order) => order,
+        Err(err) => return create_error_response(err),
+    };
 
-    return order.map_or_else(|err| create_error_response(err), |o| (StatusCode::OK, axum::Json(to_order_view_model(o))).into_response());
+    let estimates = estimate_ready_times(&state, &order, now).await;
+    let view_model = if group_by_seat { to_order_by_seat_view_model(&order, &state.menu, &estimates, now) } else { to_order_view_model(&order, &state.menu, &estimates, now) };
+    return (StatusCode::OK, axum::Json(view_model)).into_response();
 }
 
-async fn update_order_handler(State(state): State<SharedAppState>, Path(client_table_id): Path<String>, Json(payload): Json<CreateOrUpdateOrderParams>) -> Response<axum::body::Body> {
-    let app_state = &mut state.write().await;
-    let persistence = &mut app_state.persistence;
-    let table_id = from_client_table_id(&client_table_id);
+// Fewer guests than before is refused while a line is still on a seat that would go
+async fn update_order_guests_handler<P: Persistence>(
+    State(state): State<SharedAppState<P>>, Path(client_table_id): Path<String>, payload: Result<Json<UpdateOrderParams>, JsonRejection>,
+) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return create_error_response(rejection),
+    };
+    let guests = match from_client_guests(Some(payload.guests)) {
+        Ok(guests) => guests,
+        Err(err) => return create_error_response(err),
+    };
 
-    let new_items = payload.items.iter().map(|i| from_client_item(i)).collect::<Vec<TableOrderItem>>();
+    let events = &state.events;
+    let order = match persistence
+        .update_order_with(&table_id, |o| o.set_guest_count(guests), |o| events.publish(&table_id, now, OrderEventKind::OrderUpdated(o.clone())))
+        .await
+    {
+        Ok(order) => order,
+        Err(err) => return create_error_response(err),
+    };
 
-    let order = persistence.update_order(&table_id, &new_items).await;
-    return order.map_or_else(|err| create_error_response(err), |o| (StatusCode::OK, axum::Json(to_order_view_model(o))).into_response());
+    let estimates = estimate_ready_times(&state, &order, now).await;
+    return (StatusCode::OK, axum::Json(to_order_view_model(&order, &state.menu, &estimates, now))).into_response();
 }
 
-async fn delete_order_handler(State(state): State<SharedAppState>, Path(client_table_id): Path<String>) -> Response<axum::body::Body> {
-    let app_state = &mut state.write().await;
-    let persistence = &mut app_state.persistence;
-    let table_id = from_client_table_id(&client_table_id);
+async fn update_order_handler<P: Persistence>(
+    State(state): State<SharedAppState<P>>, Path(client_table_id): Path<String>, payload: Result<Json<CreateOrUpdateOrderParams>, JsonRejection>,
+) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return create_error_response(rejection),
+    };
 
-    let result = persistence.delete_order(&table_id).await;
-    return result.map_or_else(|err| create_error_response(err), |_| (StatusCode::NO_CONTENT, ()).into_response());
+    let new_items = match from_client_items(&payload.items, &state.menu, now) {
+        Ok(items) => items,
+        Err(err) => return create_error_response(err),
+    };
+
+    let events = &state.events;
+    let (order, removed) = match persistence
+        .update_order(&table_id, &new_items, &state.menu, MAX_ITEMS_PER_ORDER, |o| events.publish(&table_id, now, OrderEventKind::OrderUpdated(o.clone())))
+        .await
+    {
+        Ok(result) => result,
+        Err(err) => return create_error_response(err),
+    };
+
+    let estimates = estimate_ready_times(&state, &order, now).await;
+    return (StatusCode::OK, axum::Json(to_updated_order_view_model(&order, &removed, &state.menu, &estimates, now))).into_response();
 }
 
-async fn read_order_item_handler(State(state): State<SharedAppState>, Path((client_table_id, client_item_id)): Path<(String, String)>) -> Response<axum::body::Body> {
-    let app_state = &state.read().await;
-    let persistence = &app_state.persistence;
-    let table_id = from_client_table_id(&client_table_id);
-    let item_id = from_client_item_id(&client_item_id);
-    let order = persistence.find_order(&table_id).await;
+// Closing the table, only once everything on the order has been billed and paid for
+async fn delete_order_handler<P: Persistence>(State(state): State<SharedAppState<P>>, Path(client_table_id): Path<String>) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
 
-    return order
-        .map_err(|err| create_error_response(err))
-        .and_then(|o| {
-            o.items
-                .get(&item_id)
-                .ok_or_else(|| create_error_response(ReadOrderItemError::OrderItemNotFound(item_id.to_string())))
-        })
-        .map_or_else(|err_response| err_response, |i| (StatusCode::OK, axum::Json(to_order_item_detail_view_model(i))).into_response());
+    let events = &state.events;
+    let result = persistence
+        .delete_order(&table_id, now, |o| o.check_settled(), |_, settlement| events.publish(&table_id, now, OrderEventKind::OrderDeleted(settlement.cloned())))
+        .await;
+    return result.map_or_else(create_error_response, |_| (StatusCode::NO_CONTENT, ()).into_response());
 }
 
-async fn delete_order_item_handler(State(state): State<SharedAppState>, Path((client_table_id, client_item_id)): Path<(String, String)>) -> Response<axum::body::Body> {
-    let app_state = &mut state.write().await;
-    let persistence = &mut app_state.persistence;
-    let table_id = from_client_table_id(&client_table_id);
-    let item_id = from_client_item_id(&client_item_id);
-    let order = persistence.delete_order_item(&table_id, &item_id).await;
+// Unlike PUT the lines already on the order are left alone, so the kitchen keeps its place on them
+async fn add_order_items_handler<P: Persistence>(
+    State(state): State<SharedAppState<P>>, Path(client_table_id): Path<String>, payload: Result<Json<CreateOrUpdateOrderParams>, JsonRejection>,
+) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return create_error_response(rejection),
+    };
 
-    return order.map_or_else(|err| create_error_response(err), |o| (StatusCode::OK, axum::Json(to_order_view_model(o))).into_response());
+    let new_items = match from_client_items(&payload.items, &state.menu, now) {
+        Ok(items) => items,
+        Err(err) => return create_error_response(err),
+    };
+
+    let events = &state.events;
+    let order = match persistence
+        .update_order_with(&table_id, |o| o.add_items(&new_items, MAX_ITEMS_PER_ORDER), |o| events.publish(&table_id, now, OrderEventKind::OrderUpdated(o.clone())))
+        .await
+    {
+        Ok(order) => order,
+        Err(err) => return create_error_response(err),
+    };
+
+    let estimates = estimate_ready_times(&state, &order, now).await;
+    return (StatusCode::CREATED, axum::Json(to_order_view_model(&order, &state.menu, &estimates, now))).into_response();
 }
 
-async fn debug_dump_persistence_handler(State(state): State<SharedAppState>) -> Response<axum::body::Body> {
-    let app_state = &mut state.write().await;
-    let persistence = &mut app_state.persistence;
+async fn read_order_item_handler<P: Persistence>(State(state): State<SharedAppState<P>>, Path((client_table_id, client_line_id)): Path<(String, String)>) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let line_id = match from_client_line_id(&client_line_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let order = match persistence.find_order(&table_id).await {
+        Ok(order) => order,
+        Err(err) => return create_error_response(err),
+    };
 
-    return (StatusCode::OK, format!("{:?}", persistence)).into_response();
+    return create_item_detail_response(&state, &order, &line_id, now).await;
 }
 
-fn create_error_response<E>(err: E) -> Response<axum::body::Body>
-where
-    E: Clone + ToString,
-    StatusCode: From<E>,
-{
-    return (StatusCode::from(err.clone()), err.to_string()).into_response();
+async fn delete_order_item_handler<P: Persistence>(State(state): State<SharedAppState<P>>, Path((client_table_id, client_line_id)): Path<(String, String)>) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let line_id = match from_client_line_id(&client_line_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let events = &state.events;
+    let order = match persistence
+        .update_order_with(&table_id, |o| o.remove_item(&line_id), |o| events.publish(&table_id, now, OrderEventKind::ItemRemoved(line_id, o.clone())))
+        .await
+    {
+        Ok(order) => order,
+        Err(err) => return create_error_response(err),
+    };
+
+    let estimates = estimate_ready_times(&state, &order, now).await;
+    return (StatusCode::OK, axum::Json(to_order_view_model(&order, &state.menu, &estimates, now))).into_response();
 }
 
-impl From<CreateOrderError> for StatusCode {
-    fn from(value: CreateOrderError) -> Self {
-        return match value {
-            CreateOrderError::OrderAlreadyExistsForTable(_) => Self::CONFLICT,
-        };
-    }
+async fn update_order_item_handler<P: Persistence>(
+    State(state): State<SharedAppState<P>>, Path((client_table_id, client_line_id)): Path<(String, String)>, payload: Result<Json<UpdateOrderItemParams>, JsonRejection>,
+) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let line_id = match from_client_line_id(&client_line_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return create_error_response(rejection),
+    };
+    let changes = match from_client_item_changes(&line_id, &payload) {
+        Ok(changes) => changes,
+        Err(err) => return create_error_response(err),
+    };
+
+    let (menu, events) = (&state.menu, &state.events);
+    let order = match persistence
+        .update_order_with(&table_id, |o| o.change_item(&line_id, &changes, menu), |o| events.publish(&table_id, now, OrderEventKind::OrderUpdated(o.clone())))
+        .await
+    {
+        Ok(order) => order,
+        Err(err) => return create_error_response(err),
+    };
+
+    return create_item_detail_response(&state, &order, &line_id, now).await;
 }
 
-impl From<ReadOrderError> for StatusCode {
-    fn from(value: ReadOrderError) -> Self {
-        return match value {
-            ReadOrderError::OrderNotFound(_) => Self::NOT_FOUND,
+async fn update_order_item_status_handler<P: Persistence>(
+    State(state): State<SharedAppState<P>>, Path((client_table_id, client_line_id)): Path<(String, String)>, payload: Result<Json<UpdateItemStatusParams>, JsonRejection>,
+) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let line_id = match from_client_line_id(&client_line_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return create_error_response(rejection),
+    };
+    let status = match from_client_status(&payload.status) {
+        Ok(status) => status,
+        Err(err) => return create_error_response(err),
+    };
+
+    let now = state.clock.now();
+    let events = &state.events;
+    // Front of house mostly cares about food being ready to take out, any other change is just an update to the order
+    let publish = |o: &TableOrder| {
+        let kind = match o.items.get(&line_id) {
+            Some(i) if status == OrderItemStatus::Ready => OrderEventKind::ItemReady(i.clone()),
+            _ => OrderEventKind::OrderUpdated(o.clone()),
         };
+        events.publish(&table_id, now, kind);
+    };
+    let order = match persistence
+        .update_order_with(&table_id, |o| o.advance_item_status(&line_id, status, now), publish)
+        .await
+    {
+        Ok(order) => order,
+        Err(err) => return create_error_response(err),
+    };
+
+    return create_item_detail_response(&state, &order, &line_id, now).await;
+}
+
+// Each request issues a new bill from the order as it is now, so one can be asked for again after the order changes
+async fn create_bill_handler<P: Persistence>(
+    State(state): State<SharedAppState<P>>, Path(client_table_id): Path<String>, payload: Result<Json<CreateBillParams>, JsonRejection>,
+) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return create_error_response(rejection),
+    };
+    let guests = match from_client_guests(payload.guests) {
+        Ok(guests) => guests,
+        Err(err) => return create_error_response(err),
+    };
+
+    let (menu, events) = (&state.menu, &state.events);
+    let publish = |o: &TableOrder| {
+        if let Ok(bill) = o.latest_bill() {
+            events.publish(&table_id, now, OrderEventKind::BillIssued(bill.clone()));
+        }
+    };
+    let order = persistence
+        .update_order_with(&table_id, |o| o.issue_bill(menu, guests, now).map(|_| ()), publish)
+        .await;
+
+    return match order {
+        Ok(o) => o
+            .latest_bill()
+            .map_or_else(create_error_response, |b| (StatusCode::CREATED, axum::Json(to_bill_view_model(&o.table_id, b))).into_response()),
+        Err(err) => create_error_response(err),
+    };
+}
+
+async fn read_bill_handler<P: Persistence>(State(state): State<SharedAppState<P>>, Path(client_table_id): Path<String>) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let order = persistence.find_order(&table_id).await;
+
+    return match order {
+        Ok(o) => o
+            .latest_bill()
+            .map_or_else(create_error_response, |b| (StatusCode::OK, axum::Json(to_bill_view_model(&o.table_id, b))).into_response()),
+        Err(err) => create_error_response(err),
+    };
+}
+
+// Works out who pays what on the latest bill without changing anything, so a table can try several ways of splitting it
+async fn split_bill_handler<P: Persistence>(
+    State(state): State<SharedAppState<P>>, Path(client_table_id): Path<String>, payload: Result<Json<SplitBillParams>, JsonRejection>,
+) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return create_error_response(rejection),
+    };
+    let split = match from_client_bill_split(&payload) {
+        Ok(split) => split,
+        Err(err) => return create_error_response(err),
+    };
+
+    let order = match persistence.find_order(&table_id).await {
+        Ok(order) => order,
+        Err(err) => return create_error_response(err),
+    };
+    let bill = match order.latest_bill() {
+        Ok(bill) => bill,
+        Err(err) => return create_error_response(err),
+    };
+
+    return bill
+        .split(&table_id, &split)
+        .map_or_else(create_error_response, |parts| (StatusCode::OK, axum::Json(to_bill_split_view_model(&table_id, bill, &parts))).into_response());
+}
+
+// Card payments are charged through the payment gateway before they are recorded, cash is just recorded
+async fn create_payment_handler<P: Persistence + 'static>(
+    State(state): State<SharedAppState<P>>, Path(client_table_id): Path<String>, payload: Result<Json<CreatePaymentParams>, JsonRejection>,
+) -> Response<axum::body::Body> {
+    let now = state.clock.now();
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return create_error_response(rejection),
+    };
+    let method = match from_client_payment_method(&payload.method) {
+        Ok(method) => method,
+        Err(err) => return create_error_response(err),
+    };
+    let card_token = match from_client_card_token(method, payload.card_token.as_deref()) {
+        Ok(card_token) => card_token,
+        Err(err) => return create_error_response(err),
+    };
+    let amount = match payload.amount.as_deref().map(|a| from_client_amount(a, state.menu.currency())).transpose() {
+        Ok(amount) => amount,
+        Err(err) => return create_error_response(err),
+    };
+
+    return run_to_completion(take_payment(state, table_id, method, card_token, amount, now)).await;
+}
+
+async fn take_payment<P: Persistence>(
+    state: SharedAppState<P>, table_id: TableId, method: PaymentMethod, card_token: Option<String>, amount: Option<Money>, now: DateTime<Utc>,
+) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+
+    // Tried on a copy first, so a card isn't charged for a payment that would be refused
+    let order = match persistence.find_order(&table_id).await {
+        Ok(order) => order,
+        Err(err) => return create_error_response(err),
+    };
+    let amount = amount.unwrap_or_else(|| order.balance().unwrap_or(Money::zero(state.menu.currency())));
+    if let Err(err) = order.clone().record_payment(method, amount, None, now) {
+        return create_error_response(err);
     }
+
+    let gateway_reference = match &card_token {
+        Some(card_token) => match state.payment_gateway.charge(card_token, amount).await {
+            Ok(reference) => Some(reference),
+            Err(err) => return create_error_response(err),
+        },
+        None => None,
+    };
+
+    let (reference, events) = (gateway_reference.clone(), &state.events);
+    let publish = |o: &TableOrder| {
+        if let Some(payment) = o.payments.last() {
+            events.publish(&table_id, now, OrderEventKind::PaymentRecorded(payment.clone()));
+        }
+    };
+    let order = persistence
+        .update_order_with(&table_id, |o| o.record_payment(method, amount, reference, now).map(|_| ()), publish)
+        .await;
+    return match order {
+        Ok(o) => create_payments_response(&o, StatusCode::CREATED),
+        Err(err) => {
+            // The order changed since it was checked, e.g. someone else paid at the same time. The charge is given back rather than kept with no record of it.
+            if let Some(reference) = &gateway_reference {
+                if let Err(refund_err) = state.payment_gateway.refund(reference, amount).await {
+                    tracing::error!("could not give back charge {} for table {} after the payment was refused: {}", reference, table_id, refund_err);
+                }
+            }
+            create_error_response(err)
+        }
+    };
+}
+
+async fn read_payments_handler<P: Persistence>(State(state): State<SharedAppState<P>>, Path(client_table_id): Path<String>) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let order = persistence.find_order(&table_id).await;
+
+    return match order {
+        Ok(o) => o
+            .latest_bill()
+            .map_or_else(create_error_response, |b| (StatusCode::OK, axum::Json(to_payments_view_model(&o, b))).into_response()),
+        Err(err) => create_error_response(err),
+    };
+}
+
+// Works whether or not the table has an open order, settlements are only left by closing it
+async fn read_settlements_handler<P: Persistence>(State(state): State<SharedAppState<P>>, Path(client_table_id): Path<String>) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+
+    return match persistence.list_settlements(&table_id).await {
+        Ok(settlements) => (StatusCode::OK, axum::Json(to_settlements_view_model(&settlements))).into_response(),
+        Err(err) => create_error_response(err),
+    };
+}
+
+// Card payments are refunded through the payment gateway, cash is just recorded as handed back
+async fn refund_payment_handler<P: Persistence + 'static>(
+    State(state): State<SharedAppState<P>>, Path((client_table_id, client_payment_id)): Path<(String, String)>, payload: Result<Json<RefundPaymentParams>, JsonRejection>,
+) -> Response<axum::body::Body> {
+    let now = state.clock.now();
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let payment_id = match from_client_payment_id(&client_payment_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return create_error_response(rejection),
+    };
+    let amount = match payload.amount.as_deref().map(|a| from_client_amount(a, state.menu.currency())).transpose() {
+        Ok(amount) => amount,
+        Err(err) => return create_error_response(err),
+    };
+
+    return run_to_completion(give_refund(state, table_id, payment_id, amount, now)).await;
 }
 
-impl From<ReadOrderItemError> for StatusCode {
-    fn from(value: ReadOrderItemError) -> Self {
-        return match value {
-            ReadOrderItemError::OrderNotFound(_) => Self::NOT_FOUND,
-            ReadOrderItemError::OrderItemNotFound(_) => Self::NOT_FOUND,
+async fn give_refund<P: Persistence>(state: SharedAppState<P>, table_id: TableId, payment_id: PaymentId, amount: Option<Money>, now: DateTime<Utc>) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let order = match persistence.find_order(&table_id).await {
+        Ok(order) => order,
+        Err(err) => return create_error_response(err),
+    };
+    let payment = match order.find_payment(payment_id) {
+        Ok(payment) => payment,
+        Err(err) => return create_error_response(err),
+    };
+    let amount = amount.unwrap_or_else(|| payment.net_amount());
+
+    let events = &state.events;
+    let publish = |o: &TableOrder| {
+        if let Ok(payment) = o.find_payment(payment_id) {
+            events.publish(&table_id, now, OrderEventKind::RefundRecorded(payment.clone()));
+        }
+    };
+    // Cash is handed back there and then, so there is nothing to wait on
+    let Some(charge_reference) = payment.gateway_reference.clone() else {
+        return match persistence
+            .update_order_with(&table_id, |o| o.record_refund(payment_id, amount, None, now).map(|_| ()), publish)
+            .await
+        {
+            Ok(o) => create_payments_response(&o, StatusCode::CREATED),
+            Err(err) => create_error_response(err),
         };
-    }
+    };
+
+    // Reserved under the table lock before the gateway is asked, so two refunds at the same time can't both give back the same money
+    let order = match persistence
+        .update_order_with(&table_id, |o| o.reserve_refund(payment_id, amount, now).map(|_| ()), |_| ())
+        .await
+    {
+        Ok(order) => order,
+        Err(err) => return create_error_response(err),
+    };
+    let seq = match order.find_payment(payment_id) {
+        Ok(payment) => payment.refunds.len() - 1,
+        Err(err) => return create_error_response(err),
+    };
+
+    let refunded = state.payment_gateway.refund(&charge_reference, amount).await;
+    let reference = refunded.as_ref().ok().cloned();
+    let order = persistence
+        .update_order_with(
+            &table_id,
+            |o| o.finish_refund(payment_id, seq, reference).map(|_| ()),
+            |o| {
+                if refunded.is_ok() {
+                    publish(o);
+                }
+            },
+        )
+        .await;
+    return match (refunded, order) {
+        (Ok(_), Ok(o)) => create_payments_response(&o, StatusCode::CREATED),
+        (Err(err), Ok(_)) => create_error_response(err),
+        (result, Err(err)) => {
+            // The refund is left pending, which keeps the table from being closed until someone looks into it
+            tracing::error!("refund of {} for payment {} of table {} is left pending, the gateway answered {:?} but that could not be recorded: {}", amount, payment_id, table_id, result, err);
+            create_error_response(err)
+        }
+    };
+}
+
+async fn debug_dump_persistence_handler<P: Persistence>(State(state): State<SharedAppState<P>>) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+
+    return (StatusCode::OK, format!("{:?}", persistence)).into_response();
+}
+
+async fn read_kitchen_queue_handler<P: Persistence>(State(state): State<SharedAppState<P>>) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let orders = persistence.list_orders().await;
+
+    return orders.map_or_else(create_error_response, |o| {
+        let queue = schedule_kitchen_queue(&o, &state.menu, &state.kitchen, now);
+        return (StatusCode::OK, axum::Json(to_kitchen_queue_view_model(&queue, &state.kitchen, &state.menu, now))).into_response();
+    });
+}
+
+async fn read_station_tickets_handler<P: Persistence>(State(state): State<SharedAppState<P>>, Path(client_station): Path<String>) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let station = match from_client_station(&client_station) {
+        Ok(station) => station,
+        Err(err) => return create_error_response(err),
+    };
+    let orders = persistence.list_orders().await;
+
+    // The whole kitchen is scheduled even though only one station is shown, so the estimates match the kitchen queue
+    return orders.map_or_else(create_error_response, |o| {
+        let queue = schedule_kitchen_queue(&o, &state.menu, &state.kitchen, now);
+        return (StatusCode::OK, axum::Json(to_station_tickets_view_model(station, &queue, &state.kitchen, &state.menu, now))).into_response();
+    });
+}
+
+async fn read_kds_items_handler<P: Persistence>(State(state): State<SharedAppState<P>>, Query(params): Query<KdsParams>) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let station = match params.station.as_deref().map(from_client_station).transpose() {
+        Ok(station) => station,
+        Err(err) => return create_error_response(err),
+    };
+    let orders = persistence.list_orders().await;
+
+    return orders.map_or_else(create_error_response, |o| {
+        let mut pending = list_pending_items(&o, &state.menu);
+        let mut recallable = list_recallable_items(&o, &state.menu, &state.kitchen, now);
+        if station.is_some() {
+            pending.retain(|i| i.station == station);
+            recallable.retain(|i| i.station == station);
+        }
+
+        return (StatusCode::OK, axum::Json(to_kds_view_model(&pending, &recallable, &state.kitchen, &state.menu, now))).into_response();
+    });
+}
+
+async fn bump_kds_item_handler<P: Persistence>(State(state): State<SharedAppState<P>>, Path((client_table_id, client_line_id)): Path<(String, String)>) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let line_id = match from_client_line_id(&client_line_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+
+    let events = &state.events;
+    let publish = |o: &TableOrder| {
+        if let Some(i) = o.items.get(&line_id) {
+            events.publish(&table_id, now, OrderEventKind::ItemReady(i.clone()));
+        }
+    };
+    let order = match persistence.update_order_with(&table_id, |o| o.bump_item(&line_id, now), publish).await {
+        Ok(order) => order,
+        Err(err) => return create_error_response(err),
+    };
+
+    return create_item_detail_response(&state, &order, &line_id, now).await;
+}
+
+async fn recall_kds_item_handler<P: Persistence>(State(state): State<SharedAppState<P>>, Path((client_table_id, client_line_id)): Path<(String, String)>) -> Response<axum::body::Body> {
+    let persistence = &state.persistence;
+    let now = state.clock.now();
+    let table_id = match from_client_table_id(&client_table_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let line_id = match from_client_line_id(&client_line_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+
+    let recall_window = state.kitchen.recall_window;
+    let events = &state.events;
+    let order = match persistence
+        .update_order_with(&table_id, |o| o.recall_item(&line_id, now, recall_window), |o| events.publish(&table_id, now, OrderEventKind::OrderUpdated(o.clone())))
+        .await
+    {
+        Ok(order) => order,
+        Err(err) => return create_error_response(err),
+    };
+
+    return create_item_detail_response(&state, &order, &line_id, now).await;
+}
+
+async fn create_webhook_handler<P: Persistence>(State(state): State<SharedAppState<P>>, payload: Result<Json<CreateWebhookParams>, JsonRejection>) -> Response<axum::body::Body> {
+    let now = state.clock.now();
+    let Json(payload) = match payload {
+        Ok(payload) => payload,
+        Err(rejection) => return create_error_response(rejection),
+    };
+    let url = match from_client_webhook_url(&payload.url, &state.webhook_hosts) {
+        Ok(url) => url,
+        Err(err) => return create_error_response(err),
+    };
+    let event_types = match from_client_event_types(&payload.events) {
+        Ok(event_types) => event_types,
+        Err(err) => return create_error_response(err),
+    };
+    let secret = match from_client_webhook_secret(&payload.secret) {
+        Ok(secret) => secret,
+        Err(err) => return create_error_response(err),
+    };
+
+    let subscription = state.webhooks.add(&url, event_types, &secret, now);
+    return (StatusCode::CREATED, axum::Json(to_webhook_view_model(&subscription))).into_response();
+}
+
+async fn read_webhooks_handler<P: Persistence>(State(state): State<SharedAppState<P>>) -> Response<axum::body::Body> {
+    return (StatusCode::OK, axum::Json(to_webhooks_view_model(&state.webhooks.list()))).into_response();
+}
+
+async fn delete_webhook_handler<P: Persistence>(State(state): State<SharedAppState<P>>, Path(client_webhook_id): Path<String>) -> Response<axum::body::Body> {
+    let webhook_id = match from_client_webhook_id(&client_webhook_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+
+    return state
+        .webhooks
+        .remove(webhook_id)
+        .map_or_else(create_error_response, |_| (StatusCode::NO_CONTENT, ()).into_response());
+}
+
+async fn read_webhook_dead_letters_handler<P: Persistence>(State(state): State<SharedAppState<P>>) -> Response<axum::body::Body> {
+    return (StatusCode::OK, axum::Json(to_dead_letters_view_model(&state.webhooks.dead_letters()))).into_response();
+}
+
+// Accepted rather than done, the delivery goes through its retries in the background like any other
+async fn replay_webhook_dead_letter_handler<P: Persistence>(State(state): State<SharedAppState<P>>, Path(client_dead_letter_id): Path<String>) -> Response<axum::body::Body> {
+    let dead_letter_id = match from_client_dead_letter_id(&client_dead_letter_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+
+    return state
+        .webhooks
+        .replay_dead_letter(dead_letter_id)
+        .map_or_else(create_error_response, |_| (StatusCode::ACCEPTED, ()).into_response());
+}
+
+async fn read_menu_handler<P: Persistence>(State(state): State<SharedAppState<P>>) -> Response<axum::body::Body> {
+    return (StatusCode::OK, axum::Json(to_menu_view_model(&state.menu))).into_response();
+}
+
+async fn read_menu_item_handler<P: Persistence>(State(state): State<SharedAppState<P>>, Path(client_item_id): Path<String>) -> Response<axum::body::Body> {
+    let item_id = match from_client_item_id(&client_item_id) {
+        Ok(id) => id,
+        Err(err) => return create_error_response(err),
+    };
+    let menu_item = state.menu.find_item(&item_id);
+
+    return menu_item.map_or_else(create_error_response, |i| (StatusCode::OK, axum::Json(to_menu_item_view_model(i))).into_response());
+}
+
+// When each line of `order` will be ready given everything else the kitchen has to do, see schedule_kitchen_queue.
+// `order` is used as given rather than as listed, so a response reflects the change it made even if another change has landed since.
+// If the other orders can't be listed the lines keep their own estimates, rather than failing a request that has already been applied.
+async fn estimate_ready_times<P: Persistence>(state: &AppState<P>, order: &TableOrder, now: DateTime<Utc>) -> ReadyEstimates {
+    let mut orders = match state.persistence.list_orders().await {
+        Ok(orders) => orders,
+        Err(err) => {
+            tracing::warn!("could not list orders to schedule the kitchen for table {}: {}", order.table_id, err);
+            return ReadyEstimates::default();
+        }
+    };
+    orders.retain(|o| o.table_id != order.table_id);
+    orders.push(order.clone());
+
+    return ReadyEstimates::from_queue(&schedule_kitchen_queue(&orders, &state.menu, &state.kitchen, now));
+}
+
+async fn create_item_detail_response<P: Persistence>(state: &AppState<P>, order: &TableOrder, line_id: &LineId, now: DateTime<Utc>) -> Response<axum::body::Body> {
+    let Some(item) = order.items.get(line_id) else {
+        return create_error_response(OrderChangeError::OrderItemNotFound(order.table_id.to_string(), line_id.to_string()));
+    };
+
+    let estimates = estimate_ready_times(state, order, now).await;
+    return (StatusCode::OK, axum::Json(to_order_item_detail_view_model(&order.table_id, item, &state.menu, &estimates, now))).into_response();
+}
+
+// Spawned so the work carries on when the request is dropped, e.g. the client hung up while a card was being charged.
+// Anything that talks to the payment gateway runs in here, so the gateway and the order can't end up disagreeing.
+async fn run_to_completion<F>(work: F) -> Response<axum::body::Body>
+where
+    F: Future<Output = Response<axum::body::Body>> + Send + 'static,
+{
+    return tokio::spawn(work).await.unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()));
+}
+
+fn create_payments_response(order: &TableOrder, status: StatusCode) -> Response<axum::body::Body> {
+    return order
+        .latest_bill()
+        .map_or_else(create_error_response, |b| (status, axum::Json(to_payments_view_model(order, b))).into_response());
+}
+
+fn create_error_response<E>(err: E) -> Response<axum::body::Body>
+where
+    ProblemDetails: From<E>,
+{
+    return ProblemDetails::from(err).into_response();
 }
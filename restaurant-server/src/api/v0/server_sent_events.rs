@@ -0,0 +1,81 @@
+use std::{collections::HashSet, convert::Infallible};
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, Response},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+};
+use futures_util::stream::{self, Stream};
+
+use crate::{
+    events::{CursorItem, EventCursor, OrderEvent},
+    models::{menu::Menu, orders::TableId},
+    persistence::persistence::Persistence,
+    state::SharedAppState,
+};
+
+use super::{
+    client_params::{from_client_last_event_id, from_client_table_ids, OrderEventsParams},
+    problem_details::ProblemDetails,
+    view_models::{to_order_event_view_model, OrderEventViewModel},
+};
+
+const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+// The same events as the websocket, for clients that can only hold a plain HTTP response open. Each event's `id` is its event id and `event` its type.
+// Browsers send back the last id they saw as Last-Event-ID when they reconnect, and anything since then that is still in the event history is sent first.
+pub async fn order_events_sse_handler<P: Persistence + 'static>(State(state): State<SharedAppState<P>>, Query(params): Query<OrderEventsParams>, headers: HeaderMap) -> Response<axum::body::Body> {
+    let tables = match params.tables.as_deref().map(from_client_table_ids).transpose() {
+        Ok(tables) => tables,
+        Err(err) => return ProblemDetails::from(err).into_response(),
+    };
+    let last_event_id = match headers
+        .get(LAST_EVENT_ID_HEADER)
+        .map(|id| from_client_last_event_id(&String::from_utf8_lossy(id.as_bytes())))
+        .transpose()
+    {
+        Ok(id) => id,
+        Err(err) => return ProblemDetails::from(err).into_response(),
+    };
+
+    // A new client starts from whatever is published next
+    let last_event_id = last_event_id.unwrap_or_else(|| state.events.latest_id());
+    let cursor = EventCursor::new(&state.events, last_event_id);
+
+    return Sse::new(stream_order_events(state, tables, cursor))
+        .keep_alive(KeepAlive::default())
+        .into_response();
+}
+
+fn stream_order_events<P: Persistence + 'static>(state: SharedAppState<P>, tables: Option<HashSet<TableId>>, cursor: EventCursor) -> impl Stream<Item = Result<Event, Infallible>> {
+    return stream::unfold(cursor, move |mut cursor| {
+        let state = state.clone();
+        let tables = tables.clone();
+        async move {
+            loop {
+                let sse_event = match cursor.next().await? {
+                    CursorItem::Missed(missed) => to_lagged_sse_event(missed),
+                    CursorItem::Event(event) if tables.as_ref().is_some_and(|t| !t.contains(&event.table_id)) => continue,
+                    CursorItem::Event(event) => to_order_sse_event(&event, &state.menu),
+                };
+                return Some((Ok(sse_event), cursor));
+            }
+        }
+    });
+}
+
+fn to_order_sse_event(event: &OrderEvent, menu: &Menu) -> Event {
+    return Event::default()
+        .id(event.id.to_string())
+        .event(event.kind.event_type().as_str())
+        .json_data(to_order_event_view_model(event, menu))
+        .unwrap();
+}
+
+// No id, so a client reconnecting after it still resumes from the last real event
+fn to_lagged_sse_event(missed: Option<u64>) -> Event {
+    return Event::default().event("lagged").json_data(OrderEventViewModel::Lagged { missed: missed }).unwrap();
+}
@@ -1,54 +1,737 @@
 // For a given persistence model, return a fixed format for this API version
 // in addition to allowing sending extra data to clients that may be more convenient, reducing requests
 
-use crate::models::{
-    menu::get_menu_item,
-    orders::{TableOrder, TableOrderItem},
+use chrono::{DateTime, Utc};
+
+use crate::{
+    events::{OrderEvent, OrderEventKind},
+    models::{
+        billing::{Bill, BillLine, BillTax, SplitPart},
+        kitchen::{KdsItem, KitchenConfig, QueuedItem, ReadyEstimates, Station},
+        menu::{Menu, MenuItem, MenuModifier},
+        money::Money,
+        orders::{OrderItemStatus, OrderItemStatusChange, TableId, TableOrder, TableOrderItem},
+        payments::{Payment, Settlement},
+    },
+    webhooks::{DeadLetter, WebhookSubscription},
 };
 
 #[derive(serde::Serialize, serde::Deserialize)]
 
 pub struct TableOrderViewModel {
     pub table_id: String,
+    pub guests: Option<u32>,
     pub items: Vec<TableOrderItemSummaryViewModel>,
+    pub subtotal: MoneyViewModel, // before tax and service, leaving out lines that can't be priced
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub seats: Option<Vec<SeatViewModel>>, // only when grouped by seat
 }
 
+// The lines for one seat, so runners know who gets which dish
 #[derive(serde::Serialize, serde::Deserialize)]
+pub struct SeatViewModel {
+    pub seat: Option<u32>, // None for the lines shared by the table, which come last
+    pub items: Vec<TableOrderItemSummaryViewModel>,
+    pub subtotal: MoneyViewModel,
+}
 
-pub struct TableOrderItemSummaryViewModel {
+// Exact amounts, as a decimal string in the major unit for showing and as whole minor units (e.g. cents) for arithmetic. Never a JSON float.
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+pub struct MoneyViewModel {
+    pub amount: String,
+    pub minor_units: i64,
+    pub currency: String,
+}
+
+// The response to replacing an order's items, the order as it now is plus what was taken off it
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct UpdatedTableOrderViewModel {
+    #[serde(flatten)]
+    pub order: TableOrderViewModel,
+    pub removed_items: Vec<RemovedOrderItemViewModel>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RemovedOrderItemViewModel {
+    pub line_id: String, // may still be on the order, if only part of its quantity was taken off
     pub item_id: String,
     pub name: String,
+    pub quantity: i32, // taken off
+    pub status: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+
+pub struct TableOrderItemSummaryViewModel {
+    pub line_id: String,
+    pub item_id: String, // the menu item
+    pub name: String,
     pub quantity: i32,
+    pub seat: Option<u32>,
+    pub unit_price: Option<MoneyViewModel>, // None once the item is no longer on the menu
+    pub line_total: Option<MoneyViewModel>,
     pub total_preparation_time_mins: i32,
+    pub ordered_at: String,
+    pub ready_at: String,
+    pub remaining_mins: i32,
+    pub status: String,
+    pub status_changed_at: Option<String>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct TableOrderItemDetailViewModel {
+    pub line_id: String,
     pub item_id: String,
     pub name: String,
     pub quantity: i32,
+    pub seat: Option<u32>,
+    pub unit_price: Option<MoneyViewModel>,
+    pub line_total: Option<MoneyViewModel>,
     pub total_preparation_time_mins: i32,
     pub description: String,
+    pub notes: Option<String>,
+    pub modifiers: Vec<OrderItemModifierViewModel>,
+    pub ordered_at: String,
+    pub ready_at: String,
+    pub remaining_mins: i32,
+    pub status: String,
+    pub status_history: Vec<OrderItemStatusChangeViewModel>,
 }
 
-pub fn to_order_view_model(order: &TableOrder) -> TableOrderViewModel {
-    return TableOrderViewModel { table_id: order.table_id.to_string(), items: order.items.values().map(|i| to_order_item_summary_view_model(&i)).collect() };
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct OrderItemModifierViewModel {
+    pub modifier_id: String,
+    pub name: String,
 }
 
-pub fn to_order_item_summary_view_model(item: &TableOrderItem) -> TableOrderItemSummaryViewModel {
-    let menu_item = get_menu_item(&item.item_id);
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct OrderItemStatusChangeViewModel {
+    pub status: String,
+    pub at: String,
+}
 
-    return TableOrderItemSummaryViewModel { item_id: item.item_id.to_string(), name: menu_item.name, quantity: item.quantity, total_preparation_time_mins: item.total_preparation_time_mins };
+// Rates are percentages as written in the menu file, e.g. "12.5%"
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BillViewModel {
+    pub table_id: String,
+    pub bill_id: String,
+    pub issued_at: String,
+    pub guests: Option<u32>,
+    pub tax_mode: String,     // inclusive or exclusive
+    pub tax_rounding: String, // per_line or per_bill
+    pub lines: Vec<BillLineViewModel>,
+    pub taxes: Vec<BillTaxViewModel>,
+    pub subtotal: MoneyViewModel,
+    pub service_charge: Option<BillServiceChargeViewModel>,
+    pub tax_total: MoneyViewModel,
+    pub total: MoneyViewModel,
 }
 
-pub fn to_order_item_detail_view_model(item: &TableOrderItem) -> TableOrderItemDetailViewModel {
-    let menu_item = get_menu_item(&item.item_id);
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BillLineViewModel {
+    pub line_id: String,
+    pub item_id: String,
+    pub name: String,
+    pub category: String,
+    pub seat: Option<u32>,
+    pub quantity: i32,
+    pub unit_price: MoneyViewModel,
+    pub amount: MoneyViewModel,
+    pub tax_rate: String,
+    pub tax: Option<MoneyViewModel>, // only when tax is rounded per line
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BillTaxViewModel {
+    pub rate: String,
+    pub taxable: MoneyViewModel,
+    pub tax: MoneyViewModel,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BillServiceChargeViewModel {
+    pub rate: String,
+    pub amount: MoneyViewModel,
+}
+
+// The parts add up to exactly the bill's total
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BillSplitViewModel {
+    pub table_id: String,
+    pub bill_id: String,
+    pub total: MoneyViewModel,
+    pub parts: Vec<BillSplitPartViewModel>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BillSplitPartViewModel {
+    pub payer: usize, // 1 based, in the order the payers were given
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub seat: Option<u32>, // only when split by seat
+    pub amount: MoneyViewModel,
+    pub lines: Vec<BillSplitLineViewModel>, // empty for an even split
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BillSplitLineViewModel {
+    pub line_id: String,
+    pub share: String, // of the line, e.g. "1/3"
+    pub amount: MoneyViewModel,
+}
+
+// Everything paid towards the order, and what is left to pay on the latest bill
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PaymentsViewModel {
+    pub table_id: String,
+    pub bill_id: String,         // the latest bill
+    pub total: MoneyViewModel,   // of the latest bill
+    pub paid: MoneyViewModel,    // less refunds, against any bill
+    pub balance: MoneyViewModel, // negative when more has been paid than the latest bill asks for
+    pub payments: Vec<PaymentViewModel>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PaymentViewModel {
+    pub payment_id: String,
+    pub bill_id: String,
+    pub method: String, // cash or card
+    pub amount: MoneyViewModel,
+    pub refunded: MoneyViewModel,
+    pub gateway_reference: Option<String>, // None for cash
+    pub paid_at: String,
+    pub refunds: Vec<RefundViewModel>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RefundViewModel {
+    pub amount: MoneyViewModel,
+    pub gateway_reference: Option<String>,
+    pub refunded_at: String,
+    pub status: String, // pending, refunded or failed
+}
+
+// Every bill and payment of an order, kept from when its table was closed
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SettlementViewModel {
+    pub settlement_id: String,
+    pub table_id: String,
+    pub closed_at: String,
+    pub bills: Vec<BillViewModel>,
+    pub payments: Vec<PaymentViewModel>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SettlementsViewModel {
+    pub settlements: Vec<SettlementViewModel>, // oldest first
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct MenuViewModel {
+    pub items: Vec<MenuItemViewModel>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct MenuItemViewModel {
+    pub item_id: String,
+    pub name: String,
+    pub description: String,
+    pub category: String,
+    pub price: MoneyViewModel,
+    pub station: String,
+    pub prep_time_mins: i32,
+    pub prep_time_per_extra_mins: i32,
+    pub modifiers: Vec<MenuModifierViewModel>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct MenuModifierViewModel {
+    pub modifier_id: String,
+    pub name: String,
+    pub group: Option<String>,
+    pub price: Option<MoneyViewModel>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct KitchenQueueViewModel {
+    pub stations: Vec<StationCapacityViewModel>,
+    pub items: Vec<KitchenQueueItemViewModel>, // in the order they will be worked on
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct StationCapacityViewModel {
+    pub station: String,
+    pub capacity: usize,
+}
+
+// What a single station's screen shows
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct StationTicketsViewModel {
+    pub station: String,
+    pub capacity: usize,
+    pub tickets: Vec<KitchenQueueItemViewModel>, // positions are within this station
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct KitchenQueueItemViewModel {
+    pub position: usize, // 1 based
+    pub table_id: String,
+    pub line_id: String,
+    pub item_id: String,
+    pub name: String,
+    pub quantity: i32,
+    pub status: String,
+    pub ordered_at: String,
+    pub station: String,
+    pub cook: usize, // 1 based, within the station
+    pub estimated_start_at: String,
+    pub estimated_ready_at: String,
+    pub remaining_mins: i32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct KdsViewModel {
+    pub pending: Vec<KdsItemViewModel>,    // oldest first
+    pub recallable: Vec<KdsItemViewModel>, // bumped recently enough to be recalled, most recent first
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct KdsItemViewModel {
+    pub table_id: String,
+    pub line_id: String,
+    pub item_id: String,
+    pub name: String,
+    pub quantity: i32,
+    pub notes: Option<String>,
+    pub modifiers: Vec<OrderItemModifierViewModel>,
+    pub station: Option<String>,
+    pub status: String,
+    pub ordered_at: String,
+    pub age_mins: i64,
+    pub bumped_at: Option<String>,
+    pub recall_until: Option<String>,
+}
+
+// Pushed to subscribers as JSON, tagged by `type`
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OrderEventViewModel {
+    OrderCreated { table_id: String, at: String, order: TableOrderViewModel },
+    OrderUpdated { table_id: String, at: String, order: TableOrderViewModel },
+    OrderDeleted { table_id: String, at: String, settlement: Option<SettlementViewModel> },
+    ItemRemoved { table_id: String, at: String, line_id: String, order: TableOrderViewModel },
+    ItemReady { table_id: String, at: String, item: TableOrderItemSummaryViewModel },
+    BillIssued { table_id: String, at: String, bill: BillViewModel },
+    PaymentRecorded { table_id: String, at: String, payment: PaymentViewModel },
+    RefundRecorded { table_id: String, at: String, payment: PaymentViewModel },
+    // The subscriber fell too far behind and this many events were dropped (None when it can't be told), it should re-read any orders it is showing
+    Lagged { missed: Option<u64> },
+}
+
+// The secret is never sent back
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct WebhookViewModel {
+    pub webhook_id: String,
+    pub url: String,
+    pub events: Vec<String>, // empty for every event type
+    pub created_at: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct WebhooksViewModel {
+    pub webhooks: Vec<WebhookViewModel>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct DeadLettersViewModel {
+    pub dead_letters: Vec<DeadLetterViewModel>, // oldest first
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct DeadLetterViewModel {
+    pub dead_letter_id: String,
+    pub webhook_id: String,
+    pub url: String,
+    pub event_id: String,
+    pub event_type: String,
+    pub attempts: u32,
+    pub last_error: String,
+    pub failed_at: String,
+    pub payload: serde_json::Value, // exactly what was signed and sent
+}
+
+// An order can outlive the menu item it refers to (e.g. the menu file changed while the sqlite persistence kept the order)
+fn get_menu_item_name_and_description(item: &TableOrderItem, menu: &Menu) -> (String, String) {
+    return menu
+        .find_item(&item.item_id)
+        .map_or_else(|_| (format!("unknown menu item {}", item.item_id), String::new()), |m| (m.name.clone(), m.description.clone()));
+}
+
+// Named from the menu, with the same fallback as the item itself for modifiers that have since been taken off it
+fn to_order_item_modifier_view_models(item: &TableOrderItem, menu: &Menu) -> Vec<OrderItemModifierViewModel> {
+    let menu_item = menu.find_item(&item.item_id).ok();
+
+    return item
+        .modifiers
+        .iter()
+        .map(|id| OrderItemModifierViewModel {
+            modifier_id: id.to_string(),
+            name: menu_item
+                .and_then(|m| m.find_modifier(id))
+                .map_or_else(|| format!("unknown modifier {}", id), |m| m.name.clone()),
+        })
+        .collect();
+}
+
+// Timestamps are RFC 3339. `remaining_mins` depends on when it is asked, so the caller passes in the current time.
+// `ready_at` is from the kitchen queue where the caller has scheduled it, see ReadyEstimates.
+pub fn to_order_view_model(order: &TableOrder, menu: &Menu, estimates: &ReadyEstimates, now: DateTime<Utc>) -> TableOrderViewModel {
+    return TableOrderViewModel {
+        table_id: order.table_id.to_string(),
+        guests: order.guest_count,
+        items: order
+            .items
+            .values()
+            .map(|i| to_order_item_summary_view_model(&order.table_id, i, menu, estimates, now))
+            .collect(),
+        subtotal: to_money_view_model(&order.subtotal(menu)),
+        seats: None,
+    };
+}
+
+// The same as to_order_view_model, with the lines grouped by seat as well
+pub fn to_order_by_seat_view_model(order: &TableOrder, menu: &Menu, estimates: &ReadyEstimates, now: DateTime<Utc>) -> TableOrderViewModel {
+    let seats = order
+        .items_by_seat()
+        .into_iter()
+        .map(|(seat, items)| SeatViewModel {
+            seat: seat,
+            subtotal: to_money_view_model(&Money::sum(items.iter().filter_map(|i| i.line_total(menu)), menu.currency())),
+            items: items
+                .into_iter()
+                .map(|i| to_order_item_summary_view_model(&order.table_id, i, menu, estimates, now))
+                .collect(),
+        })
+        .collect();
+
+    return TableOrderViewModel { seats: Some(seats), ..to_order_view_model(order, menu, estimates, now) };
+}
+
+pub fn to_updated_order_view_model(order: &TableOrder, removed: &[TableOrderItem], menu: &Menu, estimates: &ReadyEstimates, now: DateTime<Utc>) -> UpdatedTableOrderViewModel {
+    return UpdatedTableOrderViewModel { order: to_order_view_model(order, menu, estimates, now), removed_items: removed.iter().map(|i| to_removed_order_item_view_model(i, menu)).collect() };
+}
+
+fn to_removed_order_item_view_model(item: &TableOrderItem, menu: &Menu) -> RemovedOrderItemViewModel {
+    let (name, _) = get_menu_item_name_and_description(item, menu);
+
+    return RemovedOrderItemViewModel { line_id: item.line_id.to_string(), item_id: item.item_id.to_string(), name: name, quantity: item.quantity, status: item.status.to_string() };
+}
+
+pub fn to_order_item_summary_view_model(table_id: &TableId, item: &TableOrderItem, menu: &Menu, estimates: &ReadyEstimates, now: DateTime<Utc>) -> TableOrderItemSummaryViewModel {
+    let (name, _) = get_menu_item_name_and_description(item, menu);
+    let ready_at = estimates.ready_at(table_id, item);
+
+    return TableOrderItemSummaryViewModel {
+        line_id: item.line_id.to_string(),
+        item_id: item.item_id.to_string(),
+        name: name,
+        quantity: item.quantity,
+        seat: item.seat,
+        unit_price: item.unit_price(menu).as_ref().map(to_money_view_model),
+        line_total: item.line_total(menu).as_ref().map(to_money_view_model),
+        total_preparation_time_mins: item.total_preparation_time_mins,
+        ordered_at: item.ordered_at.to_rfc3339(),
+        ready_at: ready_at.to_rfc3339(),
+        remaining_mins: item.remaining_mins(ready_at, now),
+        status: item.status.to_string(),
+        status_changed_at: item.status_changed_at().map(|at| at.to_rfc3339()),
+    };
+}
+
+pub fn to_order_item_detail_view_model(table_id: &TableId, item: &TableOrderItem, menu: &Menu, estimates: &ReadyEstimates, now: DateTime<Utc>) -> TableOrderItemDetailViewModel {
+    let (name, description) = get_menu_item_name_and_description(item, menu);
+    let ready_at = estimates.ready_at(table_id, item);
 
     return TableOrderItemDetailViewModel {
+        line_id: item.line_id.to_string(),
         item_id: item.item_id.to_string(),
-        name: menu_item.name,
+        name: name,
         quantity: item.quantity,
+        seat: item.seat,
+        unit_price: item.unit_price(menu).as_ref().map(to_money_view_model),
+        line_total: item.line_total(menu).as_ref().map(to_money_view_model),
         total_preparation_time_mins: item.total_preparation_time_mins,
-        description: menu_item.description,
+        description: description,
+        notes: item.notes.clone(),
+        modifiers: to_order_item_modifier_view_models(item, menu),
+        ordered_at: item.ordered_at.to_rfc3339(),
+        ready_at: ready_at.to_rfc3339(),
+        remaining_mins: item.remaining_mins(ready_at, now),
+        status: item.status.to_string(),
+        status_history: item.status_history.iter().map(to_status_change_view_model).collect(),
+    };
+}
+
+fn to_status_change_view_model(change: &OrderItemStatusChange) -> OrderItemStatusChangeViewModel {
+    return OrderItemStatusChangeViewModel { status: change.status.to_string(), at: change.at.to_rfc3339() };
+}
+
+pub fn to_bill_view_model(table_id: &TableId, bill: &Bill) -> BillViewModel {
+    return BillViewModel {
+        table_id: table_id.to_string(),
+        bill_id: bill.bill_id.to_string(),
+        issued_at: bill.issued_at.to_rfc3339(),
+        guests: bill.guests,
+        tax_mode: bill.tax_mode.to_string(),
+        tax_rounding: bill.tax_rounding.to_string(),
+        lines: bill.lines.iter().map(to_bill_line_view_model).collect(),
+        taxes: bill.taxes.iter().map(to_bill_tax_view_model).collect(),
+        subtotal: to_money_view_model(&bill.subtotal),
+        service_charge: bill
+            .service_charge
+            .map(|s| BillServiceChargeViewModel { rate: s.rate.to_string(), amount: to_money_view_model(&s.amount) }),
+        tax_total: to_money_view_model(&bill.tax_total),
+        total: to_money_view_model(&bill.total),
+    };
+}
+
+fn to_bill_line_view_model(line: &BillLine) -> BillLineViewModel {
+    return BillLineViewModel {
+        line_id: line.line_id.to_string(),
+        item_id: line.item_id.to_string(),
+        name: line.name.clone(),
+        category: line.category.clone(),
+        seat: line.seat,
+        quantity: line.quantity,
+        unit_price: to_money_view_model(&line.unit_price),
+        amount: to_money_view_model(&line.amount),
+        tax_rate: line.tax_rate.to_string(),
+        tax: line.tax.as_ref().map(to_money_view_model),
+    };
+}
+
+fn to_bill_tax_view_model(tax: &BillTax) -> BillTaxViewModel {
+    return BillTaxViewModel { rate: tax.rate.to_string(), taxable: to_money_view_model(&tax.taxable), tax: to_money_view_model(&tax.tax) };
+}
+
+pub fn to_bill_split_view_model(table_id: &TableId, bill: &Bill, parts: &[SplitPart]) -> BillSplitViewModel {
+    return BillSplitViewModel {
+        table_id: table_id.to_string(),
+        bill_id: bill.bill_id.to_string(),
+        total: to_money_view_model(&bill.total),
+        parts: parts
+            .iter()
+            .enumerate()
+            .map(|(index, part)| BillSplitPartViewModel {
+                payer: index + 1,
+                seat: part.seat,
+                amount: to_money_view_model(&part.amount),
+                lines: part
+                    .lines
+                    .iter()
+                    .map(|l| BillSplitLineViewModel { line_id: l.line_id.to_string(), share: format!("{}/{}", l.share, l.of), amount: to_money_view_model(&l.amount) })
+                    .collect(),
+            })
+            .collect(),
+    };
+}
+
+pub fn to_payments_view_model(order: &TableOrder, bill: &Bill) -> PaymentsViewModel {
+    let paid = order.amount_paid(bill.currency);
+    return PaymentsViewModel {
+        table_id: order.table_id.to_string(),
+        bill_id: bill.bill_id.to_string(),
+        total: to_money_view_model(&bill.total),
+        paid: to_money_view_model(&paid),
+        balance: to_money_view_model(&(bill.total - paid)),
+        payments: order.payments.iter().map(to_payment_view_model).collect(),
+    };
+}
+
+pub fn to_settlement_view_model(settlement: &Settlement) -> SettlementViewModel {
+    return SettlementViewModel {
+        settlement_id: settlement.settlement_id.to_string(),
+        table_id: settlement.table_id.to_string(),
+        closed_at: settlement.closed_at.to_rfc3339(),
+        bills: settlement.bills.iter().map(|b| to_bill_view_model(&settlement.table_id, b)).collect(),
+        payments: settlement.payments.iter().map(to_payment_view_model).collect(),
+    };
+}
+
+pub fn to_settlements_view_model(settlements: &[Settlement]) -> SettlementsViewModel {
+    return SettlementsViewModel { settlements: settlements.iter().map(to_settlement_view_model).collect() };
+}
+
+fn to_payment_view_model(payment: &Payment) -> PaymentViewModel {
+    return PaymentViewModel {
+        payment_id: payment.payment_id.to_string(),
+        bill_id: payment.bill_id.to_string(),
+        method: payment.method.to_string(),
+        amount: to_money_view_model(&payment.amount),
+        refunded: to_money_view_model(&payment.refunded()),
+        gateway_reference: payment.gateway_reference.clone(),
+        paid_at: payment.paid_at.to_rfc3339(),
+        refunds: payment
+            .refunds
+            .iter()
+            .map(|r| RefundViewModel { amount: to_money_view_model(&r.amount), gateway_reference: r.gateway_reference.clone(), refunded_at: r.refunded_at.to_rfc3339(), status: r.status.to_string() })
+            .collect(),
+    };
+}
+
+pub fn to_menu_view_model(menu: &Menu) -> MenuViewModel {
+    return MenuViewModel { items: menu.items().map(to_menu_item_view_model).collect() };
+}
+
+pub fn to_menu_item_view_model(item: &MenuItem) -> MenuItemViewModel {
+    return MenuItemViewModel {
+        item_id: item.id.to_string(),
+        name: item.name.clone(),
+        description: item.description.clone(),
+        category: item.category.clone(),
+        price: to_money_view_model(&item.price),
+        station: item.station.to_string(),
+        prep_time_mins: item.prep_time_mins,
+        prep_time_per_extra_mins: item.prep_time_per_extra_mins,
+        modifiers: item.modifiers.iter().map(to_menu_modifier_view_model).collect(),
+    };
+}
+
+fn to_menu_modifier_view_model(modifier: &MenuModifier) -> MenuModifierViewModel {
+    return MenuModifierViewModel { modifier_id: modifier.id.to_string(), name: modifier.name.clone(), group: modifier.group.clone(), price: modifier.price.as_ref().map(to_money_view_model) };
+}
+
+pub fn to_money_view_model(money: &Money) -> MoneyViewModel {
+    return MoneyViewModel { amount: money.to_decimal_string(), minor_units: money.minor_units, currency: money.currency.to_string() };
+}
+
+pub fn to_kitchen_queue_view_model(queue: &[QueuedItem], config: &KitchenConfig, menu: &Menu, now: DateTime<Utc>) -> KitchenQueueViewModel {
+    return KitchenQueueViewModel {
+        stations: config
+            .capacities()
+            .map(|(station, capacity)| StationCapacityViewModel { station: station.to_string(), capacity: capacity })
+            .collect(),
+        items: queue
+            .iter()
+            .enumerate()
+            .map(|(index, queued)| to_kitchen_queue_item_view_model(index + 1, queued, menu, now))
+            .collect(),
+    };
+}
+
+pub fn to_station_tickets_view_model(station: Station, queue: &[QueuedItem], config: &KitchenConfig, menu: &Menu, now: DateTime<Utc>) -> StationTicketsViewModel {
+    return StationTicketsViewModel {
+        station: station.to_string(),
+        capacity: config.capacity(station),
+        tickets: queue
+            .iter()
+            .filter(|queued| queued.station == station)
+            .enumerate()
+            .map(|(index, queued)| to_kitchen_queue_item_view_model(index + 1, queued, menu, now))
+            .collect(),
+    };
+}
+
+fn to_kitchen_queue_item_view_model(position: usize, queued: &QueuedItem, menu: &Menu, now: DateTime<Utc>) -> KitchenQueueItemViewModel {
+    let (name, _) = get_menu_item_name_and_description(&queued.item, menu);
+    let remaining_secs = (queued.estimated_ready_at - now).num_seconds().max(0);
+
+    return KitchenQueueItemViewModel {
+        position: position,
+        table_id: queued.table_id.to_string(),
+        line_id: queued.item.line_id.to_string(),
+        item_id: queued.item.item_id.to_string(),
+        name: name,
+        quantity: queued.item.quantity,
+        status: queued.item.status.to_string(),
+        ordered_at: queued.item.ordered_at.to_rfc3339(),
+        station: queued.station.to_string(),
+        cook: queued.cook + 1,
+        estimated_start_at: queued.estimated_start_at.to_rfc3339(),
+        estimated_ready_at: queued.estimated_ready_at.to_rfc3339(),
+        remaining_mins: ((remaining_secs + 59) / 60) as i32,
+    };
+}
+
+pub fn to_kds_view_model(pending: &[KdsItem], recallable: &[KdsItem], config: &KitchenConfig, menu: &Menu, now: DateTime<Utc>) -> KdsViewModel {
+    return KdsViewModel {
+        pending: pending.iter().map(|i| to_kds_item_view_model(i, config, menu, now)).collect(),
+        recallable: recallable.iter().map(|i| to_kds_item_view_model(i, config, menu, now)).collect(),
+    };
+}
+
+fn to_kds_item_view_model(kds_item: &KdsItem, config: &KitchenConfig, menu: &Menu, now: DateTime<Utc>) -> KdsItemViewModel {
+    let (name, _) = get_menu_item_name_and_description(&kds_item.item, menu);
+    let bumped_at = match kds_item.item.status {
+        OrderItemStatus::Ready => kds_item.item.status_changed_at(),
+        _ => None,
+    };
+
+    return KdsItemViewModel {
+        table_id: kds_item.table_id.to_string(),
+        line_id: kds_item.item.line_id.to_string(),
+        item_id: kds_item.item.item_id.to_string(),
+        name: name,
+        quantity: kds_item.item.quantity,
+        notes: kds_item.item.notes.clone(),
+        modifiers: to_order_item_modifier_view_models(&kds_item.item, menu),
+        station: kds_item.station.map(|s| s.to_string()),
+        status: kds_item.item.status.to_string(),
+        ordered_at: kds_item.item.ordered_at.to_rfc3339(),
+        age_mins: (now - kds_item.item.ordered_at).num_minutes().max(0),
+        bumped_at: bumped_at.map(|at| at.to_rfc3339()),
+        recall_until: bumped_at.map(|at| (at + config.recall_window).to_rfc3339()),
+    };
+}
+
+// Times in the event are as of when it happened rather than when it is sent.
+// The kitchen isn't scheduled for every event, so `ready_at` is each line's own estimate, see ReadyEstimates.
+pub fn to_order_event_view_model(event: &OrderEvent, menu: &Menu) -> OrderEventViewModel {
+    let table_id = event.table_id.to_string();
+    let at = event.at.to_rfc3339();
+    let estimates = ReadyEstimates::default();
+
+    return match &event.kind {
+        OrderEventKind::OrderCreated(order) => OrderEventViewModel::OrderCreated { table_id: table_id, at: at, order: to_order_view_model(order, menu, &estimates, event.at) },
+        OrderEventKind::OrderUpdated(order) => OrderEventViewModel::OrderUpdated { table_id: table_id, at: at, order: to_order_view_model(order, menu, &estimates, event.at) },
+        OrderEventKind::OrderDeleted(settlement) => OrderEventViewModel::OrderDeleted { table_id: table_id, at: at, settlement: settlement.as_ref().map(to_settlement_view_model) },
+        OrderEventKind::ItemRemoved(line_id, order) => {
+            OrderEventViewModel::ItemRemoved { table_id: table_id, at: at, line_id: line_id.to_string(), order: to_order_view_model(order, menu, &estimates, event.at) }
+        }
+        OrderEventKind::ItemReady(item) => OrderEventViewModel::ItemReady { table_id: table_id, at: at, item: to_order_item_summary_view_model(&event.table_id, item, menu, &estimates, event.at) },
+        OrderEventKind::BillIssued(bill) => OrderEventViewModel::BillIssued { table_id: table_id, at: at, bill: to_bill_view_model(&event.table_id, bill) },
+        OrderEventKind::PaymentRecorded(payment) => OrderEventViewModel::PaymentRecorded { table_id: table_id, at: at, payment: to_payment_view_model(payment) },
+        OrderEventKind::RefundRecorded(payment) => OrderEventViewModel::RefundRecorded { table_id: table_id, at: at, payment: to_payment_view_model(payment) },
+    };
+}
+
+pub fn to_webhook_view_model(subscription: &WebhookSubscription) -> WebhookViewModel {
+    return WebhookViewModel {
+        webhook_id: subscription.id.to_string(),
+        url: subscription.url.clone(),
+        events: subscription.event_types.iter().map(|t| t.to_string()).collect(),
+        created_at: subscription.created_at.to_rfc3339(),
+    };
+}
+
+pub fn to_webhooks_view_model(subscriptions: &[WebhookSubscription]) -> WebhooksViewModel {
+    return WebhooksViewModel { webhooks: subscriptions.iter().map(to_webhook_view_model).collect() };
+}
+
+pub fn to_dead_letters_view_model(dead_letters: &[DeadLetter]) -> DeadLettersViewModel {
+    return DeadLettersViewModel {
+        dead_letters: dead_letters
+            .iter()
+            .map(|d| DeadLetterViewModel {
+                dead_letter_id: d.id.to_string(),
+                webhook_id: d.webhook_id.to_string(),
+                url: d.url.clone(),
+                event_id: d.event_id.to_string(),
+                event_type: d.event_type.to_string(),
+                attempts: d.attempts,
+                last_error: d.last_error.clone(),
+                failed_at: d.failed_at.to_rfc3339(),
+                payload: serde_json::from_str(&d.payload).unwrap_or(serde_json::Value::Null),
+            })
+            .collect(),
     };
 }
@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::{
+    events::{CursorItem, EventCursor},
+    persistence::persistence::Persistence,
+    state::SharedAppState,
+    webhooks::{create_webhook_client, deliver_with_retries, WebhookClient, WebhookDelivery},
+};
+
+use super::view_models::to_order_event_view_model;
+
+// Sends every order event to the webhooks that want it, with the same JSON as the websocket, along with any dead letters asked to be replayed.
+// Each delivery retries on its own, so one slow or failing receiver doesn't hold up the others, but it also means a receiver can see events out of order (the event id header gives the order).
+// Runs until the returned task is aborted, which also stops any deliveries still retrying.
+pub fn spawn_webhook_dispatcher<P: Persistence + 'static>(state: SharedAppState<P>) -> JoinHandle<()> {
+    // Subscribed straight away, so nothing published once the dispatcher is started is missed
+    let mut cursor = EventCursor::new(&state.events, state.events.latest_id());
+
+    return tokio::spawn(async move {
+        let client = create_webhook_client(&state.webhook_hosts);
+        // Owned by this task, so the deliveries go when it does
+        let mut deliveries = JoinSet::new();
+        loop {
+            while deliveries.try_join_next().is_some() {}
+
+            let item = tokio::select! {
+                item = cursor.next() => item,
+                _ = state.webhooks.replay_requested() => {
+                    for delivery in state.webhooks.take_replays() {
+                        spawn_delivery(&mut deliveries, &state, &client, delivery);
+                    }
+                    continue;
+                }
+            };
+            let event = match item {
+                Some(CursorItem::Event(event)) => event,
+                Some(CursorItem::Missed(missed)) => {
+                    tracing::error!("webhook dispatcher fell behind, {:?} events were not delivered", missed);
+                    continue;
+                }
+                None => return,
+            };
+
+            let event_type = event.kind.event_type();
+            let subscriptions = state.webhooks.matching(event_type);
+            if subscriptions.is_empty() {
+                continue;
+            }
+
+            let payload: Arc<str> = serde_json::to_string(&to_order_event_view_model(&event, &state.menu)).unwrap().into();
+            for subscription in subscriptions {
+                let delivery = WebhookDelivery { subscription: subscription, event_id: event.id, event_type: event_type, payload: Arc::clone(&payload) };
+                spawn_delivery(&mut deliveries, &state, &client, delivery);
+            }
+        }
+    });
+}
+
+fn spawn_delivery<P: Persistence + 'static>(deliveries: &mut JoinSet<()>, state: &SharedAppState<P>, client: &WebhookClient, delivery: WebhookDelivery) {
+    let (state, client) = (Arc::clone(state), client.clone());
+    deliveries.spawn(async move {
+        deliver_with_retries(&client, &state.webhooks, &delivery, &state.webhook_retry, state.clock.as_ref()).await;
+    });
+}
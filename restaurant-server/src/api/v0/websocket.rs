@@ -0,0 +1,56 @@
+use std::collections::HashSet;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::Response,
+    response::IntoResponse,
+};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+use crate::{events::OrderEvent, models::orders::TableId, persistence::persistence::Persistence, state::SharedAppState};
+
+use super::{
+    client_params::{from_client_table_ids, OrderEventsParams},
+    problem_details::ProblemDetails,
+    view_models::{to_order_event_view_model, OrderEventViewModel},
+};
+
+// Pushes every order change as it happens, see OrderEventViewModel. `?tables=1,2` only sends changes to those tables.
+pub async fn order_events_websocket_handler<P: Persistence + 'static>(
+    State(state): State<SharedAppState<P>>, Query(params): Query<OrderEventsParams>, upgrade: WebSocketUpgrade,
+) -> Response<axum::body::Body> {
+    let tables = match params.tables.as_deref().map(from_client_table_ids).transpose() {
+        Ok(tables) => tables,
+        Err(err) => return ProblemDetails::from(err).into_response(),
+    };
+
+    // Subscribed before upgrading, so a change made as soon as the client sees the handshake complete isn't missed
+    let events = state.events.subscribe();
+    return upgrade.on_upgrade(move |socket| send_order_events(socket, events, tables, state));
+}
+
+async fn send_order_events<P: Persistence>(mut socket: WebSocket, mut events: Receiver<OrderEvent>, tables: Option<HashSet<TableId>>, state: SharedAppState<P>) {
+    loop {
+        let view_model = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if tables.as_ref().is_some_and(|t| !t.contains(&event.table_id)) => continue,
+                Ok(event) => to_order_event_view_model(&event, &state.menu),
+                Err(RecvError::Lagged(missed)) => OrderEventViewModel::Lagged { missed: Some(missed) },
+                Err(RecvError::Closed) => return,
+            },
+            // Nothing is expected from the client, reading is only to notice it going away (pings are answered for us)
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        let text = serde_json::to_string(&view_model).unwrap();
+        if socket.send(Message::Text(text)).await.is_err() {
+            return;
+        }
+    }
+}
@@ -1,19 +1,26 @@
-use std::sync::Arc;
-
-use axum::Router;
-use tokio::sync::RwLock;
+use axum::{extract::DefaultBodyLimit, Router};
 
 use crate::{
-    api,
-    persistence::memory_persistence::MemoryPersistence,
-    state::{AppState, SharedAppState},
+    api::{self, v0::client_params::MAX_REQUEST_BODY_BYTES},
+    persistence::persistence::Persistence,
+    state::SharedAppState,
 };
 
-pub fn create_app(persistence: MemoryPersistence) -> Router {
-    let app_state = AppState { persistence: persistence };
-    let shared_app_state = Arc::new(RwLock::new(app_state));
+// Defaults for everything but the persistence and menu, see AppState::new
+#[cfg(test)]
+pub fn create_app<P: Persistence + 'static>(persistence: P, menu: crate::models::menu::Menu) -> Router {
+    return create_app_with_state(crate::state::AppState::new(persistence, menu));
+}
+
+#[cfg(test)]
+pub fn create_app_with_state<P: Persistence + 'static>(app_state: crate::state::AppState<P>) -> Router {
+    return create_router(std::sync::Arc::new(app_state));
+}
 
-    return Router::<SharedAppState>::new()
+// Webhooks are only sent once the dispatcher is started as well, see spawn_webhook_dispatcher
+pub fn create_router<P: Persistence + 'static>(shared_app_state: SharedAppState<P>) -> Router {
+    return Router::<SharedAppState<P>>::new()
         .merge(api::v0::routes::create_routes())
-        .with_state(Arc::clone(&shared_app_state));
+        .layer(DefaultBodyLimit::max(MAX_REQUEST_BODY_BYTES))
+        .with_state(shared_app_state);
 }
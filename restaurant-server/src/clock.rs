@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+
+#[cfg(test)]
+use chrono::TimeDelta;
+#[cfg(test)]
+use std::sync::Mutex;
+
+// Everything that needs the current time asks the clock in AppState rather than calling Utc::now() directly,
+// so tests can swap in a ManualClock and move time forward instead of sleeping.
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        return Utc::now();
+    }
+}
+
+// Only moves when told to
+#[cfg(test)]
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        return Self { now: Mutex::new(start) };
+    }
+
+    pub fn advance(&self, by: TimeDelta) {
+        let mut now = self.now.lock().unwrap();
+        *now += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        return *self.now.lock().unwrap();
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::models::{
+    billing::Bill,
+    orders::{LineId, TableId, TableOrder, TableOrderItem},
+    payments::{Payment, Settlement},
+};
+
+// How many events a slow subscriber can fall behind before it starts missing them
+pub const EVENT_BUS_CAPACITY: usize = 256;
+// How many of the latest events are kept for subscribers resuming after a disconnect
+pub const EVENT_HISTORY_CAPACITY: usize = 1024;
+
+// Something that changed an order, published once the change has been saved but before the table is unlocked, so events for a table come in the order its changes were made
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderEvent {
+    pub id: u64, // 1 based, in the order events were published. Starts again when the server restarts.
+    pub table_id: TableId,
+    pub at: DateTime<Utc>,
+    pub kind: OrderEventKind,
+}
+
+// Carries the state after the change, so subscribers don't have to read it back
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderEventKind {
+    OrderCreated(TableOrder),
+    OrderUpdated(TableOrder),
+    OrderDeleted(Option<Settlement>), // None when nothing was ever billed or paid
+    ItemRemoved(LineId, TableOrder),
+    ItemReady(TableOrderItem),
+    BillIssued(Bill),
+    PaymentRecorded(Payment),
+    RefundRecorded(Payment), // the payment with the new refund last
+}
+
+// What kind of change an event is, without the data, for filtering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OrderEventType {
+    OrderCreated,
+    OrderUpdated,
+    OrderDeleted,
+    ItemRemoved,
+    ItemReady,
+    BillIssued,
+    PaymentRecorded,
+    RefundRecorded,
+}
+
+pub const ALL_ORDER_EVENT_TYPES: [OrderEventType; 8] = [
+    OrderEventType::OrderCreated,
+    OrderEventType::OrderUpdated,
+    OrderEventType::OrderDeleted,
+    OrderEventType::ItemRemoved,
+    OrderEventType::ItemReady,
+    OrderEventType::BillIssued,
+    OrderEventType::PaymentRecorded,
+    OrderEventType::RefundRecorded,
+];
+
+#[derive(Error, Debug, PartialEq, Clone)]
+#[error("Unknown order event type {0}.")]
+pub struct ParseOrderEventTypeError(pub String);
+
+impl OrderEventType {
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            OrderEventType::OrderCreated => "order_created",
+            OrderEventType::OrderUpdated => "order_updated",
+            OrderEventType::OrderDeleted => "order_deleted",
+            OrderEventType::ItemRemoved => "item_removed",
+            OrderEventType::ItemReady => "item_ready",
+            OrderEventType::BillIssued => "bill_issued",
+            OrderEventType::PaymentRecorded => "payment_recorded",
+            OrderEventType::RefundRecorded => "refund_recorded",
+        };
+    }
+}
+
+impl std::fmt::Display for OrderEventType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for OrderEventType {
+    type Err = ParseOrderEventTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return ALL_ORDER_EVENT_TYPES
+            .iter()
+            .find(|t| t.as_str() == s)
+            .copied()
+            .ok_or_else(|| ParseOrderEventTypeError(s.to_string()));
+    }
+}
+
+impl OrderEventKind {
+    pub fn event_type(&self) -> OrderEventType {
+        return match self {
+            OrderEventKind::OrderCreated(_) => OrderEventType::OrderCreated,
+            OrderEventKind::OrderUpdated(_) => OrderEventType::OrderUpdated,
+            OrderEventKind::OrderDeleted(_) => OrderEventType::OrderDeleted,
+            OrderEventKind::ItemRemoved(_, _) => OrderEventType::ItemRemoved,
+            OrderEventKind::ItemReady(_) => OrderEventType::ItemReady,
+            OrderEventKind::BillIssued(_) => OrderEventType::BillIssued,
+            OrderEventKind::PaymentRecorded(_) => OrderEventType::PaymentRecorded,
+            OrderEventKind::RefundRecorded(_) => OrderEventType::RefundRecorded,
+        };
+    }
+}
+
+// Whether a resuming subscriber has a gap that can't be filled from the history
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MissedEvents {
+    None,
+    Count(u64),
+    Unknown, // the id is newer than anything published, e.g. from before a restart
+}
+
+#[derive(Debug)]
+pub struct Subscription {
+    pub missed: MissedEvents,
+    pub replay: VecDeque<OrderEvent>, // everything after the given id still in the history, oldest first
+    pub receiver: broadcast::Receiver<OrderEvent>,
+}
+
+#[derive(Debug)]
+struct EventHistory {
+    next_id: u64,
+    recent: VecDeque<OrderEvent>,
+}
+
+// Fans each event out to every subscriber. Publishing never waits on subscribers, ones that fall too far behind are told how many events they missed instead.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<OrderEvent>,
+    history: Arc<Mutex<EventHistory>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        return Self { sender: sender, history: Arc::new(Mutex::new(EventHistory { next_id: 1, recent: VecDeque::with_capacity(EVENT_HISTORY_CAPACITY) })) };
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, table_id: &TableId, at: DateTime<Utc>, kind: OrderEventKind) {
+        let mut history = self.history.lock().unwrap();
+        let event = OrderEvent { id: history.next_id, table_id: table_id.clone(), at: at, kind: kind };
+        history.next_id += 1;
+        if history.recent.len() == EVENT_HISTORY_CAPACITY {
+            history.recent.pop_front();
+        }
+        history.recent.push_back(event.clone());
+
+        // Sent while still holding the history, so a subscriber resuming at the same time gets each event exactly once, either replayed or live.
+        // Only fails when nobody is subscribed, which is fine.
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<OrderEvent> {
+        return self.sender.subscribe();
+    }
+
+    // The id of the latest event published, 0 if there hasn't been one
+    pub fn latest_id(&self) -> u64 {
+        return self.history.lock().unwrap().next_id - 1;
+    }
+
+    // Live events from now on, plus any after `last_id` that were published before subscribing
+    pub fn subscribe_after(&self, last_id: u64) -> Subscription {
+        let history = self.history.lock().unwrap();
+        let receiver = self.sender.subscribe();
+
+        if last_id >= history.next_id {
+            return Subscription { missed: MissedEvents::Unknown, replay: history.recent.iter().cloned().collect(), receiver: receiver };
+        }
+
+        let oldest_id = history.recent.front().map_or(history.next_id, |e| e.id);
+        let missed = if last_id + 1 < oldest_id { MissedEvents::Count(oldest_id - last_id - 1) } else { MissedEvents::None };
+        return Subscription { missed: missed, replay: history.recent.iter().filter(|e| e.id > last_id).cloned().collect(), receiver: receiver };
+    }
+}
+
+pub enum CursorItem {
+    Event(OrderEvent),
+    Missed(Option<u64>), // how many events were lost, None when it can't be told
+}
+
+// Follows the bus on from a given event id for subscribers that mustn't silently skip events.
+// Falling behind the live events picks up again from the history, and only events that are gone from the history too are reported as missed.
+#[derive(Debug)]
+pub struct EventCursor {
+    bus: EventBus,
+    last_event_id: u64,
+    subscription: Subscription,
+}
+
+impl EventCursor {
+    pub fn new(bus: &EventBus, last_event_id: u64) -> Self {
+        return Self { bus: bus.clone(), last_event_id: last_event_id, subscription: bus.subscribe_after(last_event_id) };
+    }
+
+    // None once the bus has gone away
+    pub async fn next(&mut self) -> Option<CursorItem> {
+        loop {
+            match self.subscription.missed {
+                MissedEvents::None => {}
+                MissedEvents::Count(missed) => {
+                    self.subscription.missed = MissedEvents::None;
+                    return Some(CursorItem::Missed(Some(missed)));
+                }
+                // The ids started again, so everything in the history is new to this subscriber
+                MissedEvents::Unknown => {
+                    self.subscription.missed = MissedEvents::None;
+                    self.last_event_id = 0;
+                    return Some(CursorItem::Missed(None));
+                }
+            }
+
+            let event = match self.subscription.replay.pop_front() {
+                Some(event) => event,
+                None => match self.subscription.receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => {
+                        self.subscription = self.bus.subscribe_after(self.last_event_id);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+            };
+
+            if event.id > self.last_event_id {
+                self.last_event_id = event.id;
+                return Some(CursorItem::Event(event));
+            }
+        }
+    }
+}
@@ -1,19 +1,82 @@
-use app::create_app;
-use persistence::memory_persistence::MemoryPersistence;
+// Explicit returns and `persistence::persistence` are the house style
+#![allow(clippy::needless_return, clippy::redundant_field_names, clippy::module_inception)]
+
+use std::sync::Arc;
+
+use api::v0::webhooks::spawn_webhook_dispatcher;
+use app::create_router;
+use models::{kitchen::KitchenConfig, menu::Menu};
+use payments::{FakeGateway, PaymentGateway, UnconfiguredGateway};
+use persistence::{memory_persistence::MemoryPersistence, persistence::Persistence, sqlite_persistence::SqlitePersistence};
+use state::AppState;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use webhooks::WebhookHostAllowList;
 
 mod api;
 mod app;
+mod clock;
+mod events;
 mod models;
+mod payments;
 mod persistence;
 mod state;
+mod webhooks;
 
-#[cfg(test)]
-mod tests {
-    mod app_integration_tests;
-    mod memory_persistence_tests;
+// RESTAURANT_MENU_PATH is the menu catalog, see menu.toml
+// RESTAURANT_STATION_CAPACITIES is how many items each kitchen station can prepare at once, e.g. grill=3,bar=1
+// RESTAURANT_WEBHOOK_ALLOWED_HOSTS lets webhooks go to loopback/private hosts, e.g. localhost,10.0.0.5
+// RESTAURANT_PAYMENT_GATEWAY=fake approves cards without charging them, for trying things out. Left unset every card payment is refused
+fn create_app_state_from_env<P>(persistence: P) -> AppState<P> {
+    let menu_path = std::env::var("RESTAURANT_MENU_PATH").unwrap_or_else(|_| "menu.toml".to_string());
+    let menu = Menu::load_from_file(&menu_path).unwrap_or_else(|err| panic!("Could not load menu from {}: {}", menu_path, err));
+    tracing::debug!("loaded {} menu items from {}", menu.items().count(), menu_path);
+
+    let kitchen = match std::env::var("RESTAURANT_STATION_CAPACITIES") {
+        Ok(capacities) => KitchenConfig::from_capacities_str(&capacities).unwrap_or_else(|err| panic!("Invalid RESTAURANT_STATION_CAPACITIES '{}': {}", capacities, err)),
+        Err(_) => KitchenConfig::default(),
+    };
+
+    let webhook_hosts = WebhookHostAllowList::from_hosts_str(&std::env::var("RESTAURANT_WEBHOOK_ALLOWED_HOSTS").unwrap_or_default());
+
+    let payment_gateway: Arc<dyn PaymentGateway> = match std::env::var("RESTAURANT_PAYMENT_GATEWAY") {
+        Ok(gateway) if gateway == "fake" => {
+            tracing::warn!("using the fake payment gateway, cards are approved without being charged");
+            Arc::new(FakeGateway::default())
+        }
+        Ok(other) => panic!("Unknown RESTAURANT_PAYMENT_GATEWAY '{}', expected 'fake'", other),
+        Err(_) => Arc::new(UnconfiguredGateway),
+    };
+
+    return AppState::new(persistence, menu)
+        .with_kitchen(kitchen)
+        .with_webhook_hosts(webhook_hosts)
+        .with_payment_gateway(payment_gateway);
+}
+
+// Until ctrl-c, then the webhook dispatcher is stopped along with any deliveries it still has retrying
+async fn serve<P: Persistence + 'static>(app_state: AppState<P>) {
+    let state = Arc::new(app_state);
+    let dispatcher = spawn_webhook_dispatcher(Arc::clone(&state));
+    let app = create_router(state);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:9000").await.unwrap();
+    tracing::debug!("listening on {}", listener.local_addr().unwrap());
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            if let Err(err) = tokio::signal::ctrl_c().await {
+                tracing::error!("could not listen for ctrl-c: {}", err);
+                std::future::pending::<()>().await;
+            }
+        })
+        .await
+        .unwrap();
+
+    dispatcher.abort();
+    let _ = dispatcher.await;
+    tracing::debug!("stopped");
 }
 
+// RESTAURANT_PERSISTENCE=sqlite keeps orders across restarts, in the file at RESTAURANT_SQLITE_PATH
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
@@ -21,10 +84,33 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let persistence: MemoryPersistence = MemoryPersistence::default();
-    let app = create_app(persistence);
+    let backend = std::env::var("RESTAURANT_PERSISTENCE").unwrap_or_else(|_| "memory".to_string());
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:9000").await.unwrap();
-    tracing::debug!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    match backend.as_str() {
+        "memory" => serve(create_app_state_from_env(MemoryPersistence::default())).await,
+        "sqlite" => {
+            let path = std::env::var("RESTAURANT_SQLITE_PATH").unwrap_or_else(|_| "restaurant.db".to_string());
+            tracing::debug!("using sqlite persistence at {}", path);
+            serve(create_app_state_from_env(SqlitePersistence::open(&path).unwrap())).await
+        }
+        other => panic!("Unknown RESTAURANT_PERSISTENCE backend '{}', expected 'memory' or 'sqlite'", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    mod app_integration_tests;
+    mod billing_tests;
+    mod events_tests;
+    mod fixtures;
+    mod kitchen_tests;
+    mod memory_persistence_tests;
+    mod menu_tests;
+    mod money_tests;
+    mod order_events_tests;
+    mod orders_tests;
+    mod payments_tests;
+    mod persistence_tests;
+    mod sqlite_persistence_tests;
+    mod webhooks_tests;
 }
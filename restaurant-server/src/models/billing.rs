@@ -0,0 +1,413 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+use super::{
+    menu::{Menu, MenuItemId},
+    money::{Currency, Money, Percentage},
+    orders::{LineId, OrderChangeError, TableId, TableOrder},
+};
+
+// Whether menu prices already include tax, as is usual in Europe, or tax is added on top of them, as in the US
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaxMode {
+    #[default]
+    Exclusive,
+    Inclusive,
+}
+
+// Per line rounds each line's tax to a whole minor unit, so the printed lines add up to the total.
+// Per bill adds up the lines at each rate and rounds once, which can come out a cent or so different and is what some tax authorities require.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaxRounding {
+    #[default]
+    PerLine,
+    PerBill,
+}
+
+// Added automatically for larger parties, worked out on the subtotal and not taxed itself
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct ServiceChargeRule {
+    pub min_guests: u32,
+    pub rate: Percentage,
+}
+
+// The [billing] section of the menu file. With none, nothing is taxed and there is no service charge.
+#[derive(Debug, Clone, PartialEq, Default, serde::Deserialize)]
+pub struct BillingConfig {
+    #[serde(default)]
+    pub tax_mode: TaxMode,
+    #[serde(default)]
+    pub tax_rounding: TaxRounding,
+    #[serde(default)]
+    pub default_tax_rate: Percentage, // for categories without a rate of their own
+    #[serde(default)]
+    pub tax_rates: BTreeMap<String, Percentage>, // by menu category
+    #[serde(default)]
+    pub service_charge: Option<ServiceChargeRule>,
+}
+
+// Numbered from 1 within each order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BillId(pub i32);
+impl std::fmt::Display for BillId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// What the table was asked to pay when the bill was issued. Never changed afterwards, if the order changes a new bill is issued.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bill {
+    pub bill_id: BillId,
+    pub issued_at: DateTime<Utc>,
+    pub guests: Option<u32>,
+    pub currency: Currency,
+    pub tax_mode: TaxMode,
+    pub tax_rounding: TaxRounding,
+    pub lines: Vec<BillLine>, // in line id order
+    pub taxes: Vec<BillTax>,  // one per rate, lowest first
+    pub subtotal: Money,      // the lines added up, at menu prices
+    pub service_charge: Option<BillServiceCharge>,
+    pub tax_total: Money, // already in the subtotal when the tax is inclusive
+    pub total: Money,
+}
+
+// Names and prices are copied from the menu, so the bill still reads the same once the menu changes
+#[derive(Debug, Clone, PartialEq)]
+pub struct BillLine {
+    pub line_id: LineId,
+    pub item_id: MenuItemId,
+    pub name: String,
+    pub category: String,
+    pub seat: Option<u32>, // as it was on the order when the bill was issued
+    pub quantity: i32,
+    pub unit_price: Money, // including modifier surcharges
+    pub amount: Money,     // unit_price x quantity
+    pub tax_rate: Percentage,
+    pub tax: Option<Money>, // only when tax is rounded per line
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BillTax {
+    pub rate: Percentage,
+    pub taxable: Money, // the amounts of the lines at this rate, including the tax when it is inclusive
+    pub tax: Money,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BillServiceCharge {
+    pub rate: Percentage,
+    pub amount: Money,
+}
+
+// How a bill is shared out between the people paying it
+#[derive(Debug, Clone, PartialEq)]
+pub enum BillSplit {
+    Even(usize),                  // equally between this many
+    ByLine(Vec<Vec<PayerShare>>), // the lines each payer pays for, in payer order
+    BySeat,                       // each seat pays for its own lines, with the shared lines split equally between the seats
+}
+
+// A payer's part of one line. A line claimed by more than one payer is shared in proportion to their shares,
+// so shares of 1 and 2 pay a third and two thirds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PayerShare {
+    pub line_id: LineId,
+    pub share: u32,
+}
+
+// What one payer owes. The parts of a split always add up to exactly the bill's total.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitPart {
+    pub seat: Option<u32>, // only when split by seat
+    pub amount: Money,
+    pub lines: Vec<SplitPartLine>, // empty for an even split
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitPartLine {
+    pub line_id: LineId,
+    pub share: u32,
+    pub of: u32,       // the shares of the line claimed by every payer together
+    pub amount: Money, // this payer's part of the line, with its tax and service charge
+}
+
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum SplitBillError {
+    #[error("Order line id {2} is not on bill {1}.")]
+    LineNotOnBill(String, String, String),
+    #[error("Order line id {2} on bill {1} isn't paid for by anyone.")]
+    UnassignedLine(String, String, String),
+    #[error("Nothing on bill {1} is for a seat, so it can't be split by seat.")]
+    NoSeats(String, String),
+    #[error("The shares of order line id {2} on bill {1} add up to more than {3}.")]
+    SharesTooLarge(String, String, String, u32),
+}
+
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum ReadBillError {
+    #[error("No bill has been issued for table id {0}.")]
+    BillNotFound(String),
+}
+
+#[derive(Error, Debug, PartialEq, Clone)]
+#[error("Unknown billing option {0}.")]
+pub struct ParseBillingOptionError(pub String);
+
+impl TaxMode {
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            TaxMode::Exclusive => "exclusive",
+            TaxMode::Inclusive => "inclusive",
+        };
+    }
+
+    // The tax on a price at the given rate, rounded to a whole minor unit
+    pub fn tax_on(&self, amount: Money, rate: Percentage) -> Money {
+        return match self {
+            TaxMode::Exclusive => amount.percent(rate),
+            TaxMode::Inclusive => amount.included_tax(rate),
+        };
+    }
+}
+
+impl std::fmt::Display for TaxMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for TaxMode {
+    type Err = ParseBillingOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "exclusive" => Ok(TaxMode::Exclusive),
+            "inclusive" => Ok(TaxMode::Inclusive),
+            _ => Err(ParseBillingOptionError(s.to_string())),
+        };
+    }
+}
+
+impl TaxRounding {
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            TaxRounding::PerLine => "per_line",
+            TaxRounding::PerBill => "per_bill",
+        };
+    }
+}
+
+impl std::fmt::Display for TaxRounding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for TaxRounding {
+    type Err = ParseBillingOptionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "per_line" => Ok(TaxRounding::PerLine),
+            "per_bill" => Ok(TaxRounding::PerBill),
+            _ => Err(ParseBillingOptionError(s.to_string())),
+        };
+    }
+}
+
+impl BillingConfig {
+    pub fn tax_rate(&self, category: &str) -> Percentage {
+        return self.tax_rates.get(category).copied().unwrap_or(self.default_tax_rate);
+    }
+}
+
+impl Bill {
+    // Prices every line on the order from the menu as it is now. A line for an item taken off the menu since can't be priced,
+    // so no bill is issued until it is removed rather than quietly leaving it off.
+    pub fn new(bill_id: BillId, order: &TableOrder, menu: &Menu, guests: Option<u32>, issued_at: DateTime<Utc>) -> Result<Self, OrderChangeError> {
+        let config = menu.billing();
+        let currency = menu.currency();
+
+        let lines = order
+            .items
+            .values()
+            .map(|item| {
+                let menu_item = menu
+                    .find_item(&item.item_id)
+                    .map_err(|_| OrderChangeError::UnpricedItem(order.table_id.to_string(), item.line_id.to_string(), item.item_id.to_string()))?;
+                let unit_price = menu_item.get_unit_price(&item.modifiers);
+                let amount = unit_price.times(item.quantity);
+                let tax_rate = config.tax_rate(&menu_item.category);
+
+                return Ok(BillLine {
+                    line_id: item.line_id,
+                    item_id: item.item_id.clone(),
+                    name: menu_item.name.clone(),
+                    category: menu_item.category.clone(),
+                    seat: item.seat,
+                    quantity: item.quantity,
+                    unit_price: unit_price,
+                    amount: amount,
+                    tax_rate: tax_rate,
+                    tax: match config.tax_rounding {
+                        TaxRounding::PerLine => Some(config.tax_mode.tax_on(amount, tax_rate)),
+                        TaxRounding::PerBill => None,
+                    },
+                });
+            })
+            .collect::<Result<Vec<BillLine>, OrderChangeError>>()?;
+
+        let rates: BTreeSet<Percentage> = lines.iter().map(|l| l.tax_rate).collect();
+        let taxes: Vec<BillTax> = rates
+            .into_iter()
+            .map(|rate| {
+                let at_rate = || lines.iter().filter(move |l| l.tax_rate == rate);
+                let taxable = Money::sum(at_rate().map(|l| l.amount), currency);
+                let tax = match config.tax_rounding {
+                    TaxRounding::PerLine => Money::sum(at_rate().filter_map(|l| l.tax), currency),
+                    TaxRounding::PerBill => config.tax_mode.tax_on(taxable, rate),
+                };
+                return BillTax { rate: rate, taxable: taxable, tax: tax };
+            })
+            .collect();
+
+        let subtotal = Money::sum(lines.iter().map(|l| l.amount), currency);
+        let service_charge = config
+            .service_charge
+            .as_ref()
+            .filter(|rule| guests.is_some_and(|g| g >= rule.min_guests))
+            .map(|rule| BillServiceCharge { rate: rule.rate, amount: subtotal.percent(rule.rate) });
+        let tax_total = Money::sum(taxes.iter().map(|t| t.tax), currency);
+        let added_tax = match config.tax_mode {
+            TaxMode::Exclusive => tax_total,
+            TaxMode::Inclusive => Money::zero(currency),
+        };
+        let total = Money::sum([subtotal, added_tax].into_iter().chain(service_charge.map(|s| s.amount)), currency);
+
+        return Ok(Self {
+            bill_id: bill_id,
+            issued_at: issued_at,
+            guests: guests,
+            currency: currency,
+            tax_mode: config.tax_mode,
+            tax_rounding: config.tax_rounding,
+            lines: lines,
+            taxes: taxes,
+            subtotal: subtotal,
+            service_charge: service_charge,
+            tax_total: tax_total,
+            total: total,
+        });
+    }
+
+    // What each line comes to with its tax (when added on top) and its part of the service charge, adding up to exactly the total.
+    // Tax rounded per bill and the service charge are shared out between the lines in proportion to their amounts.
+    pub fn line_totals(&self) -> Vec<Money> {
+        let amounts: Vec<i64> = self.lines.iter().map(|l| l.amount.minor_units).collect();
+        let mut totals: Vec<Money> = self.lines.iter().map(|l| l.amount).collect();
+
+        if self.tax_mode == TaxMode::Exclusive {
+            for tax in &self.taxes {
+                let at_rate: Vec<usize> = (0..self.lines.len()).filter(|i| self.lines[*i].tax_rate == tax.rate).collect();
+                let shares = tax.tax.allocate(&at_rate.iter().map(|i| amounts[*i]).collect::<Vec<i64>>());
+                for (index, share) in at_rate.into_iter().zip(shares) {
+                    totals[index] = totals[index] + self.lines[index].tax.unwrap_or(share);
+                }
+            }
+        }
+        if let Some(service_charge) = self.service_charge {
+            for (total, share) in totals.iter_mut().zip(service_charge.amount.allocate(&amounts)) {
+                *total = *total + share;
+            }
+        }
+
+        return totals;
+    }
+
+    // Every line on the bill has to be claimed by at least one payer, so nothing is left over
+    pub fn split(&self, table_id: &TableId, how: &BillSplit) -> Result<Vec<SplitPart>, SplitBillError> {
+        let (payers, seats) = match how {
+            BillSplit::Even(ways) => {
+                return Ok(self
+                    .total
+                    .allocate(&vec![1; *ways])
+                    .into_iter()
+                    .map(|a| SplitPart { seat: None, amount: a, lines: vec![] })
+                    .collect())
+            }
+            BillSplit::ByLine(payers) => (payers.clone(), vec![None; payers.len()]),
+            BillSplit::BySeat => self.payers_by_seat(table_id)?.into_iter().map(|(seat, shares)| (shares, Some(seat))).unzip(),
+        };
+
+        if let Some(share) = payers.iter().flatten().find(|s| !self.lines.iter().any(|l| l.line_id == s.line_id)) {
+            return Err(SplitBillError::LineNotOnBill(table_id.to_string(), self.bill_id.to_string(), share.line_id.to_string()));
+        }
+
+        let mut parts: Vec<SplitPart> = seats
+            .into_iter()
+            .map(|seat| SplitPart { seat: seat, amount: Money::zero(self.currency), lines: vec![] })
+            .collect();
+        for (line, line_total) in self.lines.iter().zip(self.line_totals()) {
+            // The shares come from the client, so adding them up can't be trusted not to overflow
+            let shares_too_large = || SplitBillError::SharesTooLarge(table_id.to_string(), self.bill_id.to_string(), line.line_id.to_string(), u32::MAX);
+            // A payer listing the same line twice has their shares added together
+            let claims = payers
+                .iter()
+                .enumerate()
+                .map(|(payer, shares)| {
+                    let share = total_shares(shares.iter().filter(|s| s.line_id == line.line_id).map(|s| s.share)).ok_or_else(shares_too_large)?;
+                    return Ok((payer, share));
+                })
+                .filter(|claim| !matches!(claim, Ok((_, 0))))
+                .collect::<Result<Vec<(usize, u32)>, SplitBillError>>()?;
+            if claims.is_empty() {
+                return Err(SplitBillError::UnassignedLine(table_id.to_string(), self.bill_id.to_string(), line.line_id.to_string()));
+            }
+
+            let of = total_shares(claims.iter().map(|(_, share)| *share)).ok_or_else(shares_too_large)?;
+            let amounts = line_total.allocate(&claims.iter().map(|(_, share)| *share as i64).collect::<Vec<i64>>());
+            for ((payer, share), amount) in claims.into_iter().zip(amounts) {
+                parts[payer].amount = parts[payer].amount + amount;
+                parts[payer]
+                    .lines
+                    .push(SplitPartLine { line_id: line.line_id, share: share, of: of, amount: amount });
+            }
+        }
+
+        return Ok(parts);
+    }
+
+    // One payer per seat with something on the bill, lowest seat first
+    fn payers_by_seat(&self, table_id: &TableId) -> Result<Vec<(u32, Vec<PayerShare>)>, SplitBillError> {
+        let seats: BTreeSet<u32> = self.lines.iter().filter_map(|l| l.seat).collect();
+        if seats.is_empty() {
+            return Err(SplitBillError::NoSeats(table_id.to_string(), self.bill_id.to_string()));
+        }
+
+        return Ok(seats
+            .into_iter()
+            .map(|seat| {
+                let shares = self
+                    .lines
+                    .iter()
+                    .filter(|l| l.seat.is_none() || l.seat == Some(seat))
+                    .map(|l| PayerShare { line_id: l.line_id, share: 1 })
+                    .collect();
+                return (seat, shares);
+            })
+            .collect());
+    }
+}
+
+// None if the total doesn't fit in a u32
+fn total_shares<I: Iterator<Item = u32>>(mut shares: I) -> Option<u32> {
+    return shares.try_fold(0u32, |total, share| total.checked_add(share));
+}
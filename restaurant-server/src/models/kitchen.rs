@@ -0,0 +1,220 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use thiserror::Error;
+
+use super::{
+    menu::Menu,
+    orders::{LineId, OrderItemStatus, TableId, TableOrder, TableOrderItem},
+};
+
+pub const DEFAULT_STATION_CAPACITY: usize = 2;
+pub const DEFAULT_RECALL_WINDOW_MINS: i64 = 5;
+
+// Where in the kitchen an item is prepared, each station has its own cooks and its own screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Station {
+    Grill,
+    Fryer,
+    Cold,
+    Bar,
+}
+
+pub const ALL_STATIONS: [Station; 4] = [Station::Grill, Station::Fryer, Station::Cold, Station::Bar];
+
+#[derive(Error, Debug, PartialEq, Clone)]
+#[error("Unknown station {0}.")]
+pub struct ParseStationError(pub String);
+
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum ParseKitchenConfigError {
+    #[error("Expected station=capacity, got '{0}'.")]
+    InvalidEntry(String),
+    #[error(transparent)]
+    UnknownStation(#[from] ParseStationError),
+    #[error("Capacity for station {0} must be a number greater than zero.")]
+    InvalidCapacity(String),
+}
+
+impl Station {
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            Station::Grill => "grill",
+            Station::Fryer => "fryer",
+            Station::Cold => "cold",
+            Station::Bar => "bar",
+        };
+    }
+}
+
+impl std::fmt::Display for Station {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Station {
+    type Err = ParseStationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return ALL_STATIONS
+            .iter()
+            .find(|station| station.as_str() == s)
+            .copied()
+            .ok_or_else(|| ParseStationError(s.to_string()));
+    }
+}
+
+// How many items each station can prepare at the same time, and how long after a bump it can be recalled
+#[derive(Debug, Clone, PartialEq)]
+pub struct KitchenConfig {
+    capacities: BTreeMap<Station, usize>,
+    pub recall_window: TimeDelta,
+}
+
+impl Default for KitchenConfig {
+    fn default() -> Self {
+        return Self { capacities: ALL_STATIONS.iter().map(|s| (*s, DEFAULT_STATION_CAPACITY)).collect(), recall_window: TimeDelta::minutes(DEFAULT_RECALL_WINDOW_MINS) };
+    }
+}
+
+impl KitchenConfig {
+    pub fn with_capacity(mut self, station: Station, capacity: usize) -> Self {
+        self.capacities.insert(station, capacity.max(1));
+        return self;
+    }
+
+    pub fn capacity(&self, station: Station) -> usize {
+        return self.capacities.get(&station).copied().unwrap_or(DEFAULT_STATION_CAPACITY);
+    }
+
+    // Ordered by station
+    pub fn capacities(&self) -> impl Iterator<Item = (Station, usize)> + '_ {
+        return self.capacities.iter().map(|(s, c)| (*s, *c));
+    }
+
+    // e.g. "grill=3,bar=1", stations that aren't listed keep the default capacity
+    pub fn from_capacities_str(capacities: &str) -> Result<Self, ParseKitchenConfigError> {
+        let mut config = Self::default();
+        for entry in capacities.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (station, capacity) = entry.split_once('=').ok_or_else(|| ParseKitchenConfigError::InvalidEntry(entry.to_string()))?;
+            let station: Station = station.trim().parse()?;
+            let capacity = match capacity.trim().parse::<usize>() {
+                Ok(capacity) if capacity > 0 => capacity,
+                _ => return Err(ParseKitchenConfigError::InvalidCapacity(station.to_string())),
+            };
+            config = config.with_capacity(station, capacity);
+        }
+
+        return Ok(config);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueuedItem {
+    pub table_id: TableId,
+    pub item: TableOrderItem,
+    pub station: Station,
+    pub cook: usize, // 0 based, within the station
+    pub estimated_start_at: DateTime<Utc>,
+    pub estimated_ready_at: DateTime<Utc>,
+}
+
+// Works out when every item still to be cooked will be ready, given each station can only work on its capacity's worth of items at once.
+// Items already being prepared keep their cook, then the rest are handed out in the order they were ordered, each to whichever cook at its station frees up first.
+// An item is treated as taking its full preparation time once started, regardless of how many other items that cook has waiting.
+// Ready and served items are done and not part of the queue. Items no longer on the menu can't be routed to a station, so they are left out too.
+pub fn schedule_kitchen_queue(orders: &[TableOrder], menu: &Menu, config: &KitchenConfig, now: DateTime<Utc>) -> Vec<QueuedItem> {
+    let mut waiting = orders
+        .iter()
+        .flat_map(|o| o.items.values().map(move |i| (o.table_id.clone(), i.clone())))
+        .filter(|(_, i)| matches!(i.status, OrderItemStatus::Ordered | OrderItemStatus::Preparing))
+        .filter_map(|(table_id, i)| match menu.find_item(&i.item_id) {
+            Ok(menu_item) => Some((table_id, menu_item.station, i)),
+            Err(_) => {
+                tracing::warn!("table {} item {} is not on the menu, so has no station", table_id, i.item_id);
+                None
+            }
+        })
+        .collect::<Vec<(TableId, Station, TableOrderItem)>>();
+    // Table and line id only break ties, so the queue is stable between requests
+    waiting.sort_by(|(a_table, _, a), (b_table, _, b)| {
+        let a_key = (a.status != OrderItemStatus::Preparing, a.status_changed_at().unwrap_or(a.ordered_at), a.ordered_at, a_table, a.line_id);
+        let b_key = (b.status != OrderItemStatus::Preparing, b.status_changed_at().unwrap_or(b.ordered_at), b.ordered_at, b_table, b.line_id);
+        return a_key.cmp(&b_key);
+    });
+
+    // Stations don't share cooks, so scheduling every station in one pass gives the same result as scheduling each on its own
+    let mut cook_free_at: BTreeMap<Station, Vec<DateTime<Utc>>> = ALL_STATIONS.iter().map(|s| (*s, vec![now; config.capacity(*s)])).collect();
+
+    return waiting
+        .into_iter()
+        .map(|(table_id, station, item)| {
+            let cooks = cook_free_at.get_mut(&station).unwrap();
+            let cook = (0..cooks.len()).min_by_key(|c| cooks[*c]).unwrap();
+            let preparation_time = TimeDelta::minutes(item.total_preparation_time_mins as i64);
+
+            let (estimated_start_at, remaining) = match item.status_changed_at() {
+                Some(started_at) if item.status == OrderItemStatus::Preparing => (started_at, (started_at + preparation_time - now).max(TimeDelta::zero())),
+                _ => (cooks[cook], preparation_time),
+            };
+            let estimated_ready_at = cooks[cook] + remaining;
+            cooks[cook] = estimated_ready_at;
+
+            return QueuedItem { table_id: table_id, item: item, station: station, cook: cook, estimated_start_at: estimated_start_at, estimated_ready_at: estimated_ready_at };
+        })
+        .collect();
+}
+
+// When each line in the kitchen queue is expected to be ready, so orders can show the same times as the kitchen
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReadyEstimates {
+    ready_at: HashMap<(TableId, LineId), DateTime<Utc>>,
+}
+
+impl ReadyEstimates {
+    pub fn from_queue(queue: &[QueuedItem]) -> Self {
+        return Self { ready_at: queue.iter().map(|q| ((q.table_id.clone(), q.item.line_id), q.estimated_ready_at)).collect() };
+    }
+
+    // Lines that aren't queued keep their own estimate, i.e. ones that are done and ones no longer on the menu that have no station
+    pub fn ready_at(&self, table_id: &TableId, item: &TableOrderItem) -> DateTime<Utc> {
+        return self.ready_at.get(&(table_id.clone(), item.line_id)).copied().unwrap_or(item.ready_at);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KdsItem {
+    pub table_id: TableId,
+    pub item: TableOrderItem,
+    pub station: Option<Station>, // None for items no longer on the menu, which still need cooking
+}
+
+// Everything the kitchen still has to do, oldest order first
+pub fn list_pending_items(orders: &[TableOrder], menu: &Menu) -> Vec<KdsItem> {
+    let mut pending = list_kds_items(orders, menu, |i| matches!(i.status, OrderItemStatus::Ordered | OrderItemStatus::Preparing));
+    pending.sort_by(|a, b| (a.item.ordered_at, &a.table_id, a.item.line_id).cmp(&(b.item.ordered_at, &b.table_id, b.item.line_id)));
+    return pending;
+}
+
+// Bumped items that can still be recalled, most recently bumped first
+pub fn list_recallable_items(orders: &[TableOrder], menu: &Menu, config: &KitchenConfig, now: DateTime<Utc>) -> Vec<KdsItem> {
+    let mut recallable = list_kds_items(orders, menu, |i| {
+        return i.status == OrderItemStatus::Ready && i.status_changed_at().is_some_and(|bumped_at| now - bumped_at <= config.recall_window);
+    });
+    recallable.sort_by(|a, b| (b.item.status_changed_at(), &a.table_id, a.item.line_id).cmp(&(a.item.status_changed_at(), &b.table_id, b.item.line_id)));
+    return recallable;
+}
+
+fn list_kds_items<F: Fn(&TableOrderItem) -> bool>(orders: &[TableOrder], menu: &Menu, include: F) -> Vec<KdsItem> {
+    return orders
+        .iter()
+        .flat_map(|o| o.items.values().map(move |i| (&o.table_id, i)))
+        .filter(|(_, i)| include(i))
+        .map(|(table_id, i)| KdsItem { table_id: table_id.clone(), item: i.clone(), station: menu.find_item(&i.item_id).ok().map(|m| m.station) })
+        .collect();
+}
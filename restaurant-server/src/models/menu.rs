@@ -1,4 +1,13 @@
-use rand::Rng;
+use std::{collections::BTreeMap, path::Path};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use thiserror::Error;
+
+use super::{
+    billing::BillingConfig,
+    kitchen::Station,
+    money::{Currency, Money},
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize)]
 pub struct MenuItemId(pub i32);
@@ -8,18 +17,199 @@ impl std::fmt::Display for MenuItemId {
     }
 }
 
-#[derive(Debug, PartialEq)]
+// Only unique within its menu item, e.g. every dish with a side salad can have its own "no_dressing"
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize)]
+pub struct ModifierId(pub String);
+impl std::fmt::Display for ModifierId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// A choice guests can make about how an item is prepared, e.g. "No onions" or "Medium rare"
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct MenuModifier {
+    pub id: ModifierId,
+    pub name: String,
+    #[serde(default)]
+    pub group: Option<String>, // at most one modifier from a group can be chosen, e.g. one cooking temperature
+    #[serde(default)]
+    pub price: Option<Money>, // added to the item's price, free when not given
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
 pub struct MenuItem {
     pub id: MenuItemId,
     pub name: String,
     pub description: String, // details, ingredients etc
+    pub category: String,
+    pub price: Money, // for a quantity of one, without modifiers
+    pub station: Station,
+    pub prep_time_mins: i32, // for a quantity of one
+    #[serde(default)]
+    pub prep_time_per_extra_mins: i32, // added for each one after the first, since a larger batch takes longer but not proportionally
+    #[serde(default)]
+    pub modifiers: Vec<MenuModifier>, // in the order they are shown
+}
+
+impl MenuItem {
+    pub fn find_modifier(&self, id: &ModifierId) -> Option<&MenuModifier> {
+        return self.modifiers.iter().find(|m| m.id == *id);
+    }
+
+    // The price of one with the given modifiers' surcharges added. Modifiers the item doesn't have (any more) cost nothing.
+    pub fn get_unit_price(&self, modifiers: &[ModifierId]) -> Money {
+        return modifiers
+            .iter()
+            .filter_map(|id| self.find_modifier(id))
+            .filter_map(|m| m.price)
+            .fold(self.price, |total, price| total + price);
+    }
+}
+
+// No estimate is longer than a day
+pub const MAX_PREPARATION_TIME_MINS: i32 = 24 * 60;
+
+// Optional random variation on top of the configured times, to make simulations less uniform.
+// Seeded by the item and quantity, so the same order still always gets the same estimate.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct PreparationJitter {
+    pub seed: u64,
+    pub max_mins: u32,
+}
+
+#[derive(Error, Debug)]
+pub enum LoadMenuError {
+    #[error("Could not read menu file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse menu file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Menu item id {0} is defined more than once.")]
+    DuplicateItemId(String),
+    #[error("Modifier '{1}' is defined more than once for menu item id {0}.")]
+    DuplicateModifierId(String, String),
+    #[error("Menu item id {0} is priced in {1}, but the rest of the menu is in {2}.")]
+    MixedCurrencies(String, Currency, Currency),
+    #[error("Menu item id {0} has a negative price.")]
+    NegativePrice(String),
+    #[error("There is a tax rate for category '{0}', but no menu item in that category.")]
+    UnknownTaxCategory(String),
+}
+
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum ReadMenuItemError {
+    #[error("Menu item id {0} not found.")]
+    MenuItemNotFound(String),
+}
+
+// Shape of the menu file, see menu.toml
+#[derive(serde::Deserialize)]
+struct MenuFile {
+    items: Vec<MenuItem>,
+    #[serde(default)]
+    preparation_jitter: Option<PreparationJitter>,
+    #[serde(default)]
+    billing: BillingConfig,
 }
 
-// for simplicity, assume all ids are valid rather than pulling from some kind of list
-pub fn get_menu_item(id: &MenuItemId) -> MenuItem {
-    return MenuItem { id: id.clone(), name: format!("menu item {}", &id), description: format!("menu item desc {}", &id) };
+// The catalog of everything that can be ordered. Loaded once at startup and read-only afterwards, so it needs no locking.
+#[derive(Debug, Default)]
+pub struct Menu {
+    items: BTreeMap<MenuItemId, MenuItem>,
+    currency: Currency, // every price on the menu is in this
+    preparation_jitter: Option<PreparationJitter>,
+    billing: BillingConfig,
+}
+
+impl Menu {
+    pub fn new(items: Vec<MenuItem>) -> Result<Self, LoadMenuError> {
+        let currency = items.first().map(|i| i.price.currency).unwrap_or_default();
+        let mut result = BTreeMap::new();
+        for item in items {
+            let prices = std::iter::once(item.price).chain(item.modifiers.iter().filter_map(|m| m.price));
+            for price in prices {
+                if price.currency != currency {
+                    return Err(LoadMenuError::MixedCurrencies(item.id.to_string(), price.currency, currency));
+                }
+                if price.minor_units < 0 {
+                    return Err(LoadMenuError::NegativePrice(item.id.to_string()));
+                }
+            }
+            for (index, modifier) in item.modifiers.iter().enumerate() {
+                if item.modifiers[..index].iter().any(|m| m.id == modifier.id) {
+                    return Err(LoadMenuError::DuplicateModifierId(item.id.to_string(), modifier.id.to_string()));
+                }
+            }
+            if let Some(duplicate) = result.insert(item.id.clone(), item) {
+                return Err(LoadMenuError::DuplicateItemId(duplicate.id.to_string()));
+            }
+        }
+
+        return Ok(Self { items: result, currency: currency, preparation_jitter: None, billing: BillingConfig::default() });
+    }
+
+    pub fn with_preparation_jitter(mut self, jitter: PreparationJitter) -> Self {
+        self.preparation_jitter = Some(jitter);
+        return self;
+    }
+
+    pub fn with_billing(mut self, billing: BillingConfig) -> Self {
+        self.billing = billing;
+        return self;
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, LoadMenuError> {
+        return Self::from_toml(&std::fs::read_to_string(path)?);
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, LoadMenuError> {
+        let menu_file: MenuFile = toml::from_str(contents)?;
+        let menu = Self::new(menu_file.items)?;
+
+        // Most likely a typo, which would otherwise leave the category taxed at the default rate
+        if let Some(category) = menu_file.billing.tax_rates.keys().find(|c| !menu.items().any(|i| i.category == **c)) {
+            return Err(LoadMenuError::UnknownTaxCategory(category.clone()));
+        }
+        let menu = menu.with_billing(menu_file.billing);
+
+        return Ok(match menu_file.preparation_jitter {
+            Some(jitter) => menu.with_preparation_jitter(jitter),
+            None => menu,
+        });
+    }
+
+    pub fn find_item(&self, id: &MenuItemId) -> Result<&MenuItem, ReadMenuItemError> {
+        return self.items.get(id).ok_or_else(|| ReadMenuItemError::MenuItemNotFound(id.to_string()));
+    }
+
+    pub fn currency(&self) -> Currency {
+        return self.currency;
+    }
+
+    pub fn billing(&self) -> &BillingConfig {
+        return &self.billing;
+    }
+
+    // Ordered by id
+    pub fn items(&self) -> impl Iterator<Item = &MenuItem> {
+        return self.items.values();
+    }
+
+    // Saturating, so however large the quantity or the menu's times the estimate is capped rather than overflowing
+    pub fn get_preparation_time(&self, item: &MenuItem, quantity: i32) -> i32 {
+        let extra = item.prep_time_per_extra_mins.saturating_mul(quantity.saturating_sub(1).max(0));
+        let configured = item.prep_time_mins.saturating_add(extra);
+
+        let estimate = match &self.preparation_jitter {
+            Some(jitter) => configured.saturating_add(get_jitter_mins(jitter, &item.id, quantity)),
+            None => configured,
+        };
+        return estimate.clamp(0, MAX_PREPARATION_TIME_MINS);
+    }
 }
 
-pub fn get_preparation_time(_item_id: &MenuItemId) -> i32 {
-    return rand::thread_rng().gen_range(10..20);
+fn get_jitter_mins(jitter: &PreparationJitter, item_id: &MenuItemId, quantity: i32) -> i32 {
+    let max_mins = jitter.max_mins.min(MAX_PREPARATION_TIME_MINS as u32) as i32;
+    let mut rng = StdRng::seed_from_u64(jitter.seed ^ ((item_id.0 as u64) << 32) ^ quantity as u64);
+    return rng.gen_range(-max_mins..=max_mins);
 }
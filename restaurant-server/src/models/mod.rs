@@ -1,2 +1,6 @@
+pub mod billing;
+pub mod kitchen;
 pub mod menu;
+pub mod money;
 pub mod orders;
+pub mod payments;
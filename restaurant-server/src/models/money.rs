@@ -0,0 +1,259 @@
+use std::{
+    ops::{Add, Sub},
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+// The currencies a menu can be priced in. Add more as needed, along with how many decimal places their minor unit is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Currency {
+    #[default]
+    Eur,
+    Gbp,
+    Usd,
+    Jpy,
+}
+
+// An exact amount, kept as a whole number of the currency's minor unit (e.g. cents) so no floating point is ever involved.
+// Written and parsed as a decimal amount followed by the ISO 4217 code, e.g. "12.50 EUR".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct Money {
+    pub minor_units: i64,
+    pub currency: Currency,
+}
+
+// A rate such as a tax rate, kept as thousandths of a percent so rates like 8.875% are exact. Written and parsed as e.g. "12.5%".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct Percentage(pub i64);
+
+const PERCENTAGE_DIGITS: u32 = 3;
+const HUNDRED_PERCENT: i64 = 100_000;
+
+#[derive(Error, Debug, PartialEq, Clone)]
+#[error("Unknown currency {0}, expected one of EUR, GBP, USD or JPY.")]
+pub struct ParseCurrencyError(pub String);
+
+#[derive(Error, Debug, PartialEq, Clone)]
+#[error("'{0}' is not an amount and currency like 12.50 EUR.")]
+pub struct ParseMoneyError(pub String);
+
+#[derive(Error, Debug, PartialEq, Clone)]
+#[error("'{0}' is not a percentage like 12.5%.")]
+pub struct ParsePercentageError(pub String);
+
+impl Currency {
+    pub fn code(&self) -> &'static str {
+        return match self {
+            Currency::Eur => "EUR",
+            Currency::Gbp => "GBP",
+            Currency::Usd => "USD",
+            Currency::Jpy => "JPY",
+        };
+    }
+
+    // Decimal places of the minor unit, e.g. 2 for cents
+    pub fn minor_digits(&self) -> u32 {
+        return match self {
+            Currency::Jpy => 0,
+            Currency::Eur | Currency::Gbp | Currency::Usd => 2,
+        };
+    }
+}
+
+impl std::fmt::Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = ParseCurrencyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "EUR" => Ok(Currency::Eur),
+            "GBP" => Ok(Currency::Gbp),
+            "USD" => Ok(Currency::Usd),
+            "JPY" => Ok(Currency::Jpy),
+            _ => Err(ParseCurrencyError(s.to_string())),
+        };
+    }
+}
+
+impl Money {
+    pub fn new(minor_units: i64, currency: Currency) -> Self {
+        return Self { minor_units: minor_units, currency: currency };
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        return Self::new(0, currency);
+    }
+
+    pub fn times(&self, quantity: i32) -> Self {
+        return Self::new(self.minor_units * quantity as i64, self.currency);
+    }
+
+    // Zero rather than nothing when there is nothing to add up, so it needs to be told the currency
+    pub fn sum<I: IntoIterator<Item = Money>>(amounts: I, currency: Currency) -> Self {
+        return amounts.into_iter().fold(Self::zero(currency), |total, amount| total + amount);
+    }
+
+    // This percentage of the amount, e.g. the tax to add on at 20%
+    pub fn percent(self, rate: Percentage) -> Self {
+        return Self::new(divide_rounded(self.minor_units as i128 * rate.0 as i128, HUNDRED_PERCENT as i128), self.currency);
+    }
+
+    // The part of the amount that is tax when the tax is already included in it, e.g. 20.00 of 120.00 at 20%
+    pub fn included_tax(self, rate: Percentage) -> Self {
+        return Self::new(divide_rounded(self.minor_units as i128 * rate.0 as i128, (HUNDRED_PERCENT + rate.0) as i128), self.currency);
+    }
+
+    // Splits the amount in proportion to the weights so the parts add up to exactly the amount (the largest remainder method).
+    // Each part is rounded down, then the minor units left over go one each to the parts that lost the most to rounding, earliest first on a tie.
+    // Weights that are all zero are treated as equal.
+    pub fn allocate(self, weights: &[i64]) -> Vec<Self> {
+        let weights: Vec<i128> = match weights.iter().all(|w| *w == 0) {
+            true => vec![1; weights.len()],
+            false => weights.iter().map(|w| *w as i128).collect(),
+        };
+        let total_weight: i128 = weights.iter().sum();
+        let amount = self.minor_units as i128;
+
+        let mut parts: Vec<i64> = weights.iter().map(|w| (amount * w).div_euclid(total_weight) as i64).collect();
+        let mut by_remainder: Vec<usize> = (0..weights.len()).collect();
+        by_remainder.sort_by_key(|i| std::cmp::Reverse((amount * weights[*i]).rem_euclid(total_weight)));
+        let left_over = self.minor_units - parts.iter().sum::<i64>();
+        for index in by_remainder.into_iter().take(left_over as usize) {
+            parts[index] += 1;
+        }
+
+        return parts.into_iter().map(|p| Self::new(p, self.currency)).collect();
+    }
+
+    // Just the amount in the major unit, e.g. "12.50" or "-0.05"
+    pub fn to_decimal_string(self) -> String {
+        let digits = self.currency.minor_digits();
+        let sign = if self.minor_units < 0 { "-" } else { "" };
+        let units = self.minor_units.unsigned_abs();
+        if digits == 0 {
+            return format!("{}{}", sign, units);
+        }
+
+        let scale = 10u64.pow(digits);
+        return format!("{}{}.{:0width$}", sign, units / scale, units % scale, width = digits as usize);
+    }
+}
+
+// A menu only has the one currency, so amounts in different currencies being added is a bug rather than something to recover from
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        assert_eq!(self.currency, other.currency, "cannot add {} to {}", other.currency, self.currency);
+        return Money::new(self.minor_units + other.minor_units, self.currency);
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, other: Money) -> Money {
+        assert_eq!(self.currency, other.currency, "cannot subtract {} from {}", other.currency, self.currency);
+        return Money::new(self.minor_units - other.minor_units, self.currency);
+    }
+}
+
+impl std::fmt::Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}", self.to_decimal_string(), self.currency)
+    }
+}
+
+impl FromStr for Money {
+    type Err = ParseMoneyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (amount, currency) = s.trim().split_once(' ').ok_or_else(|| ParseMoneyError(s.to_string()))?;
+        let currency: Currency = currency.trim().parse().map_err(|_| ParseMoneyError(s.to_string()))?;
+        let minor_units = parse_minor_units(amount, currency.minor_digits()).ok_or_else(|| ParseMoneyError(s.to_string()))?;
+
+        return Ok(Money::new(minor_units, currency));
+    }
+}
+
+impl TryFrom<String> for Money {
+    type Error = ParseMoneyError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        return value.parse();
+    }
+}
+
+impl std::fmt::Display for Percentage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let scale = 10i64.pow(PERCENTAGE_DIGITS);
+        let fraction = format!("{:0width$}", self.0 % scale, width = PERCENTAGE_DIGITS as usize);
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            return write!(f, "{}%", self.0 / scale);
+        }
+        write!(f, "{}.{}%", self.0 / scale, fraction)
+    }
+}
+
+// Never negative, a discount is not a tax
+impl FromStr for Percentage {
+    type Err = ParsePercentageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return s
+            .trim()
+            .strip_suffix('%')
+            .filter(|amount| !amount.starts_with('-'))
+            .and_then(|amount| parse_minor_units(amount.trim(), PERCENTAGE_DIGITS))
+            .map(Percentage)
+            .ok_or_else(|| ParsePercentageError(s.to_string()));
+    }
+}
+
+impl TryFrom<String> for Percentage {
+    type Error = ParsePercentageError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        return value.parse();
+    }
+}
+
+// Rounds half away from zero to a whole minor unit, so half a cent is charged on an amount and given back on a refund alike
+fn divide_rounded(numerator: i128, denominator: i128) -> i64 {
+    let quotient = (numerator.abs() * 2 + denominator) / (denominator * 2);
+    return (numerator.signum() * quotient) as i64;
+}
+
+// Digits only, with an optional sign and at most as many decimal places as the currency has, so nothing is ever rounded
+fn parse_minor_units(amount: &str, minor_digits: u32) -> Option<i64> {
+    let (negative, digits) = match amount.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, amount),
+    };
+    let (whole, fraction) = match digits.split_once('.') {
+        Some((whole, fraction)) if !fraction.is_empty() => (whole, fraction),
+        Some(_) => return None,
+        None => (digits, ""),
+    };
+    if whole.is_empty() || fraction.len() > minor_digits as usize || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let fraction = format!("{:0<width$}", fraction, width = minor_digits as usize);
+    let units = whole
+        .parse::<i64>()
+        .ok()?
+        .checked_mul(10i64.pow(minor_digits))?
+        .checked_add(if fraction.is_empty() { 0 } else { fraction.parse::<i64>().ok()? })?;
+
+    return Some(if negative { -units } else { units });
+}
@@ -1,6 +1,14 @@
-use std::collections::HashMap;
+use std::{collections::BTreeMap, str::FromStr};
 
-use super::menu::MenuItemId;
+use chrono::{DateTime, TimeDelta, Utc};
+use thiserror::Error;
+
+use super::{
+    billing::{Bill, BillId, ReadBillError},
+    menu::{Menu, MenuItemId, ModifierId},
+    money::{Currency, Money},
+    payments::{Payment, PaymentId, PaymentMethod, Refund, RefundStatus},
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize)]
 pub struct TableId(pub i32);
@@ -10,15 +18,569 @@ impl std::fmt::Display for TableId {
     }
 }
 
+// Identifies one line of an order, so the same menu item can be ordered more than once. Only unique within its table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineId(pub i32);
+impl std::fmt::Display for LineId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct TableOrder {
     pub table_id: TableId,
-    pub items: HashMap<MenuItemId, TableOrderItem>,
+    pub items: BTreeMap<LineId, TableOrderItem>,
+    pub next_line_id: LineId,     // ids are never reused within an order, even once their line is removed
+    pub guest_count: Option<u32>, // seats are numbered from 1 up to this, an order without one can't seat its lines
+    pub bills: Vec<Bill>,         // oldest first, never changed once issued
+    pub payments: Vec<Payment>,   // oldest first, against whichever bill was the latest when they were taken
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct TableOrderItem {
-    pub item_id: MenuItemId, // could make item id distinct from menu item id, but will assume a table order can only contain one of each menu item
+    pub line_id: LineId, // assigned when the item is added to an order
+    pub item_id: MenuItemId,
     pub quantity: i32,
+    pub notes: Option<String>,      // free text for the kitchen, e.g. "allergic to nuts"
+    pub modifiers: Vec<ModifierId>, // in the order they were asked for, already checked against the menu item
+    pub seat: Option<u32>,          // who it is for, None for something shared by the table
     pub total_preparation_time_mins: i32,
+    pub ordered_at: DateTime<Utc>,
+    pub ready_at: DateTime<Utc>, // expected, ordered_at + total_preparation_time_mins
+    pub status: OrderItemStatus,
+    pub status_history: Vec<OrderItemStatusChange>, // oldest first, one entry per transition out of Ordered
+}
+
+// Where an item is in the kitchen. Items only ever move forward one step at a time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OrderItemStatus {
+    Ordered,
+    Preparing,
+    Ready,
+    Served,
+}
+
+// A partial edit of one line, anything left as None stays as it is
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct OrderItemChanges {
+    pub quantity: Option<i32>,
+    pub notes: Option<Option<String>>, // Some(None) clears the notes
+    pub seat: Option<Option<u32>>,     // Some(None) makes the line shared by the table again
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderItemStatusChange {
+    pub status: OrderItemStatus,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum OrderChangeError {
+    #[error("Order line id {1} not found.")]
+    OrderItemNotFound(String, String),
+    #[error("Order line id {1} cannot move from {2} to {3}.")]
+    IllegalStatusTransition(String, String, OrderItemStatus, OrderItemStatus),
+    #[error("Order line id {1} is {2}, only bumped (ready) items can be recalled.")]
+    NotBumped(String, String, OrderItemStatus),
+    #[error("Order line id {1} was bumped more than {2} minutes ago and can no longer be recalled.")]
+    RecallWindowExpired(String, String, i64),
+    #[error("Order for table id {0} can contain at most {1} items.")]
+    TooManyItems(String, usize),
+    #[error("Order line id {1} is {2}, its quantity can only be changed before the kitchen starts it.")]
+    AlreadyStarted(String, String, OrderItemStatus),
+    #[error("Order line id {1} is for menu item id {2}, which is no longer on the menu and can't be billed. Remove the line first.")]
+    UnpricedItem(String, String, String),
+    #[error("Seat {1} is not at table id {0}, which seats {2} guests.")]
+    SeatOutOfRange(String, u32, u32),
+    #[error("Order for table id {0} has changed since it was last billed, issue a new bill first.")]
+    UnbilledItems(String),
+    #[error("Order for table id {0} still has {1} left to pay.")]
+    OutstandingBalance(String, Money),
+    #[error("Order for table id {0} has been paid {1} more than its latest bill, refund it before closing the table.")]
+    Overpaid(String, Money),
+    #[error("Order for table id {0} has already been paid in full.")]
+    AlreadyPaid(String),
+    #[error("Payment of {1} is more than the {2} left to pay for table id {0}.")]
+    Overpayment(String, Money, Money),
+    #[error("Payment id {1} not found.")]
+    PaymentNotFound(String, String),
+    #[error("Payment id {1} only has {2} left to refund.")]
+    RefundExceedsPayment(String, String, Money),
+    #[error("Payment id {1} has a refund the payment gateway hasn't answered for yet.")]
+    RefundPending(String, String),
+}
+
+#[derive(Error, Debug, PartialEq, Clone)]
+#[error("Unknown order item status {0}.")]
+pub struct ParseOrderItemStatusError(pub String);
+
+impl OrderItemStatus {
+    pub fn can_transition_to(&self, next: OrderItemStatus) -> bool {
+        return matches!(
+            (self, next),
+            (OrderItemStatus::Ordered, OrderItemStatus::Preparing) | (OrderItemStatus::Preparing, OrderItemStatus::Ready) | (OrderItemStatus::Ready, OrderItemStatus::Served)
+        );
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            OrderItemStatus::Ordered => "ordered",
+            OrderItemStatus::Preparing => "preparing",
+            OrderItemStatus::Ready => "ready",
+            OrderItemStatus::Served => "served",
+        };
+    }
+}
+
+impl std::fmt::Display for OrderItemStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for OrderItemStatus {
+    type Err = ParseOrderItemStatusError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "ordered" => Ok(OrderItemStatus::Ordered),
+            "preparing" => Ok(OrderItemStatus::Preparing),
+            "ready" => Ok(OrderItemStatus::Ready),
+            "served" => Ok(OrderItemStatus::Served),
+            _ => Err(ParseOrderItemStatusError(s.to_string())),
+        };
+    }
+}
+
+impl TableOrderItem {
+    pub fn new(item_id: MenuItemId, quantity: i32, total_preparation_time_mins: i32, ordered_at: DateTime<Utc>) -> Self {
+        return Self {
+            line_id: LineId(0),
+            item_id: item_id,
+            quantity: quantity,
+            notes: None,
+            modifiers: vec![],
+            seat: None,
+            total_preparation_time_mins: total_preparation_time_mins,
+            ordered_at: ordered_at,
+            ready_at: ordered_at + TimeDelta::minutes(total_preparation_time_mins as i64),
+            status: OrderItemStatus::Ordered,
+            status_history: vec![],
+        };
+    }
+
+    pub fn with_notes(mut self, notes: Option<String>) -> Self {
+        self.notes = notes;
+        return self;
+    }
+
+    pub fn with_modifiers(mut self, modifiers: Vec<ModifierId>) -> Self {
+        self.modifiers = modifiers;
+        return self;
+    }
+
+    pub fn with_seat(mut self, seat: Option<u32>) -> Self {
+        self.seat = seat;
+        return self;
+    }
+
+    // Whole minutes until `ready_at`, rounded up so an item isn't shown as 0 while it still has seconds to go.
+    // Never negative, and 0 once the kitchen has marked it ready regardless of the estimate.
+    // `ready_at` is the line's own estimate or the kitchen queue's, see ReadyEstimates.
+    pub fn remaining_mins(&self, ready_at: DateTime<Utc>, now: DateTime<Utc>) -> i32 {
+        if matches!(self.status, OrderItemStatus::Ready | OrderItemStatus::Served) {
+            return 0;
+        }
+
+        let remaining_secs = (ready_at - now).num_seconds().max(0);
+        return ((remaining_secs + 59) / 60) as i32;
+    }
+
+    // When the item entered its current status, None while it is still Ordered
+    pub fn status_changed_at(&self) -> Option<DateTime<Utc>> {
+        return self.status_history.last().map(|c| c.at);
+    }
+
+    // Priced from the menu as it is now, including modifier surcharges. None for an item no longer on the menu, which can't be priced.
+    pub fn unit_price(&self, menu: &Menu) -> Option<Money> {
+        return menu.find_item(&self.item_id).ok().map(|m| m.get_unit_price(&self.modifiers));
+    }
+
+    pub fn line_total(&self, menu: &Menu) -> Option<Money> {
+        return self.unit_price(menu).map(|p| p.times(self.quantity));
+    }
+
+    // The same dish cooked the same way for the same seat, whatever the quantity and wherever it is in the kitchen
+    pub fn is_same_dish(&self, other: &TableOrderItem) -> bool {
+        return self.item_id == other.item_id && self.modifiers == other.modifiers && self.notes == other.notes && self.seat == other.seat;
+    }
+
+    // Lines the kitchen hasn't started are re-estimated for the new quantity, still counted from when they were ordered.
+    // Started lines and items taken off the menu since keep their old estimate.
+    fn set_quantity(&mut self, quantity: i32, menu: &Menu) {
+        if quantity == self.quantity {
+            return;
+        }
+        if let (OrderItemStatus::Ordered, Ok(menu_item)) = (self.status, menu.find_item(&self.item_id)) {
+            self.total_preparation_time_mins = menu.get_preparation_time(menu_item, quantity);
+            self.ready_at = self.ordered_at + TimeDelta::minutes(self.total_preparation_time_mins as i64);
+        }
+        self.quantity = quantity;
+    }
+}
+
+// None if the total doesn't fit in an i32
+fn total_quantity<'a, I: Iterator<Item = &'a TableOrderItem>>(lines: I) -> Option<i32> {
+    return lines.map(|l| l.quantity).try_fold(0i32, |total, quantity| total.checked_add(quantity));
+}
+
+// Seats are numbered from 1 up to the guest count, so with no guest count no line can have a seat
+pub fn check_seats<'a, I: IntoIterator<Item = &'a TableOrderItem>>(table_id: &TableId, guest_count: Option<u32>, items: I) -> Result<(), OrderChangeError> {
+    let guests = guest_count.unwrap_or(0);
+    return match items.into_iter().filter_map(|i| i.seat).find(|seat| *seat > guests) {
+        Some(seat) => Err(OrderChangeError::SeatOutOfRange(table_id.to_string(), seat, guests)),
+        None => Ok(()),
+    };
+}
+
+impl TableOrder {
+    // Lines are numbered from 1 in the order given
+    pub fn new(table_id: TableId, items: &[TableOrderItem]) -> Self {
+        let mut order = Self { table_id: table_id, items: BTreeMap::new(), next_line_id: LineId(1), guest_count: None, bills: vec![], payments: vec![] };
+        for item in items {
+            order.add_item(item.clone());
+        }
+        return order;
+    }
+
+    pub fn with_guest_count(mut self, guest_count: Option<u32>) -> Self {
+        self.guest_count = guest_count;
+        return self;
+    }
+
+    // Fewer guests than before is only allowed once no line is left on a seat that would go
+    pub fn set_guest_count(&mut self, guest_count: Option<u32>) -> Result<(), OrderChangeError> {
+        check_seats(&self.table_id, guest_count, self.items.values())?;
+        self.guest_count = guest_count;
+        return Ok(());
+    }
+
+    // The lines on each seat, lowest seat first, with the shared lines last. Seats nothing has been ordered for are left out.
+    pub fn items_by_seat(&self) -> Vec<(Option<u32>, Vec<&TableOrderItem>)> {
+        let mut seats: BTreeMap<(bool, Option<u32>), Vec<&TableOrderItem>> = BTreeMap::new();
+        for item in self.items.values() {
+            seats.entry((item.seat.is_none(), item.seat)).or_default().push(item);
+        }
+        return seats.into_iter().map(|((_, seat), items)| (seat, items)).collect();
+    }
+
+    // What the table owes so far, before any tax or service charge. Lines that can't be priced are left out.
+    pub fn subtotal(&self, menu: &Menu) -> Money {
+        return Money::sum(self.items.values().filter_map(|i| i.line_total(menu)), menu.currency());
+    }
+
+    // Issues a new bill for the order as it is now, earlier bills are kept as they were. The party size is the order's guest count unless told otherwise.
+    pub fn issue_bill(&mut self, menu: &Menu, guests: Option<u32>, issued_at: DateTime<Utc>) -> Result<&Bill, OrderChangeError> {
+        let bill = Bill::new(BillId(self.bills.len() as i32 + 1), self, menu, guests.or(self.guest_count), issued_at)?;
+        self.bills.push(bill);
+        return Ok(self.bills.last().unwrap());
+    }
+
+    pub fn latest_bill(&self) -> Result<&Bill, ReadBillError> {
+        return self.bills.last().ok_or_else(|| ReadBillError::BillNotFound(self.table_id.to_string()));
+    }
+
+    // Whether the latest bill still has exactly the order's lines and quantities on it. An order with nothing on it needs no bill.
+    pub fn is_billed(&self) -> bool {
+        return match self.bills.last() {
+            Some(bill) => {
+                bill.lines.len() == self.items.len()
+                    && bill
+                        .lines
+                        .iter()
+                        .zip(self.items.values())
+                        .all(|(b, i)| b.line_id == i.line_id && b.item_id == i.item_id && b.quantity == i.quantity)
+            }
+            None => self.items.is_empty(),
+        };
+    }
+
+    // The latest bill's total less everything paid so far, whichever bill it was paid against, so reissuing a bill doesn't ask for it again.
+    // Negative when more has been paid than the latest bill asks for.
+    pub fn balance(&self) -> Result<Money, ReadBillError> {
+        let bill = self.latest_bill()?;
+        return Ok(bill.total - self.amount_paid(bill.currency));
+    }
+
+    // Everything paid so far less what was refunded
+    pub fn amount_paid(&self, currency: Currency) -> Money {
+        return Money::sum(self.payments.iter().map(|p| p.net_amount()), currency);
+    }
+
+    pub fn find_payment(&self, payment_id: PaymentId) -> Result<&Payment, OrderChangeError> {
+        return self
+            .payments
+            .iter()
+            .find(|p| p.payment_id == payment_id)
+            .ok_or_else(|| OrderChangeError::PaymentNotFound(self.table_id.to_string(), payment_id.to_string()));
+    }
+
+    // Only against a bill that is up to date with the order, and never for more than is left to pay
+    pub fn record_payment(&mut self, method: PaymentMethod, amount: Money, gateway_reference: Option<String>, paid_at: DateTime<Utc>) -> Result<&Payment, OrderChangeError> {
+        let (bill_id, balance) = match (self.bills.last(), self.balance()) {
+            (Some(bill), Ok(balance)) if self.is_billed() => (bill.bill_id, balance),
+            _ => return Err(OrderChangeError::UnbilledItems(self.table_id.to_string())),
+        };
+        if balance.minor_units <= 0 {
+            return Err(OrderChangeError::AlreadyPaid(self.table_id.to_string()));
+        }
+        if amount.minor_units > balance.minor_units {
+            return Err(OrderChangeError::Overpayment(self.table_id.to_string(), amount, balance));
+        }
+
+        self.payments.push(Payment {
+            payment_id: PaymentId(self.payments.len() as i32 + 1),
+            bill_id: bill_id,
+            method: method,
+            amount: amount,
+            gateway_reference: gateway_reference,
+            paid_at: paid_at,
+            refunds: vec![],
+        });
+        return Ok(self.payments.last().unwrap());
+    }
+
+    // A payment can be refunded in parts, but never for more than was paid with it
+    pub fn record_refund(&mut self, payment_id: PaymentId, amount: Money, gateway_reference: Option<String>, refunded_at: DateTime<Utc>) -> Result<&Payment, OrderChangeError> {
+        return self.add_refund(payment_id, Refund { amount: amount, gateway_reference: gateway_reference, refunded_at: refunded_at, status: RefundStatus::Refunded });
+    }
+
+    // Holds the amount against the payment while the gateway is asked for it, finish it with finish_refund once the gateway has answered.
+    // The new refund is the payment's last one.
+    pub fn reserve_refund(&mut self, payment_id: PaymentId, amount: Money, requested_at: DateTime<Utc>) -> Result<&Payment, OrderChangeError> {
+        return self.add_refund(payment_id, Refund { amount: amount, gateway_reference: None, refunded_at: requested_at, status: RefundStatus::Pending });
+    }
+
+    // With the gateway's reference when it made the refund, None when it refused. A refund that is no longer pending is left as it is.
+    pub fn finish_refund(&mut self, payment_id: PaymentId, seq: usize, gateway_reference: Option<String>) -> Result<&Payment, OrderChangeError> {
+        self.find_payment(payment_id)?;
+        let payment = self.payments.iter_mut().find(|p| p.payment_id == payment_id).unwrap();
+        if let Some(refund) = payment.refunds.get_mut(seq).filter(|r| r.status == RefundStatus::Pending) {
+            refund.status = if gateway_reference.is_some() { RefundStatus::Refunded } else { RefundStatus::Failed };
+            refund.gateway_reference = gateway_reference;
+        }
+        return Ok(payment);
+    }
+
+    fn add_refund(&mut self, payment_id: PaymentId, refund: Refund) -> Result<&Payment, OrderChangeError> {
+        let refundable = self.find_payment(payment_id)?.net_amount();
+        if refundable.minor_units <= 0 || refund.amount.minor_units > refundable.minor_units {
+            return Err(OrderChangeError::RefundExceedsPayment(self.table_id.to_string(), payment_id.to_string(), refundable));
+        }
+
+        let payment = self.payments.iter_mut().find(|p| p.payment_id == payment_id).unwrap();
+        payment.refunds.push(refund);
+        return Ok(payment);
+    }
+
+    // An order can only be closed once everything on it has been billed and paid for, exactly. Anything paid beyond the latest bill,
+    // e.g. when a line came off and the table was billed again, has to be refunded first. One that never had anything on it can always be closed.
+    pub fn check_settled(&self) -> Result<(), OrderChangeError> {
+        if !self.is_billed() {
+            return Err(OrderChangeError::UnbilledItems(self.table_id.to_string()));
+        }
+        if let Some(payment) = self.payments.iter().find(|p| p.has_pending_refund()) {
+            return Err(OrderChangeError::RefundPending(self.table_id.to_string(), payment.payment_id.to_string()));
+        }
+
+        return match self.balance() {
+            Ok(balance) if balance.minor_units > 0 => Err(OrderChangeError::OutstandingBalance(self.table_id.to_string(), balance)),
+            Ok(balance) if balance.minor_units < 0 => Err(OrderChangeError::Overpaid(self.table_id.to_string(), Money::zero(balance.currency) - balance)),
+            _ => Ok(()),
+        };
+    }
+
+    // An order nothing was ever billed or paid on leaves no settlement behind when it is closed
+    pub fn has_billing(&self) -> bool {
+        return !self.bills.is_empty() || !self.payments.is_empty();
+    }
+
+    pub fn add_item(&mut self, item: TableOrderItem) -> LineId {
+        let line_id = self.next_line_id;
+        self.next_line_id = LineId(line_id.0 + 1);
+        self.items.insert(line_id, TableOrderItem { line_id: line_id, ..item });
+        return line_id;
+    }
+
+    // Appended after the existing lines, which keep their ids, status and timing. Nothing is added if the order would end up with more than `max_items`.
+    pub fn add_items(&mut self, items: &[TableOrderItem], max_items: usize) -> Result<(), OrderChangeError> {
+        if self.items.len() + items.len() > max_items {
+            return Err(OrderChangeError::TooManyItems(self.table_id.to_string(), max_items));
+        }
+        check_seats(&self.table_id, self.guest_count, items)?;
+
+        for item in items {
+            self.add_item(item.clone());
+        }
+        return Ok(());
+    }
+
+    // A new quantity re-estimates the preparation time, still counted from when the line was ordered. It can only change before the kitchen starts the line,
+    // after that it would already be cooking too much or too little. Notes and the seat can change at any time.
+    pub fn change_item(&mut self, line_id: &LineId, changes: &OrderItemChanges, menu: &Menu) -> Result<(), OrderChangeError> {
+        if let Some(seat) = changes.seat.flatten().filter(|s| *s > self.guest_count.unwrap_or(0)) {
+            return Err(OrderChangeError::SeatOutOfRange(self.table_id.to_string(), seat, self.guest_count.unwrap_or(0)));
+        }
+        let item = self
+            .items
+            .get_mut(line_id)
+            .ok_or_else(|| OrderChangeError::OrderItemNotFound(self.table_id.to_string(), line_id.to_string()))?;
+
+        if let Some(quantity) = changes.quantity.filter(|q| *q != item.quantity) {
+            if item.status != OrderItemStatus::Ordered {
+                return Err(OrderChangeError::AlreadyStarted(self.table_id.to_string(), line_id.to_string(), item.status));
+            }
+            item.set_quantity(quantity, menu);
+        }
+        if let Some(notes) = &changes.notes {
+            item.notes = notes.clone();
+        }
+        if let Some(seat) = changes.seat {
+            item.seat = seat;
+        }
+        return Ok(());
+    }
+
+    // Like cutting a line back, taking it off the order is only allowed before the kitchen starts it
+    pub fn remove_item(&mut self, line_id: &LineId) -> Result<(), OrderChangeError> {
+        let item = self
+            .items
+            .get(line_id)
+            .ok_or_else(|| OrderChangeError::OrderItemNotFound(self.table_id.to_string(), line_id.to_string()))?;
+        if item.status != OrderItemStatus::Ordered {
+            return Err(OrderChangeError::AlreadyStarted(self.table_id.to_string(), line_id.to_string(), item.status));
+        }
+
+        self.items.remove(line_id);
+        return Ok(());
+    }
+
+    // Brings the order in line with `items` while disturbing as little as possible, so resending an unchanged order changes nothing.
+    // Lines are matched by dish rather than line id. Anything asked for less of is taken off the newest lines first, and anything asked for more of
+    // grows the newest line if the kitchen hasn't started it, or goes on a new line if it has. Returns what was taken off, one entry per line with
+    // the quantity taken off it. A line only partly taken off stays on the order with what is left.
+    // As with change_item, a line the kitchen has started can't be cut back. Nothing changes if the change is refused.
+    pub fn replace_items(&mut self, items: &[TableOrderItem], menu: &Menu, max_items: usize) -> Result<Vec<TableOrderItem>, OrderChangeError> {
+        check_seats(&self.table_id, self.guest_count, items)?;
+        let too_many_items = || OrderChangeError::TooManyItems(self.table_id.to_string(), max_items);
+
+        // Changed on a copy, so a refused change leaves nothing half applied
+        let mut updated = self.clone();
+        let mut removed = vec![];
+        let line_ids: Vec<LineId> = updated.items.keys().rev().copied().collect();
+        for line_id in line_ids {
+            let line = updated.items[&line_id].clone();
+            let wanted = total_quantity(items.iter().filter(|i| i.is_same_dish(&line))).ok_or_else(too_many_items)?;
+            let on_older_lines = total_quantity(updated.items.range(..line_id).map(|(_, l)| l).filter(|l| l.is_same_dish(&line))).ok_or_else(too_many_items)?;
+            let keep = (wanted - on_older_lines).clamp(0, line.quantity);
+            if keep == line.quantity {
+                continue;
+            }
+            if line.status != OrderItemStatus::Ordered {
+                return Err(OrderChangeError::AlreadyStarted(self.table_id.to_string(), line_id.to_string(), line.status));
+            }
+
+            if keep == 0 {
+                updated.items.remove(&line_id);
+            } else {
+                updated.items.get_mut(&line_id).unwrap().set_quantity(keep, menu);
+            }
+            removed.push(TableOrderItem { quantity: line.quantity - keep, ..line });
+        }
+
+        // Worked out up front, so what is added for one item doesn't count as already ordered for the next
+        let extras = items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let on_order = total_quantity(updated.items.values().filter(|l| l.is_same_dish(item))).ok_or_else(too_many_items)?;
+                let asked_before = total_quantity(items[..index].iter().filter(|i| i.is_same_dish(item))).ok_or_else(too_many_items)?;
+                return Ok(item.quantity - (on_order - asked_before).clamp(0, item.quantity));
+            })
+            .collect::<Result<Vec<i32>, OrderChangeError>>()?;
+        for (item, extra) in items.iter().zip(extras) {
+            if extra == 0 {
+                continue;
+            }
+
+            let newest_unstarted = updated
+                .items
+                .values_mut()
+                .rev()
+                .find(|l| l.is_same_dish(item))
+                .filter(|l| l.status == OrderItemStatus::Ordered);
+            match newest_unstarted {
+                Some(line) if extra < item.quantity => {
+                    let quantity = line.quantity + extra;
+                    line.set_quantity(quantity, menu);
+                }
+                _ => {
+                    let mut line = item.clone();
+                    line.set_quantity(extra, menu);
+                    updated.add_item(line);
+                }
+            }
+        }
+        // Started lines stay even when their dish isn't asked for again, so there can be more lines than `items`
+        if updated.items.len() > max_items {
+            return Err(too_many_items());
+        }
+
+        *self = updated;
+        return Ok(removed);
+    }
+
+    pub fn advance_item_status(&mut self, line_id: &LineId, next: OrderItemStatus, at: DateTime<Utc>) -> Result<(), OrderChangeError> {
+        let item = self
+            .items
+            .get_mut(line_id)
+            .ok_or_else(|| OrderChangeError::OrderItemNotFound(self.table_id.to_string(), line_id.to_string()))?;
+
+        if !item.status.can_transition_to(next) {
+            return Err(OrderChangeError::IllegalStatusTransition(self.table_id.to_string(), line_id.to_string(), item.status, next));
+        }
+
+        item.status = next;
+        item.status_history.push(OrderItemStatusChange { status: next, at: at });
+        return Ok(());
+    }
+
+    // The kitchen marking an item done. Cooks don't always start items on the screen first, so an item that was never started goes through preparing on the way.
+    pub fn bump_item(&mut self, line_id: &LineId, at: DateTime<Utc>) -> Result<(), OrderChangeError> {
+        if self.items.get(line_id).map(|i| i.status) == Some(OrderItemStatus::Ordered) {
+            self.advance_item_status(line_id, OrderItemStatus::Preparing, at)?;
+        }
+
+        return self.advance_item_status(line_id, OrderItemStatus::Ready, at);
+    }
+
+    // Undoes a bump made by mistake, putting the item back to preparing. Only allowed shortly after the bump, before front of house is likely to have acted on it.
+    pub fn recall_item(&mut self, line_id: &LineId, at: DateTime<Utc>, window: TimeDelta) -> Result<(), OrderChangeError> {
+        let item = self
+            .items
+            .get_mut(line_id)
+            .ok_or_else(|| OrderChangeError::OrderItemNotFound(self.table_id.to_string(), line_id.to_string()))?;
+
+        if item.status != OrderItemStatus::Ready {
+            return Err(OrderChangeError::NotBumped(self.table_id.to_string(), line_id.to_string(), item.status));
+        }
+        if item.status_changed_at().is_some_and(|bumped_at| at - bumped_at > window) {
+            return Err(OrderChangeError::RecallWindowExpired(self.table_id.to_string(), line_id.to_string(), window.num_minutes()));
+        }
+
+        item.status = OrderItemStatus::Preparing;
+        item.status_history.push(OrderItemStatusChange { status: OrderItemStatus::Preparing, at: at });
+        return Ok(());
+    }
 }
@@ -0,0 +1,160 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+use super::{
+    billing::{Bill, BillId},
+    money::Money,
+    orders::{TableId, TableOrder},
+};
+
+// Numbered from 1 within each order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PaymentId(pub i32);
+impl std::fmt::Display for PaymentId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Card payments and their refunds go through the payment gateway, cash is just recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PaymentMethod {
+    Cash,
+    Card,
+}
+
+// Taken against the latest bill at the time. Never changed afterwards other than to record refunds against it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Payment {
+    pub payment_id: PaymentId,
+    pub bill_id: BillId,
+    pub method: PaymentMethod,
+    pub amount: Money,
+    pub gateway_reference: Option<String>, // the gateway's id for the charge, None for cash
+    pub paid_at: DateTime<Utc>,
+    pub refunds: Vec<Refund>, // oldest first
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Refund {
+    pub amount: Money,
+    pub gateway_reference: Option<String>, // the gateway's id for the refund, None for cash and until the gateway has made it
+    pub refunded_at: DateTime<Utc>,
+    pub status: RefundStatus,
+}
+
+// A card refund is recorded as pending before the gateway is asked for it, so the same money can't be given back twice.
+// Failed ones are kept to show it was tried, but no longer count against the payment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RefundStatus {
+    Pending,
+    Refunded,
+    Failed,
+}
+
+// Numbered from 1 across every table, in the order the tables were closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SettlementId(pub i64);
+impl std::fmt::Display for SettlementId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// What a closed table was billed and how it paid, kept once its order is gone
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settlement {
+    pub settlement_id: SettlementId,
+    pub table_id: TableId,
+    pub closed_at: DateTime<Utc>,
+    pub bills: Vec<Bill>,       // oldest first
+    pub payments: Vec<Payment>, // oldest first
+}
+
+#[derive(Error, Debug, PartialEq, Clone)]
+#[error("Unknown payment method {0}.")]
+pub struct ParsePaymentMethodError(pub String);
+
+#[derive(Error, Debug, PartialEq, Clone)]
+#[error("Unknown refund status {0}.")]
+pub struct ParseRefundStatusError(pub String);
+
+impl PaymentMethod {
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            PaymentMethod::Cash => "cash",
+            PaymentMethod::Card => "card",
+        };
+    }
+}
+
+impl std::fmt::Display for PaymentMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for PaymentMethod {
+    type Err = ParsePaymentMethodError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "cash" => Ok(PaymentMethod::Cash),
+            "card" => Ok(PaymentMethod::Card),
+            _ => Err(ParsePaymentMethodError(s.to_string())),
+        };
+    }
+}
+
+impl RefundStatus {
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            RefundStatus::Pending => "pending",
+            RefundStatus::Refunded => "refunded",
+            RefundStatus::Failed => "failed",
+        };
+    }
+}
+
+impl std::fmt::Display for RefundStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for RefundStatus {
+    type Err = ParseRefundStatusError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "pending" => Ok(RefundStatus::Pending),
+            "refunded" => Ok(RefundStatus::Refunded),
+            "failed" => Ok(RefundStatus::Failed),
+            _ => Err(ParseRefundStatusError(s.to_string())),
+        };
+    }
+}
+
+impl Settlement {
+    pub fn new(settlement_id: SettlementId, order: &TableOrder, closed_at: DateTime<Utc>) -> Self {
+        return Self { settlement_id: settlement_id, table_id: order.table_id.clone(), closed_at: closed_at, bills: order.bills.clone(), payments: order.payments.clone() };
+    }
+}
+
+impl Payment {
+    // Pending refunds count too, the money is on its way back
+    pub fn refunded(&self) -> Money {
+        return Money::sum(self.refunds.iter().filter(|r| r.status != RefundStatus::Failed).map(|r| r.amount), self.amount.currency);
+    }
+
+    pub fn has_pending_refund(&self) -> bool {
+        return self.refunds.iter().any(|r| r.status == RefundStatus::Pending);
+    }
+
+    // What the table has actually paid with this payment once its refunds are taken off
+    pub fn net_amount(&self) -> Money {
+        return self.amount - self.refunded();
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::HashMap,
+    future::ready,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use futures_util::future::BoxFuture;
+use thiserror::Error;
+
+use crate::models::money::Money;
+
+// Card tokens the fake gateway treats specially, so the failure paths can be tried by hand as well as from tests
+pub const FAKE_DECLINED_CARD_TOKEN: &str = "tok_declined";
+pub const FAKE_UNAVAILABLE_CARD_TOKEN: &str = "tok_unavailable";
+
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum PaymentGatewayError {
+    #[error("The payment gateway declined: {0}.")]
+    Declined(String),
+    #[error("Payment gateway error: {0}")]
+    Unavailable(String),
+}
+
+// Card payments are taken and refunded through this, cash never goes near it.
+// Boxed futures rather than `async fn` so the gateway can sit behind an Arc<dyn PaymentGateway> in AppState and be swapped like the clock.
+pub trait PaymentGateway: std::fmt::Debug + Send + Sync {
+    // Charges the card the token stands for, returning the gateway's reference for the charge
+    fn charge<'a>(&'a self, card_token: &'a str, amount: Money) -> BoxFuture<'a, Result<String, PaymentGatewayError>>;
+
+    // Gives back some or all of an earlier charge, returning the gateway's reference for the refund
+    fn refund<'a>(&'a self, charge_reference: &'a str, amount: Money) -> BoxFuture<'a, Result<String, PaymentGatewayError>>;
+}
+
+// What the server runs with until a gateway is configured, so no card is ever taken as paid without one having charged it
+#[derive(Debug, Default)]
+pub struct UnconfiguredGateway;
+
+impl PaymentGateway for UnconfiguredGateway {
+    fn charge<'a>(&'a self, _card_token: &'a str, _amount: Money) -> BoxFuture<'a, Result<String, PaymentGatewayError>> {
+        return Box::pin(ready(Err(PaymentGatewayError::Unavailable("no payment gateway is configured".to_string()))));
+    }
+
+    fn refund<'a>(&'a self, _charge_reference: &'a str, _amount: Money) -> BoxFuture<'a, Result<String, PaymentGatewayError>> {
+        return Box::pin(ready(Err(PaymentGatewayError::Unavailable("no payment gateway is configured".to_string()))));
+    }
+}
+
+// Stands in for a real gateway without anything leaving the process. Every card is approved apart from the special tokens above,
+// and it remembers what it charged so a refund can't give back more than was taken, as a real gateway wouldn't let it.
+#[derive(Debug, Default)]
+pub struct FakeGateway {
+    charges: Mutex<HashMap<String, FakeCharge>>,
+    next_reference: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FakeCharge {
+    amount: Money,
+    refunded: Money,
+}
+
+impl FakeGateway {
+    fn new_reference(&self, prefix: &str) -> String {
+        return format!("{}_{}", prefix, self.next_reference.fetch_add(1, Ordering::Relaxed) + 1);
+    }
+
+    fn charge_now(&self, card_token: &str, amount: Money) -> Result<String, PaymentGatewayError> {
+        match card_token {
+            FAKE_DECLINED_CARD_TOKEN => return Err(PaymentGatewayError::Declined("insufficient funds".to_string())),
+            FAKE_UNAVAILABLE_CARD_TOKEN => return Err(PaymentGatewayError::Unavailable("the fake gateway was asked to be unavailable".to_string())),
+            _ => {}
+        }
+
+        let reference = self.new_reference("fake_ch");
+        self.charges
+            .lock()
+            .unwrap()
+            .insert(reference.clone(), FakeCharge { amount: amount, refunded: Money::zero(amount.currency) });
+        return Ok(reference);
+    }
+
+    fn refund_now(&self, charge_reference: &str, amount: Money) -> Result<String, PaymentGatewayError> {
+        let mut charges = self.charges.lock().unwrap();
+        let charge = charges
+            .get_mut(charge_reference)
+            .ok_or_else(|| PaymentGatewayError::Declined(format!("no charge {}", charge_reference)))?;
+        if (charge.refunded + amount).minor_units > charge.amount.minor_units {
+            return Err(PaymentGatewayError::Declined(format!("refund is more than is left on charge {}", charge_reference)));
+        }
+
+        charge.refunded = charge.refunded + amount;
+        return Ok(self.new_reference("fake_re"));
+    }
+
+    #[cfg(test)]
+    pub fn refunded(&self, charge_reference: &str) -> Option<Money> {
+        return self.charges.lock().unwrap().get(charge_reference).map(|c| c.refunded);
+    }
+}
+
+impl PaymentGateway for FakeGateway {
+    fn charge<'a>(&'a self, card_token: &'a str, amount: Money) -> BoxFuture<'a, Result<String, PaymentGatewayError>> {
+        return Box::pin(ready(self.charge_now(card_token, amount)));
+    }
+
+    fn refund<'a>(&'a self, charge_reference: &'a str, amount: Money) -> BoxFuture<'a, Result<String, PaymentGatewayError>> {
+        return Box::pin(ready(self.refund_now(charge_reference, amount)));
+    }
+}
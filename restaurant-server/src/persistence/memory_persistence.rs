@@ -1,80 +1,150 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use chrono::{DateTime, Utc};
 
 use crate::models::{
-    menu::MenuItemId,
-    orders::{TableId, TableOrder, TableOrderItem},
+    menu::Menu,
+    orders::{OrderChangeError, TableId, TableOrder, TableOrderItem},
+    payments::{Settlement, SettlementId},
 };
 
-use super::persistence::{CreateOrderError, Persistence, ReadOrderError, ReadOrderItemError};
+use super::persistence::{CreateOrderError, ModifyOrderError, Persistence, ReadOrderError};
+
+// Each table has its own lock, so requests for unrelated tables never wait on each other.
+// The outer lock only guards the index of tables, and is held just long enough to add/remove/look up an entry.
+// A deleted table is left as None, so a request that looked up the entry before the delete still sees it as gone.
+type TableEntry = Arc<Mutex<Option<TableOrder>>>;
 
 #[derive(Default, Debug)]
 pub struct MemoryPersistence {
-    data: HashMap<TableId, TableOrder>,
+    data: RwLock<HashMap<TableId, TableEntry>>,
+    settlements: Mutex<Vec<Settlement>>, // oldest first, so each one's id is one more than its index
 }
 
 impl MemoryPersistence {
+    #[cfg(test)]
     pub fn new(data: HashMap<TableId, TableOrder>) -> Self {
-        return Self { data: data };
+        return Self { data: RwLock::new(data.into_iter().map(|(k, v)| (k, Arc::new(Mutex::new(Some(v))))).collect()), settlements: Mutex::default() };
+    }
+
+    fn find_entry(&self, table_id: &TableId) -> Option<TableEntry> {
+        return self.data.read().unwrap().get(table_id).cloned();
     }
 }
 
 impl Persistence for MemoryPersistence {
-    async fn create_order(&mut self, table_id: &TableId, items: &[TableOrderItem]) -> Result<&TableOrder, CreateOrderError> {
-        if self.data.contains_key(&table_id) {
+    async fn create_order<S>(&self, table_id: &TableId, guest_count: Option<u32>, items: &[TableOrderItem], saved: S) -> Result<TableOrder, CreateOrderError>
+    where
+        S: FnOnce(&TableOrder) + Send,
+    {
+        let mut data = self.data.write().unwrap();
+        if data.contains_key(table_id) {
             return Err(CreateOrderError::OrderAlreadyExistsForTable(table_id.to_string()));
         }
 
-        let new_record: TableOrder = TableOrder { table_id: table_id.clone(), items: item_slice_to_hashmap(items) };
+        let new_record: TableOrder = TableOrder::new(table_id.clone(), items).with_guest_count(guest_count);
 
-        self.data.insert(table_id.clone(), new_record);
+        data.insert(table_id.clone(), Arc::new(Mutex::new(Some(new_record.clone()))));
+        // Still holding the index, so nothing can get at the new table before this
+        saved(&new_record);
 
-        return Ok(self.data.get(&table_id).unwrap());
+        return Ok(new_record);
     }
 
-    async fn find_order(&self, table_id: &TableId) -> Result<&TableOrder, ReadOrderError> {
-        return self.data.get(&table_id).ok_or_else(|| ReadOrderError::OrderNotFound(table_id.to_string()));
+    async fn find_order(&self, table_id: &TableId) -> Result<TableOrder, ReadOrderError> {
+        return self
+            .find_entry(table_id)
+            .and_then(|entry| entry.lock().unwrap().clone())
+            .ok_or_else(|| ReadOrderError::OrderNotFound(table_id.to_string()));
     }
 
-    async fn update_order(&mut self, table_id: &TableId, new_items: &[TableOrderItem]) -> Result<&TableOrder, ReadOrderError> {
-        return self
-            .data
-            .get_mut(&table_id)
-            .ok_or_else(|| ReadOrderError::OrderNotFound(table_id.to_string()))
-            .map(|o| {
-                o.items = item_slice_to_hashmap(new_items);
-                return &*o;
-            });
+    // Only the index is locked while taking the snapshot of entries, then each table is locked in turn.
+    // So this is not a point in time snapshot across tables, but it never holds up requests for more than one table at once.
+    async fn list_orders(&self) -> Result<Vec<TableOrder>, ReadOrderError> {
+        let entries = self.data.read().unwrap().values().cloned().collect::<Vec<TableEntry>>();
+
+        return Ok(entries.iter().filter_map(|entry| entry.lock().unwrap().clone()).collect());
     }
 
-    async fn delete_order(&mut self, table_id: &TableId) -> Result<(), ReadOrderError> {
-        return match self.data.remove(&table_id) {
-            Some(_) => Ok(()),
-            None => Err(ReadOrderError::OrderNotFound(table_id.to_string())),
-        };
+    async fn update_order<S>(&self, table_id: &TableId, new_items: &[TableOrderItem], menu: &Menu, max_items: usize, saved: S) -> Result<(TableOrder, Vec<TableOrderItem>), ModifyOrderError>
+    where
+        S: FnOnce(&TableOrder) + Send,
+    {
+        let entry = self.find_entry(table_id).ok_or_else(|| ModifyOrderError::OrderNotFound(table_id.to_string()))?;
+        let mut order = entry.lock().unwrap();
+        let current = order.as_mut().ok_or_else(|| ModifyOrderError::OrderNotFound(table_id.to_string()))?;
+
+        // replace_items leaves the order as it was when it refuses a change, so there is nothing to roll back
+        let removed = current.replace_items(new_items, menu, max_items)?;
+        saved(current);
+        return Ok((current.clone(), removed));
     }
 
-    async fn delete_order_item(&mut self, table_id: &TableId, item_id: &MenuItemId) -> Result<&TableOrder, ReadOrderItemError> {
-        return self
-            .data
-            .get_mut(&table_id)
-            .ok_or_else(|| ReadOrderItemError::OrderNotFound(table_id.to_string()))
-            .and_then(|o| {
-                return match o.items.remove(&item_id) {
-                    Some(_) => Ok(&*o),
-                    None => Err(ReadOrderItemError::OrderItemNotFound(item_id.to_string())),
-                };
-            });
+    async fn delete_order<F, S>(&self, table_id: &TableId, closed_at: DateTime<Utc>, check: F, saved: S) -> Result<Option<Settlement>, ModifyOrderError>
+    where
+        F: FnOnce(&TableOrder) -> Result<(), OrderChangeError> + Send,
+        S: FnOnce(&TableOrder, Option<&Settlement>) + Send,
+    {
+        let entry = self.find_entry(table_id).ok_or_else(|| ModifyOrderError::OrderNotFound(table_id.to_string()))?;
+        let mut order = entry.lock().unwrap();
+        check(order.as_ref().ok_or_else(|| ModifyOrderError::OrderNotFound(table_id.to_string()))?)?;
+
+        // Nothing ever waits on a table's lock while holding the index lock, so taking the index lock here can't deadlock.
+        // The entry is still in the index until now, so no new order can be created for the table in between.
+        // The index is held until `saved` is done too, so a new order for the table can't get in before it either.
+        let mut data = self.data.write().unwrap();
+        let mut settlement = None;
+        if let Some(deleted) = order.take() {
+            if deleted.has_billing() {
+                let mut settlements = self.settlements.lock().unwrap();
+                let settlement_id = SettlementId(settlements.len() as i64 + 1);
+                settlements.push(Settlement::new(settlement_id, &deleted, closed_at));
+                settlement = settlements.last().cloned();
+            }
+            saved(&deleted, settlement.as_ref());
+        }
+        data.remove(table_id);
+        return Ok(settlement);
     }
-}
 
-pub fn item_slice_to_hashmap(items: &[TableOrderItem]) -> HashMap<MenuItemId, TableOrderItem> {
-    return items
-        .iter()
-        .map(|i| (i.item_id.clone(), i.clone()))
-        .collect::<HashMap<MenuItemId, TableOrderItem>>();
+    async fn list_settlements(&self, table_id: &TableId) -> Result<Vec<Settlement>, ReadOrderError> {
+        return Ok(self.settlements.lock().unwrap().iter().filter(|s| &s.table_id == table_id).cloned().collect());
+    }
+
+    async fn update_order_with<F, S>(&self, table_id: &TableId, change: F, saved: S) -> Result<TableOrder, ModifyOrderError>
+    where
+        F: FnOnce(&mut TableOrder) -> Result<(), OrderChangeError> + Send,
+        S: FnOnce(&TableOrder) + Send,
+    {
+        let entry = self.find_entry(table_id).ok_or_else(|| ModifyOrderError::OrderNotFound(table_id.to_string()))?;
+        let mut order = entry.lock().unwrap();
+        let current = order.as_mut().ok_or_else(|| ModifyOrderError::OrderNotFound(table_id.to_string()))?;
+
+        // Changed on a copy so a rejected change leaves nothing half applied
+        let mut updated = current.clone();
+        change(&mut updated)?;
+        *current = updated.clone();
+        saved(&updated);
+
+        return Ok(updated);
+    }
 }
 
 #[cfg(test)]
 pub fn get_underlying_data(memory_persistence: MemoryPersistence) -> HashMap<TableId, TableOrder> {
-    return memory_persistence.data;
+    return memory_persistence
+        .data
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .filter_map(|(k, v)| v.lock().unwrap().clone().map(|o| (k, o)))
+        .collect();
+}
+
+#[cfg(test)]
+pub fn get_table_lock(memory_persistence: &MemoryPersistence, table_id: &TableId) -> Option<TableEntry> {
+    return memory_persistence.find_entry(table_id);
 }
@@ -1,2 +1,3 @@
 pub mod memory_persistence;
 pub mod persistence;
+pub mod sqlite_persistence;
@@ -1,6 +1,11 @@
+use std::future::Future;
+
+use chrono::{DateTime, Utc};
+
 use crate::models::{
-    menu::MenuItemId,
-    orders::{TableId, TableOrder, TableOrderItem},
+    menu::Menu,
+    orders::{OrderChangeError, TableId, TableOrder, TableOrderItem},
+    payments::Settlement,
 };
 use thiserror::Error;
 
@@ -8,30 +13,63 @@ use thiserror::Error;
 pub enum ReadOrderError {
     #[error("Order id {0} not found.")]
     OrderNotFound(String),
+    #[error("Storage error: {0}")]
+    Storage(String),
 }
 
 #[derive(Error, Debug, PartialEq, Clone)]
 pub enum CreateOrderError {
     #[error("An order already exists for table id {0}.")]
     OrderAlreadyExistsForTable(String),
+    #[error("Storage error: {0}")]
+    Storage(String),
 }
 
 #[derive(Error, Debug, PartialEq, Clone)]
-pub enum ReadOrderItemError {
+pub enum ModifyOrderError {
     #[error("Order id {0} not found.")]
     OrderNotFound(String),
-    #[error("Order item id {0} not found.")]
-    OrderItemNotFound(String),
+    #[error(transparent)]
+    Rejected(#[from] OrderChangeError),
+    #[error("Storage error: {0}")]
+    Storage(String),
 }
 
-pub trait Persistence {
-    // In production this would likely be async, if it were using a DB or redis etc
-    async fn create_order(&mut self, table_id: &TableId, items: &[TableOrderItem]) -> Result<&TableOrder, CreateOrderError>;
+// Orders are returned by value since a database backed implementation has nothing to borrow from.
+// The futures are spelled out rather than using `async fn` so they can be required to be Send,
+// which is what lets the axum handlers be generic over the implementation. Implementations can still use `async fn`.
+// Every write calls `saved` with the order as it was saved (or as it was just before being deleted), while still holding the table's lock.
+// So anything done from it, e.g. publishing an event, happens in the same order as the writes themselves. It isn't called if nothing was saved.
+pub trait Persistence: std::fmt::Debug + Send + Sync {
+    fn create_order<S>(&self, table_id: &TableId, guest_count: Option<u32>, items: &[TableOrderItem], saved: S) -> impl Future<Output = Result<TableOrder, CreateOrderError>> + Send
+    where
+        S: FnOnce(&TableOrder) + Send;
+
+    fn find_order(&self, table_id: &TableId) -> impl Future<Output = Result<TableOrder, ReadOrderError>> + Send;
+    // Every open order, in no particular order
+    fn list_orders(&self) -> impl Future<Output = Result<Vec<TableOrder>, ReadOrderError>> + Send;
 
-    async fn find_order(&self, table_id: &TableId) -> Result<&TableOrder, ReadOrderError>;
+    // Diffs `new_items` against the current items, see TableOrder::replace_items. The menu is needed to re-estimate lines whose quantity changes.
+    // Returns the updated order along with what was taken off it, or an error and no change if replace_items refuses it.
+    fn update_order<S>(
+        &self, table_id: &TableId, new_items: &[TableOrderItem], menu: &Menu, max_items: usize, saved: S,
+    ) -> impl Future<Output = Result<(TableOrder, Vec<TableOrderItem>), ModifyOrderError>> + Send
+    where
+        S: FnOnce(&TableOrder) + Send;
 
-    async fn update_order(&mut self, table_id: &TableId, new_items: &[TableOrderItem]) -> Result<&TableOrder, ReadOrderError>;
+    // Only deletes if `check` accepts the order as it is at the time, checked under the same lock or transaction as the delete so nothing can change in between.
+    // The order's bills and payments are kept as a settlement, saved along with the delete, unless it never had any. `saved` gets it too.
+    fn delete_order<F, S>(&self, table_id: &TableId, closed_at: DateTime<Utc>, check: F, saved: S) -> impl Future<Output = Result<Option<Settlement>, ModifyOrderError>> + Send
+    where
+        F: FnOnce(&TableOrder) -> Result<(), OrderChangeError> + Send,
+        S: FnOnce(&TableOrder, Option<&Settlement>) + Send;
+    // Every settlement left by closing the table, oldest first
+    fn list_settlements(&self, table_id: &TableId) -> impl Future<Output = Result<Vec<Settlement>, ReadOrderError>> + Send;
 
-    async fn delete_order(&mut self, table_id: &TableId) -> Result<(), ReadOrderError>;
-    async fn delete_order_item(&mut self, table_id: &TableId, item_id: &MenuItemId) -> Result<&TableOrder, ReadOrderItemError>;
+    // Applies `change` to the current order while holding the table's lock (or inside a transaction), so read-check-write changes can't race.
+    // If `change` returns an error nothing is saved.
+    fn update_order_with<F, S>(&self, table_id: &TableId, change: F, saved: S) -> impl Future<Output = Result<TableOrder, ModifyOrderError>> + Send
+    where
+        F: FnOnce(&mut TableOrder) -> Result<(), OrderChangeError> + Send,
+        S: FnOnce(&TableOrder) + Send;
 }
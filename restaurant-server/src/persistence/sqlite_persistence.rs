@@ -0,0 +1,1114 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{
+    params,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+    Connection, OptionalExtension, ToSql, Transaction, TransactionBehavior,
+};
+use tokio::sync::{OwnedMutexGuard, OwnedSemaphorePermit, Semaphore};
+
+use crate::models::{
+    billing::{Bill, BillId, BillLine, BillServiceCharge, BillTax, TaxMode, TaxRounding},
+    menu::{Menu, MenuItemId, ModifierId},
+    money::{Currency, Money, Percentage},
+    orders::{LineId, OrderChangeError, OrderItemStatus, OrderItemStatusChange, TableId, TableOrder, TableOrderItem},
+    payments::{Payment, PaymentId, PaymentMethod, Refund, RefundStatus, Settlement, SettlementId},
+};
+
+use super::persistence::{CreateOrderError, ModifyOrderError, Persistence, ReadOrderError};
+
+// Each entry is applied once, in order, and the index + 1 is recorded in the database's user_version.
+// Never edit an existing entry, only append new ones.
+pub const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE table_orders (
+        table_id INTEGER PRIMARY KEY NOT NULL
+    );
+
+    CREATE TABLE table_order_items (
+        table_id INTEGER NOT NULL REFERENCES table_orders(table_id) ON DELETE CASCADE,
+        item_id INTEGER NOT NULL,
+        quantity INTEGER NOT NULL,
+        total_preparation_time_mins INTEGER NOT NULL,
+        PRIMARY KEY (table_id, item_id)
+    );
+",
+    "
+    ALTER TABLE table_order_items ADD COLUMN status TEXT NOT NULL DEFAULT 'ordered';
+
+    CREATE TABLE table_order_item_status_changes (
+        table_id INTEGER NOT NULL,
+        item_id INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        at TEXT NOT NULL,
+        PRIMARY KEY (table_id, item_id, seq),
+        FOREIGN KEY (table_id, item_id) REFERENCES table_order_items(table_id, item_id) ON DELETE CASCADE
+    );
+",
+    "
+    -- Items from before this migration have no recorded order time, so treat them as ordered now
+    ALTER TABLE table_order_items ADD COLUMN ordered_at TEXT NOT NULL DEFAULT '';
+    ALTER TABLE table_order_items ADD COLUMN ready_at TEXT NOT NULL DEFAULT '';
+    UPDATE table_order_items SET
+        ordered_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now'),
+        ready_at = strftime('%Y-%m-%d %H:%M:%f+00:00', 'now', '+' || total_preparation_time_mins || ' minutes');
+",
+    "
+    -- Lines get their own id so a menu item can be ordered more than once.
+    -- Tables could only hold one line per menu item until now, so existing lines keep the menu item id as their line id.
+    ALTER TABLE table_orders ADD COLUMN next_line_id INTEGER NOT NULL DEFAULT 1;
+
+    CREATE TABLE table_order_lines (
+        table_id INTEGER NOT NULL REFERENCES table_orders(table_id) ON DELETE CASCADE,
+        line_id INTEGER NOT NULL,
+        item_id INTEGER NOT NULL,
+        quantity INTEGER NOT NULL,
+        total_preparation_time_mins INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        ordered_at TEXT NOT NULL,
+        ready_at TEXT NOT NULL,
+        PRIMARY KEY (table_id, line_id)
+    );
+
+    CREATE TABLE table_order_line_status_changes (
+        table_id INTEGER NOT NULL,
+        line_id INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        at TEXT NOT NULL,
+        PRIMARY KEY (table_id, line_id, seq),
+        FOREIGN KEY (table_id, line_id) REFERENCES table_order_lines(table_id, line_id) ON DELETE CASCADE
+    );
+
+    INSERT INTO table_order_lines (table_id, line_id, item_id, quantity, total_preparation_time_mins, status, ordered_at, ready_at)
+        SELECT table_id, item_id, item_id, quantity, total_preparation_time_mins, status, ordered_at, ready_at FROM table_order_items;
+    INSERT INTO table_order_line_status_changes (table_id, line_id, seq, status, at)
+        SELECT table_id, item_id, seq, status, at FROM table_order_item_status_changes;
+    UPDATE table_orders SET next_line_id = 1 + COALESCE((SELECT MAX(line_id) FROM table_order_lines WHERE table_order_lines.table_id = table_orders.table_id), 0);
+
+    DROP TABLE table_order_item_status_changes;
+    DROP TABLE table_order_items;
+",
+    "
+    ALTER TABLE table_order_lines ADD COLUMN notes TEXT;
+
+    CREATE TABLE table_order_line_modifiers (
+        table_id INTEGER NOT NULL,
+        line_id INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        modifier_id TEXT NOT NULL,
+        PRIMARY KEY (table_id, line_id, seq),
+        FOREIGN KEY (table_id, line_id) REFERENCES table_order_lines(table_id, line_id) ON DELETE CASCADE
+    );
+",
+    "
+    -- Bills are copied out of the order when issued and never change afterwards. Amounts are minor units of the bill's currency,
+    -- rates are thousandths of a percent.
+    CREATE TABLE table_order_bills (
+        table_id INTEGER NOT NULL REFERENCES table_orders(table_id) ON DELETE CASCADE,
+        bill_id INTEGER NOT NULL,
+        issued_at TEXT NOT NULL,
+        guests INTEGER,
+        currency TEXT NOT NULL,
+        tax_mode TEXT NOT NULL,
+        tax_rounding TEXT NOT NULL,
+        subtotal INTEGER NOT NULL,
+        service_charge_rate INTEGER,
+        service_charge INTEGER,
+        tax_total INTEGER NOT NULL,
+        total INTEGER NOT NULL,
+        PRIMARY KEY (table_id, bill_id)
+    );
+
+    CREATE TABLE table_order_bill_lines (
+        table_id INTEGER NOT NULL,
+        bill_id INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        line_id INTEGER NOT NULL,
+        item_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        category TEXT NOT NULL,
+        quantity INTEGER NOT NULL,
+        unit_price INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        tax_rate INTEGER NOT NULL,
+        tax INTEGER,
+        PRIMARY KEY (table_id, bill_id, seq),
+        FOREIGN KEY (table_id, bill_id) REFERENCES table_order_bills(table_id, bill_id) ON DELETE CASCADE
+    );
+
+    CREATE TABLE table_order_bill_taxes (
+        table_id INTEGER NOT NULL,
+        bill_id INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        rate INTEGER NOT NULL,
+        taxable INTEGER NOT NULL,
+        tax INTEGER NOT NULL,
+        PRIMARY KEY (table_id, bill_id, seq),
+        FOREIGN KEY (table_id, bill_id) REFERENCES table_order_bills(table_id, bill_id) ON DELETE CASCADE
+    );
+",
+    "
+    -- Orders and bills from before this migration have no guest count or seats
+    ALTER TABLE table_orders ADD COLUMN guest_count INTEGER;
+    ALTER TABLE table_order_lines ADD COLUMN seat INTEGER;
+    ALTER TABLE table_order_bill_lines ADD COLUMN seat INTEGER;
+",
+    "
+    -- Payments never change once taken, refunds are recorded against them instead. Amounts are minor units of the payment's currency.
+    CREATE TABLE table_order_payments (
+        table_id INTEGER NOT NULL REFERENCES table_orders(table_id) ON DELETE CASCADE,
+        payment_id INTEGER NOT NULL,
+        bill_id INTEGER NOT NULL,
+        method TEXT NOT NULL,
+        currency TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        gateway_reference TEXT,
+        paid_at TEXT NOT NULL,
+        PRIMARY KEY (table_id, payment_id)
+    );
+
+    CREATE TABLE table_order_payment_refunds (
+        table_id INTEGER NOT NULL,
+        payment_id INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        gateway_reference TEXT,
+        refunded_at TEXT NOT NULL,
+        PRIMARY KEY (table_id, payment_id, seq),
+        FOREIGN KEY (table_id, payment_id) REFERENCES table_order_payments(table_id, payment_id) ON DELETE CASCADE
+    );
+",
+    "
+    -- Closing a table copies its bills and payments here before the order and everything on it is deleted.
+    -- Same columns as the order's tables, but keyed by settlement, since the table can have been closed many times.
+    CREATE TABLE settlements (
+        settlement_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        table_id INTEGER NOT NULL,
+        closed_at TEXT NOT NULL
+    );
+    CREATE INDEX settlements_by_table ON settlements(table_id);
+
+    CREATE TABLE settled_bills (
+        settlement_id INTEGER NOT NULL REFERENCES settlements(settlement_id),
+        bill_id INTEGER NOT NULL,
+        issued_at TEXT NOT NULL,
+        guests INTEGER,
+        currency TEXT NOT NULL,
+        tax_mode TEXT NOT NULL,
+        tax_rounding TEXT NOT NULL,
+        subtotal INTEGER NOT NULL,
+        service_charge_rate INTEGER,
+        service_charge INTEGER,
+        tax_total INTEGER NOT NULL,
+        total INTEGER NOT NULL,
+        PRIMARY KEY (settlement_id, bill_id)
+    );
+
+    CREATE TABLE settled_bill_lines (
+        settlement_id INTEGER NOT NULL,
+        bill_id INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        line_id INTEGER NOT NULL,
+        item_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        category TEXT NOT NULL,
+        seat INTEGER,
+        quantity INTEGER NOT NULL,
+        unit_price INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        tax_rate INTEGER NOT NULL,
+        tax INTEGER,
+        PRIMARY KEY (settlement_id, bill_id, seq),
+        FOREIGN KEY (settlement_id, bill_id) REFERENCES settled_bills(settlement_id, bill_id)
+    );
+
+    CREATE TABLE settled_bill_taxes (
+        settlement_id INTEGER NOT NULL,
+        bill_id INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        rate INTEGER NOT NULL,
+        taxable INTEGER NOT NULL,
+        tax INTEGER NOT NULL,
+        PRIMARY KEY (settlement_id, bill_id, seq),
+        FOREIGN KEY (settlement_id, bill_id) REFERENCES settled_bills(settlement_id, bill_id)
+    );
+
+    CREATE TABLE settled_payments (
+        settlement_id INTEGER NOT NULL REFERENCES settlements(settlement_id),
+        payment_id INTEGER NOT NULL,
+        bill_id INTEGER NOT NULL,
+        method TEXT NOT NULL,
+        currency TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        gateway_reference TEXT,
+        paid_at TEXT NOT NULL,
+        PRIMARY KEY (settlement_id, payment_id)
+    );
+
+    CREATE TABLE settled_payment_refunds (
+        settlement_id INTEGER NOT NULL,
+        payment_id INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        gateway_reference TEXT,
+        refunded_at TEXT NOT NULL,
+        PRIMARY KEY (settlement_id, payment_id, seq),
+        FOREIGN KEY (settlement_id, payment_id) REFERENCES settled_payments(settlement_id, payment_id)
+    );
+",
+    "
+    -- Card refunds are recorded as pending before the gateway is asked for them. Every refund from before this migration went through.
+    ALTER TABLE table_order_payment_refunds ADD COLUMN status TEXT NOT NULL DEFAULT 'refunded';
+    ALTER TABLE settled_payment_refunds ADD COLUMN status TEXT NOT NULL DEFAULT 'refunded';
+",
+];
+
+// Connections handed out by `SqlitePersistence::run`. Each one is only ever used from one blocking thread at a time.
+// An in-memory database only exists on the connection that created it, so that gets a pool of one.
+const FILE_CONNECTION_POOL_SIZE: usize = 8;
+// How long a write waits for another connection's write to finish before giving up with SQLITE_BUSY
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// load_bills and load_payments read either an open order's bills and payments, keyed by table id, or the settled copies of a closed one's, keyed by settlement id
+struct BillingTables {
+    key: &'static str,
+    bills: &'static str,
+    bill_lines: &'static str,
+    bill_taxes: &'static str,
+    payments: &'static str,
+    payment_refunds: &'static str,
+}
+
+const ORDER_BILLING: BillingTables = BillingTables {
+    key: "table_id",
+    bills: "table_order_bills",
+    bill_lines: "table_order_bill_lines",
+    bill_taxes: "table_order_bill_taxes",
+    payments: "table_order_payments",
+    payment_refunds: "table_order_payment_refunds",
+};
+
+const SETTLED_BILLING: BillingTables = BillingTables {
+    key: "settlement_id",
+    bills: "settled_bills",
+    bill_lines: "settled_bill_lines",
+    bill_taxes: "settled_bill_taxes",
+    payments: "settled_payments",
+    payment_refunds: "settled_payment_refunds",
+};
+
+// Every query runs on tokio's blocking threads with a connection from the pool, so disk I/O never stalls the async workers.
+// Like MemoryPersistence each table has its own lock, held across the read-check-write of a change,
+// so changes to one table are applied one at a time while unrelated tables only share SQLite's single writer for the length of a commit.
+// Reads take no table lock, they see the last commit. In WAL mode they don't wait on writers either.
+#[derive(Debug)]
+pub struct SqlitePersistence {
+    pool: Arc<ConnectionPool>,
+    table_locks: TableLocks,
+}
+
+#[derive(Debug)]
+struct ConnectionPool {
+    idle: Mutex<Vec<Connection>>,
+    available: Arc<Semaphore>, // one permit per idle connection
+}
+
+// Returns the connection to the pool when dropped, even if the blocking task panicked or the request that started it went away
+struct PooledConnection {
+    connection: Option<Connection>,
+    pool: Arc<ConnectionPool>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            self.pool.idle.lock().unwrap().push(connection);
+        }
+    }
+}
+
+// Entries are removed again once nobody holds or waits on them, so tables that have come and gone don't pile up
+#[derive(Debug, Default)]
+struct TableLocks {
+    locks: Mutex<HashMap<TableId, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+pub struct TableGuard<'a> {
+    guard: Option<OwnedMutexGuard<()>>,
+    table_id: TableId,
+    table_locks: &'a TableLocks,
+}
+
+impl TableLocks {
+    async fn lock(&self, table_id: &TableId) -> TableGuard<'_> {
+        let lock = Arc::clone(self.locks.lock().unwrap().entry(table_id.clone()).or_default());
+
+        return TableGuard { guard: Some(lock.lock_owned().await), table_id: table_id.clone(), table_locks: self };
+    }
+}
+
+// Holds on to a write's `saved` until its commit is known. If the request is dropped while the write is still running on its blocking thread,
+// this waits for it there and then, so a commit is never left without its `saved`. It is declared after the table's guard, so that happens under the lock too.
+struct PendingWrite<T, S: FnOnce(&T)> {
+    saved: Option<S>,
+    committed: mpsc::Receiver<Result<T, rusqlite::Error>>,
+}
+
+impl<T, S: FnOnce(&T)> PendingWrite<T, S> {
+    fn finish(mut self) -> Result<T, rusqlite::Error> {
+        // The blocking task always sends before it finishes, unless it panicked, and then the panic has already been resumed
+        let result = self.committed.recv().unwrap();
+        if let (Ok(value), Some(saved)) = (&result, self.saved.take()) {
+            saved(value);
+        }
+        return result;
+    }
+}
+
+impl<T, S: FnOnce(&T)> Drop for PendingWrite<T, S> {
+    fn drop(&mut self) {
+        if let Some(saved) = self.saved.take() {
+            // Only blocks for the rest of a single write, and only when the request went away part way through it
+            if let Ok(Ok(value)) = self.committed.recv() {
+                saved(&value);
+            }
+        }
+    }
+}
+
+impl Drop for TableGuard<'_> {
+    fn drop(&mut self) {
+        self.guard.take();
+
+        // Anyone else holding or waiting on the lock has their own Arc, and nobody can clone the map's one while the map is locked
+        let mut locks = self.table_locks.locks.lock().unwrap();
+        if locks.get(&self.table_id).is_some_and(|lock| Arc::strong_count(lock) == 1) {
+            locks.remove(&self.table_id);
+        }
+    }
+}
+
+impl SqlitePersistence {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, rusqlite::Error> {
+        let mut first = open_connection(Connection::open(&path)?)?;
+        first.pragma_update(None, "journal_mode", "WAL")?;
+        run_migrations(&mut first)?;
+
+        let mut connections = vec![first];
+        for _ in 1..FILE_CONNECTION_POOL_SIZE {
+            connections.push(open_connection(Connection::open(&path)?)?);
+        }
+
+        return Ok(Self::from_connections(connections));
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self, rusqlite::Error> {
+        let mut connection = open_connection(Connection::open_in_memory()?)?;
+        run_migrations(&mut connection)?;
+
+        return Ok(Self::from_connections(vec![connection]));
+    }
+
+    fn from_connections(connections: Vec<Connection>) -> Self {
+        let pool = ConnectionPool { available: Arc::new(Semaphore::new(connections.len())), idle: Mutex::new(connections) };
+
+        return Self { pool: Arc::new(pool), table_locks: TableLocks::default() };
+    }
+
+    // Runs `query` on a blocking thread with a connection to itself, waiting for one to be free if they are all in use
+    async fn run<T, F>(&self, query: F) -> Result<T, rusqlite::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, rusqlite::Error> + Send + 'static,
+    {
+        // The semaphore is never closed
+        let permit = Arc::clone(&self.pool.available).acquire_owned().await.unwrap();
+        let connection = self.pool.idle.lock().unwrap().pop();
+        let mut pooled = PooledConnection { connection: connection, pool: Arc::clone(&self.pool), _permit: permit };
+
+        return tokio::task::spawn_blocking(move || query(pooled.connection.as_mut().unwrap()))
+            .await
+            .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()));
+    }
+
+    // Like run, but `saved` is called with what `write` returns once it has committed, even if the caller stops waiting for it, see PendingWrite
+    async fn run_write<T, F, S>(&self, write: F, saved: S) -> Result<T, rusqlite::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, rusqlite::Error> + Send + 'static,
+        S: FnOnce(&T),
+    {
+        let (sender, receiver) = mpsc::channel();
+        let pending = PendingWrite { saved: Some(saved), committed: receiver };
+
+        self.run(move |connection| {
+            let _ = sender.send(write(connection));
+            return Ok(());
+        })
+        .await?;
+
+        return pending.finish();
+    }
+
+    async fn load_order(&self, table_id: &TableId) -> Result<Option<TableOrder>, rusqlite::Error> {
+        let table_id = table_id.clone();
+
+        return self
+            .run(move |connection| {
+                // One read transaction so every query sees the same commit
+                let tx = connection.transaction()?;
+                return load_order(&tx, &table_id);
+            })
+            .await;
+    }
+
+    // Writes the whole order in one transaction, see save_order
+    async fn save_order<S>(&self, order: TableOrder, saved: S) -> Result<TableOrder, rusqlite::Error>
+    where
+        S: FnOnce(&TableOrder),
+    {
+        return self
+            .run_write(
+                move |connection| {
+                    let tx = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                    save_order(&tx, &order)?;
+                    tx.commit()?;
+                    return Ok(order);
+                },
+                saved,
+            )
+            .await;
+    }
+}
+
+fn open_connection(connection: Connection) -> Result<Connection, rusqlite::Error> {
+    connection.pragma_update(None, "foreign_keys", "ON")?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    return Ok(connection);
+}
+
+pub fn run_migrations(connection: &mut Connection) -> Result<(), rusqlite::Error> {
+    let current_version: i64 = connection.pragma_query_value(None, "user_version", |r| r.get(0))?;
+
+    let tx = connection.transaction()?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(current_version as usize) {
+        tracing::debug!("applying sqlite migration {}", index + 1);
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index as i64 + 1)?;
+    }
+
+    return tx.commit();
+}
+
+impl Persistence for SqlitePersistence {
+    async fn create_order<S>(&self, table_id: &TableId, guest_count: Option<u32>, items: &[TableOrderItem], saved: S) -> Result<TableOrder, CreateOrderError>
+    where
+        S: FnOnce(&TableOrder) + Send,
+    {
+        let _table_guard = self.table_locks.lock(table_id).await;
+        let new_record = TableOrder::new(table_id.clone(), items).with_guest_count(guest_count);
+
+        let record = new_record.clone();
+        let created = self
+            .run_write(
+                move |connection| {
+                    let tx = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                    if order_exists(&tx, &record.table_id)? {
+                        return Ok(false);
+                    }
+                    insert_order(&tx, &record)?;
+                    tx.commit()?;
+                    return Ok(true);
+                },
+                |created| {
+                    if *created {
+                        saved(&new_record);
+                    }
+                },
+            )
+            .await
+            .map_err(|e| CreateOrderError::Storage(e.to_string()))?;
+        if !created {
+            return Err(CreateOrderError::OrderAlreadyExistsForTable(table_id.to_string()));
+        }
+
+        return Ok(new_record);
+    }
+
+    async fn find_order(&self, table_id: &TableId) -> Result<TableOrder, ReadOrderError> {
+        return self
+            .load_order(table_id)
+            .await
+            .map_err(|e| ReadOrderError::Storage(e.to_string()))?
+            .ok_or_else(|| ReadOrderError::OrderNotFound(table_id.to_string()));
+    }
+
+    async fn list_orders(&self) -> Result<Vec<TableOrder>, ReadOrderError> {
+        return self
+            .run(|connection| {
+                let tx = connection.transaction()?;
+                return list_table_ids(&tx)?
+                    .iter()
+                    .filter_map(|id| load_order(&tx, id).transpose())
+                    .collect::<Result<Vec<TableOrder>, rusqlite::Error>>();
+            })
+            .await
+            .map_err(|e| ReadOrderError::Storage(e.to_string()));
+    }
+
+    async fn update_order<S>(&self, table_id: &TableId, new_items: &[TableOrderItem], menu: &Menu, max_items: usize, saved: S) -> Result<(TableOrder, Vec<TableOrderItem>), ModifyOrderError>
+    where
+        S: FnOnce(&TableOrder) + Send,
+    {
+        let _table_guard = self.table_locks.lock(table_id).await;
+
+        // Loaded rather than just checked for, the new items are diffed against the current ones
+        let mut updated_record = self
+            .load_order(table_id)
+            .await
+            .map_err(|e| ModifyOrderError::Storage(e.to_string()))?
+            .ok_or_else(|| ModifyOrderError::OrderNotFound(table_id.to_string()))?;
+        let removed = updated_record.replace_items(new_items, menu, max_items)?;
+
+        let updated_record = self.save_order(updated_record, saved).await.map_err(|e| ModifyOrderError::Storage(e.to_string()))?;
+
+        return Ok((updated_record, removed));
+    }
+
+    async fn delete_order<F, S>(&self, table_id: &TableId, closed_at: DateTime<Utc>, check: F, saved: S) -> Result<Option<Settlement>, ModifyOrderError>
+    where
+        F: FnOnce(&TableOrder) -> Result<(), OrderChangeError> + Send,
+        S: FnOnce(&TableOrder, Option<&Settlement>) + Send,
+    {
+        let _table_guard = self.table_locks.lock(table_id).await;
+
+        let order = self
+            .load_order(table_id)
+            .await
+            .map_err(|e| ModifyOrderError::Storage(e.to_string()))?
+            .ok_or_else(|| ModifyOrderError::OrderNotFound(table_id.to_string()))?;
+        check(&order)?;
+
+        // The settlement is copied out in the same transaction as the delete, which takes the order's own bills and payments with it
+        let (_, settlement) = self
+            .run_write(
+                move |connection| {
+                    let tx = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                    let settlement = if order.has_billing() { Some(settle_order(&tx, &order, closed_at)?) } else { None };
+                    tx.execute("DELETE FROM table_orders WHERE table_id = ?1", params![order.table_id.0])?;
+                    tx.commit()?;
+                    return Ok((order, settlement));
+                },
+                |(order, settlement)| saved(order, settlement.as_ref()),
+            )
+            .await
+            .map_err(|e| ModifyOrderError::Storage(e.to_string()))?;
+
+        return Ok(settlement);
+    }
+
+    async fn list_settlements(&self, table_id: &TableId) -> Result<Vec<Settlement>, ReadOrderError> {
+        let table_id = table_id.clone();
+
+        return self
+            .run(move |connection| {
+                let tx = connection.transaction()?;
+                return load_settlements(&tx, &table_id);
+            })
+            .await
+            .map_err(|e| ReadOrderError::Storage(e.to_string()));
+    }
+
+    async fn update_order_with<F, S>(&self, table_id: &TableId, change: F, saved: S) -> Result<TableOrder, ModifyOrderError>
+    where
+        F: FnOnce(&mut TableOrder) -> Result<(), OrderChangeError> + Send,
+        S: FnOnce(&TableOrder) + Send,
+    {
+        let _table_guard = self.table_locks.lock(table_id).await;
+
+        let mut order = self
+            .load_order(table_id)
+            .await
+            .map_err(|e| ModifyOrderError::Storage(e.to_string()))?
+            .ok_or_else(|| ModifyOrderError::OrderNotFound(table_id.to_string()))?;
+
+        // Nothing has been written yet, so a rejected change saves nothing
+        change(&mut order)?;
+
+        let order = self.save_order(order, saved).await.map_err(|e| ModifyOrderError::Storage(e.to_string()))?;
+
+        return Ok(order);
+    }
+}
+
+impl ToSql for OrderItemStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        return Ok(ToSqlOutput::from(self.as_str()));
+    }
+}
+
+impl FromSql for OrderItemStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        return value.as_str()?.parse().map_err(|e| FromSqlError::Other(Box::new(e)));
+    }
+}
+
+impl ToSql for Currency {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        return Ok(ToSqlOutput::from(self.code()));
+    }
+}
+
+impl FromSql for Currency {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        return value.as_str()?.parse().map_err(|e| FromSqlError::Other(Box::new(e)));
+    }
+}
+
+impl ToSql for PaymentMethod {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        return Ok(ToSqlOutput::from(self.as_str()));
+    }
+}
+
+impl FromSql for PaymentMethod {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        return value.as_str()?.parse().map_err(|e| FromSqlError::Other(Box::new(e)));
+    }
+}
+
+impl ToSql for RefundStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        return Ok(ToSqlOutput::from(self.as_str()));
+    }
+}
+
+impl FromSql for RefundStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        return value.as_str()?.parse().map_err(|e| FromSqlError::Other(Box::new(e)));
+    }
+}
+
+impl ToSql for TaxMode {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        return Ok(ToSqlOutput::from(self.as_str()));
+    }
+}
+
+impl FromSql for TaxMode {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        return value.as_str()?.parse().map_err(|e| FromSqlError::Other(Box::new(e)));
+    }
+}
+
+impl ToSql for TaxRounding {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        return Ok(ToSqlOutput::from(self.as_str()));
+    }
+}
+
+impl FromSql for TaxRounding {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        return value.as_str()?.parse().map_err(|e| FromSqlError::Other(Box::new(e)));
+    }
+}
+
+fn order_exists(connection: &Connection, table_id: &TableId) -> Result<bool, rusqlite::Error> {
+    return connection
+        .query_row("SELECT 1 FROM table_orders WHERE table_id = ?1", params![table_id.0], |_| Ok(()))
+        .optional()
+        .map(|r| r.is_some());
+}
+
+fn list_table_ids(connection: &Connection) -> Result<Vec<TableId>, rusqlite::Error> {
+    return connection
+        .prepare("SELECT table_id FROM table_orders")?
+        .query_map([], |row| row.get(0).map(TableId))?
+        .collect::<Result<Vec<TableId>, rusqlite::Error>>();
+}
+
+fn insert_order(tx: &Transaction, order: &TableOrder) -> Result<(), rusqlite::Error> {
+    tx.execute("INSERT INTO table_orders (table_id, next_line_id, guest_count) VALUES (?1, ?2, ?3)", params![order.table_id.0, order.next_line_id.0, order.guest_count])?;
+    insert_order_items(tx, order)?;
+    insert_new_bills(tx, order)?;
+    return insert_new_payments(tx, order);
+}
+
+// Overwrites an existing order's lines with the ones in `order`
+fn save_order(tx: &Transaction, order: &TableOrder) -> Result<(), rusqlite::Error> {
+    tx.execute("UPDATE table_orders SET next_line_id = ?2, guest_count = ?3 WHERE table_id = ?1", params![order.table_id.0, order.next_line_id.0, order.guest_count])?;
+    tx.execute("DELETE FROM table_order_lines WHERE table_id = ?1", params![order.table_id.0])?;
+    insert_order_items(tx, order)?;
+    insert_new_bills(tx, order)?;
+    return insert_new_payments(tx, order);
+}
+
+fn insert_order_items(tx: &Transaction, order: &TableOrder) -> Result<(), rusqlite::Error> {
+    let mut statement = tx.prepare(
+        "INSERT INTO table_order_lines (table_id, line_id, item_id, quantity, notes, seat, total_preparation_time_mins, ordered_at, ready_at, status)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+    )?;
+    let mut change_statement = tx.prepare("INSERT INTO table_order_line_status_changes (table_id, line_id, seq, status, at) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+    let mut modifier_statement = tx.prepare("INSERT INTO table_order_line_modifiers (table_id, line_id, seq, modifier_id) VALUES (?1, ?2, ?3, ?4)")?;
+    for item in order.items.values() {
+        statement.execute(params![
+            order.table_id.0,
+            item.line_id.0,
+            item.item_id.0,
+            item.quantity,
+            item.notes,
+            item.seat,
+            item.total_preparation_time_mins,
+            item.ordered_at,
+            item.ready_at,
+            item.status
+        ])?;
+        for (seq, change) in item.status_history.iter().enumerate() {
+            change_statement.execute(params![order.table_id.0, item.line_id.0, seq as i64, change.status, change.at])?;
+        }
+        for (seq, modifier) in item.modifiers.iter().enumerate() {
+            modifier_statement.execute(params![order.table_id.0, item.line_id.0, seq as i64, modifier.0])?;
+        }
+    }
+
+    return Ok(());
+}
+
+// Bills never change once issued, so only the ones not saved yet are written
+fn insert_new_bills(tx: &Transaction, order: &TableOrder) -> Result<(), rusqlite::Error> {
+    let mut bill_statement = tx.prepare(
+        "INSERT OR IGNORE INTO table_order_bills (table_id, bill_id, issued_at, guests, currency, tax_mode, tax_rounding, subtotal, service_charge_rate, service_charge, tax_total, total)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+    )?;
+    let mut line_statement = tx.prepare(
+        "INSERT INTO table_order_bill_lines (table_id, bill_id, seq, line_id, item_id, name, category, seat, quantity, unit_price, amount, tax_rate, tax)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+    )?;
+    let mut tax_statement = tx.prepare("INSERT INTO table_order_bill_taxes (table_id, bill_id, seq, rate, taxable, tax) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+    for bill in &order.bills {
+        let inserted = bill_statement.execute(params![
+            order.table_id.0,
+            bill.bill_id.0,
+            bill.issued_at,
+            bill.guests,
+            bill.currency,
+            bill.tax_mode,
+            bill.tax_rounding,
+            bill.subtotal.minor_units,
+            bill.service_charge.map(|s| s.rate.0),
+            bill.service_charge.map(|s| s.amount.minor_units),
+            bill.tax_total.minor_units,
+            bill.total.minor_units
+        ])?;
+        if inserted == 0 {
+            continue;
+        }
+
+        for (seq, line) in bill.lines.iter().enumerate() {
+            line_statement.execute(params![
+                order.table_id.0,
+                bill.bill_id.0,
+                seq as i64,
+                line.line_id.0,
+                line.item_id.0,
+                line.name,
+                line.category,
+                line.seat,
+                line.quantity,
+                line.unit_price.minor_units,
+                line.amount.minor_units,
+                line.tax_rate.0,
+                line.tax.map(|t| t.minor_units)
+            ])?;
+        }
+        for (seq, tax) in bill.taxes.iter().enumerate() {
+            tax_statement.execute(params![order.table_id.0, bill.bill_id.0, seq as i64, tax.rate.0, tax.taxable.minor_units, tax.tax.minor_units])?;
+        }
+    }
+
+    return Ok(());
+}
+
+// Payments are never changed and refunds are only ever added, so like bills only what isn't saved yet is written.
+// The one exception is a pending refund, which gets its status and the gateway's reference once the gateway has answered.
+fn insert_new_payments(tx: &Transaction, order: &TableOrder) -> Result<(), rusqlite::Error> {
+    let mut payment_statement = tx.prepare(
+        "INSERT OR IGNORE INTO table_order_payments (table_id, payment_id, bill_id, method, currency, amount, gateway_reference, paid_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?;
+    let mut refund_statement = tx.prepare(
+        "INSERT INTO table_order_payment_refunds (table_id, payment_id, seq, amount, gateway_reference, refunded_at, status) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            ON CONFLICT (table_id, payment_id, seq) DO UPDATE SET gateway_reference = excluded.gateway_reference, status = excluded.status WHERE status = 'pending'",
+    )?;
+    for payment in &order.payments {
+        payment_statement.execute(params![
+            order.table_id.0,
+            payment.payment_id.0,
+            payment.bill_id.0,
+            payment.method,
+            payment.amount.currency,
+            payment.amount.minor_units,
+            payment.gateway_reference,
+            payment.paid_at
+        ])?;
+        for (seq, refund) in payment.refunds.iter().enumerate() {
+            refund_statement.execute(params![order.table_id.0, payment.payment_id.0, seq as i64, refund.amount.minor_units, refund.gateway_reference, refund.refunded_at, refund.status])?;
+        }
+    }
+
+    return Ok(());
+}
+
+// Copies the order's bills and payments, with their lines, taxes and refunds, to the settled tables under a new settlement
+fn settle_order(tx: &Transaction, order: &TableOrder, closed_at: DateTime<Utc>) -> Result<Settlement, rusqlite::Error> {
+    tx.execute("INSERT INTO settlements (table_id, closed_at) VALUES (?1, ?2)", params![order.table_id.0, closed_at])?;
+    let settlement_id = tx.last_insert_rowid();
+
+    let copies = [
+        "INSERT INTO settled_bills (settlement_id, bill_id, issued_at, guests, currency, tax_mode, tax_rounding, subtotal, service_charge_rate, service_charge, tax_total, total)
+            SELECT ?1, bill_id, issued_at, guests, currency, tax_mode, tax_rounding, subtotal, service_charge_rate, service_charge, tax_total, total FROM table_order_bills WHERE table_id = ?2",
+        "INSERT INTO settled_bill_lines (settlement_id, bill_id, seq, line_id, item_id, name, category, seat, quantity, unit_price, amount, tax_rate, tax)
+            SELECT ?1, bill_id, seq, line_id, item_id, name, category, seat, quantity, unit_price, amount, tax_rate, tax FROM table_order_bill_lines WHERE table_id = ?2",
+        "INSERT INTO settled_bill_taxes (settlement_id, bill_id, seq, rate, taxable, tax) SELECT ?1, bill_id, seq, rate, taxable, tax FROM table_order_bill_taxes WHERE table_id = ?2",
+        "INSERT INTO settled_payments (settlement_id, payment_id, bill_id, method, currency, amount, gateway_reference, paid_at)
+            SELECT ?1, payment_id, bill_id, method, currency, amount, gateway_reference, paid_at FROM table_order_payments WHERE table_id = ?2",
+        "INSERT INTO settled_payment_refunds (settlement_id, payment_id, seq, amount, gateway_reference, refunded_at, status)
+            SELECT ?1, payment_id, seq, amount, gateway_reference, refunded_at, status FROM table_order_payment_refunds WHERE table_id = ?2",
+    ];
+    for copy in copies {
+        tx.execute(copy, params![settlement_id, order.table_id.0])?;
+    }
+
+    return Ok(Settlement::new(SettlementId(settlement_id), order, closed_at));
+}
+
+fn load_settlements(connection: &Connection, table_id: &TableId) -> Result<Vec<Settlement>, rusqlite::Error> {
+    let mut statement = connection.prepare("SELECT settlement_id, closed_at FROM settlements WHERE table_id = ?1 ORDER BY settlement_id")?;
+    let settlements = statement
+        .query_map(params![table_id.0], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, DateTime<Utc>>(1)?)))?
+        .collect::<Result<Vec<(i64, DateTime<Utc>)>, rusqlite::Error>>()?;
+
+    return settlements
+        .into_iter()
+        .map(|(settlement_id, closed_at)| {
+            return Ok(Settlement {
+                settlement_id: SettlementId(settlement_id),
+                table_id: table_id.clone(),
+                closed_at: closed_at,
+                bills: load_bills(connection, &SETTLED_BILLING, settlement_id)?,
+                payments: load_payments(connection, &SETTLED_BILLING, settlement_id)?,
+            });
+        })
+        .collect();
+}
+
+fn load_payments(connection: &Connection, tables: &BillingTables, key: i64) -> Result<Vec<Payment>, rusqlite::Error> {
+    let mut payment_statement =
+        connection.prepare(&format!("SELECT payment_id, bill_id, method, currency, amount, gateway_reference, paid_at FROM {} WHERE {} = ?1 ORDER BY payment_id", tables.payments, tables.key))?;
+    let mut payments = payment_statement
+        .query_map(params![key], |row| {
+            return Ok(Payment {
+                payment_id: PaymentId(row.get(0)?),
+                bill_id: BillId(row.get(1)?),
+                method: row.get(2)?,
+                amount: Money::new(row.get(4)?, row.get(3)?),
+                gateway_reference: row.get(5)?,
+                paid_at: row.get(6)?,
+                refunds: vec![],
+            });
+        })?
+        .collect::<Result<Vec<Payment>, rusqlite::Error>>()?;
+
+    let mut refund_statement = connection.prepare(&format!(
+        "SELECT r.payment_id, p.currency, r.amount, r.gateway_reference, r.refunded_at, r.status
+            FROM {} r JOIN {} p USING ({key}, payment_id) WHERE r.{key} = ?1 ORDER BY r.payment_id, r.seq",
+        tables.payment_refunds,
+        tables.payments,
+        key = tables.key
+    ))?;
+    let refunds = refund_statement.query_map(params![key], |row| {
+        return Ok((PaymentId(row.get(0)?), Refund { amount: Money::new(row.get(2)?, row.get(1)?), gateway_reference: row.get(3)?, refunded_at: row.get(4)?, status: row.get(5)? }));
+    })?;
+    for refund in refunds {
+        let (payment_id, refund) = refund?;
+        if let Some(payment) = payments.iter_mut().find(|p| p.payment_id == payment_id) {
+            payment.refunds.push(refund);
+        }
+    }
+
+    return Ok(payments);
+}
+
+fn load_bills(connection: &Connection, tables: &BillingTables, key: i64) -> Result<Vec<Bill>, rusqlite::Error> {
+    let mut bill_statement = connection.prepare(&format!(
+        "SELECT bill_id, issued_at, guests, currency, tax_mode, tax_rounding, subtotal, service_charge_rate, service_charge, tax_total, total
+            FROM {} WHERE {} = ?1 ORDER BY bill_id",
+        tables.bills, tables.key
+    ))?;
+    let mut bills = bill_statement
+        .query_map(params![key], |row| {
+            let currency: Currency = row.get(3)?;
+            let service_charge_rate: Option<i64> = row.get(7)?;
+            let service_charge: Option<i64> = row.get(8)?;
+            return Ok(Bill {
+                bill_id: BillId(row.get(0)?),
+                issued_at: row.get(1)?,
+                guests: row.get(2)?,
+                currency: currency,
+                tax_mode: row.get(4)?,
+                tax_rounding: row.get(5)?,
+                lines: vec![],
+                taxes: vec![],
+                subtotal: Money::new(row.get(6)?, currency),
+                service_charge: service_charge_rate
+                    .zip(service_charge)
+                    .map(|(rate, amount)| BillServiceCharge { rate: Percentage(rate), amount: Money::new(amount, currency) }),
+                tax_total: Money::new(row.get(9)?, currency),
+                total: Money::new(row.get(10)?, currency),
+            });
+        })?
+        .collect::<Result<Vec<Bill>, rusqlite::Error>>()?;
+
+    let mut line_statement = connection.prepare(&format!(
+        "SELECT l.bill_id, b.currency, l.line_id, l.item_id, l.name, l.category, l.quantity, l.unit_price, l.amount, l.tax_rate, l.tax, l.seat
+            FROM {} l JOIN {} b USING ({key}, bill_id) WHERE l.{key} = ?1 ORDER BY l.bill_id, l.seq",
+        tables.bill_lines,
+        tables.bills,
+        key = tables.key
+    ))?;
+    let lines = line_statement.query_map(params![key], |row| {
+        let currency: Currency = row.get(1)?;
+        let tax: Option<i64> = row.get(10)?;
+        return Ok((
+            BillId(row.get(0)?),
+            BillLine {
+                line_id: LineId(row.get(2)?),
+                item_id: MenuItemId(row.get(3)?),
+                name: row.get(4)?,
+                category: row.get(5)?,
+                seat: row.get(11)?,
+                quantity: row.get(6)?,
+                unit_price: Money::new(row.get(7)?, currency),
+                amount: Money::new(row.get(8)?, currency),
+                tax_rate: Percentage(row.get(9)?),
+                tax: tax.map(|t| Money::new(t, currency)),
+            },
+        ));
+    })?;
+    for line in lines {
+        let (bill_id, line) = line?;
+        if let Some(bill) = bills.iter_mut().find(|b| b.bill_id == bill_id) {
+            bill.lines.push(line);
+        }
+    }
+
+    let mut tax_statement = connection.prepare(&format!(
+        "SELECT t.bill_id, b.currency, t.rate, t.taxable, t.tax
+            FROM {} t JOIN {} b USING ({key}, bill_id) WHERE t.{key} = ?1 ORDER BY t.bill_id, t.seq",
+        tables.bill_taxes,
+        tables.bills,
+        key = tables.key
+    ))?;
+    let taxes = tax_statement.query_map(params![key], |row| {
+        let currency: Currency = row.get(1)?;
+        return Ok((BillId(row.get(0)?), BillTax { rate: Percentage(row.get(2)?), taxable: Money::new(row.get(3)?, currency), tax: Money::new(row.get(4)?, currency) }));
+    })?;
+    for tax in taxes {
+        let (bill_id, tax) = tax?;
+        if let Some(bill) = bills.iter_mut().find(|b| b.bill_id == bill_id) {
+            bill.taxes.push(tax);
+        }
+    }
+
+    return Ok(bills);
+}
+
+fn load_order(connection: &Connection, table_id: &TableId) -> Result<Option<TableOrder>, rusqlite::Error> {
+    let header = connection
+        .query_row("SELECT next_line_id, guest_count FROM table_orders WHERE table_id = ?1", params![table_id.0], |row| {
+            return Ok((LineId(row.get(0)?), row.get(1)?));
+        })
+        .optional()?;
+    let Some((next_line_id, guest_count)) = header else {
+        return Ok(None);
+    };
+
+    let mut statement = connection.prepare("SELECT line_id, item_id, quantity, notes, total_preparation_time_mins, ordered_at, ready_at, status, seat FROM table_order_lines WHERE table_id = ?1")?;
+    let items = statement
+        .query_map(params![table_id.0], |row| {
+            return Ok(TableOrderItem {
+                line_id: LineId(row.get(0)?),
+                item_id: MenuItemId(row.get(1)?),
+                quantity: row.get(2)?,
+                notes: row.get(3)?,
+                modifiers: vec![],
+                seat: row.get(8)?,
+                total_preparation_time_mins: row.get(4)?,
+                ordered_at: row.get(5)?,
+                ready_at: row.get(6)?,
+                status: row.get(7)?,
+                status_history: vec![],
+            });
+        })?
+        .map(|item| item.map(|i| (i.line_id, i)))
+        .collect::<Result<BTreeMap<LineId, TableOrderItem>, rusqlite::Error>>()?;
+    let mut order = TableOrder {
+        table_id: table_id.clone(),
+        items: items,
+        next_line_id: next_line_id,
+        guest_count: guest_count,
+        bills: load_bills(connection, &ORDER_BILLING, table_id.0 as i64)?,
+        payments: load_payments(connection, &ORDER_BILLING, table_id.0 as i64)?,
+    };
+
+    let mut change_statement = connection.prepare("SELECT line_id, status, at FROM table_order_line_status_changes WHERE table_id = ?1 ORDER BY line_id, seq")?;
+    let changes = change_statement.query_map(params![table_id.0], |row| {
+        return Ok((LineId(row.get(0)?), OrderItemStatusChange { status: row.get(1)?, at: row.get(2)? }));
+    })?;
+    for change in changes {
+        let (line_id, change) = change?;
+        if let Some(item) = order.items.get_mut(&line_id) {
+            item.status_history.push(change);
+        }
+    }
+
+    let mut modifier_statement = connection.prepare("SELECT line_id, modifier_id FROM table_order_line_modifiers WHERE table_id = ?1 ORDER BY line_id, seq")?;
+    let modifiers = modifier_statement.query_map(params![table_id.0], |row| {
+        return Ok((LineId(row.get(0)?), ModifierId(row.get(1)?)));
+    })?;
+    for modifier in modifiers {
+        let (line_id, modifier) = modifier?;
+        if let Some(item) = order.items.get_mut(&line_id) {
+            item.modifiers.push(modifier);
+        }
+    }
+
+    return Ok(Some(order));
+}
+
+#[cfg(test)]
+pub fn get_underlying_data(sqlite_persistence: SqlitePersistence) -> HashMap<TableId, TableOrder> {
+    // Nothing is running, so every connection is idle
+    let connection = sqlite_persistence.pool.idle.lock().unwrap().pop().unwrap();
+
+    return list_table_ids(&connection)
+        .unwrap()
+        .into_iter()
+        .map(|id| (id.clone(), load_order(&connection, &id).unwrap().unwrap()))
+        .collect();
+}
+
+#[cfg(test)]
+pub async fn lock_table<'a>(sqlite_persistence: &'a SqlitePersistence, table_id: &TableId) -> TableGuard<'a> {
+    return sqlite_persistence.table_locks.lock(table_id).await;
+}
@@ -1,14 +1,68 @@
 use std::sync::Arc;
 
-use tokio::sync::RwLock;
+use crate::{
+    clock::{Clock, SystemClock},
+    events::EventBus,
+    models::{kitchen::KitchenConfig, menu::Menu},
+    payments::{PaymentGateway, UnconfiguredGateway},
+    webhooks::{RetryPolicy, WebhookHostAllowList, WebhookRegistry},
+};
 
-use crate::persistence::memory_persistence::MemoryPersistence;
+// No lock around the whole state, the persistence is expected to handle concurrent access itself (see MemoryPersistence)
+pub type SharedAppState<P> = Arc<AppState<P>>;
 
-// This ultimately means the whole hashmap is locked during writes, even for readers wanting to read unrelated keys
-// For this demo it's probably not worth, and perhaps a real restaurant might be OK with this too.
-pub type SharedAppState = Arc<RwLock<AppState>>;
+// Generic rather than Box<dyn Persistence>, since the trait's async methods make it not object safe
+pub struct AppState<P> {
+    pub persistence: P,
+    pub menu: Menu,
+    pub clock: Arc<dyn Clock>,
+    pub kitchen: KitchenConfig,
+    pub events: EventBus,
+    pub webhooks: WebhookRegistry,
+    pub webhook_retry: RetryPolicy,
+    pub webhook_hosts: WebhookHostAllowList,      // non-public hosts webhooks may still be sent to
+    pub payment_gateway: Arc<dyn PaymentGateway>, // refuses every card unless one is configured, see with_payment_gateway
+}
+
+impl<P> AppState<P> {
+    pub fn new(persistence: P, menu: Menu) -> Self {
+        return Self {
+            persistence: persistence,
+            menu: menu,
+            clock: Arc::new(SystemClock),
+            kitchen: KitchenConfig::default(),
+            events: EventBus::default(),
+            webhooks: WebhookRegistry::default(),
+            webhook_retry: RetryPolicy::default(),
+            webhook_hosts: WebhookHostAllowList::default(),
+            payment_gateway: Arc::new(UnconfiguredGateway),
+        };
+    }
+
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        return self;
+    }
+
+    #[cfg(test)]
+    pub fn with_webhook_retry(mut self, webhook_retry: RetryPolicy) -> Self {
+        self.webhook_retry = webhook_retry;
+        return self;
+    }
+
+    pub fn with_kitchen(mut self, kitchen: KitchenConfig) -> Self {
+        self.kitchen = kitchen;
+        return self;
+    }
+
+    pub fn with_payment_gateway(mut self, payment_gateway: Arc<dyn PaymentGateway>) -> Self {
+        self.payment_gateway = payment_gateway;
+        return self;
+    }
 
-// For simplicitly i'm not going to try and unravel async traits and Box<dyn Persistence>
-pub struct AppState {
-    pub persistence: MemoryPersistence,
+    pub fn with_webhook_hosts(mut self, webhook_hosts: WebhookHostAllowList) -> Self {
+        self.webhook_hosts = webhook_hosts;
+        return self;
+    }
 }
@@ -1,54 +1,2183 @@
 #[allow(non_snake_case)]
 #[cfg(test)]
+mod tests {
+    use crate::{
+        api::v0::client_params::{MAX_ITEMS_PER_ORDER, MAX_NOTES_CHARS, MAX_REQUEST_BODY_BYTES},
+        api::v0::problem_details::{ProblemDetails, PROBLEM_JSON_CONTENT_TYPE},
+        api::v0::view_models::{
+            BillSplitViewModel, BillViewModel, KdsViewModel, KitchenQueueViewModel, MenuItemViewModel, MenuViewModel, MoneyViewModel, PaymentsViewModel, SettlementsViewModel, StationTicketsViewModel,
+            TableOrderItemDetailViewModel, TableOrderItemSummaryViewModel, TableOrderViewModel, UpdatedTableOrderViewModel,
+        },
+        app::{create_app, create_app_with_state},
+        clock::ManualClock,
+        models::{
+            billing::{BillingConfig, ServiceChargeRule, TaxMode, TaxRounding},
+            kitchen::{KitchenConfig, Station},
+            menu::{Menu, MenuItemId, MenuModifier, ModifierId},
+            money::{Currency, Money, Percentage},
+            orders::{LineId, OrderChangeError, OrderItemStatus, TableId, TableOrder, TableOrderItem},
+            payments::Settlement,
+        },
+        payments::{FakeGateway, PaymentGateway, PaymentGatewayError, FAKE_DECLINED_CARD_TOKEN},
+        persistence::{
+            memory_persistence::MemoryPersistence,
+            persistence::{CreateOrderError, ModifyOrderError, Persistence, ReadOrderError},
+            sqlite_persistence::SqlitePersistence,
+        },
+        state::AppState,
+        tests::fixtures::menu_item,
+    };
+
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use axum::{
+        body::Body,
+        http::{self, Request, Response, StatusCode},
+    };
+    use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+    use futures_util::future::BoxFuture;
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use tower::{Service, ServiceExt};
+
+    async fn assert_problem_response(response: Response<Body>, expected_status: StatusCode, expected_code: &str, expected_detail: &str) -> ProblemDetails {
+        assert_eq!(expected_status, response.status());
+        assert_eq!(PROBLEM_JSON_CONTENT_TYPE, response.headers().get(http::header::CONTENT_TYPE).unwrap());
+
+        let problem: ProblemDetails = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(expected_status.as_u16(), problem.status);
+        assert_eq!(expected_code, problem.code);
+        assert_eq!(format!("urn:restaurant:problem:{}", expected_code), problem.problem_type);
+        assert_eq!(expected_detail, problem.detail);
+        return problem;
+    }
+
+    fn get_assertable_items_sorted(items: &[TableOrderItemSummaryViewModel]) -> Vec<(String, String, i32)> {
+        let mut result = items
+            .iter()
+            .map(|i| (i.item_id.clone(), i.name.clone(), i.quantity))
+            .collect::<Vec<(String, String, i32)>>();
+        result.sort();
+        return result;
+    }
+
+    fn json_request(method: http::Method, uri: &str, body: &Value) -> Request<Body> {
+        return Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(serde_json::to_string(body).unwrap()))
+            .unwrap();
+    }
+
+    async fn get_body_json(response: Response<Body>) -> Value {
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        return serde_json::from_slice(&body).unwrap();
+    }
+
+    // Items 1-4 are cooked on the grill, 5 at the bar. Item n costs n x 2.50 EUR.
+    fn create_test_modifiers() -> Vec<MenuModifier> {
+        return vec![
+            MenuModifier { id: ModifierId("no_onions".to_string()), name: "No onions".to_string(), group: None, price: None },
+            MenuModifier { id: ModifierId("rare".to_string()), name: "Rare".to_string(), group: Some("temperature".to_string()), price: None },
+            MenuModifier { id: ModifierId("well_done".to_string()), name: "Well done".to_string(), group: Some("temperature".to_string()), price: None },
+            MenuModifier { id: ModifierId("extra_cheese".to_string()), name: "Extra cheese".to_string(), group: None, price: Some(Money::new(75, Currency::Eur)) },
+        ];
+    }
+
+    fn create_test_menu() -> Menu {
+        let items = (1..=5)
+            .map(|i| {
+                menu_item(i, &format!("menu item {}", i))
+                    .with_description(&format!("menu item desc {}", i))
+                    .with_price(Money::new(i as i64 * 250, Currency::Eur))
+                    .with_station(if i == 5 { Station::Bar } else { Station::Grill })
+                    .with_prep_time(10, 2)
+                    .with_modifiers(if i == 1 { create_test_modifiers() } else { vec![] })
+            })
+            .collect();
+        return Menu::new(items).unwrap();
+    }
+
+    // Test double standing in for a backend whose storage is unavailable
+    #[derive(Debug)]
+    struct UnavailablePersistence;
+
+    impl Persistence for UnavailablePersistence {
+        async fn create_order<S>(&self, _table_id: &TableId, _guest_count: Option<u32>, _items: &[TableOrderItem], _saved: S) -> Result<TableOrder, CreateOrderError>
+        where
+            S: FnOnce(&TableOrder) + Send,
+        {
+            return Err(CreateOrderError::Storage("unavailable".to_string()));
+        }
+
+        async fn find_order(&self, _table_id: &TableId) -> Result<TableOrder, ReadOrderError> {
+            return Err(ReadOrderError::Storage("unavailable".to_string()));
+        }
+
+        async fn list_orders(&self) -> Result<Vec<TableOrder>, ReadOrderError> {
+            return Err(ReadOrderError::Storage("unavailable".to_string()));
+        }
+
+        async fn update_order<S>(&self, _table_id: &TableId, _new_items: &[TableOrderItem], _menu: &Menu, _max_items: usize, _saved: S) -> Result<(TableOrder, Vec<TableOrderItem>), ModifyOrderError>
+        where
+            S: FnOnce(&TableOrder) + Send,
+        {
+            return Err(ModifyOrderError::Storage("unavailable".to_string()));
+        }
+
+        async fn delete_order<F, S>(&self, _table_id: &TableId, _closed_at: DateTime<Utc>, _check: F, _saved: S) -> Result<Option<Settlement>, ModifyOrderError>
+        where
+            F: FnOnce(&TableOrder) -> Result<(), OrderChangeError> + Send,
+            S: FnOnce(&TableOrder, Option<&Settlement>) + Send,
+        {
+            return Err(ModifyOrderError::Storage("unavailable".to_string()));
+        }
+
+        async fn list_settlements(&self, _table_id: &TableId) -> Result<Vec<Settlement>, ReadOrderError> {
+            return Err(ReadOrderError::Storage("unavailable".to_string()));
+        }
+
+        async fn update_order_with<F, S>(&self, _table_id: &TableId, _change: F, _saved: S) -> Result<TableOrder, ModifyOrderError>
+        where
+            F: FnOnce(&mut TableOrder) -> Result<(), OrderChangeError> + Send,
+            S: FnOnce(&TableOrder) + Send,
+        {
+            return Err(ModifyOrderError::Storage("unavailable".to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn get_order__persistence_unavailable__is_500() {
+        let sut = create_app(UnavailablePersistence, create_test_menu());
+
+        let response = sut.oneshot(Request::builder().uri("/v0/orders/123").body(Body::empty()).unwrap()).await.unwrap();
+
+        assert_problem_response(response, StatusCode::INTERNAL_SERVER_ERROR, "storage_error", "The order could not be read or saved, try again later.").await;
+    }
+
+    #[tokio::test]
+    async fn create_order__sqlite_persistence__can_be_read_back() {
+        let mut sut = create_app(SqlitePersistence::open_in_memory().unwrap(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 2 }] });
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/v0/orders/123")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(serde_json::to_string(&body).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().method(http::Method::GET).uri("/v0/orders/123").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        let response_order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(vec![("1".to_string(), "menu item 1".to_string(), 2)], get_assertable_items_sorted(&response_order.items));
+    }
+
+    #[tokio::test]
+    async fn get_menu__lists_all_items() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+
+        let response = sut.oneshot(Request::builder().uri("/v0/menu").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        let response_menu: MenuViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(vec!["1", "2", "3", "4", "5"], response_menu.items.iter().map(|i| i.item_id.as_str()).collect::<Vec<&str>>());
+    }
+
+    #[tokio::test]
+    async fn get_menu_item__item_exists__returns_item() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+
+        let response = sut.oneshot(Request::builder().uri("/v0/menu/2").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        let response_item: MenuItemViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!("2", response_item.item_id);
+        assert_eq!("menu item 2", response_item.name);
+        assert_eq!("menu item desc 2", response_item.description);
+        assert_eq!("test", response_item.category);
+        assert_eq!(MoneyViewModel { amount: "5.00".to_string(), minor_units: 500, currency: "EUR".to_string() }, response_item.price);
+        assert_eq!(10, response_item.prep_time_mins);
+    }
+
+    #[tokio::test]
+    async fn get_menu_item__item_does_not_exist__is_404() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+
+        let response = sut.oneshot(Request::builder().uri("/v0/menu/999").body(Body::empty()).unwrap()).await.unwrap();
+
+        assert_problem_response(response, StatusCode::NOT_FOUND, "menu_item_not_found", "Menu item id 999 not found.").await;
+    }
+
+    #[tokio::test]
+    async fn create_order__unknown_menu_item__is_422() {
+        let mut sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }, { "item_id": "999", "qty": 1 }] });
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/v0/orders/123")
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(serde_json::to_string(&body).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "unknown_menu_item", "Menu item id 999 does not exist.").await;
+
+        // Nothing was created
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().method(http::Method::GET).uri("/v0/orders/123").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+    }
+
+    #[tokio::test]
+    async fn get_order__non_numeric_table_id__is_400() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+
+        let response = sut.oneshot(Request::builder().uri("/v0/orders/abc").body(Body::empty()).unwrap()).await.unwrap();
+
+        let problem = assert_problem_response(response, StatusCode::BAD_REQUEST, "invalid_table_id", "Table id 'abc' is not a valid number.").await;
+        assert_eq!(Some("abc".to_string()), problem.table_id);
+    }
+
+    #[tokio::test]
+    async fn get_order_item__non_numeric_line_id__is_400() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+
+        let response = sut
+            .oneshot(Request::builder().uri("/v0/orders/123/items/abc").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let problem = assert_problem_response(response, StatusCode::BAD_REQUEST, "invalid_line_id", "Line id 'abc' is not a valid number.").await;
+        assert_eq!(Some("abc".to_string()), problem.line_id);
+    }
+
+    #[tokio::test]
+    async fn create_order__preparation_time__comes_from_menu() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }, { "item_id": "2", "qty": 3 }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123", &body)).await.unwrap();
+
+        let response_order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        let mut prep_times = response_order
+            .items
+            .iter()
+            .map(|i| (i.item_id.clone(), i.total_preparation_time_mins))
+            .collect::<Vec<(String, i32)>>();
+        prep_times.sort();
+        assert_eq!(vec![("1".to_string(), 10), ("2".to_string(), 14)], prep_times);
+    }
+
+    #[tokio::test]
+    async fn create_order__non_numeric_item_id__is_400() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "one", "qty": 1 }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123", &body)).await.unwrap();
+
+        assert_problem_response(response, StatusCode::BAD_REQUEST, "invalid_item_id", "Item id 'one' is not a valid number.").await;
+    }
+
+    #[tokio::test]
+    async fn create_order__zero_quantity__is_422() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }, { "item_id": "2", "qty": 0 }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123", &body)).await.unwrap();
+
+        let problem = assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "invalid_quantity", "Quantity 0 for item id 2 must be between 1 and 100.").await;
+        assert_eq!(Some("2".to_string()), problem.item_id);
+    }
+
+    #[tokio::test]
+    async fn create_order__negative_quantity__is_422() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": -3 }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123", &body)).await.unwrap();
+
+        assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "invalid_quantity", "Quantity -3 for item id 1 must be between 1 and 100.").await;
+    }
+
+    #[tokio::test]
+    async fn create_order__quantity_over_max__is_422() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "2", "qty": 2_000_000_000 }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123", &body)).await.unwrap();
+
+        let problem = assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "invalid_quantity", "Quantity 2000000000 for item id 2 must be between 1 and 100.").await;
+        assert_eq!(Some("2".to_string()), problem.item_id);
+    }
+
+    #[tokio::test]
+    async fn create_order__notes_and_modifiers__are_shown_in_item_details() {
+        let mut sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1, "notes": "  nut allergy ", "modifiers": ["well_done", "no_onions"] }, { "item_id": "1", "qty": 1, "notes": " " }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let first = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/orders/123/items/1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let second = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/orders/123/items/2").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let first: TableOrderItemDetailViewModel = serde_json::from_value(get_body_json(first).await).unwrap();
+        assert_eq!(Some("nut allergy".to_string()), first.notes);
+        assert_eq!(
+            vec![("well_done".to_string(), "Well done".to_string()), ("no_onions".to_string(), "No onions".to_string())],
+            first
+                .modifiers
+                .iter()
+                .map(|m| (m.modifier_id.clone(), m.name.clone()))
+                .collect::<Vec<(String, String)>>()
+        );
+        let second: TableOrderItemDetailViewModel = serde_json::from_value(get_body_json(second).await).unwrap();
+        assert_eq!(None, second.notes);
+        assert!(second.modifiers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn create_order__modifier_not_offered_for_item__is_422() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "2", "qty": 1, "modifiers": ["no_onions"] }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123", &body)).await.unwrap();
+
+        let problem = assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "unknown_modifier", "Modifier 'no_onions' is not available for menu item id 2.").await;
+        assert_eq!(Some("2".to_string()), problem.item_id);
+    }
+
+    #[tokio::test]
+    async fn create_order__two_modifiers_from_one_group__is_422() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1, "modifiers": ["rare", "no_onions", "well_done"] }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123", &body)).await.unwrap();
+
+        assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "conflicting_modifiers", "Only one temperature modifier can be chosen for item id 1.").await;
+    }
+
+    #[tokio::test]
+    async fn create_order__same_modifier_twice__is_422() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1, "modifiers": ["no_onions", "no_onions"] }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123", &body)).await.unwrap();
+
+        assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "duplicate_modifier", "Modifier 'no_onions' is listed more than once for item id 1.").await;
+    }
+
+    #[tokio::test]
+    async fn create_order__notes_too_long__is_422() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1, "notes": "a".repeat(MAX_NOTES_CHARS + 1) }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123", &body)).await.unwrap();
+
+        assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "notes_too_long", "Notes for item id 1 can be at most 200 characters.").await;
+    }
+
+    #[tokio::test]
+    async fn create_order__priced_items__line_totals_and_subtotal_include_modifier_surcharges() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 2, "modifiers": ["extra_cheese", "no_onions"] }, { "item_id": "2", "qty": 1 }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123", &body)).await.unwrap();
+
+        assert_eq!(StatusCode::CREATED, response.status());
+        let response_order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(
+            vec![(Some("3.25".to_string()), Some(650)), (Some("5.00".to_string()), Some(500))],
+            response_order
+                .items
+                .iter()
+                .map(|i| (i.unit_price.as_ref().map(|p| p.amount.clone()), i.line_total.as_ref().map(|t| t.minor_units)))
+                .collect::<Vec<(Option<String>, Option<i64>)>>()
+        );
+        assert_eq!(MoneyViewModel { amount: "11.50".to_string(), minor_units: 1150, currency: "EUR".to_string() }, response_order.subtotal);
+    }
+
+    #[tokio::test]
+    async fn create_order__empty_item_list__is_422() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123", &body)).await.unwrap();
+
+        assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "empty_item_list", "An order must contain at least one item.").await;
+    }
+
+    #[tokio::test]
+    async fn create_order__same_menu_item_twice__is_a_line_each() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }, { "item_id": "2", "qty": 1 }, { "item_id": "1", "qty": 2 }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123", &body)).await.unwrap();
+
+        assert_eq!(StatusCode::CREATED, response.status());
+        let response_order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(
+            vec![("1".to_string(), "1".to_string(), 1), ("2".to_string(), "2".to_string(), 1), ("3".to_string(), "1".to_string(), 2)],
+            response_order
+                .items
+                .iter()
+                .map(|i| (i.line_id.clone(), i.item_id.clone(), i.quantity))
+                .collect::<Vec<(String, String, i32)>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn create_order__too_many_items__is_422() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let items = (0..101).map(|_| json!({ "item_id": "1", "qty": 1 })).collect::<Vec<Value>>();
+        let body = json!({ "items": items });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123", &body)).await.unwrap();
+
+        assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "too_many_items", "An order can contain at most 100 items, 101 were given.").await;
+    }
+
+    #[tokio::test]
+    async fn create_order__oversized_payload__is_413() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1, "notes": "a".repeat(MAX_REQUEST_BODY_BYTES) }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123", &body)).await.unwrap();
+
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+        let problem: ProblemDetails = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!("payload_too_large", problem.code);
+    }
+
+    #[tokio::test]
+    async fn create_order__malformed_json__is_problem_response() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let request = Request::builder()
+            .method(http::Method::POST)
+            .uri("/v0/orders/123")
+            .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from("{ \"items\": [{ \"item_id\": \"1\" }] }"))
+            .unwrap();
+
+        let response = sut.oneshot(request).await.unwrap();
+
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+        assert_eq!(PROBLEM_JSON_CONTENT_TYPE, response.headers().get(http::header::CONTENT_TYPE).unwrap());
+        let problem: ProblemDetails = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!("invalid_body", problem.code);
+    }
+
+    #[tokio::test]
+    async fn update_order__invalid_items__is_422_and_order_is_unchanged() {
+        let mut sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 2 }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let body = json!({ "items": [{ "item_id": "1", "qty": 0 }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::PUT, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().method(http::Method::GET).uri("/v0/orders/123").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let response_order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(vec![("1".to_string(), "menu item 1".to_string(), 2)], get_assertable_items_sorted(&response_order.items));
+    }
+
+    #[tokio::test]
+    async fn update_order_item_status__legal_transitions__status_is_advanced() {
+        let mut sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 2 }, { "item_id": "2", "qty": 1 }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+        let response_order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert!(response_order.items.iter().all(|i| i.status == "ordered" && i.status_changed_at.is_none()));
+        for item in &response_order.items {
+            let ordered_at = chrono::DateTime::parse_from_rfc3339(&item.ordered_at).unwrap();
+            let ready_at = chrono::DateTime::parse_from_rfc3339(&item.ready_at).unwrap();
+            assert_eq!(item.total_preparation_time_mins as i64, (ready_at - ordered_at).num_minutes());
+            assert_eq!(item.total_preparation_time_mins, item.remaining_mins);
+        }
+
+        for status in ["preparing", "ready", "served"] {
+            let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+                .await
+                .unwrap()
+                .call(json_request(http::Method::POST, "/v0/orders/123/items/1/status", &json!({ "status": status })))
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::OK, response.status());
+            let response_item: TableOrderItemDetailViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+            assert_eq!(status, response_item.status);
+        }
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/v0/orders/123/items/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let response_item: TableOrderItemDetailViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(vec!["preparing", "ready", "served"], response_item.status_history.iter().map(|c| c.status.as_str()).collect::<Vec<&str>>());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().method(http::Method::GET).uri("/v0/orders/123").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let response_order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        let served = response_order.items.iter().find(|i| i.item_id == "1").unwrap();
+        assert_eq!("served", served.status);
+        assert_eq!(Some(&response_item.status_history[2].at), served.status_changed_at.as_ref());
+        assert_eq!("ordered", response_order.items.iter().find(|i| i.item_id == "2").unwrap().status);
+    }
+
+    #[tokio::test]
+    async fn update_order_item_status__illegal_transition__is_409() {
+        let mut sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 2 }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/items/1/status", &json!({ "status": "served" })))
+            .await
+            .unwrap();
+
+        let problem = assert_problem_response(response, StatusCode::CONFLICT, "illegal_status_transition", "Order line id 1 cannot move from ordered to served.").await;
+        assert_eq!(Some("123".to_string()), problem.table_id);
+        assert_eq!(Some("1".to_string()), problem.line_id);
+    }
+
+    #[tokio::test]
+    async fn update_order_item_status__unknown_status__is_422() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+
+        let response = sut
+            .oneshot(json_request(http::Method::POST, "/v0/orders/123/items/1/status", &json!({ "status": "cooking" })))
+            .await
+            .unwrap();
+
+        assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "unknown_status", "Status 'cooking' is not one of ordered, preparing, ready or served.").await;
+    }
+
+    #[tokio::test]
+    async fn delete_order_item__same_menu_item_on_two_lines__only_that_line_is_removed() {
+        let mut sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }, { "item_id": "1", "qty": 2 }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(
+                Request::builder()
+                    .method(http::Method::DELETE)
+                    .uri("/v0/orders/123/items/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let response_order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(
+            vec![("2".to_string(), "1".to_string(), 2)],
+            response_order
+                .items
+                .iter()
+                .map(|i| (i.line_id.clone(), i.item_id.clone(), i.quantity))
+                .collect::<Vec<(String, String, i32)>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_order_item__line_the_kitchen_started__is_409_and_line_is_kept() {
+        let mut sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 2 }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/items/1/status", &json!({ "status": "preparing" })))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(
+                Request::builder()
+                    .method(http::Method::DELETE)
+                    .uri("/v0/orders/123/items/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_problem_response(response, StatusCode::CONFLICT, "item_already_started", "Order line id 1 is preparing, its quantity can only be changed before the kitchen starts it.").await;
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/orders/123").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let response_order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(1, response_order.items.len());
+    }
+
+    #[tokio::test]
+    async fn update_order_item_status__item_not_in_order__is_404() {
+        let mut sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 2 }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/items/2/status", &json!({ "status": "preparing" })))
+            .await
+            .unwrap();
+
+        assert_problem_response(response, StatusCode::NOT_FOUND, "order_item_not_found", "Order line id 2 not found.").await;
+    }
+
+    #[tokio::test]
+    async fn get_order__time_passes__remaining_mins_counts_down() {
+        let ordered_at = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(ordered_at));
+        let mut sut = create_app_with_state(AppState::new(MemoryPersistence::default(), create_test_menu()).with_clock(clock.clone()));
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+        let response_order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        let prep_mins = response_order.items[0].total_preparation_time_mins;
+        assert_eq!(ordered_at.to_rfc3339(), response_order.items[0].ordered_at);
+        assert_eq!((ordered_at + TimeDelta::minutes(prep_mins as i64)).to_rfc3339(), response_order.items[0].ready_at);
+        assert_eq!(prep_mins, response_order.items[0].remaining_mins);
+        // Until a cook starts it the kitchen can't promise it any sooner, so start it straight away
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/items/1/status", &json!({ "status": "preparing" })))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        clock.advance(TimeDelta::minutes(4));
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().method(http::Method::GET).uri("/v0/orders/123").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let response_order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(prep_mins - 4, response_order.items[0].remaining_mins);
+
+        clock.advance(TimeDelta::minutes(prep_mins as i64));
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/v0/orders/123/items/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let response_item: TableOrderItemDetailViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(0, response_item.remaining_mins);
+    }
+
+    #[tokio::test]
+    async fn update_order_item_status__status_changes__are_timestamped_by_clock() {
+        let ordered_at = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(ordered_at));
+        let mut sut = create_app_with_state(AppState::new(MemoryPersistence::default(), create_test_menu()).with_clock(clock.clone()));
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        clock.advance(TimeDelta::minutes(1));
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/items/1/status", &json!({ "status": "preparing" })))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        clock.advance(TimeDelta::minutes(5));
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/items/1/status", &json!({ "status": "ready" })))
+            .await
+            .unwrap();
+
+        let response_item: TableOrderItemDetailViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        let expected_times = vec![(ordered_at + TimeDelta::minutes(1)).to_rfc3339(), (ordered_at + TimeDelta::minutes(6)).to_rfc3339()];
+        assert_eq!(expected_times, response_item.status_history.iter().map(|c| c.at.clone()).collect::<Vec<String>>());
+        assert_eq!(0, response_item.remaining_mins);
+    }
+
+    #[tokio::test]
+    async fn update_order__items_resent_later__keep_their_timing_and_status() {
+        let ordered_at = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(ordered_at));
+        let mut sut = create_app_with_state(AppState::new(MemoryPersistence::default(), create_test_menu()).with_clock(clock.clone()));
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }, { "item_id": "2", "qty": 2 }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/v0/kds/tables/123/items/1/bump")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        clock.advance(TimeDelta::minutes(5));
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }, { "item_id": "2", "qty": 1 }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::PUT, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let response_order: UpdatedTableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        let expected = vec![("1".to_string(), 1, "ready".to_string(), ordered_at.to_rfc3339()), ("2".to_string(), 1, "ordered".to_string(), ordered_at.to_rfc3339())];
+        assert_eq!(
+            expected,
+            response_order
+                .order
+                .items
+                .iter()
+                .map(|i| (i.line_id.clone(), i.quantity, i.status.clone(), i.ordered_at.clone()))
+                .collect::<Vec<(String, i32, String, String)>>()
+        );
+        assert_eq!(
+            vec![("2".to_string(), 1, "ordered".to_string())],
+            response_order
+                .removed_items
+                .iter()
+                .map(|i| (i.line_id.clone(), i.quantity, i.status.clone()))
+                .collect::<Vec<(String, i32, String)>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn update_order__less_of_a_cooking_line__is_409_and_order_is_unchanged() {
+        let persistence = MemoryPersistence::default();
+        persistence
+            .create_order(&TableId(123), None, &[TableOrderItem::new(MenuItemId(1), 2, 12, Utc::now())], |_| ())
+            .await
+            .unwrap();
+        persistence
+            .update_order_with(&TableId(123), |o| o.advance_item_status(&LineId(1), OrderItemStatus::Preparing, Utc::now()), |_| ())
+            .await
+            .unwrap();
+        let mut sut = create_app(persistence, create_test_menu());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::PUT, "/v0/orders/123", &json!({ "items": [{ "item_id": "1", "qty": 1 }] })))
+            .await
+            .unwrap();
+
+        let problem =
+            assert_problem_response(response, StatusCode::CONFLICT, "item_already_started", "Order line id 1 is preparing, its quantity can only be changed before the kitchen starts it.").await;
+        assert_eq!(Some("1".to_string()), problem.line_id);
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/orders/123").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let response_order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(vec![("1".to_string(), "menu item 1".to_string(), 2)], get_assertable_items_sorted(&response_order.items));
+    }
+
+    #[tokio::test]
+    async fn update_order__served_line_left_out__is_409() {
+        let persistence = MemoryPersistence::default();
+        persistence
+            .create_order(&TableId(123), None, &[TableOrderItem::new(MenuItemId(1), 1, 10, Utc::now()), TableOrderItem::new(MenuItemId(2), 1, 10, Utc::now())], |_| ())
+            .await
+            .unwrap();
+        for status in [OrderItemStatus::Preparing, OrderItemStatus::Ready, OrderItemStatus::Served] {
+            persistence
+                .update_order_with(&TableId(123), |o| o.advance_item_status(&LineId(1), status, Utc::now()), |_| ())
+                .await
+                .unwrap();
+        }
+        let sut = create_app(persistence, create_test_menu());
+
+        let response = sut
+            .oneshot(json_request(http::Method::PUT, "/v0/orders/123", &json!({ "items": [{ "item_id": "2", "qty": 1 }] })))
+            .await
+            .unwrap();
+
+        let problem =
+            assert_problem_response(response, StatusCode::CONFLICT, "item_already_started", "Order line id 1 is served, its quantity can only be changed before the kitchen starts it.").await;
+        assert_eq!(Some("1".to_string()), problem.line_id);
+    }
+
+    #[tokio::test]
+    async fn add_order_items__existing_order__appends_lines_and_leaves_existing_timing_alone() {
+        let ordered_at = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(ordered_at));
+        let mut sut = create_app_with_state(AppState::new(MemoryPersistence::default(), create_test_menu()).with_clock(clock.clone()));
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &json!({ "items": [{ "item_id": "1", "qty": 1 }] })))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/items/1/status", &json!({ "status": "preparing" })))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        clock.advance(TimeDelta::minutes(3));
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/items", &json!({ "items": [{ "item_id": "1", "qty": 2 }] })))
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::CREATED, response.status());
+        let response_order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        let expected = vec![
+            ("1".to_string(), 1, ordered_at.to_rfc3339(), (ordered_at + TimeDelta::minutes(10)).to_rfc3339()),
+            ("2".to_string(), 2, (ordered_at + TimeDelta::minutes(3)).to_rfc3339(), (ordered_at + TimeDelta::minutes(15)).to_rfc3339()),
+        ];
+        assert_eq!(
+            expected,
+            response_order
+                .items
+                .iter()
+                .map(|i| (i.line_id.clone(), i.quantity, i.ordered_at.clone(), i.ready_at.clone()))
+                .collect::<Vec<(String, i32, String, String)>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn add_order_items__no_order__is_404() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123/items", &body)).await.unwrap();
+
+        assert_problem_response(response, StatusCode::NOT_FOUND, "order_not_found", "Order id 123 not found.").await;
+    }
+
+    #[tokio::test]
+    async fn add_order_items__order_would_go_over_max_items__is_422() {
+        let persistence = MemoryPersistence::default();
+        let items = vec![TableOrderItem::new(MenuItemId(1), 1, 10, Utc::now()); MAX_ITEMS_PER_ORDER];
+        persistence.create_order(&TableId(123), None, &items, |_| ()).await.unwrap();
+        let sut = create_app(persistence, create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123/items", &body)).await.unwrap();
+
+        assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "too_many_items", "Order for table id 123 can contain at most 100 items.").await;
+    }
+
+    #[tokio::test]
+    async fn update_order_item__new_quantity__re_estimates_only_that_line() {
+        let ordered_at = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(ordered_at));
+        let mut sut = create_app_with_state(AppState::new(MemoryPersistence::default(), create_test_menu()).with_clock(clock.clone()));
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }, { "item_id": "2", "qty": 1 }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/items/2/status", &json!({ "status": "preparing" })))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        clock.advance(TimeDelta::minutes(2));
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::PATCH, "/v0/orders/123/items/1", &json!({ "qty": 3 })))
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let response_item: TableOrderItemDetailViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!((3, 14), (response_item.quantity, response_item.total_preparation_time_mins));
+        // No cook has started it yet, so the kitchen counts the new time from now
+        assert_eq!((ordered_at + TimeDelta::minutes(16)).to_rfc3339(), response_item.ready_at);
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/orders/123/items/2").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let other_item: TableOrderItemDetailViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!((1, (ordered_at + TimeDelta::minutes(10)).to_rfc3339()), (other_item.quantity, other_item.ready_at));
+    }
+
+    #[tokio::test]
+    async fn update_order_item__quantity_of_started_line__is_409() {
+        let persistence = MemoryPersistence::default();
+        persistence
+            .create_order(&TableId(123), None, &[TableOrderItem::new(MenuItemId(1), 1, 10, Utc::now())], |_| ())
+            .await
+            .unwrap();
+        persistence
+            .update_order_with(&TableId(123), |o| o.bump_item(&LineId(1), Utc::now()), |_| ())
+            .await
+            .unwrap();
+        let sut = create_app(persistence, create_test_menu());
+
+        let response = sut
+            .oneshot(json_request(http::Method::PATCH, "/v0/orders/123/items/1", &json!({ "qty": 2 })))
+            .await
+            .unwrap();
+
+        let problem = assert_problem_response(response, StatusCode::CONFLICT, "item_already_started", "Order line id 1 is ready, its quantity can only be changed before the kitchen starts it.").await;
+        assert_eq!(Some("1".to_string()), problem.line_id);
+    }
+
+    #[tokio::test]
+    async fn update_order_item__blank_notes__clears_them() {
+        let persistence = MemoryPersistence::default();
+        let item = TableOrderItem::new(MenuItemId(1), 1, 10, Utc::now()).with_notes(Some("no salt".to_string()));
+        persistence.create_order(&TableId(123), None, &[item], |_| ()).await.unwrap();
+        let sut = create_app(persistence, create_test_menu());
+
+        let response = sut
+            .oneshot(json_request(http::Method::PATCH, "/v0/orders/123/items/1", &json!({ "notes": "  " })))
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let response_item: TableOrderItemDetailViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!((None, 1), (response_item.notes, response_item.quantity));
+    }
+
+    #[tokio::test]
+    async fn update_order_item__null_seat__line_is_shared_again() {
+        let persistence = MemoryPersistence::default();
+        let item = TableOrderItem::new(MenuItemId(1), 1, 10, Utc::now()).with_seat(Some(2));
+        persistence.create_order(&TableId(123), Some(2), &[item], |_| ()).await.unwrap();
+        let mut sut = create_app(persistence, create_test_menu());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::PATCH, "/v0/orders/123/items/1", &json!({ "notes": "no salt" })))
+            .await
+            .unwrap();
+        let response_item: TableOrderItemDetailViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(Some(2), response_item.seat);
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::PATCH, "/v0/orders/123/items/1", &json!({ "seat": null })))
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let response_item: TableOrderItemDetailViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!((None, Some("no salt".to_string())), (response_item.seat, response_item.notes));
+    }
+
+    #[tokio::test]
+    async fn update_order_item__quantity_over_max__is_422() {
+        let persistence = MemoryPersistence::default();
+        persistence
+            .create_order(&TableId(123), None, &[TableOrderItem::new(MenuItemId(1), 1, 10, Utc::now())], |_| ())
+            .await
+            .unwrap();
+        let sut = create_app(persistence, create_test_menu());
+
+        let response = sut
+            .oneshot(json_request(http::Method::PATCH, "/v0/orders/123/items/1", &json!({ "qty": 101 })))
+            .await
+            .unwrap();
+
+        let problem = assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "invalid_quantity", "Quantity 101 for line id 1 must be between 1 and 100.").await;
+        assert_eq!(Some("1".to_string()), problem.line_id);
+    }
+
+    #[tokio::test]
+    async fn update_order_item__nothing_to_change__is_422() {
+        let persistence = MemoryPersistence::default();
+        persistence
+            .create_order(&TableId(123), None, &[TableOrderItem::new(MenuItemId(1), 1, 10, Utc::now())], |_| ())
+            .await
+            .unwrap();
+        let sut = create_app(persistence, create_test_menu());
+
+        let response = sut.oneshot(json_request(http::Method::PATCH, "/v0/orders/123/items/1", &json!({}))).await.unwrap();
+
+        assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "nothing_to_change", "Nothing to change on line id 1, give a new qty, notes or seat.").await;
+    }
+
+    #[tokio::test]
+    async fn create_bill__order_exists__bill_is_issued_and_can_be_read_back() {
+        let billing = BillingConfig {
+            tax_mode: TaxMode::Exclusive,
+            tax_rounding: TaxRounding::PerLine,
+            default_tax_rate: Percentage(10000),
+            tax_rates: Default::default(),
+            service_charge: Some(ServiceChargeRule { min_guests: 4, rate: Percentage(12500) }),
+        };
+        let persistence = MemoryPersistence::default();
+        persistence
+            .create_order(&TableId(123), None, &[TableOrderItem::new(MenuItemId(2), 2, 10, Utc::now())], |_| ())
+            .await
+            .unwrap();
+        let mut sut = create_app(persistence, create_test_menu().with_billing(billing));
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/bill", &json!({ "guests": 4 })))
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::CREATED, response.status());
+        let bill: BillViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(("1".to_string(), Some(4), "exclusive".to_string()), (bill.bill_id, bill.guests, bill.tax_mode));
+        assert_eq!(
+            vec![("menu item 2".to_string(), "5.00".to_string(), "10%".to_string())],
+            bill.lines
+                .iter()
+                .map(|l| (l.name.clone(), l.unit_price.amount.clone(), l.tax_rate.clone()))
+                .collect::<Vec<(String, String, String)>>()
+        );
+        assert_eq!(
+            ("10.00".to_string(), "1.00".to_string(), Some("1.25".to_string()), "12.25".to_string()),
+            (bill.subtotal.amount, bill.tax_total.amount, bill.service_charge.map(|s| s.amount.amount), bill.total.amount)
+        );
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/orders/123/bill").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let read_back: BillViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(("1".to_string(), 1225), (read_back.bill_id, read_back.total.minor_units));
+    }
+
+    #[tokio::test]
+    async fn read_bill__none_issued__is_404() {
+        let persistence = MemoryPersistence::default();
+        persistence
+            .create_order(&TableId(123), None, &[TableOrderItem::new(MenuItemId(1), 1, 10, Utc::now())], |_| ())
+            .await
+            .unwrap();
+        let sut = create_app(persistence, create_test_menu());
+
+        let response = sut
+            .oneshot(Request::builder().uri("/v0/orders/123/bill").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_problem_response(response, StatusCode::NOT_FOUND, "bill_not_found", "No bill has been issued for table id 123.").await;
+    }
+
+    #[tokio::test]
+    async fn create_bill__guests_not_positive__is_422() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+
+        let response = sut
+            .oneshot(json_request(http::Method::POST, "/v0/orders/123/bill", &json!({ "guests": 0 })))
+            .await
+            .unwrap();
 
-mod tests {
-    use crate::{
-        api::v0::view_models::{TableOrderItemDetailViewModel, TableOrderItemSummaryViewModel, TableOrderViewModel},
-        app::create_app,
-        persistence::memory_persistence::MemoryPersistence,
-    };
+        assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "invalid_guests", "Guests 0 must be greater than zero.").await;
+    }
 
-    use axum::{
-        body::Body,
-        http::{self, Request, Response, StatusCode},
-    };
-    use http_body_util::BodyExt;
-    use serde_json::{json, Value};
-    use tower::{Service, ServiceExt};
+    #[tokio::test]
+    async fn create_bill__item_no_longer_on_menu__is_409() {
+        let persistence = MemoryPersistence::default();
+        persistence
+            .create_order(&TableId(123), None, &[TableOrderItem::new(MenuItemId(9), 1, 10, Utc::now())], |_| ())
+            .await
+            .unwrap();
+        let sut = create_app(persistence, create_test_menu());
 
-    async fn assert_response(response: Response<Body>, expected_status: StatusCode, expected_body: &str) {
-        assert_eq!(expected_status, response.status());
-        let body = response.into_body().collect().await.unwrap().to_bytes();
-        let body_str: &str = std::str::from_utf8(&body).unwrap();
-        assert_eq!(expected_body, body_str);
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123/bill", &json!({}))).await.unwrap();
+
+        let problem = assert_problem_response(
+            response,
+            StatusCode::CONFLICT,
+            "unpriced_item",
+            "Order line id 1 is for menu item id 9, which is no longer on the menu and can't be billed. Remove the line first.",
+        )
+        .await;
+        assert_eq!((Some("1".to_string()), Some("9".to_string())), (problem.line_id, problem.item_id));
     }
 
-    fn get_assertable_items_sorted(items: &[TableOrderItemSummaryViewModel]) -> Vec<(String, String, i32)> {
-        let mut result = items
+    #[tokio::test]
+    async fn read_order__grouped_by_seat__shared_lines_come_last() {
+        let mut sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "guests": 2, "items": [{ "item_id": "1", "qty": 1, "seat": 2 }, { "item_id": "2", "qty": 1, "seat": 1 }, { "item_id": "3", "qty": 2 }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/orders/123?group_by=seat").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(Some(2), order.guests);
+        assert_eq!(3, order.items.len());
+        assert_eq!(
+            vec![(Some(1), vec!["2".to_string()], "5.00".to_string()), (Some(2), vec!["1".to_string()], "2.50".to_string()), (None, vec!["3".to_string()], "15.00".to_string())],
+            order
+                .seats
+                .unwrap()
+                .into_iter()
+                .map(|s| (s.seat, s.items.into_iter().map(|i| i.item_id).collect(), s.subtotal.amount))
+                .collect::<Vec<(Option<u32>, Vec<String>, String)>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn create_order__seat_beyond_guests__is_422() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+
+        let response = sut
+            .oneshot(json_request(http::Method::POST, "/v0/orders/123", &json!({ "guests": 2, "items": [{ "item_id": "1", "qty": 1, "seat": 3 }] })))
+            .await
+            .unwrap();
+
+        assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "seat_out_of_range", "Seat 3 is not at table id 123, which seats 2 guests.").await;
+    }
+
+    #[tokio::test]
+    async fn update_order_guests__fewer_than_a_seat_in_use__is_422_and_unchanged() {
+        let persistence = MemoryPersistence::default();
+        persistence
+            .create_order(&TableId(123), Some(4), &[TableOrderItem::new(MenuItemId(1), 1, 10, Utc::now()).with_seat(Some(4))], |_| ())
+            .await
+            .unwrap();
+        let mut sut = create_app(persistence, create_test_menu());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::PATCH, "/v0/orders/123", &json!({ "guests": 3 })))
+            .await
+            .unwrap();
+        assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "seat_out_of_range", "Seat 4 is not at table id 123, which seats 3 guests.").await;
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::PATCH, "/v0/orders/123", &json!({ "guests": 6 })))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(Some(6), order.guests);
+    }
+
+    // Line 1 is 2.50 and line 2 is 10.00, with nothing taxed
+    async fn create_billed_order() -> MemoryPersistence {
+        let persistence = MemoryPersistence::default();
+        persistence
+            .create_order(&TableId(123), None, &[TableOrderItem::new(MenuItemId(1), 1, 10, Utc::now()), TableOrderItem::new(MenuItemId(2), 2, 10, Utc::now())], |_| ())
+            .await
+            .unwrap();
+        persistence
+            .update_order_with(&TableId(123), |o| o.issue_bill(&create_test_menu(), None, Utc::now()).map(|_| ()), |_| ())
+            .await
+            .unwrap();
+        return persistence;
+    }
+
+    #[tokio::test]
+    async fn split_bill__evenly__parts_add_up_to_the_total() {
+        let sut = create_app(create_billed_order().await, create_test_menu());
+
+        let response = sut
+            .oneshot(json_request(http::Method::POST, "/v0/orders/123/bill/split", &json!({ "even": 3 })))
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let split: BillSplitViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(("1".to_string(), "12.50".to_string()), (split.bill_id, split.total.amount));
+        assert_eq!(vec![(1, "4.17".to_string()), (2, "4.17".to_string()), (3, "4.16".to_string())], split.parts.iter().map(|p| (p.payer, p.amount.amount.clone())).collect::<Vec<(usize, String)>>());
+    }
+
+    #[tokio::test]
+    async fn split_bill__by_payers__each_pays_their_lines_and_share() {
+        let sut = create_app(create_billed_order().await, create_test_menu());
+        let body = json!({ "payers": [{ "lines": [{ "line_id": "1" }, { "line_id": "2" }] }, { "lines": [{ "line_id": "2", "share": 3 }] }] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123/bill/split", &body)).await.unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let split: BillSplitViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(vec!["5.00".to_string(), "7.50".to_string()], split.parts.iter().map(|p| p.amount.amount.clone()).collect::<Vec<String>>());
+        assert_eq!(
+            vec![("1".to_string(), "1/1".to_string(), "2.50".to_string()), ("2".to_string(), "1/4".to_string(), "2.50".to_string())],
+            split.parts[0]
+                .lines
+                .iter()
+                .map(|l| (l.line_id.clone(), l.share.clone(), l.amount.amount.clone()))
+                .collect::<Vec<(String, String, String)>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn split_bill__both_even_and_payers__is_422() {
+        let sut = create_app(create_billed_order().await, create_test_menu());
+
+        let response = sut
+            .oneshot(json_request(http::Method::POST, "/v0/orders/123/bill/split", &json!({ "even": 2, "payers": [] })))
+            .await
+            .unwrap();
+
+        assert_problem_response(
+            response,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_split",
+            "Give one of even, the number of ways to split the bill, payers, the lines each payer pays for, or by_seat.",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn split_bill__line_paid_by_no_one__is_422() {
+        let sut = create_app(create_billed_order().await, create_test_menu());
+
+        let response = sut
+            .oneshot(json_request(http::Method::POST, "/v0/orders/123/bill/split", &json!({ "payers": [{ "lines": [{ "line_id": "2" }] }] })))
+            .await
+            .unwrap();
+
+        let problem = assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "unassigned_line", "Order line id 1 on bill 1 isn't paid for by anyone.").await;
+        assert_eq!(Some("1".to_string()), problem.line_id);
+    }
+
+    #[tokio::test]
+    async fn split_bill__shares_adding_up_past_u32__is_422() {
+        let sut = create_app(create_billed_order().await, create_test_menu());
+        let body = json!({ "payers": [
+            { "lines": [{ "line_id": "1" }, { "line_id": "2", "share": i32::MAX }] },
+            { "lines": [{ "line_id": "2", "share": i32::MAX }, { "line_id": "2", "share": i32::MAX }] }
+        ] });
+
+        let response = sut.oneshot(json_request(http::Method::POST, "/v0/orders/123/bill/split", &body)).await.unwrap();
+
+        let problem = assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "invalid_share", "The shares of order line id 2 on bill 1 add up to more than 4294967295.").await;
+        assert_eq!(Some("2".to_string()), problem.line_id);
+    }
+
+    #[tokio::test]
+    async fn create_payment__part_by_card_then_rest_in_cash__order_can_be_closed() {
+        let mut sut = create_app_with_state(AppState::new(create_billed_order().await, create_test_menu()).with_payment_gateway(Arc::new(FakeGateway::default())));
+
+        let body = json!({ "method": "card", "amount": "5.00", "card_token": "tok_visa" });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/payments", &body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let payments: PaymentsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(("5.00".to_string(), "7.50".to_string()), (payments.paid.amount, payments.balance.amount));
+        assert!(payments.payments[0].gateway_reference.is_some());
+
+        // No amount pays whatever is left
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/payments", &json!({ "method": "cash" })))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/orders/123/payments").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let payments: PaymentsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(
+            vec![("1".to_string(), "card".to_string(), "5.00".to_string()), ("2".to_string(), "cash".to_string(), "7.50".to_string())],
+            payments
+                .payments
+                .iter()
+                .map(|p| (p.payment_id.clone(), p.method.clone(), p.amount.amount.clone()))
+                .collect::<Vec<(String, String, String)>>()
+        );
+        assert_eq!(("12.50".to_string(), "0.00".to_string()), (payments.total.amount, payments.balance.amount));
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().method(http::Method::DELETE).uri("/v0/orders/123").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, response.status());
+    }
+
+    #[tokio::test]
+    async fn delete_order__paid_in_full__bills_and_payments_can_be_read_back() {
+        let closed_at = Utc.with_ymd_and_hms(2024, 1, 1, 14, 0, 0).unwrap();
+        let state = AppState::new(create_billed_order().await, create_test_menu()).with_clock(Arc::new(ManualClock::new(closed_at)));
+        let mut sut = create_app_with_state(state);
+        for (uri, body) in
+            [("/v0/orders/123/payments", json!({ "method": "cash" })), ("/v0/orders/123/payments/1/refund", json!({ "amount": "1.00" })), ("/v0/orders/123/payments", json!({ "method": "cash" }))]
+        {
+            let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+                .await
+                .unwrap()
+                .call(json_request(http::Method::POST, uri, &body))
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::CREATED, response.status());
+        }
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().method(http::Method::DELETE).uri("/v0/orders/123").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, response.status());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/tables/123/settlements").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let settlements: SettlementsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(1, settlements.settlements.len());
+        let settlement = &settlements.settlements[0];
+        assert_eq!(("1".to_string(), "123".to_string(), closed_at.to_rfc3339()), (settlement.settlement_id.clone(), settlement.table_id.clone(), settlement.closed_at.clone()));
+        assert_eq!(
+            vec![("1".to_string(), "12.50".to_string())],
+            settlement
+                .bills
+                .iter()
+                .map(|b| (b.bill_id.clone(), b.total.amount.clone()))
+                .collect::<Vec<(String, String)>>()
+        );
+        assert_eq!(
+            vec![("1".to_string(), "12.50".to_string(), "1.00".to_string()), ("2".to_string(), "1.00".to_string(), "0.00".to_string())],
+            settlement
+                .payments
+                .iter()
+                .map(|p| (p.payment_id.clone(), p.amount.amount.clone(), p.refunded.amount.clone()))
+                .collect::<Vec<(String, String, String)>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn read_settlements__table_never_closed__is_empty() {
+        let sut = create_app(create_billed_order().await, create_test_menu());
+
+        let response = sut
+            .oneshot(Request::builder().uri("/v0/tables/123/settlements").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let settlements: SettlementsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert!(settlements.settlements.is_empty());
+    }
+
+    #[tokio::test]
+    async fn delete_order__part_paid__is_409_and_order_is_kept() {
+        let mut sut = create_app(create_billed_order().await, create_test_menu());
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/payments", &json!({ "method": "cash", "amount": "10" })))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().method(http::Method::DELETE).uri("/v0/orders/123").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_problem_response(response, StatusCode::CONFLICT, "outstanding_balance", "Order for table id 123 still has 2.50 EUR left to pay.").await;
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/orders/123").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+    }
+
+    #[tokio::test]
+    async fn create_payment__declined_card__is_402_and_nothing_is_recorded() {
+        let mut sut = create_app_with_state(AppState::new(create_billed_order().await, create_test_menu()).with_payment_gateway(Arc::new(FakeGateway::default())));
+
+        let body = json!({ "method": "card", "card_token": FAKE_DECLINED_CARD_TOKEN });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/payments", &body))
+            .await
+            .unwrap();
+        assert_problem_response(response, StatusCode::PAYMENT_REQUIRED, "payment_declined", "The payment gateway declined: insufficient funds.").await;
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/orders/123/payments").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let payments: PaymentsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!((0, "12.50".to_string()), (payments.payments.len(), payments.balance.amount));
+    }
+
+    #[tokio::test]
+    async fn create_payment__card_with_no_gateway_configured__is_502_and_nothing_is_recorded() {
+        let mut sut = create_app(create_billed_order().await, create_test_menu());
+
+        let body = json!({ "method": "card", "card_token": "tok_visa" });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/payments", &body))
+            .await
+            .unwrap();
+        assert_problem_response(response, StatusCode::BAD_GATEWAY, "payment_gateway_error", "The payment gateway could not be reached, try again later.").await;
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/orders/123/payments").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let payments: PaymentsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!((0, "12.50".to_string()), (payments.payments.len(), payments.balance.amount));
+    }
+
+    #[tokio::test]
+    async fn create_payment__more_than_is_left__is_422() {
+        let sut = create_app(create_billed_order().await, create_test_menu());
+
+        let response = sut
+            .oneshot(json_request(http::Method::POST, "/v0/orders/123/payments", &json!({ "method": "cash", "amount": "12.51" })))
+            .await
+            .unwrap();
+
+        assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "overpayment", "Payment of 12.51 EUR is more than the 12.50 EUR left to pay for table id 123.").await;
+    }
+
+    #[tokio::test]
+    async fn create_payment__invalid_params__is_422() {
+        let mut sut = create_app(create_billed_order().await, create_test_menu());
+
+        for (body, code) in [
+            (json!({ "method": "cheque" }), "unknown_payment_method"),
+            (json!({ "method": "card" }), "missing_card_token"),
+            (json!({ "method": "cash", "amount": "-1.00" }), "invalid_amount"),
+            (json!({ "method": "cash", "amount": "1.005" }), "invalid_amount"),
+        ] {
+            let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+                .await
+                .unwrap()
+                .call(json_request(http::Method::POST, "/v0/orders/123/payments", &body))
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+            let problem: ProblemDetails = serde_json::from_value(get_body_json(response).await).unwrap();
+            assert_eq!(code, problem.code);
+        }
+    }
+
+    #[tokio::test]
+    async fn create_payment__order_changed_since_bill__is_409() {
+        let persistence = create_billed_order().await;
+        persistence
+            .update_order_with(&TableId(123), |o| o.add_items(&[TableOrderItem::new(MenuItemId(3), 1, 10, Utc::now())], MAX_ITEMS_PER_ORDER), |_| ())
+            .await
+            .unwrap();
+        let sut = create_app(persistence, create_test_menu());
+
+        let response = sut
+            .oneshot(json_request(http::Method::POST, "/v0/orders/123/payments", &json!({ "method": "cash" })))
+            .await
+            .unwrap();
+
+        assert_problem_response(response, StatusCode::CONFLICT, "unbilled_items", "Order for table id 123 has changed since it was last billed, issue a new bill first.").await;
+    }
+
+    #[tokio::test]
+    async fn refund_payment__card__is_refunded_through_the_gateway_and_is_owed_again() {
+        let gateway = Arc::new(FakeGateway::default());
+        let mut sut = create_app_with_state(AppState::new(create_billed_order().await, create_test_menu()).with_payment_gateway(gateway.clone()));
+        let body = json!({ "method": "card", "card_token": "tok_visa" });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/payments", &body))
+            .await
+            .unwrap();
+        let payments: PaymentsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        let charge_reference = payments.payments[0].gateway_reference.clone().unwrap();
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/payments/1/refund", &json!({ "amount": "2.50" })))
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::CREATED, response.status());
+        let payments: PaymentsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(("2.50".to_string(), "2.50".to_string()), (payments.payments[0].refunded.amount.clone(), payments.balance.amount));
+        assert_eq!(Some(Money::new(250, Currency::Eur)), gateway.refunded(&charge_reference));
+
+        // No amount refunds whatever is left of the payment
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/payments/1/refund", &json!({})))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+        assert_eq!(Some(Money::new(1250, Currency::Eur)), gateway.refunded(&charge_reference));
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123/payments/1/refund", &json!({ "amount": "0.01" })))
+            .await
+            .unwrap();
+        let problem = assert_problem_response(response, StatusCode::UNPROCESSABLE_ENTITY, "refund_exceeds_payment", "Payment id 1 only has 0.00 EUR left to refund.").await;
+        assert_eq!(Some("1".to_string()), problem.payment_id);
+    }
+
+    // Charges and refunds straight away but takes a while to say so, like a gateway on a slow network.
+    // That leaves time for a second request to come in, or for the client to give up, while the first is still waiting on the gateway.
+    #[derive(Debug, Default)]
+    struct SlowGateway {
+        inner: FakeGateway,
+        refund_calls: AtomicUsize,
+    }
+
+    impl PaymentGateway for SlowGateway {
+        fn charge<'a>(&'a self, card_token: &'a str, amount: Money) -> BoxFuture<'a, Result<String, PaymentGatewayError>> {
+            return Box::pin(async move {
+                let result = self.inner.charge(card_token, amount).await;
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                return result;
+            });
+        }
+
+        fn refund<'a>(&'a self, charge_reference: &'a str, amount: Money) -> BoxFuture<'a, Result<String, PaymentGatewayError>> {
+            self.refund_calls.fetch_add(1, Ordering::SeqCst);
+            return Box::pin(async move {
+                let result = self.inner.refund(charge_reference, amount).await;
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                return result;
+            });
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn refund_payment__same_card_payment_twice_at_once__gateway_is_asked_once() {
+        let gateway = Arc::new(SlowGateway::default());
+        let sut = create_app_with_state(AppState::new(create_billed_order().await, create_test_menu()).with_payment_gateway(gateway.clone()));
+        let body = json!({ "method": "card", "card_token": "tok_visa" });
+        let response = sut
+            .clone()
+            .oneshot(json_request(http::Method::POST, "/v0/orders/123/payments", &body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let refund = || {
+            sut.clone()
+                .oneshot(json_request(http::Method::POST, "/v0/orders/123/payments/1/refund", &json!({})))
+        };
+        let (first, second) = tokio::join!(refund(), refund());
+
+        let mut statuses = vec![first.unwrap().status(), second.unwrap().status()];
+        statuses.sort();
+        assert_eq!(vec![StatusCode::CREATED, StatusCode::UNPROCESSABLE_ENTITY], statuses);
+        assert_eq!(1, gateway.refund_calls.load(Ordering::SeqCst));
+        let response = sut.oneshot(Request::get("/v0/orders/123/payments").body(Body::empty()).unwrap()).await.unwrap();
+        let payments: PaymentsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(vec!["refunded".to_string()], payments.payments[0].refunds.iter().map(|r| r.status.clone()).collect::<Vec<String>>());
+    }
+
+    #[tokio::test]
+    async fn create_payment__card_request_dropped_while_charging__payment_is_still_recorded() {
+        let gateway = Arc::new(SlowGateway::default());
+        let sut = create_app_with_state(AppState::new(create_billed_order().await, create_test_menu()).with_payment_gateway(gateway.clone()));
+        let body = json!({ "method": "card", "card_token": "tok_visa" });
+
+        let result = tokio::time::timeout(Duration::from_millis(20), sut.clone().oneshot(json_request(http::Method::POST, "/v0/orders/123/payments", &body))).await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        assert!(result.is_err());
+        assert_eq!(Some(Money::new(0, Currency::Eur)), gateway.inner.refunded("fake_ch_1"));
+        let response = sut.oneshot(Request::get("/v0/orders/123/payments").body(Body::empty()).unwrap()).await.unwrap();
+        let payments: PaymentsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(vec![Some("fake_ch_1".to_string())], payments.payments.iter().map(|p| p.gateway_reference.clone()).collect::<Vec<Option<String>>>());
+        assert_eq!("0.00", payments.balance.amount);
+    }
+
+    #[tokio::test]
+    async fn refund_payment__request_dropped_while_refunding__refund_is_still_recorded() {
+        let gateway = Arc::new(SlowGateway::default());
+        let sut = create_app_with_state(AppState::new(create_billed_order().await, create_test_menu()).with_payment_gateway(gateway.clone()));
+        let body = json!({ "method": "card", "card_token": "tok_visa" });
+        sut.clone()
+            .oneshot(json_request(http::Method::POST, "/v0/orders/123/payments", &body))
+            .await
+            .unwrap();
+
+        let result = tokio::time::timeout(
+            Duration::from_millis(20),
+            sut.clone()
+                .oneshot(json_request(http::Method::POST, "/v0/orders/123/payments/1/refund", &json!({}))),
+        )
+        .await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        assert!(result.is_err());
+        assert_eq!(Some(Money::new(1250, Currency::Eur)), gateway.inner.refunded("fake_ch_1"));
+        let response = sut.oneshot(Request::get("/v0/orders/123/payments").body(Body::empty()).unwrap()).await.unwrap();
+        let payments: PaymentsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(vec!["refunded".to_string()], payments.payments[0].refunds.iter().map(|r| r.status.clone()).collect::<Vec<String>>());
+    }
+
+    #[tokio::test]
+    async fn refund_payment__gateway_refuses__refund_is_failed_and_payment_can_be_refunded_again() {
+        let gateway = Arc::new(FakeGateway::default());
+        let sut = create_app_with_state(AppState::new(create_billed_order().await, create_test_menu()).with_payment_gateway(gateway.clone()));
+        let body = json!({ "method": "card", "card_token": "tok_visa" });
+        sut.clone()
+            .oneshot(json_request(http::Method::POST, "/v0/orders/123/payments", &body))
+            .await
+            .unwrap();
+        gateway.refund("fake_ch_1", Money::new(1000, Currency::Eur)).await.unwrap();
+
+        let response = sut
+            .clone()
+            .oneshot(json_request(http::Method::POST, "/v0/orders/123/payments/1/refund", &json!({ "amount": "5.00" })))
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::PAYMENT_REQUIRED, response.status());
+        let response = sut
+            .oneshot(json_request(http::Method::POST, "/v0/orders/123/payments/1/refund", &json!({ "amount": "2.50" })))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let payments: PaymentsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(
+            vec![("5.00".to_string(), "failed".to_string()), ("2.50".to_string(), "refunded".to_string())],
+            payments.payments[0]
+                .refunds
+                .iter()
+                .map(|r| (r.amount.amount.clone(), r.status.clone()))
+                .collect::<Vec<(String, String)>>()
+        );
+        assert_eq!("2.50", payments.payments[0].refunded.amount);
+    }
+
+    #[tokio::test]
+    async fn refund_payment__unknown_payment__is_404() {
+        let sut = create_app(create_billed_order().await, create_test_menu());
+
+        let response = sut
+            .oneshot(json_request(http::Method::POST, "/v0/orders/123/payments/9/refund", &json!({})))
+            .await
+            .unwrap();
+
+        assert_problem_response(response, StatusCode::NOT_FOUND, "payment_not_found", "Payment id 9 not found.").await;
+    }
+
+    #[tokio::test]
+    async fn get_kitchen_queue__orders_from_several_tables__queued_across_cooks() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(start));
+        let app_state = AppState::new(MemoryPersistence::default(), create_test_menu())
+            .with_clock(clock.clone())
+            .with_kitchen(KitchenConfig::default().with_capacity(Station::Grill, 2));
+        let mut sut = create_app_with_state(app_state);
+        for (table_id, body) in [(1, json!({ "items": [{ "item_id": "1", "qty": 1 }, { "item_id": "2", "qty": 1 }] })), (2, json!({ "items": [{ "item_id": "3", "qty": 2 }] }))] {
+            let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+                .await
+                .unwrap()
+                .call(json_request(http::Method::POST, &format!("/v0/orders/{}", table_id), &body))
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::CREATED, response.status());
+            clock.advance(TimeDelta::minutes(1));
+        }
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().method(http::Method::GET).uri("/v0/kitchen/queue").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let queue: KitchenQueueViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(("grill".to_string(), 2), (queue.stations[0].station.clone(), queue.stations[0].capacity));
+        assert!(queue.items.iter().all(|i| i.station == "grill"));
+        let summary = queue
+            .items
             .iter()
-            .map(|i| (i.item_id.clone(), i.name.clone(), i.quantity))
-            .collect::<Vec<(String, String, i32)>>();
-        result.sort_by(|a, b| a.cmp(b));
-        return result;
+            .map(|i| (i.position, i.table_id.clone(), i.item_id.clone(), i.cook, i.estimated_ready_at.clone(), i.remaining_mins))
+            .collect::<Vec<(usize, String, String, usize, String, i32)>>();
+        // Both cooks start on table 1 when asked at 12:02, so table 2's item (12 mins for 2) waits for the first to finish at 12:12
+        assert_eq!(
+            vec![
+                (1, "1".to_string(), "1".to_string(), 1, (start + TimeDelta::minutes(12)).to_rfc3339(), 10),
+                (2, "1".to_string(), "2".to_string(), 2, (start + TimeDelta::minutes(12)).to_rfc3339(), 10),
+                (3, "2".to_string(), "3".to_string(), 1, (start + TimeDelta::minutes(24)).to_rfc3339(), 22),
+            ],
+            summary
+        );
     }
 
-    async fn get_body_json(response: Response<Body>) -> Value {
-        let body = response.into_body().collect().await.unwrap().to_bytes();
-        return serde_json::from_slice(&body).unwrap();
+    #[tokio::test]
+    async fn read_order__two_orders_compete_for_one_cook__ready_time_waits_for_the_first() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(start));
+        let app_state = AppState::new(MemoryPersistence::default(), create_test_menu())
+            .with_clock(clock.clone())
+            .with_kitchen(KitchenConfig::default().with_capacity(Station::Grill, 1));
+        let mut sut = create_app_with_state(app_state);
+        let mut created = vec![];
+        for (table_id, item_id) in [(1, "1"), (2, "2")] {
+            let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+                .await
+                .unwrap()
+                .call(json_request(http::Method::POST, &format!("/v0/orders/{}", table_id), &json!({ "items": [{ "item_id": item_id, "qty": 1 }] })))
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::CREATED, response.status());
+            let order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+            created.push((order.items[0].ready_at.clone(), order.items[0].remaining_mins));
+            if table_id == 1 {
+                let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+                    .await
+                    .unwrap()
+                    .call(json_request(http::Method::POST, "/v0/orders/1/items/1/status", &json!({ "status": "preparing" })))
+                    .await
+                    .unwrap();
+                assert_eq!(StatusCode::OK, response.status());
+            }
+            clock.advance(TimeDelta::minutes(1));
+        }
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/orders/2").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/orders/2/items/1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let item: TableOrderItemDetailViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+
+        // The only grill cook started table 1's item at 12:00 and is on it until 12:10, so table 2's can't be ready before 12:20 even though it only takes 10 minutes
+        let table_2_ready_at = (start + TimeDelta::minutes(20)).to_rfc3339();
+        assert_eq!(vec![((start + TimeDelta::minutes(10)).to_rfc3339(), 10), (table_2_ready_at.clone(), 19)], created);
+        assert_eq!((table_2_ready_at.clone(), 18), (order.items[0].ready_at.clone(), order.items[0].remaining_mins));
+        assert_eq!((table_2_ready_at, 18), (item.ready_at, item.remaining_mins));
+    }
+
+    #[tokio::test]
+    async fn get_station_tickets__order_for_several_stations__each_station_sees_only_its_items() {
+        let mut sut = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }, { "item_id": "5", "qty": 2 }, { "item_id": "2", "qty": 1 }] });
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let mut tickets_by_station = vec![];
+        for station in ["grill", "bar", "fryer"] {
+            let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+                .await
+                .unwrap()
+                .call(
+                    Request::builder()
+                        .method(http::Method::GET)
+                        .uri(format!("/v0/stations/{}/tickets", station))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::OK, response.status());
+            let tickets: StationTicketsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+            assert_eq!(station, tickets.station);
+            tickets_by_station.push(tickets.tickets.iter().map(|t| (t.position, t.item_id.clone())).collect::<Vec<(usize, String)>>());
+        }
+
+        assert_eq!(vec![vec![(1, "1".to_string()), (2, "2".to_string())], vec![(1, "5".to_string())], vec![]], tickets_by_station);
+    }
+
+    #[tokio::test]
+    async fn get_station_tickets__unknown_station__is_404() {
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
+
+        let response = sut
+            .oneshot(Request::builder().uri("/v0/stations/oven/tickets").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_problem_response(response, StatusCode::NOT_FOUND, "station_not_found", "Station 'oven' does not exist, expected one of grill, fryer, cold or bar.").await;
+    }
+
+    #[tokio::test]
+    async fn get_kds_items__orders_from_several_tables__oldest_first_across_tables() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(start));
+        let mut sut = create_app_with_state(AppState::new(MemoryPersistence::default(), create_test_menu()).with_clock(clock.clone()));
+        for (table_id, item_id) in [(2, "3"), (1, "5"), (3, "1")] {
+            let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+                .await
+                .unwrap()
+                .call(json_request(http::Method::POST, &format!("/v0/orders/{}", table_id), &json!({ "items": [{ "item_id": item_id, "qty": 1 }] })))
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::CREATED, response.status());
+            clock.advance(TimeDelta::minutes(2));
+        }
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/kds/items").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        let kds: KdsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        let summary = kds
+            .pending
+            .iter()
+            .map(|i| (i.table_id.clone(), i.item_id.clone(), i.age_mins))
+            .collect::<Vec<(String, String, i64)>>();
+        assert_eq!(vec![("2".to_string(), "3".to_string(), 6), ("1".to_string(), "5".to_string(), 4), ("3".to_string(), "1".to_string(), 2)], summary);
+        assert!(kds.recallable.is_empty());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/kds/items?station=bar").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let kds: KdsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!(vec!["5".to_string()], kds.pending.iter().map(|i| i.item_id.clone()).collect::<Vec<String>>());
+    }
+
+    #[tokio::test]
+    async fn bump_kds_item__then_recall_within_window__front_of_house_sees_each_status() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(start));
+        let mut sut = create_app_with_state(AppState::new(MemoryPersistence::default(), create_test_menu()).with_clock(clock.clone()));
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &json!({ "items": [{ "item_id": "1", "qty": 1 }] })))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        clock.advance(TimeDelta::minutes(8));
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/v0/kds/tables/123/items/1/bump")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/orders/123").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let order: TableOrderViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!("ready", order.items[0].status);
+
+        clock.advance(TimeDelta::minutes(3));
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(Request::builder().uri("/v0/kds/items").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let kds: KdsViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert!(kds.pending.is_empty());
+        assert_eq!(Some((start + TimeDelta::minutes(13)).to_rfc3339()), kds.recallable[0].recall_until);
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/v0/kds/tables/123/items/1/recall")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let item: TableOrderItemDetailViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert_eq!("preparing", item.status);
+    }
+
+    #[tokio::test]
+    async fn recall_kds_item__after_window__is_409() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let clock = Arc::new(ManualClock::new(start));
+        let mut sut = create_app_with_state(AppState::new(MemoryPersistence::default(), create_test_menu()).with_clock(clock.clone()));
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(json_request(http::Method::POST, "/v0/orders/123", &json!({ "items": [{ "item_id": "1", "qty": 1 }] })))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/v0/kds/tables/123/items/1/bump")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        clock.advance(TimeDelta::minutes(6));
+        let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+            .await
+            .unwrap()
+            .call(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/v0/kds/tables/123/items/1/recall")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_problem_response(response, StatusCode::CONFLICT, "recall_window_expired", "Order line id 1 was bumped more than 5 minutes ago and can no longer be recalled.").await;
+    }
+
+    #[tokio::test]
+    async fn get_kitchen_queue__persistence_unavailable__is_500() {
+        let sut = create_app(UnavailablePersistence, create_test_menu());
+
+        let response = sut.oneshot(Request::builder().uri("/v0/kitchen/queue").body(Body::empty()).unwrap()).await.unwrap();
+
+        assert_problem_response(response, StatusCode::INTERNAL_SERVER_ERROR, "storage_error", "The order could not be read or saved, try again later.").await;
     }
 
     #[tokio::test]
     async fn get_order__order_does_not_exist__is_404() {
-        let sut = create_app(MemoryPersistence::default());
+        let sut = create_app(MemoryPersistence::default(), create_test_menu());
 
         let response = sut.oneshot(Request::builder().uri("/v0/orders/123").body(Body::empty()).unwrap()).await.unwrap();
 
-        assert_response(response, StatusCode::NOT_FOUND, "Order id 123 not found.").await;
+        let problem = assert_problem_response(response, StatusCode::NOT_FOUND, "order_not_found", "Order id 123 not found.").await;
+        assert_eq!(Some("123".to_string()), problem.table_id);
+        assert_eq!(None, problem.item_id);
     }
 
     #[tokio::test]
     async fn end_to_end_test() {
-        let mut sut = create_app(MemoryPersistence::default());
+        let mut sut = create_app(MemoryPersistence::default(), create_test_menu());
 
         // No orders initially
         {
@@ -59,7 +2188,7 @@ mod tests {
                 .await
                 .unwrap();
 
-            assert_response(response, StatusCode::NOT_FOUND, "Order id 123 not found.").await;
+            assert_problem_response(response, StatusCode::NOT_FOUND, "order_not_found", "Order id 123 not found.").await;
         }
 
         // Can add order
@@ -155,7 +2284,9 @@ mod tests {
                 .await
                 .unwrap();
             assert_eq!(StatusCode::NOT_FOUND, response.status());
-            assert_response(response, StatusCode::NOT_FOUND, "Order item id 404 not found.").await;
+            let problem = assert_problem_response(response, StatusCode::NOT_FOUND, "order_item_not_found", "Order line id 404 not found.").await;
+            assert_eq!(Some("123".to_string()), problem.table_id);
+            assert_eq!(Some("404".to_string()), problem.line_id);
         }
 
         // Can update the order with deleted and new items
@@ -183,13 +2314,21 @@ mod tests {
             assert_eq!(StatusCode::OK, response.status());
 
             let response_json = get_body_json(response).await;
-            let response_order: TableOrderViewModel = serde_json::from_value(response_json).unwrap();
+            let response_order: UpdatedTableOrderViewModel = serde_json::from_value(response_json).unwrap();
 
-            assert_eq!("123", response_order.table_id);
-            assert_eq!(vec![("1".to_string(), "menu item 1".to_string(), 1), ("4".to_string(), "menu item 4".to_string(), 4),], get_assertable_items_sorted(&response_order.items));
+            assert_eq!("123", response_order.order.table_id);
+            assert_eq!(vec![("1".to_string(), "menu item 1".to_string(), 1), ("4".to_string(), "menu item 4".to_string(), 4),], get_assertable_items_sorted(&response_order.order.items));
+            assert_eq!(
+                vec![("3".to_string(), 3), ("2".to_string(), 2)],
+                response_order
+                    .removed_items
+                    .iter()
+                    .map(|i| (i.item_id.clone(), i.quantity))
+                    .collect::<Vec<(String, i32)>>()
+            );
         }
 
-        // Can delete a single item
+        // Can delete a single item, menu item 1 kept line 1 through the update and 4 went on line 4
         {
             let response = ServiceExt::<Request<Body>>::ready(&mut sut)
                 .await
@@ -219,6 +2358,27 @@ mod tests {
             );
         }
 
+        // Can't delete the order until it has been billed and paid for
+        {
+            let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+                .await
+                .unwrap()
+                .call(Request::builder().method(http::Method::DELETE).uri("/v0/orders/123").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_problem_response(response, StatusCode::CONFLICT, "unbilled_items", "Order for table id 123 has changed since it was last billed, issue a new bill first.").await;
+
+            for (uri, body) in [("/v0/orders/123/bill", json!({})), ("/v0/orders/123/payments", json!({ "method": "cash" }))] {
+                let response = ServiceExt::<Request<Body>>::ready(&mut sut)
+                    .await
+                    .unwrap()
+                    .call(json_request(http::Method::POST, uri, &body))
+                    .await
+                    .unwrap();
+                assert_eq!(StatusCode::CREATED, response.status());
+            }
+        }
+
         // Can delete the order
         {
             let response = ServiceExt::<Request<Body>>::ready(&mut sut)
@@ -239,7 +2399,7 @@ mod tests {
                 .await
                 .unwrap();
 
-            assert_response(response, StatusCode::NOT_FOUND, "Order id 123 not found.").await;
+            assert_problem_response(response, StatusCode::NOT_FOUND, "order_not_found", "Order id 123 not found.").await;
         }
     }
 }
@@ -0,0 +1,245 @@
+#![allow(non_snake_case)]
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use crate::{
+        models::{
+            billing::{
+                Bill, BillId, BillServiceCharge, BillSplit, BillTax, BillingConfig, PayerShare, ReadBillError, ServiceChargeRule, SplitBillError, SplitPart, SplitPartLine, TaxMode, TaxRounding,
+            },
+            menu::{Menu, MenuItemId},
+            money::{Currency, Money, Percentage},
+            orders::{LineId, OrderChangeError, TableId, TableOrder, TableOrderItem},
+        },
+        tests::fixtures::menu_item,
+    };
+
+    fn issued_at() -> DateTime<Utc> {
+        return Utc.with_ymd_and_hms(2024, 1, 1, 21, 0, 0).unwrap();
+    }
+
+    fn eur(minor_units: i64) -> Money {
+        return Money::new(minor_units, Currency::Eur);
+    }
+
+    // Food is taxed at 10% and drinks at 20%
+    fn create_billing_config(tax_mode: TaxMode, tax_rounding: TaxRounding) -> BillingConfig {
+        return BillingConfig {
+            tax_mode: tax_mode,
+            tax_rounding: tax_rounding,
+            default_tax_rate: Percentage(10000),
+            tax_rates: [("drinks".to_string(), Percentage(20000))].into_iter().collect(),
+            service_charge: Some(ServiceChargeRule { min_guests: 6, rate: Percentage(12500) }),
+        };
+    }
+
+    // Item 1 is 1.05 of food, item 2 is 3.50 of drink
+    fn create_test_menu(billing: BillingConfig) -> Menu {
+        let create_item = |id: i32, category: &str, price: i64| menu_item(id, &format!("menu item {}", id)).with_category(category).with_price(eur(price));
+
+        return Menu::new(vec![create_item(1, "mains", 105), create_item(2, "drinks", 350)])
+            .unwrap()
+            .with_billing(billing);
+    }
+
+    fn create_order(items: &[(i32, i32)]) -> TableOrder {
+        let items = items
+            .iter()
+            .map(|(item_id, quantity)| TableOrderItem::new(MenuItemId(*item_id), *quantity, 10, issued_at()))
+            .collect::<Vec<TableOrderItem>>();
+        return TableOrder::new(TableId(123), &items);
+    }
+
+    #[test]
+    fn new__exclusive_tax__is_added_to_the_total() {
+        let menu = create_test_menu(create_billing_config(TaxMode::Exclusive, TaxRounding::PerLine));
+        let order = create_order(&[(1, 3), (2, 1)]);
+
+        let bill = Bill::new(BillId(1), &order, &menu, None, issued_at()).unwrap();
+
+        assert_eq!(vec![Some(eur(32)), Some(eur(70))], bill.lines.iter().map(|l| l.tax).collect::<Vec<Option<Money>>>());
+        assert_eq!(vec![BillTax { rate: Percentage(10000), taxable: eur(315), tax: eur(32) }, BillTax { rate: Percentage(20000), taxable: eur(350), tax: eur(70) }], bill.taxes);
+        assert_eq!(eur(665), bill.subtotal);
+        assert_eq!(eur(102), bill.tax_total);
+        assert_eq!(eur(767), bill.total);
+        assert_eq!("menu item 1", bill.lines[0].name);
+    }
+
+    #[test]
+    fn new__inclusive_tax__is_already_in_the_total() {
+        let menu = create_test_menu(create_billing_config(TaxMode::Inclusive, TaxRounding::PerLine));
+        let order = create_order(&[(2, 1)]);
+
+        let bill = Bill::new(BillId(1), &order, &menu, None, issued_at()).unwrap();
+
+        // 3.50 / 1.2 = 2.9167, so 0.58 of it is tax
+        assert_eq!(vec![BillTax { rate: Percentage(20000), taxable: eur(350), tax: eur(58) }], bill.taxes);
+        assert_eq!(eur(58), bill.tax_total);
+        assert_eq!(eur(350), bill.total);
+    }
+
+    #[test]
+    fn new__rounded_per_line_or_per_bill__can_differ_by_a_cent() {
+        let order = create_order(&[(1, 1), (1, 1), (1, 1)]);
+
+        let per_line = Bill::new(BillId(1), &order, &create_test_menu(create_billing_config(TaxMode::Exclusive, TaxRounding::PerLine)), None, issued_at()).unwrap();
+        let per_bill = Bill::new(BillId(1), &order, &create_test_menu(create_billing_config(TaxMode::Exclusive, TaxRounding::PerBill)), None, issued_at()).unwrap();
+
+        // 10% of 1.05 is 0.105, rounded up on each of the three lines but only once on the 3.15 they add up to
+        assert_eq!(eur(33), per_line.tax_total);
+        assert_eq!(eur(348), per_line.total);
+        assert_eq!(eur(32), per_bill.tax_total);
+        assert_eq!(eur(347), per_bill.total);
+        assert!(per_bill.lines.iter().all(|l| l.tax.is_none()));
+    }
+
+    #[test]
+    fn new__party_size__service_charge_only_from_min_guests() {
+        let menu = create_test_menu(create_billing_config(TaxMode::Inclusive, TaxRounding::PerLine));
+        let order = create_order(&[(2, 4)]);
+
+        let large = Bill::new(BillId(1), &order, &menu, Some(6), issued_at()).unwrap();
+        let small = Bill::new(BillId(1), &order, &menu, Some(5), issued_at()).unwrap();
+        let unknown = Bill::new(BillId(1), &order, &menu, None, issued_at()).unwrap();
+
+        assert_eq!(Some(BillServiceCharge { rate: Percentage(12500), amount: eur(175) }), large.service_charge);
+        assert_eq!(eur(1575), large.total);
+        assert_eq!(eur(233), large.tax_total); // the service charge isn't taxed
+        assert_eq!(None, small.service_charge);
+        assert_eq!(eur(1400), small.total);
+        assert_eq!(None, unknown.service_charge);
+    }
+
+    #[test]
+    fn new__item_no_longer_on_menu__is_error() {
+        let menu = create_test_menu(BillingConfig::default());
+        let order = create_order(&[(1, 1), (9, 1)]);
+
+        let result = Bill::new(BillId(1), &order, &menu, None, issued_at());
+
+        assert_eq!(Err(OrderChangeError::UnpricedItem("123".to_string(), "2".to_string(), "9".to_string())), result);
+    }
+
+    #[test]
+    fn issue_bill__order_changed_since__earlier_bill_is_kept_as_it_was() {
+        let menu = create_test_menu(BillingConfig::default());
+        let mut order = create_order(&[(1, 1)]);
+        assert_eq!(Err(ReadBillError::BillNotFound("123".to_string())), order.latest_bill());
+
+        order.issue_bill(&menu, None, issued_at()).unwrap();
+        order.add_item(TableOrderItem::new(MenuItemId(2), 1, 10, issued_at()));
+        order.issue_bill(&menu, Some(2), issued_at()).unwrap();
+
+        assert_eq!(vec![BillId(1), BillId(2)], order.bills.iter().map(|b| b.bill_id).collect::<Vec<BillId>>());
+        assert_eq!(vec![LineId(1)], order.bills[0].lines.iter().map(|l| l.line_id).collect::<Vec<LineId>>());
+        assert_eq!(eur(105), order.bills[0].total);
+        assert_eq!(eur(455), order.latest_bill().unwrap().total);
+        assert_eq!(Some(2), order.latest_bill().unwrap().guests);
+    }
+
+    #[test]
+    fn line_totals__tax_rounded_per_bill_and_service_charge__add_up_to_the_total() {
+        let menu = create_test_menu(create_billing_config(TaxMode::Exclusive, TaxRounding::PerBill));
+        let order = create_order(&[(1, 1), (1, 1), (1, 1), (2, 1)]);
+        let bill = Bill::new(BillId(1), &order, &menu, Some(6), issued_at()).unwrap();
+
+        let line_totals = bill.line_totals();
+
+        // The 0.32 of tax at 10% is shared out as 0.11, 0.11 and 0.10, the 0.83 service charge in proportion to the amounts
+        assert_eq!(vec![eur(105 + 11 + 13), eur(105 + 11 + 13), eur(105 + 10 + 13), eur(350 + 70 + 44)], line_totals);
+        assert_eq!(bill.total, Money::sum(line_totals, Currency::Eur));
+    }
+
+    #[test]
+    fn split__evenly__parts_differ_by_at_most_a_cent() {
+        let menu = create_test_menu(create_billing_config(TaxMode::Exclusive, TaxRounding::PerLine));
+        let bill = Bill::new(BillId(1), &create_order(&[(1, 3), (2, 1)]), &menu, None, issued_at()).unwrap();
+
+        let parts = bill.split(&TableId(123), &BillSplit::Even(3)).unwrap();
+
+        assert_eq!(vec![eur(256), eur(256), eur(255)], parts.iter().map(|p| p.amount).collect::<Vec<Money>>());
+        assert!(parts.iter().all(|p| p.lines.is_empty()));
+    }
+
+    #[test]
+    fn split__by_line_with_a_shared_line__each_pays_their_lines_and_parts_add_up() {
+        let menu = create_test_menu(create_billing_config(TaxMode::Exclusive, TaxRounding::PerLine));
+        let bill = Bill::new(BillId(1), &create_order(&[(1, 1), (2, 1)]), &menu, None, issued_at()).unwrap();
+        let payers = vec![vec![PayerShare { line_id: LineId(1), share: 1 }, PayerShare { line_id: LineId(2), share: 1 }], vec![PayerShare { line_id: LineId(2), share: 2 }]];
+
+        let parts = bill.split(&TableId(123), &BillSplit::ByLine(payers)).unwrap();
+
+        // Line 1 is 1.05 + 0.11 tax, line 2 is 3.50 + 0.70 tax, shared a third and two thirds
+        assert_eq!(
+            vec![
+                SplitPart {
+                    seat: None,
+                    amount: eur(116 + 140),
+                    lines: vec![SplitPartLine { line_id: LineId(1), share: 1, of: 1, amount: eur(116) }, SplitPartLine { line_id: LineId(2), share: 1, of: 3, amount: eur(140) }]
+                },
+                SplitPart { seat: None, amount: eur(280), lines: vec![SplitPartLine { line_id: LineId(2), share: 2, of: 3, amount: eur(280) }] },
+            ],
+            parts
+        );
+        assert_eq!(bill.total, Money::sum(parts.iter().map(|p| p.amount), Currency::Eur));
+    }
+
+    #[test]
+    fn split__by_line_leaving_a_line_out__is_error() {
+        let menu = create_test_menu(BillingConfig::default());
+        let bill = Bill::new(BillId(1), &create_order(&[(1, 1), (2, 1)]), &menu, None, issued_at()).unwrap();
+
+        let unassigned = bill.split(&TableId(123), &BillSplit::ByLine(vec![vec![PayerShare { line_id: LineId(1), share: 1 }]]));
+        let unknown = bill.split(&TableId(123), &BillSplit::ByLine(vec![vec![PayerShare { line_id: LineId(3), share: 1 }]]));
+
+        assert_eq!(Err(SplitBillError::UnassignedLine("123".to_string(), "1".to_string(), "2".to_string())), unassigned);
+        assert_eq!(Err(SplitBillError::LineNotOnBill("123".to_string(), "1".to_string(), "3".to_string())), unknown);
+    }
+
+    #[test]
+    fn split__by_line_with_shares_past_u32__is_error() {
+        let menu = create_test_menu(BillingConfig::default());
+        let bill = Bill::new(BillId(1), &create_order(&[(1, 1)]), &menu, None, issued_at()).unwrap();
+        let same_payer = vec![vec![PayerShare { line_id: LineId(1), share: u32::MAX }, PayerShare { line_id: LineId(1), share: 1 }]];
+        let between_payers = vec![vec![PayerShare { line_id: LineId(1), share: u32::MAX }], vec![PayerShare { line_id: LineId(1), share: 1 }]];
+
+        let expected = Err(SplitBillError::SharesTooLarge("123".to_string(), "1".to_string(), "1".to_string(), u32::MAX));
+        assert_eq!(expected, bill.split(&TableId(123), &BillSplit::ByLine(same_payer)));
+        assert_eq!(expected, bill.split(&TableId(123), &BillSplit::ByLine(between_payers)));
+    }
+
+    #[test]
+    fn split__by_seat__each_seat_pays_its_own_lines_and_shares_the_rest() {
+        let menu = create_test_menu(BillingConfig::default());
+        let mut order = create_order(&[]).with_guest_count(Some(3));
+        order
+            .add_items(
+                &[
+                    TableOrderItem::new(MenuItemId(2), 1, 10, issued_at()).with_seat(Some(1)),
+                    TableOrderItem::new(MenuItemId(1), 1, 10, issued_at()),
+                    TableOrderItem::new(MenuItemId(1), 1, 10, issued_at()).with_seat(Some(3)),
+                ],
+                100,
+            )
+            .unwrap();
+        let bill = order.issue_bill(&menu, None, issued_at()).unwrap();
+
+        let parts = bill.split(&TableId(123), &BillSplit::BySeat).unwrap();
+
+        // Seat 2 has nothing of its own so it isn't a payer, the shared 1.05 is split between seats 1 and 3
+        assert_eq!(vec![(Some(1), eur(350 + 53)), (Some(3), eur(105 + 52))], parts.iter().map(|p| (p.seat, p.amount)).collect::<Vec<(Option<u32>, Money)>>());
+        assert_eq!(Some(3), bill.guests);
+    }
+
+    #[test]
+    fn split__by_seat_with_nothing_seated__is_error() {
+        let menu = create_test_menu(BillingConfig::default());
+        let bill = Bill::new(BillId(1), &create_order(&[(1, 1)]), &menu, None, issued_at()).unwrap();
+
+        let result = bill.split(&TableId(123), &BillSplit::BySeat);
+
+        assert_eq!(Err(SplitBillError::NoSeats("123".to_string(), "1".to_string())), result);
+    }
+}
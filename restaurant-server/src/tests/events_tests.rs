@@ -0,0 +1,80 @@
+#![allow(non_snake_case)]
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use crate::{
+        events::{EventBus, MissedEvents, OrderEventKind, EVENT_HISTORY_CAPACITY},
+        models::orders::TableId,
+    };
+
+    fn published_at() -> DateTime<Utc> {
+        return Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+    }
+
+    fn publish_deletes(bus: &EventBus, count: usize) {
+        for table_id in 0..count {
+            bus.publish(&TableId(table_id as i32), published_at(), OrderEventKind::OrderDeleted(None));
+        }
+    }
+
+    #[test]
+    fn publish__no_subscribers__ids_still_count_up() {
+        let bus = EventBus::default();
+        assert_eq!(0, bus.latest_id());
+
+        publish_deletes(&bus, 3);
+
+        assert_eq!(3, bus.latest_id());
+    }
+
+    #[tokio::test]
+    async fn subscribe_after__earlier_id__replays_later_events_then_receives_live_ones() {
+        let bus = EventBus::default();
+        publish_deletes(&bus, 3);
+
+        let mut subscription = bus.subscribe_after(1);
+        bus.publish(&TableId(9), published_at(), OrderEventKind::OrderDeleted(None));
+
+        assert_eq!(MissedEvents::None, subscription.missed);
+        assert_eq!(vec![2, 3], subscription.replay.iter().map(|e| e.id).collect::<Vec<u64>>());
+        let live = subscription.receiver.recv().await.unwrap();
+        assert_eq!((4, TableId(9)), (live.id, live.table_id));
+    }
+
+    #[test]
+    fn subscribe_after__latest_id__nothing_to_replay() {
+        let bus = EventBus::default();
+        publish_deletes(&bus, 3);
+
+        let subscription = bus.subscribe_after(bus.latest_id());
+
+        assert_eq!(MissedEvents::None, subscription.missed);
+        assert!(subscription.replay.is_empty());
+    }
+
+    #[test]
+    fn subscribe_after__id_no_longer_in_history__counts_missed_events() {
+        let bus = EventBus::default();
+        publish_deletes(&bus, EVENT_HISTORY_CAPACITY + 10);
+
+        let subscription = bus.subscribe_after(5);
+
+        // 1 to 10 were dropped from the history, the client saw up to 5
+        assert_eq!(MissedEvents::Count(5), subscription.missed);
+        assert_eq!(EVENT_HISTORY_CAPACITY, subscription.replay.len());
+        assert_eq!(Some(11), subscription.replay.front().map(|e| e.id));
+    }
+
+    #[test]
+    fn subscribe_after__id_newer_than_any_event__is_unknown_and_replays_whole_history() {
+        let bus = EventBus::default();
+        publish_deletes(&bus, 2);
+
+        let subscription = bus.subscribe_after(500);
+
+        assert_eq!(MissedEvents::Unknown, subscription.missed);
+        assert_eq!(vec![1, 2], subscription.replay.iter().map(|e| e.id).collect::<Vec<u64>>());
+    }
+}
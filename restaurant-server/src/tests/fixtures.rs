@@ -0,0 +1,48 @@
+// Builders for the test data most test modules need, so a new field only has to be given a default here
+use crate::models::{
+    kitchen::Station,
+    menu::{MenuItem, MenuItemId, MenuModifier},
+    money::{Currency, Money},
+};
+
+// 10.00 on the grill, taking 10 minutes however many are ordered. Change whatever a test cares about with the with_* setters below.
+pub fn menu_item(id: i32, name: &str) -> MenuItem {
+    return MenuItem {
+        id: MenuItemId(id),
+        name: name.to_string(),
+        description: String::new(),
+        category: "test".to_string(),
+        price: Money::new(1000, Currency::Eur),
+        station: Station::Grill,
+        prep_time_mins: 10,
+        prep_time_per_extra_mins: 0,
+        modifiers: vec![],
+    };
+}
+
+// Only the tests build menu items by hand, the server loads them from menu.toml
+impl MenuItem {
+    pub fn with_description(self, description: &str) -> Self {
+        return Self { description: description.to_string(), ..self };
+    }
+
+    pub fn with_category(self, category: &str) -> Self {
+        return Self { category: category.to_string(), ..self };
+    }
+
+    pub fn with_price(self, price: Money) -> Self {
+        return Self { price: price, ..self };
+    }
+
+    pub fn with_station(self, station: Station) -> Self {
+        return Self { station: station, ..self };
+    }
+
+    pub fn with_prep_time(self, prep_time_mins: i32, prep_time_per_extra_mins: i32) -> Self {
+        return Self { prep_time_mins: prep_time_mins, prep_time_per_extra_mins: prep_time_per_extra_mins, ..self };
+    }
+
+    pub fn with_modifiers(self, modifiers: Vec<MenuModifier>) -> Self {
+        return Self { modifiers: modifiers, ..self };
+    }
+}
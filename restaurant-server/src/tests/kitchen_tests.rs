@@ -0,0 +1,190 @@
+#![allow(non_snake_case)]
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+
+    use crate::{
+        models::{
+            kitchen::{list_pending_items, list_recallable_items, schedule_kitchen_queue, KitchenConfig, ParseKitchenConfigError, ParseStationError, Station},
+            menu::{Menu, MenuItemId},
+            orders::{LineId, OrderItemStatus, TableId, TableOrder, TableOrderItem},
+        },
+        tests::fixtures::menu_item,
+    };
+
+    fn start_time() -> DateTime<Utc> {
+        return Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+    }
+
+    fn create_order(table_id: i32, items: &[TableOrderItem]) -> TableOrder {
+        return TableOrder::new(TableId(table_id), items);
+    }
+
+    // Items 1-3 are cooked on the grill, 4 at the bar
+    fn create_test_menu() -> Menu {
+        let items = (1..=4)
+            .map(|i| menu_item(i, &format!("menu item {}", i)).with_station(if i == 4 { Station::Bar } else { Station::Grill }))
+            .collect();
+        return Menu::new(items).unwrap();
+    }
+
+    fn get_queue_summary(orders: &[TableOrder], grill_cooks: usize, now: DateTime<Utc>) -> Vec<(i32, i32, usize, i64)> {
+        let config = KitchenConfig::default().with_capacity(Station::Grill, grill_cooks);
+        return schedule_kitchen_queue(orders, &create_test_menu(), &config, now)
+            .iter()
+            .map(|q| (q.table_id.0, q.item.item_id.0, q.cook, (q.estimated_ready_at - start_time()).num_minutes()))
+            .collect();
+    }
+
+    #[test]
+    fn schedule_kitchen_queue__no_orders__is_empty() {
+        assert!(schedule_kitchen_queue(&[], &create_test_menu(), &KitchenConfig::default(), start_time()).is_empty());
+    }
+
+    #[test]
+    fn schedule_kitchen_queue__enough_cooks__items_are_ready_after_their_own_time() {
+        let orders = vec![create_order(1, &[TableOrderItem::new(MenuItemId(1), 1, 10, start_time()), TableOrderItem::new(MenuItemId(2), 1, 5, start_time())])];
+
+        let result = get_queue_summary(&orders, 2, start_time());
+
+        assert_eq!(vec![(1, 1, 0, 10), (1, 2, 1, 5)], result);
+    }
+
+    #[test]
+    fn schedule_kitchen_queue__one_cook__items_wait_in_arrival_order() {
+        let orders =
+            vec![create_order(2, &[TableOrderItem::new(MenuItemId(1), 1, 10, start_time() + TimeDelta::minutes(1))]), create_order(1, &[TableOrderItem::new(MenuItemId(3), 1, 5, start_time())])];
+
+        let result = get_queue_summary(&orders, 1, start_time() + TimeDelta::minutes(1));
+
+        assert_eq!(vec![(1, 3, 0, 6), (2, 1, 0, 16)], result);
+    }
+
+    #[test]
+    fn schedule_kitchen_queue__more_items_than_cooks__next_item_goes_to_first_free_cook() {
+        let orders = vec![create_order(
+            1,
+            &[TableOrderItem::new(MenuItemId(1), 1, 10, start_time()), TableOrderItem::new(MenuItemId(2), 1, 4, start_time()), TableOrderItem::new(MenuItemId(3), 1, 3, start_time())],
+        )];
+
+        let result = get_queue_summary(&orders, 2, start_time());
+
+        assert_eq!(vec![(1, 1, 0, 10), (1, 2, 1, 4), (1, 3, 1, 7)], result);
+    }
+
+    #[test]
+    fn schedule_kitchen_queue__item_being_prepared__goes_first_and_only_remaining_time_counts() {
+        let waiting_order = create_order(1, &[TableOrderItem::new(MenuItemId(1), 1, 5, start_time())]);
+        let mut preparing_order = create_order(2, &[TableOrderItem::new(MenuItemId(2), 1, 10, start_time() + TimeDelta::minutes(1))]);
+        preparing_order
+            .advance_item_status(&LineId(1), OrderItemStatus::Preparing, start_time() + TimeDelta::minutes(2))
+            .unwrap();
+
+        let result = get_queue_summary(&[waiting_order, preparing_order], 1, start_time() + TimeDelta::minutes(4));
+
+        assert_eq!(vec![(2, 2, 0, 12), (1, 1, 0, 17)], result);
+    }
+
+    #[test]
+    fn schedule_kitchen_queue__ready_and_served_items__are_not_queued() {
+        let mut order = create_order(1, &[TableOrderItem::new(MenuItemId(1), 1, 5, start_time()), TableOrderItem::new(MenuItemId(2), 1, 5, start_time())]);
+        order.advance_item_status(&LineId(1), OrderItemStatus::Preparing, start_time()).unwrap();
+        order.advance_item_status(&LineId(1), OrderItemStatus::Ready, start_time()).unwrap();
+
+        let result = get_queue_summary(&[order], 1, start_time());
+
+        assert_eq!(vec![(1, 2, 0, 5)], result);
+    }
+
+    #[test]
+    fn schedule_kitchen_queue__overdue_item_being_prepared__is_ready_now() {
+        let mut order = create_order(1, &[TableOrderItem::new(MenuItemId(1), 1, 5, start_time())]);
+        order.advance_item_status(&LineId(1), OrderItemStatus::Preparing, start_time()).unwrap();
+
+        let result = get_queue_summary(&[order], 1, start_time() + TimeDelta::minutes(8));
+
+        assert_eq!(vec![(1, 1, 0, 8)], result);
+    }
+
+    #[test]
+    fn schedule_kitchen_queue__items_for_different_stations__do_not_wait_on_each_other() {
+        let orders = vec![create_order(
+            1,
+            &[TableOrderItem::new(MenuItemId(1), 1, 10, start_time()), TableOrderItem::new(MenuItemId(2), 1, 10, start_time()), TableOrderItem::new(MenuItemId(4), 1, 2, start_time())],
+        )];
+        let config = KitchenConfig::default().with_capacity(Station::Grill, 1).with_capacity(Station::Bar, 1);
+
+        let result = schedule_kitchen_queue(&orders, &create_test_menu(), &config, start_time());
+
+        let summary = result
+            .iter()
+            .map(|q| (q.item.item_id.0, q.station, q.cook, (q.estimated_ready_at - start_time()).num_minutes()))
+            .collect::<Vec<(i32, Station, usize, i64)>>();
+        assert_eq!(vec![(1, Station::Grill, 0, 10), (2, Station::Grill, 0, 20), (4, Station::Bar, 0, 2)], summary);
+    }
+
+    #[test]
+    fn schedule_kitchen_queue__item_not_on_menu__is_left_out() {
+        let orders = vec![create_order(1, &[TableOrderItem::new(MenuItemId(1), 1, 10, start_time()), TableOrderItem::new(MenuItemId(99), 1, 10, start_time())])];
+
+        let result = get_queue_summary(&orders, 1, start_time());
+
+        assert_eq!(vec![(1, 1, 0, 10)], result);
+    }
+
+    #[test]
+    fn station_from_str__round_trips_display() {
+        for station in [Station::Grill, Station::Fryer, Station::Cold, Station::Bar] {
+            assert_eq!(Ok(station), station.to_string().parse::<Station>());
+        }
+        assert_eq!(Err(ParseStationError("oven".to_string())), "oven".parse::<Station>());
+    }
+
+    #[test]
+    fn from_capacities_str__listed_stations__override_defaults() {
+        let result = KitchenConfig::from_capacities_str("grill=3, bar=1");
+
+        let config = result.unwrap();
+        assert_eq!(3, config.capacity(Station::Grill));
+        assert_eq!(1, config.capacity(Station::Bar));
+        assert_eq!(KitchenConfig::default().capacity(Station::Fryer), config.capacity(Station::Fryer));
+    }
+
+    #[test]
+    fn from_capacities_str__invalid_entries__are_errors() {
+        assert_eq!(Err(ParseKitchenConfigError::InvalidEntry("grill".to_string())), KitchenConfig::from_capacities_str("grill"));
+        assert_eq!(Err(ParseKitchenConfigError::UnknownStation(ParseStationError("oven".to_string()))), KitchenConfig::from_capacities_str("oven=2"));
+        assert_eq!(Err(ParseKitchenConfigError::InvalidCapacity("grill".to_string())), KitchenConfig::from_capacities_str("grill=0"));
+        assert_eq!(Err(ParseKitchenConfigError::InvalidCapacity("bar".to_string())), KitchenConfig::from_capacities_str("bar=lots"));
+    }
+
+    #[test]
+    fn list_pending_items__across_tables__oldest_first_and_done_items_left_out() {
+        let mut done = create_order(3, &[TableOrderItem::new(MenuItemId(1), 1, 10, start_time())]);
+        done.bump_item(&LineId(1), start_time()).unwrap();
+        let orders = vec![
+            create_order(1, &[TableOrderItem::new(MenuItemId(1), 1, 10, start_time() + TimeDelta::minutes(2)), TableOrderItem::new(MenuItemId(4), 1, 10, start_time() + TimeDelta::minutes(2))]),
+            create_order(2, &[TableOrderItem::new(MenuItemId(2), 1, 10, start_time() + TimeDelta::minutes(1)), TableOrderItem::new(MenuItemId(99), 1, 10, start_time() + TimeDelta::minutes(3))]),
+            done,
+        ];
+
+        let result = list_pending_items(&orders, &create_test_menu());
+
+        let summary = result.iter().map(|i| (i.table_id.0, i.item.item_id.0, i.station)).collect::<Vec<_>>();
+        assert_eq!(vec![(2, 2, Some(Station::Grill)), (1, 1, Some(Station::Grill)), (1, 4, Some(Station::Bar)), (2, 99, None)], summary);
+    }
+
+    #[test]
+    fn list_recallable_items__bumped_within_window__most_recent_first() {
+        let mut order = create_order(1, &[1, 2, 3].map(|i| TableOrderItem::new(MenuItemId(i), 1, 10, start_time())));
+        order.bump_item(&LineId(1), start_time()).unwrap();
+        order.bump_item(&LineId(2), start_time() + TimeDelta::minutes(3)).unwrap();
+        order.bump_item(&LineId(3), start_time() + TimeDelta::minutes(4)).unwrap();
+        let now = start_time() + TimeDelta::minutes(6);
+
+        let result = list_recallable_items(&[order], &create_test_menu(), &KitchenConfig::default(), now);
+
+        assert_eq!(vec![3, 2], result.iter().map(|i| i.item.item_id.0).collect::<Vec<_>>());
+    }
+}
@@ -4,308 +4,94 @@
 // For the main reason that when I do a (text) search in my editor for a function call later, I want to be able to filter out test usages to see the actual usages.
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::{
+        collections::HashMap,
+        sync::{mpsc, Arc},
+        time::Duration,
+    };
 
     use crate::{
         models::{
             menu::MenuItemId,
-            orders::{TableId, TableOrder, TableOrderItem},
+            orders::{LineId, OrderItemStatus, TableId, TableOrder, TableOrderItem},
         },
         persistence::{
-            memory_persistence::{get_underlying_data, item_slice_to_hashmap, MemoryPersistence},
-            persistence::{CreateOrderError, Persistence, ReadOrderError, ReadOrderItemError},
+            memory_persistence::{get_table_lock, get_underlying_data, MemoryPersistence},
+            persistence::{Persistence, ReadOrderError},
         },
+        tests::persistence_tests::{all_quantities_are_20, ordered_at, persistence_tests, run_concurrent_requests},
     };
 
-    #[tokio::test]
-    async fn create_order__no_existing_order__is_created() {
-        let table_id = TableId(123);
-        let items = vec![
-            TableOrderItem { item_id: MenuItemId(1), quantity: 1, total_preparation_time_mins: 10 },
-            TableOrderItem { item_id: MenuItemId(2), quantity: 1, total_preparation_time_mins: 11 },
-            TableOrderItem { item_id: MenuItemId(3), quantity: 1, total_preparation_time_mins: 12 },
-        ];
-        let mut sut = MemoryPersistence::default();
-
-        let result = sut.create_order(&table_id, &items).await;
-
-        assert!(result.is_ok());
-        let result = result.unwrap();
-        assert_eq!(TableId(123), result.table_id);
-        assert_eq!(3, result.items.len());
-        assert_eq!(items[0], *result.items.get(&items[0].item_id).unwrap());
-        assert_eq!(items[1], *result.items.get(&items[1].item_id).unwrap());
-        assert_eq!(items[2], *result.items.get(&items[2].item_id).unwrap());
-        assert_eq!(1, get_underlying_data(sut).len());
+    async fn create_sut(data: HashMap<TableId, TableOrder>) -> MemoryPersistence {
+        return MemoryPersistence::new(data);
     }
 
-    #[tokio::test]
-    async fn create_order__table_has_existing_order__is_error() {
-        let table_id = TableId(123);
-        let items = vec![
-            TableOrderItem { item_id: MenuItemId(1), quantity: 1, total_preparation_time_mins: 10 },
-            TableOrderItem { item_id: MenuItemId(2), quantity: 1, total_preparation_time_mins: 11 },
-            TableOrderItem { item_id: MenuItemId(3), quantity: 1, total_preparation_time_mins: 12 },
-        ];
-        let mut data: HashMap<TableId, TableOrder> = HashMap::new();
-        data.insert(table_id.clone(), TableOrder { table_id: table_id.clone(), items: HashMap::default() });
-        let mut sut = MemoryPersistence::new(data);
+    persistence_tests!();
 
-        let result = sut.create_order(&table_id, &items).await;
+    // Runs the lookup on its own thread, so a test can observe whether it is stuck waiting on a lock
+    fn find_order_on_thread(sut: Arc<MemoryPersistence>, table_id: TableId) -> mpsc::Receiver<Result<TableOrder, ReadOrderError>> {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let _ = sender.send(runtime.block_on(sut.find_order(&table_id)));
+        });
 
-        assert!(result.is_err());
-        assert_eq!(CreateOrderError::OrderAlreadyExistsForTable(table_id.to_string()), result.unwrap_err());
-        assert_eq!(1, get_underlying_data(sut).len());
+        return receiver;
     }
 
-    #[tokio::test]
-    async fn update_order__no_existing_order__is_error() {
-        let table_id = TableId(123);
-        let items = vec![
-            TableOrderItem { item_id: MenuItemId(1), quantity: 1, total_preparation_time_mins: 10 },
-            TableOrderItem { item_id: MenuItemId(2), quantity: 1, total_preparation_time_mins: 11 },
-            TableOrderItem { item_id: MenuItemId(3), quantity: 1, total_preparation_time_mins: 12 },
-        ];
-        let mut sut = MemoryPersistence::default();
-
-        let result = sut.update_order(&table_id, &items).await;
-
-        assert!(result.is_err());
-        assert_eq!(ReadOrderError::OrderNotFound(table_id.to_string()), result.unwrap_err());
-        assert_eq!(0, get_underlying_data(sut).len());
+    fn create_orders_for_tables(table_ids: &[TableId]) -> HashMap<TableId, TableOrder> {
+        return table_ids.iter().map(|id| (id.clone(), TableOrder::new(id.clone(), &[]))).collect();
     }
 
     #[tokio::test]
-    async fn update_order__existing_order__replaces_items() {
+    async fn update_order_with__change_succeeds__saved_is_called_while_table_is_locked() {
         let table_id = TableId(123);
-        let existing_items = vec![
-            TableOrderItem { item_id: MenuItemId(1), quantity: 1, total_preparation_time_mins: 10 },
-            TableOrderItem { item_id: MenuItemId(2), quantity: 1, total_preparation_time_mins: 11 },
-            TableOrderItem { item_id: MenuItemId(3), quantity: 1, total_preparation_time_mins: 12 },
-        ];
         let mut data: HashMap<TableId, TableOrder> = HashMap::new();
-        data.insert(table_id.clone(), TableOrder { table_id: table_id.clone(), items: item_slice_to_hashmap(&existing_items) });
-
-        let mut sut = MemoryPersistence::new(data);
-
-        let table_id = TableId(123);
-        let new_items = vec![
-            TableOrderItem { item_id: MenuItemId(2), quantity: 1, total_preparation_time_mins: 11 },
-            TableOrderItem { item_id: MenuItemId(3), quantity: 1, total_preparation_time_mins: 12 },
-            TableOrderItem { item_id: MenuItemId(4), quantity: 1, total_preparation_time_mins: 13 },
-            TableOrderItem { item_id: MenuItemId(5), quantity: 1, total_preparation_time_mins: 14 },
-        ];
-
-        let result = sut.update_order(&table_id, &new_items).await;
-
-        assert!(result.is_ok());
-        let result = result.unwrap();
-        assert_eq!(TableId(123), result.table_id);
-        assert_eq!(4, result.items.len());
-        assert_eq!(new_items[0], *result.items.get(&new_items[0].item_id).unwrap());
-        assert_eq!(new_items[1], *result.items.get(&new_items[1].item_id).unwrap());
-        assert_eq!(new_items[2], *result.items.get(&new_items[2].item_id).unwrap());
-        assert_eq!(new_items[3], *result.items.get(&new_items[3].item_id).unwrap());
-        assert_eq!(1, get_underlying_data(sut).len());
-    }
-
-    #[tokio::test]
-    async fn find_order__unknown_id__is_error() {
-        let table_id = TableId(123);
-        let sut = MemoryPersistence::default();
-
-        let result = sut.find_order(&table_id).await;
-
-        assert!(result.is_err());
-        assert_eq!(ReadOrderError::OrderNotFound(table_id.to_string()), result.unwrap_err());
-    }
-
-    #[tokio::test]
-    async fn find_order__id_exists__returns_order() {
-        let table_id = TableId(123);
-        let mut data: HashMap<TableId, TableOrder> = HashMap::new();
-
-        let expected_order = TableOrder { table_id: table_id.clone(), items: HashMap::default() };
-        data.insert(table_id.clone(), expected_order.clone());
-
+        data.insert(table_id.clone(), TableOrder::new(table_id.clone(), &[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]));
         let sut = MemoryPersistence::new(data);
+        let table_lock = get_table_lock(&sut, &table_id).unwrap();
 
-        let result = sut.find_order(&table_id).await;
-
-        assert!(result.is_ok());
-        assert_eq!(expected_order, *result.unwrap());
-    }
-
-    #[tokio::test]
-    async fn delete_order__order_does_not_exist__is_error() {
-        let mut sut = MemoryPersistence::default();
-
-        let table_id = TableId(123);
-        let result = sut.delete_order(&table_id).await;
+        let mut saved = None;
+        let result = sut
+            .update_order_with(&table_id, |o| o.advance_item_status(&LineId(1), OrderItemStatus::Preparing, ordered_at()), |o| saved = Some((o.clone(), table_lock.try_lock().is_err())))
+            .await;
 
-        assert!(result.is_err());
-        assert_eq!(ReadOrderError::OrderNotFound(table_id.to_string()), result.unwrap_err());
+        assert_eq!(Some((result.unwrap(), true)), saved);
     }
 
-    #[tokio::test]
-    async fn delete_order__order_exists__is_deleted() {
-        let table_id = TableId(123);
-        let mut data: HashMap<TableId, TableOrder> = HashMap::new();
-        data.insert(table_id.clone(), TableOrder { table_id: table_id.clone(), items: HashMap::default() });
-        let mut sut = MemoryPersistence::new(data);
+    #[test]
+    fn find_order__other_table_is_locked__is_not_blocked() {
+        let sut = Arc::new(MemoryPersistence::new(create_orders_for_tables(&[TableId(1), TableId(2)])));
+        let table_1_lock = get_table_lock(&sut, &TableId(1)).unwrap();
+        let _table_1_guard = table_1_lock.lock().unwrap();
 
-        let table_id = TableId(123);
-        let result = sut.delete_order(&table_id).await;
+        let result = find_order_on_thread(Arc::clone(&sut), TableId(2)).recv_timeout(Duration::from_secs(5));
 
         assert!(result.is_ok());
-        assert_eq!(0, get_underlying_data(sut).len());
+        assert_eq!(TableId(2), result.unwrap().unwrap().table_id);
     }
 
-    #[tokio::test]
-    async fn delete_order_item__order_does_not_exist__is_error() {
-        let mut sut = MemoryPersistence::default();
+    #[test]
+    fn find_order__same_table_is_locked__waits_for_lock() {
+        let sut = Arc::new(MemoryPersistence::new(create_orders_for_tables(&[TableId(1), TableId(2)])));
+        let table_1_lock = get_table_lock(&sut, &TableId(1)).unwrap();
+        let table_1_guard = table_1_lock.lock().unwrap();
 
-        let table_id = TableId(123);
-        let item_id = MenuItemId(1);
-        let result = sut.delete_order_item(&table_id, &item_id).await;
+        let receiver = find_order_on_thread(Arc::clone(&sut), TableId(1));
 
-        assert!(result.is_err());
-        assert_eq!(ReadOrderItemError::OrderNotFound(table_id.to_string()), result.unwrap_err());
+        assert_eq!(Err(mpsc::RecvTimeoutError::Timeout), receiver.recv_timeout(Duration::from_millis(100)));
+        drop(table_1_guard);
+        assert_eq!(TableId(1), receiver.recv_timeout(Duration::from_secs(5)).unwrap().unwrap().table_id);
     }
 
-    #[tokio::test]
-    async fn delete_order_item__order_item_does_not_exist__is_error() {
-        let table_id = TableId(123);
-        let mut data: HashMap<TableId, TableOrder> = HashMap::new();
-        let existing_items = vec![
-            TableOrderItem { item_id: MenuItemId(1), quantity: 1, total_preparation_time_mins: 10 },
-            TableOrderItem { item_id: MenuItemId(2), quantity: 1, total_preparation_time_mins: 11 },
-            TableOrderItem { item_id: MenuItemId(3), quantity: 1, total_preparation_time_mins: 12 },
-        ];
-        data.insert(table_id.clone(), TableOrder { table_id: table_id.clone(), items: item_slice_to_hashmap(&existing_items) });
-        let mut sut = MemoryPersistence::new(data);
-
-        let item_id = MenuItemId(9999);
-        let result = sut.delete_order_item(&table_id, &item_id).await;
-
-        assert!(result.is_err());
-        assert_eq!(ReadOrderItemError::OrderItemNotFound(item_id.to_string()), result.unwrap_err());
-        assert_eq!(3, get_underlying_data(sut).get(&table_id).unwrap().items.len());
-    }
-
-    #[tokio::test]
-    async fn delete_order_item__order_and_item_exists__is_deleted() {
-        let table_id = TableId(123);
-        let mut data: HashMap<TableId, TableOrder> = HashMap::new();
-        let existing_items = vec![
-            TableOrderItem { item_id: MenuItemId(1), quantity: 1, total_preparation_time_mins: 10 },
-            TableOrderItem { item_id: MenuItemId(2), quantity: 1, total_preparation_time_mins: 11 },
-            TableOrderItem { item_id: MenuItemId(3), quantity: 1, total_preparation_time_mins: 12 },
-        ];
-        data.insert(table_id.clone(), TableOrder { table_id: table_id.clone(), items: item_slice_to_hashmap(&existing_items) });
-        let mut sut = MemoryPersistence::new(data);
-
-        let item_id = MenuItemId(2);
-        let result = sut.delete_order_item(&table_id, &item_id).await;
-
-        assert!(result.is_ok());
-
-        let mut order_item_ids = result.unwrap().items.iter().map(|kv| kv.1.item_id.clone()).collect::<Vec<MenuItemId>>();
-        order_item_ids.sort();
-
-        assert_eq!(vec![MenuItemId(1), MenuItemId(3)], order_item_ids);
-
-        let mut underlying_item_ids = get_underlying_data(sut)
-            .get(&table_id)
-            .unwrap()
-            .items
-            .iter()
-            .map(|kv| kv.1.item_id.clone())
-            .collect::<Vec<MenuItemId>>();
-        underlying_item_ids.sort();
-        assert_eq!(vec![MenuItemId(1), MenuItemId(3)], underlying_item_ids);
-    }
-
-    #[tokio::test]
-    async fn general_persistence_behavior() {
-        let table_id = TableId(123);
-        let items = vec![
-            TableOrderItem { item_id: MenuItemId(1), quantity: 1, total_preparation_time_mins: 10 },
-            TableOrderItem { item_id: MenuItemId(2), quantity: 1, total_preparation_time_mins: 11 },
-            TableOrderItem { item_id: MenuItemId(3), quantity: 1, total_preparation_time_mins: 12 },
-        ];
-        let mut sut = MemoryPersistence::default();
-
-        // No orders initially
-        assert!(sut.find_order(&table_id).await.is_err());
-
-        // Can add order
-        let added_order;
-        {
-            let result = sut.create_order(&table_id, &items).await;
-            assert!(result.is_ok());
-            added_order = result.unwrap().clone();
-
-            let mut added_order_item_ids = added_order.items.values().into_iter().map(|i| i.item_id.clone()).collect::<Vec<MenuItemId>>();
-            added_order_item_ids.sort();
-            assert_eq!(TableId(123), added_order.table_id);
-            assert_eq!(vec![MenuItemId(1), MenuItemId(2), MenuItemId(3)], added_order_item_ids);
-        }
-
-        // Can find the order after creation
-        {
-            let found_order = sut.find_order(&table_id).await;
-            assert!(found_order.is_ok());
-            let found_order = found_order.unwrap();
-            let mut found_order_item_ids = found_order.items.values().into_iter().map(|i| i.item_id.clone()).collect::<Vec<MenuItemId>>();
-            found_order_item_ids.sort();
-            assert_eq!(TableId(123), found_order.table_id);
-            assert_eq!(vec![MenuItemId(1), MenuItemId(2), MenuItemId(3)], found_order_item_ids);
-        }
-
-        // Can update the order with deleted and new items
-        let updated_order;
-        {
-            let new_items =
-                vec![TableOrderItem { item_id: MenuItemId(2), quantity: 1, total_preparation_time_mins: 11 }, TableOrderItem { item_id: MenuItemId(4), quantity: 1, total_preparation_time_mins: 14 }];
-
-            let result = sut.update_order(&table_id, &new_items).await;
-            assert!(result.is_ok());
-            updated_order = result.unwrap().clone();
-
-            let mut updated_order_item_ids = updated_order.items.values().into_iter().map(|i| i.item_id.clone()).collect::<Vec<MenuItemId>>();
-            updated_order_item_ids.sort();
-            assert_eq!(TableId(123), updated_order.table_id);
-            assert_eq!(vec![MenuItemId(2), MenuItemId(4)], updated_order_item_ids);
-        }
-
-        // Can delete a single item
-        {
-            let result = sut.delete_order_item(&table_id, &MenuItemId(2)).await;
-            assert!(result.is_ok());
-
-            let order_after_deletion = result.unwrap();
-            let mut order_after_deletion_item_ids = order_after_deletion
-                .items
-                .values()
-                .into_iter()
-                .map(|i| i.item_id.clone())
-                .collect::<Vec<MenuItemId>>();
-            order_after_deletion_item_ids.sort();
-            assert_eq!(TableId(123), order_after_deletion.table_id);
-            assert_eq!(vec![MenuItemId(4)], order_after_deletion_item_ids);
-        }
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_requests__many_tables__all_applied() {
+        let sut = Arc::new(MemoryPersistence::default());
 
-        // Can delete the order
-        {
-            let result = sut.delete_order(&table_id).await;
-            assert!(result.is_ok());
-        }
+        run_concurrent_requests(&sut).await;
 
-        // Can no longer find the order
-        {
-            let result = sut.find_order(&table_id).await;
-            assert!(result.is_err());
-        }
+        let data = get_underlying_data(Arc::into_inner(sut).unwrap());
+        assert_eq!(50, data.len());
+        assert!(all_quantities_are_20(&data));
     }
 }
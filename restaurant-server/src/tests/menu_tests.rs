@@ -0,0 +1,376 @@
+#![allow(non_snake_case)]
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        models::{
+            billing::{BillingConfig, ServiceChargeRule, TaxMode, TaxRounding},
+            menu::{LoadMenuError, Menu, MenuItem, MenuItemId, MenuModifier, ModifierId, PreparationJitter, ReadMenuItemError, MAX_PREPARATION_TIME_MINS},
+            money::{Currency, Money, Percentage},
+        },
+        tests::fixtures::menu_item,
+    };
+
+    fn create_menu_item(prep_time_mins: i32, prep_time_per_extra_mins: i32) -> MenuItem {
+        return menu_item(1, "Cheeseburger")
+            .with_category("mains")
+            .with_price(Money::new(1250, Currency::Eur))
+            .with_prep_time(prep_time_mins, prep_time_per_extra_mins);
+    }
+
+    #[test]
+    fn from_toml__valid_menu__items_are_found() {
+        let contents = r#"
+            [[items]]
+            id = 2
+            name = "Caesar salad"
+            description = "Romaine and parmesan."
+            category = "starters"
+            price = "6.50 EUR"
+            station = "cold"
+            prep_time_mins = 7
+
+            [[items]]
+            id = 1
+            name = "Tomato soup"
+            description = "Served with sourdough."
+            category = "starters"
+            price = "5.00 EUR"
+            station = "cold"
+            prep_time_mins = 5
+        "#;
+
+        let result = Menu::from_toml(contents);
+
+        assert!(result.is_ok());
+        let menu = result.unwrap();
+        assert_eq!(vec![MenuItemId(1), MenuItemId(2)], menu.items().map(|i| i.id.clone()).collect::<Vec<MenuItemId>>());
+        assert_eq!("Caesar salad", menu.find_item(&MenuItemId(2)).unwrap().name);
+        assert_eq!(7, menu.find_item(&MenuItemId(2)).unwrap().prep_time_mins);
+        assert_eq!(ReadMenuItemError::MenuItemNotFound("3".to_string()), menu.find_item(&MenuItemId(3)).unwrap_err());
+    }
+
+    #[test]
+    fn from_toml__duplicate_item_id__is_error() {
+        let contents = r#"
+            [[items]]
+            id = 1
+            name = "Tomato soup"
+            description = ""
+            category = "starters"
+            price = "5.00 EUR"
+            station = "cold"
+            prep_time_mins = 5
+
+            [[items]]
+            id = 1
+            name = "Caesar salad"
+            description = ""
+            category = "starters"
+            price = "7.50 EUR"
+            station = "cold"
+            prep_time_mins = 7
+        "#;
+
+        let result = Menu::from_toml(contents);
+
+        assert!(matches!(result, Err(LoadMenuError::DuplicateItemId(id)) if id == "1"));
+    }
+
+    #[test]
+    fn from_toml__item_modifiers__are_loaded_in_order() {
+        let contents = r#"
+            [[items]]
+            id = 4
+            name = "Ribeye steak"
+            description = ""
+            category = "mains"
+            price = "24.00 EUR"
+            station = "grill"
+            prep_time_mins = 18
+
+            [[items.modifiers]]
+            id = "rare"
+            name = "Rare"
+            group = "temperature"
+
+            [[items.modifiers]]
+            id = "no_sauce"
+            name = "No sauce"
+        "#;
+
+        let menu = Menu::from_toml(contents).unwrap();
+
+        let item = menu.find_item(&MenuItemId(4)).unwrap();
+        assert_eq!(
+            vec![
+                MenuModifier { id: ModifierId("rare".to_string()), name: "Rare".to_string(), group: Some("temperature".to_string()), price: None },
+                MenuModifier { id: ModifierId("no_sauce".to_string()), name: "No sauce".to_string(), group: None, price: None },
+            ],
+            item.modifiers
+        );
+        assert_eq!(Some("No sauce"), item.find_modifier(&ModifierId("no_sauce".to_string())).map(|m| m.name.as_str()));
+        assert_eq!(None, item.find_modifier(&ModifierId("extra_cheese".to_string())));
+    }
+
+    #[test]
+    fn from_toml__duplicate_modifier_id__is_error() {
+        let contents = r#"
+            [[items]]
+            id = 3
+            name = "Cheeseburger"
+            description = ""
+            category = "mains"
+            price = "12.00 EUR"
+            station = "grill"
+            prep_time_mins = 12
+
+            [[items.modifiers]]
+            id = "no_onions"
+            name = "No onions"
+
+            [[items.modifiers]]
+            id = "no_onions"
+            name = "Hold the onions"
+        "#;
+
+        let result = Menu::from_toml(contents);
+
+        assert!(matches!(result, Err(LoadMenuError::DuplicateModifierId(item_id, modifier_id)) if item_id == "3" && modifier_id == "no_onions"));
+    }
+
+    #[test]
+    fn from_toml__modifier_prices__are_added_to_unit_price() {
+        let contents = r#"
+            [[items]]
+            id = 3
+            name = "Cheeseburger"
+            description = ""
+            category = "mains"
+            price = "11.50 EUR"
+            station = "grill"
+            prep_time_mins = 12
+
+            [[items.modifiers]]
+            id = "no_onions"
+            name = "No onions"
+
+            [[items.modifiers]]
+            id = "extra_cheese"
+            name = "Extra cheese"
+            price = "1.20 EUR"
+        "#;
+
+        let menu = Menu::from_toml(contents).unwrap();
+
+        let item = menu.find_item(&MenuItemId(3)).unwrap();
+        assert_eq!(Money::new(1150, Currency::Eur), item.get_unit_price(&[]));
+        assert_eq!(Money::new(1270, Currency::Eur), item.get_unit_price(&[ModifierId("extra_cheese".to_string()), ModifierId("no_onions".to_string())]));
+        assert_eq!(Currency::Eur, menu.currency());
+    }
+
+    #[test]
+    fn new__prices_in_different_currencies__is_error() {
+        let other = MenuItem { id: MenuItemId(2), price: Money::new(900, Currency::Usd), ..create_menu_item(5, 0) };
+
+        let result = Menu::new(vec![create_menu_item(12, 3), other]);
+
+        assert!(matches!(result, Err(LoadMenuError::MixedCurrencies(id, Currency::Usd, Currency::Eur)) if id == "2"));
+    }
+
+    #[test]
+    fn new__negative_price__is_error() {
+        let item = MenuItem { price: Money::new(-1, Currency::Eur), ..create_menu_item(12, 3) };
+
+        let result = Menu::new(vec![item]);
+
+        assert!(matches!(result, Err(LoadMenuError::NegativePrice(id)) if id == "1"));
+    }
+
+    #[test]
+    fn from_toml__missing_field__is_error() {
+        let result = Menu::from_toml("[[items]]\nid = 1\nname = \"Tomato soup\"");
+
+        assert!(matches!(result, Err(LoadMenuError::Parse(_))));
+    }
+
+    #[test]
+    fn from_toml__unknown_station__is_error() {
+        let result = Menu::from_toml("[[items]]\nid = 1\nname = \"Pizza\"\ndescription = \"\"\ncategory = \"mains\"\nprice = \"11.00 EUR\"\nstation = \"oven\"\nprep_time_mins = 12");
+
+        assert!(matches!(result, Err(LoadMenuError::Parse(_))));
+    }
+
+    #[test]
+    fn load_from_file__shipped_menu__is_valid() {
+        let result = Menu::load_from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/menu.toml"));
+
+        assert!(result.is_ok());
+        assert!(result.unwrap().items().count() > 0);
+    }
+
+    #[test]
+    fn from_toml__per_extra_time_not_given__defaults_to_zero() {
+        let menu = Menu::from_toml("[[items]]\nid = 1\nname = \"Lemonade\"\ndescription = \"\"\ncategory = \"drinks\"\nprice = \"3.00 EUR\"\nstation = \"bar\"\nprep_time_mins = 2").unwrap();
+
+        let item = menu.find_item(&MenuItemId(1)).unwrap();
+        assert_eq!(0, item.prep_time_per_extra_mins);
+        assert_eq!(2, menu.get_preparation_time(item, 5));
+    }
+
+    #[test]
+    fn get_preparation_time__single_item__is_base_time() {
+        let item = create_menu_item(12, 3);
+        let menu = Menu::new(vec![item.clone()]).unwrap();
+
+        assert_eq!(12, menu.get_preparation_time(&item, 1));
+    }
+
+    #[test]
+    fn get_preparation_time__extra_quantity__adds_increment_per_extra() {
+        let item = create_menu_item(12, 3);
+        let menu = Menu::new(vec![item.clone()]).unwrap();
+
+        assert_eq!(15, menu.get_preparation_time(&item, 2));
+        assert_eq!(21, menu.get_preparation_time(&item, 4));
+    }
+
+    #[test]
+    fn get_preparation_time__with_jitter__is_repeatable_and_within_range() {
+        let item = create_menu_item(12, 3);
+        let menu = Menu::new(vec![item.clone()])
+            .unwrap()
+            .with_preparation_jitter(PreparationJitter { seed: 42, max_mins: 2 });
+
+        for quantity in 1..=10 {
+            let configured = 12 + 3 * (quantity - 1);
+            let result = menu.get_preparation_time(&item, quantity);
+            assert_eq!(result, menu.get_preparation_time(&item, quantity));
+            assert!((configured - 2..=configured + 2).contains(&result), "{} is not within 2 of {}", result, configured);
+        }
+    }
+
+    #[test]
+    fn get_preparation_time__very_large_quantity__is_capped() {
+        let item = create_menu_item(12, 3);
+        let menu = Menu::new(vec![item.clone()]).unwrap();
+
+        assert_eq!(MAX_PREPARATION_TIME_MINS, menu.get_preparation_time(&item, 2_000_000_000));
+        assert_eq!(MAX_PREPARATION_TIME_MINS, menu.get_preparation_time(&item, i32::MAX));
+    }
+
+    #[test]
+    fn get_preparation_time__very_large_quantity_with_jitter__is_capped() {
+        let item = create_menu_item(i32::MAX, i32::MAX);
+        let menu = Menu::new(vec![item.clone()])
+            .unwrap()
+            .with_preparation_jitter(PreparationJitter { seed: 42, max_mins: u32::MAX });
+
+        assert!((0..=MAX_PREPARATION_TIME_MINS).contains(&menu.get_preparation_time(&item, i32::MAX)));
+        assert!((0..=MAX_PREPARATION_TIME_MINS).contains(&menu.get_preparation_time(&item, i32::MIN)));
+    }
+
+    #[test]
+    fn get_preparation_time__jitter_larger_than_time__is_never_negative() {
+        let item = create_menu_item(0, 0);
+        let menu = Menu::new(vec![item.clone()])
+            .unwrap()
+            .with_preparation_jitter(PreparationJitter { seed: 7, max_mins: 5 });
+
+        assert!((1..=20).all(|quantity| menu.get_preparation_time(&item, quantity) >= 0));
+    }
+
+    #[test]
+    fn from_toml__jitter_section__is_applied() {
+        let contents = r#"
+            [preparation_jitter]
+            seed = 42
+            max_mins = 3
+
+            [[items]]
+            id = 1
+            name = "Cheeseburger"
+            description = ""
+            category = "mains"
+            price = "12.00 EUR"
+            station = "grill"
+            prep_time_mins = 12
+        "#;
+        let with_jitter = Menu::from_toml(contents).unwrap();
+        let without_jitter = Menu::new(vec![create_menu_item(12, 0)])
+            .unwrap()
+            .with_preparation_jitter(PreparationJitter { seed: 42, max_mins: 3 });
+
+        let item = with_jitter.find_item(&MenuItemId(1)).unwrap();
+        assert!((1..=5).all(|quantity| with_jitter.get_preparation_time(item, quantity) == without_jitter.get_preparation_time(item, quantity)));
+    }
+
+    #[test]
+    fn from_toml__billing_section__is_loaded() {
+        let contents = r#"
+            [billing]
+            tax_mode = "inclusive"
+            tax_rounding = "per_bill"
+            default_tax_rate = "9%"
+
+            [billing.tax_rates]
+            drinks = "21%"
+
+            [billing.service_charge]
+            min_guests = 6
+            rate = "12.5%"
+
+            [[items]]
+            id = 1
+            name = "Lemonade"
+            description = ""
+            category = "drinks"
+            price = "3.50 EUR"
+            station = "bar"
+            prep_time_mins = 2
+        "#;
+
+        let menu = Menu::from_toml(contents).unwrap();
+
+        assert_eq!(
+            &BillingConfig {
+                tax_mode: TaxMode::Inclusive,
+                tax_rounding: TaxRounding::PerBill,
+                default_tax_rate: Percentage(9000),
+                tax_rates: [("drinks".to_string(), Percentage(21000))].into_iter().collect(),
+                service_charge: Some(ServiceChargeRule { min_guests: 6, rate: Percentage(12500) }),
+            },
+            menu.billing()
+        );
+        assert_eq!(Percentage(9000), menu.billing().tax_rate("mains"));
+    }
+
+    #[test]
+    fn from_toml__no_billing_section__nothing_is_taxed() {
+        let menu = Menu::from_toml("[[items]]\nid = 1\nname = \"Pizza\"\ndescription = \"\"\ncategory = \"mains\"\nprice = \"11.00 EUR\"\nstation = \"grill\"\nprep_time_mins = 12").unwrap();
+
+        assert_eq!(&BillingConfig::default(), menu.billing());
+        assert_eq!(Percentage(0), menu.billing().tax_rate("mains"));
+    }
+
+    #[test]
+    fn from_toml__tax_rate_for_unknown_category__is_error() {
+        let contents = r#"
+            [billing.tax_rates]
+            drink = "21%"
+
+            [[items]]
+            id = 1
+            name = "Lemonade"
+            description = ""
+            category = "drinks"
+            price = "3.50 EUR"
+            station = "bar"
+            prep_time_mins = 2
+        "#;
+
+        let result = Menu::from_toml(contents);
+
+        assert!(matches!(result, Err(LoadMenuError::UnknownTaxCategory(category)) if category == "drink"));
+    }
+}
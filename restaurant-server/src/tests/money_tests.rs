@@ -0,0 +1,85 @@
+#![allow(non_snake_case)]
+
+#[cfg(test)]
+mod tests {
+    use crate::models::money::{Currency, Money, ParseMoneyError, ParsePercentageError, Percentage};
+
+    #[test]
+    fn from_str__decimal_amounts__are_exact_minor_units() {
+        assert_eq!(Ok(Money::new(1250, Currency::Eur)), "12.50 EUR".parse::<Money>());
+        assert_eq!(Ok(Money::new(1250, Currency::Eur)), "12.5 EUR".parse::<Money>());
+        assert_eq!(Ok(Money::new(1200, Currency::Gbp)), "12 GBP".parse::<Money>());
+        assert_eq!(Ok(Money::new(-5, Currency::Usd)), "-0.05 USD".parse::<Money>());
+        assert_eq!(Ok(Money::new(500, Currency::Jpy)), "500 JPY".parse::<Money>());
+    }
+
+    #[test]
+    fn from_str__malformed_or_more_precise_than_currency__is_error() {
+        for text in ["12.505 EUR", "12. EUR", ".5 EUR", "1.5 JPY", "12.50", "12.50 XYZ", "1e3 EUR", "+1 EUR", "99999999999999999999 EUR"] {
+            assert_eq!(Err(ParseMoneyError(text.to_string())), text.parse::<Money>(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn to_string__parsed_back__is_the_same_amount() {
+        for money in [Money::new(0, Currency::Eur), Money::new(5, Currency::Eur), Money::new(-5, Currency::Eur), Money::new(100_000, Currency::Usd), Money::new(700, Currency::Jpy)] {
+            assert_eq!(Ok(money), money.to_string().parse::<Money>());
+        }
+        assert_eq!("0.05 EUR", Money::new(5, Currency::Eur).to_string());
+        assert_eq!("-12.50", Money::new(-1250, Currency::Eur).to_decimal_string());
+    }
+
+    #[test]
+    fn add_and_times__same_currency__are_exact() {
+        let price = Money::new(110, Currency::Eur);
+
+        assert_eq!(Money::new(330, Currency::Eur), price.times(3));
+        assert_eq!(Money::new(440, Currency::Eur), price.times(3) + price);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot add USD to EUR")]
+    fn add__different_currencies__panics() {
+        let _ = Money::new(100, Currency::Eur) + Money::new(100, Currency::Usd);
+    }
+
+    #[test]
+    fn percentage_from_str__up_to_three_decimals__is_exact() {
+        assert_eq!(Ok(Percentage(20000)), "20%".parse::<Percentage>());
+        assert_eq!(Ok(Percentage(12500)), "12.5%".parse::<Percentage>());
+        assert_eq!(Ok(Percentage(8875)), "8.875%".parse::<Percentage>());
+        for text in ["20", "-5%", "1.2345%", "%", "x%"] {
+            assert_eq!(Err(ParsePercentageError(text.to_string())), text.parse::<Percentage>(), "{}", text);
+        }
+        assert_eq!(
+            vec!["20%", "12.5%", "8.875%", "0%"],
+            [Percentage(20000), Percentage(12500), Percentage(8875), Percentage(0)]
+                .map(|p| p.to_string())
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn percent__half_a_minor_unit__rounds_away_from_zero() {
+        assert_eq!(Money::new(11, Currency::Eur), Money::new(105, Currency::Eur).percent(Percentage(10000)));
+        assert_eq!(Money::new(-11, Currency::Eur), Money::new(-105, Currency::Eur).percent(Percentage(10000)));
+        assert_eq!(Money::new(10, Currency::Eur), Money::new(104, Currency::Eur).percent(Percentage(10000)));
+        assert_eq!(Money::new(0, Currency::Eur), Money::new(1000, Currency::Eur).percent(Percentage(0)));
+    }
+
+    #[test]
+    fn included_tax__price_including_tax__is_the_tax_part() {
+        assert_eq!(Money::new(2000, Currency::Eur), Money::new(12000, Currency::Eur).included_tax(Percentage(20000)));
+        // 350 / 1.21 = 289.26, so 60.74 of it is tax
+        assert_eq!(Money::new(61, Currency::Eur), Money::new(350, Currency::Eur).included_tax(Percentage(21000)));
+    }
+
+    #[test]
+    fn allocate__uneven_split__parts_add_up_with_largest_remainders_rounded_up() {
+        assert_eq!(vec![Money::new(34, Currency::Eur), Money::new(33, Currency::Eur), Money::new(33, Currency::Eur)], Money::new(100, Currency::Eur).allocate(&[1, 1, 1]));
+        // 33.33 and 66.67, so the one cent left over goes to the second part
+        assert_eq!(vec![Money::new(33, Currency::Eur), Money::new(67, Currency::Eur)], Money::new(100, Currency::Eur).allocate(&[1, 2]));
+        assert_eq!(vec![Money::new(0, Currency::Eur), Money::new(5, Currency::Eur)], Money::new(5, Currency::Eur).allocate(&[0, 3]));
+        assert_eq!(vec![Money::new(3, Currency::Eur), Money::new(2, Currency::Eur)], Money::new(5, Currency::Eur).allocate(&[0, 0]));
+    }
+}
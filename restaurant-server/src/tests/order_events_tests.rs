@@ -0,0 +1,319 @@
+#![allow(non_snake_case)]
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use axum::{
+        body::Body,
+        http::{self, Request, Response, StatusCode},
+        Router,
+    };
+    use futures_util::StreamExt;
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::{connect_async, tungstenite, MaybeTlsStream, WebSocketStream};
+    use tower::ServiceExt;
+
+    use crate::{api::v0::view_models::OrderEventViewModel, app::create_app, models::menu::Menu, persistence::memory_persistence::MemoryPersistence, tests::fixtures::menu_item};
+
+    type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    fn create_test_menu() -> Menu {
+        let items = (1..=3).map(|i| menu_item(i, &format!("menu item {}", i))).collect();
+        return Menu::new(items).unwrap();
+    }
+
+    // Websockets need a real connection, so the app is served on a random port. Requests that change orders go straight to the router, which shares its state with the server.
+    async fn serve_app() -> (Router, SocketAddr) {
+        let app = create_app(MemoryPersistence::default(), create_test_menu());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let served = app.clone();
+        tokio::spawn(async move { axum::serve(listener, served).await.unwrap() });
+        return (app, addr);
+    }
+
+    async fn send_request(app: &Router, method: http::Method, uri: &str, body: Option<Value>) -> StatusCode {
+        let request = match body {
+            Some(body) => Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(serde_json::to_vec(&body).unwrap()))
+                .unwrap(),
+            None => Request::builder().method(method).uri(uri).body(Body::empty()).unwrap(),
+        };
+        return app.clone().oneshot(request).await.unwrap().status();
+    }
+
+    async fn next_event(socket: &mut Socket) -> OrderEventViewModel {
+        let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("no event within 5 seconds")
+            .unwrap()
+            .unwrap();
+        return serde_json::from_str(message.to_text().unwrap()).unwrap();
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct SseEvent {
+        id: Option<String>,
+        event: String,
+        data: Value,
+    }
+
+    async fn open_sse(app: &Router, uri: &str, last_event_id: Option<&str>) -> Response<Body> {
+        let mut request = Request::builder().uri(uri);
+        if let Some(last_event_id) = last_event_id {
+            request = request.header("Last-Event-ID", last_event_id);
+        }
+        return app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+    }
+
+    // Reads the stream until `count` events have arrived, ignoring keep-alive comments
+    async fn read_sse_events(response: Response<Body>, count: usize) -> Vec<SseEvent> {
+        let mut body = response.into_body();
+        let mut text = String::new();
+        loop {
+            let blocks = text.split("\n\n").filter(|b| b.lines().any(|l| !l.starts_with(':'))).collect::<Vec<&str>>();
+            // The last block is only complete once the blank line after it has arrived
+            let complete = if text.ends_with("\n\n") { blocks.len() } else { blocks.len().saturating_sub(1) };
+            if complete >= count {
+                return blocks[..count].iter().map(|b| parse_sse_event(b)).collect();
+            }
+
+            let frame = tokio::time::timeout(Duration::from_secs(5), body.frame())
+                .await
+                .expect("no event within 5 seconds")
+                .unwrap()
+                .unwrap();
+            if let Ok(data) = frame.into_data() {
+                text.push_str(std::str::from_utf8(&data).unwrap());
+            }
+        }
+    }
+
+    fn parse_sse_event(block: &str) -> SseEvent {
+        let field = |name: &str| {
+            block
+                .lines()
+                .find_map(|l| l.strip_prefix(&format!("{}:", name)).map(|v| v.trim_start().to_string()))
+        };
+        return SseEvent { id: field("id"), event: field("event").unwrap(), data: serde_json::from_str(&field("data").unwrap()).unwrap() };
+    }
+
+    #[tokio::test]
+    async fn order_events__every_kind_of_change__is_pushed_in_order() {
+        let (app, addr) = serve_app().await;
+        let (mut socket, _) = connect_async(format!("ws://{}/v0/ws", addr)).await.unwrap();
+
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }, { "item_id": "2", "qty": 1 }] });
+        assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, "/v0/orders/123", Some(body)).await);
+        assert_eq!(StatusCode::OK, send_request(&app, http::Method::POST, "/v0/kds/tables/123/items/1/bump", None).await);
+        assert_eq!(StatusCode::OK, send_request(&app, http::Method::DELETE, "/v0/orders/123/items/2", None).await);
+        assert_eq!(StatusCode::OK, send_request(&app, http::Method::PUT, "/v0/orders/123", Some(json!({ "items": [{ "item_id": "1", "qty": 1 }, { "item_id": "3", "qty": 2 }] }))).await);
+        assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, "/v0/orders/123/bill", Some(json!({}))).await);
+        assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, "/v0/orders/123/payments", Some(json!({ "method": "cash" }))).await);
+        assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, "/v0/orders/123/payments/1/refund", Some(json!({ "amount": "1.00" }))).await);
+        assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, "/v0/orders/123/payments", Some(json!({ "method": "cash" }))).await);
+        assert_eq!(StatusCode::NO_CONTENT, send_request(&app, http::Method::DELETE, "/v0/orders/123", None).await);
+
+        match next_event(&mut socket).await {
+            OrderEventViewModel::OrderCreated { table_id, order, .. } => {
+                assert_eq!("123", table_id);
+                assert_eq!(2, order.items.len());
+            }
+            _ => panic!("expected order_created"),
+        }
+        match next_event(&mut socket).await {
+            OrderEventViewModel::ItemReady { table_id, item, .. } => assert_eq!(("123".to_string(), "1".to_string(), "ready".to_string()), (table_id, item.item_id, item.status)),
+            _ => panic!("expected item_ready"),
+        }
+        match next_event(&mut socket).await {
+            OrderEventViewModel::ItemRemoved { line_id, order, .. } => {
+                assert_eq!("2", line_id);
+                assert_eq!(vec!["1".to_string()], order.items.iter().map(|i| i.item_id.clone()).collect::<Vec<String>>());
+            }
+            _ => panic!("expected item_removed"),
+        }
+        match next_event(&mut socket).await {
+            OrderEventViewModel::OrderUpdated { order, .. } => {
+                assert_eq!(vec![("1".to_string(), 1), ("3".to_string(), 2)], order.items.iter().map(|i| (i.item_id.clone(), i.quantity)).collect::<Vec<(String, i32)>>())
+            }
+            _ => panic!("expected order_updated"),
+        }
+        match next_event(&mut socket).await {
+            OrderEventViewModel::BillIssued { bill, .. } => assert_eq!(("1".to_string(), 2), (bill.bill_id, bill.lines.len())),
+            _ => panic!("expected bill_issued"),
+        }
+        match next_event(&mut socket).await {
+            OrderEventViewModel::PaymentRecorded { payment, .. } => assert_eq!(("1".to_string(), "cash".to_string()), (payment.payment_id, payment.method)),
+            _ => panic!("expected payment_recorded"),
+        }
+        match next_event(&mut socket).await {
+            OrderEventViewModel::RefundRecorded { payment, .. } => assert_eq!(("1".to_string(), "1.00".to_string()), (payment.payment_id, payment.refunded.amount)),
+            _ => panic!("expected refund_recorded"),
+        }
+        match next_event(&mut socket).await {
+            OrderEventViewModel::PaymentRecorded { payment, .. } => assert_eq!(("2".to_string(), "1.00".to_string()), (payment.payment_id, payment.amount.amount)),
+            _ => panic!("expected payment_recorded"),
+        }
+        match next_event(&mut socket).await {
+            OrderEventViewModel::OrderDeleted { table_id, settlement, .. } => {
+                assert_eq!("123", table_id);
+                let settlement = settlement.unwrap();
+                assert_eq!(("1".to_string(), 1, 2), (settlement.settlement_id, settlement.bills.len(), settlement.payments.len()));
+            }
+            _ => panic!("expected order_deleted"),
+        }
+    }
+
+    #[tokio::test]
+    async fn order_events__subscribed_to_some_tables__only_those_are_pushed() {
+        let (app, addr) = serve_app().await;
+        let (mut socket, _) = connect_async(format!("ws://{}/v0/ws?tables=2,3", addr)).await.unwrap();
+
+        for table_id in [1, 2, 4, 3] {
+            let body = json!({ "items": [{ "item_id": "1", "qty": 1 }] });
+            assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, &format!("/v0/orders/{}", table_id), Some(body)).await);
+        }
+
+        let mut pushed_tables = vec![];
+        for _ in 0..2 {
+            match next_event(&mut socket).await {
+                OrderEventViewModel::OrderCreated { table_id, .. } => pushed_tables.push(table_id),
+                _ => panic!("expected order_created"),
+            }
+        }
+        assert_eq!(vec!["2".to_string(), "3".to_string()], pushed_tables);
+    }
+
+    #[tokio::test]
+    async fn order_events__rejected_change__is_not_pushed() {
+        let (app, addr) = serve_app().await;
+        let (mut socket, _) = connect_async(format!("ws://{}/v0/ws", addr)).await.unwrap();
+
+        assert_eq!(StatusCode::NOT_FOUND, send_request(&app, http::Method::DELETE, "/v0/orders/123", None).await);
+        assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, "/v0/orders/5", Some(json!({ "items": [{ "item_id": "1", "qty": 1 }] }))).await);
+
+        match next_event(&mut socket).await {
+            OrderEventViewModel::OrderCreated { table_id, .. } => assert_eq!("5", table_id),
+            _ => panic!("expected order_created"),
+        }
+    }
+
+    #[tokio::test]
+    async fn order_events__non_numeric_table_id__is_400() {
+        let (_, addr) = serve_app().await;
+
+        let result = connect_async(format!("ws://{}/v0/ws?tables=1,abc", addr)).await;
+
+        match result {
+            Err(tungstenite::Error::Http(response)) => assert_eq!(StatusCode::BAD_REQUEST.as_u16(), response.status().as_u16()),
+            _ => panic!("expected the handshake to be rejected"),
+        }
+    }
+
+    #[tokio::test]
+    async fn sse_order_events__changes__are_streamed_with_ids_and_types() {
+        let app = create_app(MemoryPersistence::default(), create_test_menu());
+        let response = open_sse(&app, "/v0/events", None).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(mime::TEXT_EVENT_STREAM.as_ref(), response.headers().get(http::header::CONTENT_TYPE).unwrap());
+
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }, { "item_id": "2", "qty": 1 }] });
+        assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, "/v0/orders/123", Some(body)).await);
+        assert_eq!(StatusCode::OK, send_request(&app, http::Method::DELETE, "/v0/orders/123/items/2", None).await);
+        assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, "/v0/orders/123/bill", Some(json!({}))).await);
+        assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, "/v0/orders/123/payments", Some(json!({ "method": "cash" }))).await);
+        assert_eq!(StatusCode::NO_CONTENT, send_request(&app, http::Method::DELETE, "/v0/orders/123", None).await);
+
+        let events = read_sse_events(response, 5).await;
+        let summary = events
+            .iter()
+            .map(|e| (e.id.clone().unwrap(), e.event.clone(), e.data["type"].as_str().unwrap().to_string()))
+            .collect::<Vec<(String, String, String)>>();
+        assert_eq!(
+            vec![
+                ("1".to_string(), "order_created".to_string(), "order_created".to_string()),
+                ("2".to_string(), "item_removed".to_string(), "item_removed".to_string()),
+                ("3".to_string(), "bill_issued".to_string(), "bill_issued".to_string()),
+                ("4".to_string(), "payment_recorded".to_string(), "payment_recorded".to_string()),
+                ("5".to_string(), "order_deleted".to_string(), "order_deleted".to_string()),
+            ],
+            summary
+        );
+        assert_eq!("2", events[1].data["line_id"]);
+    }
+
+    #[tokio::test]
+    async fn sse_order_events__last_event_id__replays_missed_events_then_streams_live_ones() {
+        let app = create_app(MemoryPersistence::default(), create_test_menu());
+        for table_id in 1..=3 {
+            let body = json!({ "items": [{ "item_id": "1", "qty": 1 }] });
+            assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, &format!("/v0/orders/{}", table_id), Some(body)).await);
+        }
+
+        let response = open_sse(&app, "/v0/events", Some("1")).await;
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }] });
+        assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, "/v0/orders/4", Some(body)).await);
+
+        let events = read_sse_events(response, 3).await;
+        let summary = events
+            .iter()
+            .map(|e| (e.id.clone().unwrap(), e.data["table_id"].as_str().unwrap().to_string()))
+            .collect::<Vec<(String, String)>>();
+        assert_eq!(vec![("2".to_string(), "2".to_string()), ("3".to_string(), "3".to_string()), ("4".to_string(), "4".to_string())], summary);
+    }
+
+    #[tokio::test]
+    async fn sse_order_events__last_event_id_from_before_restart__is_lagged_then_whole_history() {
+        let app = create_app(MemoryPersistence::default(), create_test_menu());
+        let body = json!({ "items": [{ "item_id": "1", "qty": 1 }] });
+        assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, "/v0/orders/7", Some(body)).await);
+
+        let response = open_sse(&app, "/v0/events", Some("99")).await;
+
+        let events = read_sse_events(response, 2).await;
+        assert_eq!(SseEvent { id: None, event: "lagged".to_string(), data: json!({ "type": "lagged", "missed": null }) }, events[0]);
+        assert_eq!((Some("1".to_string()), "order_created".to_string()), (events[1].id.clone(), events[1].event.clone()));
+    }
+
+    #[tokio::test]
+    async fn sse_order_events__subscribed_to_some_tables__only_those_are_streamed() {
+        let app = create_app(MemoryPersistence::default(), create_test_menu());
+        let response = open_sse(&app, "/v0/events?tables=2", None).await;
+
+        for table_id in 1..=3 {
+            let body = json!({ "items": [{ "item_id": "1", "qty": 1 }] });
+            assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, &format!("/v0/orders/{}", table_id), Some(body)).await);
+        }
+        assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, "/v0/orders/2/bill", Some(json!({}))).await);
+        assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, "/v0/orders/2/payments", Some(json!({ "method": "cash" }))).await);
+        assert_eq!(StatusCode::NO_CONTENT, send_request(&app, http::Method::DELETE, "/v0/orders/2", None).await);
+
+        let events = read_sse_events(response, 4).await;
+        assert_eq!(
+            vec![
+                (Some("2".to_string()), "order_created".to_string()),
+                (Some("4".to_string()), "bill_issued".to_string()),
+                (Some("5".to_string()), "payment_recorded".to_string()),
+                (Some("6".to_string()), "order_deleted".to_string()),
+            ],
+            events.iter().map(|e| (e.id.clone(), e.event.clone())).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn sse_order_events__invalid_last_event_id__is_400() {
+        let app = create_app(MemoryPersistence::default(), create_test_menu());
+
+        let response = open_sse(&app, "/v0/events", Some("latest")).await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let problem: Value = serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes()).unwrap();
+        assert_eq!("invalid_last_event_id", problem["code"]);
+    }
+}
@@ -0,0 +1,506 @@
+#![allow(non_snake_case)]
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+
+    use crate::{
+        models::{
+            menu::{Menu, MenuItemId},
+            money::{Currency, Money},
+            orders::{LineId, OrderChangeError, OrderItemChanges, OrderItemStatus, OrderItemStatusChange, TableId, TableOrder, TableOrderItem},
+        },
+        tests::fixtures::menu_item,
+    };
+
+    fn ordered_at() -> DateTime<Utc> {
+        return Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+    }
+
+    fn create_order(items: &[TableOrderItem]) -> TableOrder {
+        return TableOrder::new(TableId(123), items);
+    }
+
+    // Item 1 takes 10 minutes plus 2 for each extra one
+    fn create_test_menu() -> Menu {
+        return Menu::new(vec![menu_item(1, "menu item 1").with_prep_time(10, 2)]).unwrap();
+    }
+
+    #[test]
+    fn can_transition_to__next_status__is_allowed() {
+        assert!(OrderItemStatus::Ordered.can_transition_to(OrderItemStatus::Preparing));
+        assert!(OrderItemStatus::Preparing.can_transition_to(OrderItemStatus::Ready));
+        assert!(OrderItemStatus::Ready.can_transition_to(OrderItemStatus::Served));
+    }
+
+    #[test]
+    fn can_transition_to__skipped_backward_or_same_status__is_not_allowed() {
+        assert!(!OrderItemStatus::Ordered.can_transition_to(OrderItemStatus::Ready));
+        assert!(!OrderItemStatus::Ordered.can_transition_to(OrderItemStatus::Ordered));
+        assert!(!OrderItemStatus::Ready.can_transition_to(OrderItemStatus::Preparing));
+        assert!(!OrderItemStatus::Served.can_transition_to(OrderItemStatus::Ordered));
+        assert!(!OrderItemStatus::Served.can_transition_to(OrderItemStatus::Served));
+    }
+
+    #[test]
+    fn from_str__round_trips_display() {
+        for status in [OrderItemStatus::Ordered, OrderItemStatus::Preparing, OrderItemStatus::Ready, OrderItemStatus::Served] {
+            assert_eq!(Ok(status), status.to_string().parse::<OrderItemStatus>());
+        }
+        assert!("cooking".parse::<OrderItemStatus>().is_err());
+    }
+
+    #[test]
+    fn new__same_menu_item_twice__gets_a_line_each_numbered_from_one() {
+        let order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(1), 2, 12, ordered_at())]);
+
+        assert_eq!(vec![LineId(1), LineId(2)], order.items.keys().copied().collect::<Vec<LineId>>());
+        assert_eq!(vec![LineId(1), LineId(2)], order.items.values().map(|i| i.line_id).collect::<Vec<LineId>>());
+        assert_eq!(vec![1, 2], order.items.values().map(|i| i.quantity).collect::<Vec<i32>>());
+        assert_eq!(LineId(3), order.next_line_id);
+    }
+
+    #[test]
+    fn add_item__after_a_line_was_removed__does_not_reuse_its_id() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(2), 1, 10, ordered_at())]);
+        order.items.remove(&LineId(2));
+
+        let line_id = order.add_item(TableOrderItem::new(MenuItemId(3), 1, 10, ordered_at()));
+
+        assert_eq!(LineId(3), line_id);
+        assert_eq!(Some(MenuItemId(3)), order.items.get(&LineId(3)).map(|i| i.item_id.clone()));
+    }
+
+    #[test]
+    fn replace_items__same_items_again__order_is_untouched() {
+        let items = [TableOrderItem::new(MenuItemId(1), 2, 12, ordered_at()), TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()).with_notes(Some("no salt".to_string()))];
+        let mut order = create_order(&items);
+        order.bump_item(&LineId(1), ordered_at() + TimeDelta::minutes(5)).unwrap();
+        let before = order.clone();
+
+        let removed = order
+            .replace_items(&[TableOrderItem::new(MenuItemId(1), 2, 12, ordered_at() + TimeDelta::minutes(9)), items[1].clone()], &create_test_menu(), 100)
+            .unwrap();
+
+        assert!(removed.is_empty());
+        assert_eq!(before, order);
+    }
+
+    #[test]
+    fn replace_items__more_of_a_started_dish__extra_goes_on_a_new_line() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+        order.bump_item(&LineId(1), ordered_at() + TimeDelta::minutes(5)).unwrap();
+        let later = ordered_at() + TimeDelta::minutes(6);
+
+        order
+            .replace_items(&[TableOrderItem::new(MenuItemId(1), 3, 14, later)], &create_test_menu(), 100)
+            .unwrap();
+
+        assert_eq!(vec![(LineId(1), 1, OrderItemStatus::Ready), (LineId(2), 2, OrderItemStatus::Ordered)], order.items.values().map(|i| (i.line_id, i.quantity, i.status)).collect::<Vec<_>>());
+        let new_line = order.items.get(&LineId(2)).unwrap();
+        assert_eq!((later, 12), (new_line.ordered_at, new_line.total_preparation_time_mins));
+    }
+
+    #[test]
+    fn replace_items__more_of_an_unstarted_dish__grows_its_line() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+
+        order
+            .replace_items(&[TableOrderItem::new(MenuItemId(1), 3, 14, ordered_at() + TimeDelta::minutes(6))], &create_test_menu(), 100)
+            .unwrap();
+
+        assert_eq!(vec![LineId(1)], order.items.keys().copied().collect::<Vec<LineId>>());
+        let line = order.items.get(&LineId(1)).unwrap();
+        assert_eq!((3, ordered_at(), ordered_at() + TimeDelta::minutes(14)), (line.quantity, line.ordered_at, line.ready_at));
+    }
+
+    #[test]
+    fn replace_items__less_of_a_dish__taken_off_newest_lines_first() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 2, 12, ordered_at()), TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+
+        let removed = order
+            .replace_items(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())], &create_test_menu(), 100)
+            .unwrap();
+
+        assert_eq!(
+            vec![(LineId(1), 1, 10)],
+            order
+                .items
+                .values()
+                .map(|i| (i.line_id, i.quantity, i.total_preparation_time_mins))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec![(LineId(2), 1), (LineId(1), 1)], removed.iter().map(|i| (i.line_id, i.quantity)).collect::<Vec<_>>());
+        assert_eq!(LineId(3), order.next_line_id);
+    }
+
+    #[test]
+    fn replace_items__less_of_a_started_dish__is_error_and_nothing_changed() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(2), 2, 12, ordered_at())]);
+        order.bump_item(&LineId(2), ordered_at() + TimeDelta::minutes(5)).unwrap();
+        let before = order.clone();
+
+        let result = order.replace_items(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(2), 1, 10, ordered_at())], &create_test_menu(), 100);
+
+        assert_eq!(Err(OrderChangeError::AlreadyStarted("123".to_string(), "2".to_string(), OrderItemStatus::Ready)), result);
+        assert_eq!(before, order);
+    }
+
+    #[test]
+    fn replace_items__quantities_add_up_past_i32__is_too_many_items() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+        let before = order.clone();
+
+        let result = order.replace_items(&[TableOrderItem::new(MenuItemId(1), i32::MAX, 10, ordered_at()), TableOrderItem::new(MenuItemId(1), i32::MAX, 10, ordered_at())], &create_test_menu(), 100);
+
+        assert_eq!(Err(OrderChangeError::TooManyItems("123".to_string(), 100)), result);
+        assert_eq!(before, order);
+    }
+
+    #[test]
+    fn replace_items__started_lines_kept_push_past_max_items__is_error_and_nothing_changed() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+        order.bump_item(&LineId(1), ordered_at() + TimeDelta::minutes(5)).unwrap();
+        let before = order.clone();
+
+        let result = order.replace_items(
+            &[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(2), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(3), 1, 10, ordered_at())],
+            &create_test_menu(),
+            2,
+        );
+
+        assert_eq!(Err(OrderChangeError::TooManyItems("123".to_string(), 2)), result);
+        assert_eq!(before, order);
+    }
+
+    #[test]
+    fn add_items__existing_lines__are_kept_and_new_ones_appended() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+        order.bump_item(&LineId(1), ordered_at() + TimeDelta::minutes(5)).unwrap();
+        let existing = order.items.get(&LineId(1)).unwrap().clone();
+
+        let result = order.add_items(&[TableOrderItem::new(MenuItemId(2), 1, 10, ordered_at() + TimeDelta::minutes(6))], 100);
+
+        assert_eq!(Ok(()), result);
+        assert_eq!(Some(&existing), order.items.get(&LineId(1)));
+        assert_eq!(Some(MenuItemId(2)), order.items.get(&LineId(2)).map(|i| i.item_id.clone()));
+    }
+
+    #[test]
+    fn add_items__over_max_items__is_error_and_nothing_added() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(2), 1, 10, ordered_at())]);
+
+        let result = order.add_items(&[TableOrderItem::new(MenuItemId(3), 1, 10, ordered_at())], 2);
+
+        assert_eq!(Err(OrderChangeError::TooManyItems("123".to_string(), 2)), result);
+        assert_eq!(2, order.items.len());
+        assert_eq!(LineId(3), order.next_line_id);
+    }
+
+    #[test]
+    fn change_item__new_quantity__re_estimates_from_ordered_at() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+
+        let result = order.change_item(&LineId(1), &OrderItemChanges { quantity: Some(3), notes: None, seat: None }, &create_test_menu());
+
+        assert_eq!(Ok(()), result);
+        let item = order.items.get(&LineId(1)).unwrap();
+        assert_eq!((3, 14), (item.quantity, item.total_preparation_time_mins));
+        assert_eq!((ordered_at(), ordered_at() + TimeDelta::minutes(14)), (item.ordered_at, item.ready_at));
+    }
+
+    #[test]
+    fn change_item__quantity_of_started_line__is_error_and_item_is_unchanged() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+        order.advance_item_status(&LineId(1), OrderItemStatus::Preparing, ordered_at()).unwrap();
+
+        let result = order.change_item(&LineId(1), &OrderItemChanges { quantity: Some(2), notes: Some(Some("no salt".to_string())), seat: None }, &create_test_menu());
+
+        assert_eq!(Err(OrderChangeError::AlreadyStarted("123".to_string(), "1".to_string(), OrderItemStatus::Preparing)), result);
+        let item = order.items.get(&LineId(1)).unwrap();
+        assert_eq!((1, None), (item.quantity, item.notes.clone()));
+    }
+
+    #[test]
+    fn change_item__notes_of_started_line__are_changed() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()).with_notes(Some("no salt".to_string()))]);
+        order.bump_item(&LineId(1), ordered_at()).unwrap();
+
+        let result = order.change_item(&LineId(1), &OrderItemChanges { quantity: Some(1), notes: Some(None), seat: None }, &create_test_menu());
+
+        assert_eq!(Ok(()), result);
+        assert_eq!(None, order.items.get(&LineId(1)).unwrap().notes);
+    }
+
+    #[test]
+    fn change_item__unknown_line__is_error() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+
+        let result = order.change_item(&LineId(2), &OrderItemChanges::default(), &create_test_menu());
+
+        assert_eq!(Err(OrderChangeError::OrderItemNotFound("123".to_string(), "2".to_string())), result);
+    }
+
+    #[test]
+    fn remove_item__line_not_started__is_removed() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(2), 1, 10, ordered_at())]);
+
+        let result = order.remove_item(&LineId(1));
+
+        assert_eq!(Ok(()), result);
+        assert_eq!(vec![LineId(2)], order.items.keys().copied().collect::<Vec<LineId>>());
+    }
+
+    #[test]
+    fn remove_item__started_line__is_error_and_line_is_kept() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+        order.bump_item(&LineId(1), ordered_at()).unwrap();
+
+        let result = order.remove_item(&LineId(1));
+
+        assert_eq!(Err(OrderChangeError::AlreadyStarted("123".to_string(), "1".to_string(), OrderItemStatus::Ready)), result);
+        assert_eq!(1, order.items.len());
+    }
+
+    #[test]
+    fn remove_item__unknown_line__is_error() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+
+        let result = order.remove_item(&LineId(2));
+
+        assert_eq!(Err(OrderChangeError::OrderItemNotFound("123".to_string(), "2".to_string())), result);
+    }
+
+    #[test]
+    fn subtotal__lines_priced_from_menu__items_no_longer_on_menu_left_out() {
+        let order = create_order(&[TableOrderItem::new(MenuItemId(1), 3, 14, ordered_at()), TableOrderItem::new(MenuItemId(9), 1, 10, ordered_at())]);
+        let menu = create_test_menu();
+
+        assert_eq!(Some(Money::new(3000, Currency::Eur)), order.items.get(&LineId(1)).unwrap().line_total(&menu));
+        assert_eq!(None, order.items.get(&LineId(2)).unwrap().line_total(&menu));
+        assert_eq!(Money::new(3000, Currency::Eur), order.subtotal(&menu));
+    }
+
+    #[test]
+    fn advance_item_status__legal_transition__records_timestamp() {
+        let at = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+
+        let result = order.advance_item_status(&LineId(1), OrderItemStatus::Preparing, at);
+
+        assert_eq!(Ok(()), result);
+        let item = order.items.get(&LineId(1)).unwrap();
+        assert_eq!(OrderItemStatus::Preparing, item.status);
+        assert_eq!(vec![OrderItemStatusChange { status: OrderItemStatus::Preparing, at: at }], item.status_history);
+        assert_eq!(Some(at), item.status_changed_at());
+    }
+
+    #[test]
+    fn advance_item_status__illegal_transition__is_error_and_item_is_unchanged() {
+        let at = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+
+        let result = order.advance_item_status(&LineId(1), OrderItemStatus::Served, at);
+
+        assert_eq!(Err(OrderChangeError::IllegalStatusTransition("123".to_string(), "1".to_string(), OrderItemStatus::Ordered, OrderItemStatus::Served)), result);
+        assert_eq!(TableOrderItem { line_id: LineId(1), ..TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()) }, *order.items.get(&LineId(1)).unwrap());
+    }
+
+    #[test]
+    fn advance_item_status__unknown_item__is_error() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+
+        let result = order.advance_item_status(&LineId(2), OrderItemStatus::Preparing, Utc::now());
+
+        assert_eq!(Err(OrderChangeError::OrderItemNotFound("123".to_string(), "2".to_string())), result);
+    }
+
+    #[test]
+    fn new__expected_ready_time__is_ordered_at_plus_preparation_time() {
+        let item = TableOrderItem::new(MenuItemId(1), 1, 12, ordered_at());
+
+        assert_eq!(Utc.with_ymd_and_hms(2024, 1, 1, 12, 12, 0).unwrap(), item.ready_at);
+    }
+
+    #[test]
+    fn remaining_mins__partway_through__rounds_up_to_whole_minutes() {
+        let item = TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at());
+
+        assert_eq!(10, item.remaining_mins(item.ready_at, ordered_at()));
+        assert_eq!(7, item.remaining_mins(item.ready_at, ordered_at() + TimeDelta::seconds(3 * 60 + 1)));
+        assert_eq!(1, item.remaining_mins(item.ready_at, ordered_at() + TimeDelta::seconds(10 * 60 - 1)));
+    }
+
+    #[test]
+    fn remaining_mins__past_ready_time__is_zero() {
+        let item = TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at());
+
+        assert_eq!(0, item.remaining_mins(item.ready_at, ordered_at() + TimeDelta::minutes(10)));
+        assert_eq!(0, item.remaining_mins(item.ready_at, ordered_at() + TimeDelta::minutes(30)));
+    }
+
+    #[test]
+    fn remaining_mins__marked_ready_early__is_zero() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+        order.advance_item_status(&LineId(1), OrderItemStatus::Preparing, ordered_at()).unwrap();
+        order
+            .advance_item_status(&LineId(1), OrderItemStatus::Ready, ordered_at() + TimeDelta::minutes(2))
+            .unwrap();
+
+        let item = order.items.get(&LineId(1)).unwrap();
+        assert_eq!(0, item.remaining_mins(item.ready_at, ordered_at() + TimeDelta::minutes(2)));
+    }
+
+    #[test]
+    fn bump_item__ordered_item__goes_through_preparing_to_ready() {
+        let at = ordered_at() + TimeDelta::minutes(8);
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+
+        let result = order.bump_item(&LineId(1), at);
+
+        assert_eq!(Ok(()), result);
+        let item = order.items.get(&LineId(1)).unwrap();
+        assert_eq!(OrderItemStatus::Ready, item.status);
+        assert_eq!(vec![OrderItemStatusChange { status: OrderItemStatus::Preparing, at: at }, OrderItemStatusChange { status: OrderItemStatus::Ready, at: at }], item.status_history);
+    }
+
+    #[test]
+    fn bump_item__preparing_item__is_ready() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+        order.advance_item_status(&LineId(1), OrderItemStatus::Preparing, ordered_at()).unwrap();
+
+        let result = order.bump_item(&LineId(1), ordered_at() + TimeDelta::minutes(8));
+
+        assert_eq!(Ok(()), result);
+        assert_eq!(OrderItemStatus::Ready, order.items.get(&LineId(1)).unwrap().status);
+        assert_eq!(2, order.items.get(&LineId(1)).unwrap().status_history.len());
+    }
+
+    #[test]
+    fn bump_item__already_ready__is_error() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+        order.bump_item(&LineId(1), ordered_at()).unwrap();
+
+        let result = order.bump_item(&LineId(1), ordered_at());
+
+        assert_eq!(Err(OrderChangeError::IllegalStatusTransition("123".to_string(), "1".to_string(), OrderItemStatus::Ready, OrderItemStatus::Ready)), result);
+    }
+
+    #[test]
+    fn recall_item__within_window__is_preparing_again() {
+        let bumped_at = ordered_at() + TimeDelta::minutes(8);
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+        order.bump_item(&LineId(1), bumped_at).unwrap();
+
+        let result = order.recall_item(&LineId(1), bumped_at + TimeDelta::minutes(5), TimeDelta::minutes(5));
+
+        assert_eq!(Ok(()), result);
+        let item = order.items.get(&LineId(1)).unwrap();
+        assert_eq!(OrderItemStatus::Preparing, item.status);
+        assert_eq!(Some(bumped_at + TimeDelta::minutes(5)), item.status_changed_at());
+    }
+
+    #[test]
+    fn recall_item__after_window__is_error_and_item_stays_ready() {
+        let bumped_at = ordered_at() + TimeDelta::minutes(8);
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+        order.bump_item(&LineId(1), bumped_at).unwrap();
+
+        let result = order.recall_item(&LineId(1), bumped_at + TimeDelta::seconds(5 * 60 + 1), TimeDelta::minutes(5));
+
+        assert_eq!(Err(OrderChangeError::RecallWindowExpired("123".to_string(), "1".to_string(), 5)), result);
+        assert_eq!(OrderItemStatus::Ready, order.items.get(&LineId(1)).unwrap().status);
+    }
+
+    #[test]
+    fn recall_item__not_bumped__is_error() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+
+        let result = order.recall_item(&LineId(1), ordered_at(), TimeDelta::minutes(5));
+
+        assert_eq!(Err(OrderChangeError::NotBumped("123".to_string(), "1".to_string(), OrderItemStatus::Ordered)), result);
+    }
+
+    #[test]
+    fn add_items__seat_beyond_guest_count__is_error_and_nothing_is_added() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]).with_guest_count(Some(2));
+        let mut unseated = create_order(&[]);
+
+        let result = order.add_items(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()).with_seat(Some(2)), TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()).with_seat(Some(3))], 100);
+        let unseated_result = unseated.add_items(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()).with_seat(Some(1))], 100);
+
+        assert_eq!(Err(OrderChangeError::SeatOutOfRange("123".to_string(), 3, 2)), result);
+        assert_eq!(1, order.items.len());
+        assert_eq!(Err(OrderChangeError::SeatOutOfRange("123".to_string(), 1, 0)), unseated_result);
+    }
+
+    #[test]
+    fn set_guest_count__fewer_than_a_seat_in_use__is_error() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()).with_seat(Some(3))]).with_guest_count(Some(4));
+
+        let fewer = order.set_guest_count(Some(2));
+        let still_enough = order.set_guest_count(Some(3));
+
+        assert_eq!(Err(OrderChangeError::SeatOutOfRange("123".to_string(), 3, 2)), fewer);
+        assert_eq!(Ok(()), still_enough);
+        assert_eq!(Some(3), order.guest_count);
+    }
+
+    #[test]
+    fn change_item__seat__moves_line_if_at_the_table() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]).with_guest_count(Some(2));
+
+        let moved = order.change_item(&LineId(1), &OrderItemChanges { seat: Some(Some(2)), ..Default::default() }, &create_test_menu());
+        let too_far = order.change_item(&LineId(1), &OrderItemChanges { seat: Some(Some(5)), ..Default::default() }, &create_test_menu());
+
+        assert_eq!(Ok(()), moved);
+        assert_eq!(Err(OrderChangeError::SeatOutOfRange("123".to_string(), 5, 2)), too_far);
+        assert_eq!(Some(2), order.items.get(&LineId(1)).unwrap().seat);
+    }
+
+    #[test]
+    fn change_item__no_seat__line_is_shared_again() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()).with_seat(Some(2))]).with_guest_count(Some(2));
+
+        let result = order.change_item(&LineId(1), &OrderItemChanges { seat: Some(None), ..Default::default() }, &create_test_menu());
+
+        assert_eq!(Ok(()), result);
+        assert_eq!(None, order.items.get(&LineId(1)).unwrap().seat);
+    }
+
+    #[test]
+    fn replace_items__same_dish_for_another_seat__goes_on_its_own_line() {
+        let mut order = create_order(&[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()).with_seat(Some(1))]).with_guest_count(Some(2));
+
+        let removed = order
+            .replace_items(
+                &[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()).with_seat(Some(1)), TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()).with_seat(Some(2))],
+                &create_test_menu(),
+                100,
+            )
+            .unwrap();
+
+        assert!(removed.is_empty());
+        assert_eq!(vec![(LineId(1), Some(1), 1), (LineId(2), Some(2), 1)], order.items.values().map(|i| (i.line_id, i.seat, i.quantity)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn items_by_seat__seated_and_shared_lines__lowest_seat_first_and_shared_last() {
+        let order = create_order(&[
+            TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()),
+            TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()).with_seat(Some(3)),
+            TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()).with_seat(Some(1)),
+            TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()).with_seat(Some(3)),
+        ]);
+
+        let seats = order.items_by_seat();
+
+        assert_eq!(
+            vec![(Some(1), vec![LineId(3)]), (Some(3), vec![LineId(2), LineId(4)]), (None, vec![LineId(1)])],
+            seats
+                .into_iter()
+                .map(|(seat, items)| (seat, items.iter().map(|i| i.line_id).collect()))
+                .collect::<Vec<(Option<u32>, Vec<LineId>)>>()
+        );
+    }
+}
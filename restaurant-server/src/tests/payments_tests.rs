@@ -0,0 +1,266 @@
+#![allow(non_snake_case)]
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use crate::{
+        models::{
+            menu::{Menu, MenuItemId},
+            money::{Currency, Money},
+            orders::{LineId, OrderChangeError, TableId, TableOrder, TableOrderItem},
+            payments::{PaymentId, PaymentMethod, Refund, RefundStatus},
+        },
+        payments::{FakeGateway, PaymentGateway, PaymentGatewayError, FAKE_DECLINED_CARD_TOKEN},
+        tests::fixtures::menu_item,
+    };
+
+    fn paid_at() -> DateTime<Utc> {
+        return Utc.with_ymd_and_hms(2024, 1, 1, 22, 0, 0).unwrap();
+    }
+
+    fn eur(minor_units: i64) -> Money {
+        return Money::new(minor_units, Currency::Eur);
+    }
+
+    // Untaxed with no service charge, so a bill's total is just its lines. Item 1 is 10.00, item 2 is 2.50.
+    fn create_test_menu() -> Menu {
+        let create_item = |id: i32, price: i64| menu_item(id, &format!("menu item {}", id)).with_category("mains").with_price(eur(price));
+
+        return Menu::new(vec![create_item(1, 1000), create_item(2, 250)]).unwrap();
+    }
+
+    // 12.50 to pay, already billed
+    fn create_billed_order(menu: &Menu) -> TableOrder {
+        let items = [TableOrderItem::new(MenuItemId(1), 1, 10, paid_at()), TableOrderItem::new(MenuItemId(2), 1, 10, paid_at())];
+        let mut order = TableOrder::new(TableId(123), &items);
+        order.issue_bill(menu, None, paid_at()).unwrap();
+        return order;
+    }
+
+    #[test]
+    fn record_payment__partial_payments__reduce_the_balance_until_paid() {
+        let menu = create_test_menu();
+        let mut order = create_billed_order(&menu);
+
+        order.record_payment(PaymentMethod::Cash, eur(500), None, paid_at()).unwrap();
+        assert_eq!(eur(750), order.balance().unwrap());
+
+        let payment = order
+            .record_payment(PaymentMethod::Card, eur(750), Some("fake_ch_1".to_string()), paid_at())
+            .unwrap();
+        assert_eq!(PaymentId(2), payment.payment_id);
+        assert_eq!(Some("fake_ch_1".to_string()), payment.gateway_reference);
+        assert_eq!(eur(0), order.balance().unwrap());
+        assert_eq!(Ok(()), order.check_settled());
+    }
+
+    #[test]
+    fn record_payment__more_than_is_left__is_error() {
+        let menu = create_test_menu();
+        let mut order = create_billed_order(&menu);
+        order.record_payment(PaymentMethod::Cash, eur(1000), None, paid_at()).unwrap();
+
+        let result = order.record_payment(PaymentMethod::Cash, eur(251), None, paid_at());
+
+        assert_eq!(Err(OrderChangeError::Overpayment("123".to_string(), eur(251), eur(250))), result.map(|_| ()));
+        assert_eq!(1, order.payments.len());
+    }
+
+    #[test]
+    fn record_payment__already_paid__is_error() {
+        let menu = create_test_menu();
+        let mut order = create_billed_order(&menu);
+        order.record_payment(PaymentMethod::Cash, eur(1250), None, paid_at()).unwrap();
+
+        let result = order.record_payment(PaymentMethod::Cash, eur(1), None, paid_at());
+
+        assert_eq!(Err(OrderChangeError::AlreadyPaid("123".to_string())), result.map(|_| ()));
+    }
+
+    #[test]
+    fn record_payment__order_changed_since_bill__is_error() {
+        let menu = create_test_menu();
+        let mut order = create_billed_order(&menu);
+        order.add_item(TableOrderItem::new(MenuItemId(2), 1, 10, paid_at()));
+
+        let result = order.record_payment(PaymentMethod::Cash, eur(100), None, paid_at());
+
+        assert_eq!(Err(OrderChangeError::UnbilledItems("123".to_string())), result.map(|_| ()));
+    }
+
+    #[test]
+    fn balance__bill_reissued_after_paying__counts_what_was_already_paid() {
+        let menu = create_test_menu();
+        let mut order = create_billed_order(&menu);
+        order.record_payment(PaymentMethod::Cash, eur(1250), None, paid_at()).unwrap();
+
+        order.add_item(TableOrderItem::new(MenuItemId(2), 2, 10, paid_at()));
+        order.issue_bill(&menu, None, paid_at()).unwrap();
+
+        assert_eq!(eur(500), order.balance().unwrap());
+        assert_eq!(Err(OrderChangeError::OutstandingBalance("123".to_string(), eur(500))), order.check_settled());
+    }
+
+    #[test]
+    fn record_refund__in_parts__up_to_what_was_paid() {
+        let menu = create_test_menu();
+        let mut order = create_billed_order(&menu);
+        order.record_payment(PaymentMethod::Cash, eur(1250), None, paid_at()).unwrap();
+
+        order.record_refund(PaymentId(1), eur(200), None, paid_at()).unwrap();
+        let payment = order.record_refund(PaymentId(1), eur(50), None, paid_at()).unwrap();
+
+        assert_eq!(eur(250), payment.refunded());
+        assert_eq!(eur(1000), payment.net_amount());
+        assert_eq!(eur(250), order.balance().unwrap());
+        assert_eq!(Err(OrderChangeError::RefundExceedsPayment("123".to_string(), "1".to_string(), eur(1000))), order.record_refund(PaymentId(1), eur(1001), None, paid_at()).map(|_| ()));
+    }
+
+    #[test]
+    fn record_refund__unknown_payment__is_error() {
+        let menu = create_test_menu();
+        let mut order = create_billed_order(&menu);
+
+        let result = order.record_refund(PaymentId(7), eur(100), None, paid_at());
+
+        assert_eq!(Err(OrderChangeError::PaymentNotFound("123".to_string(), "7".to_string())), result.map(|_| ()));
+    }
+
+    #[test]
+    fn reserve_refund__same_money_again_while_pending__is_error() {
+        let menu = create_test_menu();
+        let mut order = create_billed_order(&menu);
+        order
+            .record_payment(PaymentMethod::Card, eur(1250), Some("fake_ch_1".to_string()), paid_at())
+            .unwrap();
+
+        let payment = order.reserve_refund(PaymentId(1), eur(1250), paid_at()).unwrap();
+
+        assert_eq!(eur(1250), payment.refunded());
+        assert_eq!(Err(OrderChangeError::RefundExceedsPayment("123".to_string(), "1".to_string(), eur(0))), order.reserve_refund(PaymentId(1), eur(1250), paid_at()).map(|_| ()));
+    }
+
+    #[test]
+    fn finish_refund__gateway_refused__no_longer_counts_against_payment() {
+        let menu = create_test_menu();
+        let mut order = create_billed_order(&menu);
+        order
+            .record_payment(PaymentMethod::Card, eur(1250), Some("fake_ch_1".to_string()), paid_at())
+            .unwrap();
+        order.reserve_refund(PaymentId(1), eur(250), paid_at()).unwrap();
+
+        let payment = order.finish_refund(PaymentId(1), 0, None).unwrap();
+
+        assert_eq!(eur(0), payment.refunded());
+        assert_eq!(RefundStatus::Failed, payment.refunds[0].status);
+        assert_eq!(Ok(()), order.check_settled());
+    }
+
+    #[test]
+    fn finish_refund__gateway_refunded__is_refunded_with_its_reference() {
+        let menu = create_test_menu();
+        let mut order = create_billed_order(&menu);
+        order
+            .record_payment(PaymentMethod::Card, eur(1250), Some("fake_ch_1".to_string()), paid_at())
+            .unwrap();
+        order.reserve_refund(PaymentId(1), eur(250), paid_at()).unwrap();
+
+        order.finish_refund(PaymentId(1), 0, Some("fake_re_1".to_string())).unwrap();
+        let payment = order.finish_refund(PaymentId(1), 0, None).unwrap();
+
+        assert_eq!(vec![Refund { amount: eur(250), gateway_reference: Some("fake_re_1".to_string()), refunded_at: paid_at(), status: RefundStatus::Refunded }], payment.refunds);
+    }
+
+    #[test]
+    fn check_settled__refund_pending__is_error() {
+        let menu = create_test_menu();
+        let mut order = create_billed_order(&menu);
+        order
+            .record_payment(PaymentMethod::Card, eur(1250), Some("fake_ch_1".to_string()), paid_at())
+            .unwrap();
+        order.remove_item(&LineId(2)).unwrap();
+        order.issue_bill(&menu, None, paid_at()).unwrap();
+        order.reserve_refund(PaymentId(1), eur(250), paid_at()).unwrap();
+
+        assert_eq!(Err(OrderChangeError::RefundPending("123".to_string(), "1".to_string())), order.check_settled());
+    }
+
+    #[test]
+    fn check_settled__nothing_ever_ordered__is_ok() {
+        let order = TableOrder::new(TableId(123), &[]);
+
+        assert_eq!(Ok(()), order.check_settled());
+    }
+
+    #[test]
+    fn check_settled__items_never_billed__is_error() {
+        let items = [TableOrderItem::new(MenuItemId(1), 1, 10, paid_at())];
+        let order = TableOrder::new(TableId(123), &items);
+
+        assert_eq!(Err(OrderChangeError::UnbilledItems("123".to_string())), order.check_settled());
+    }
+
+    #[test]
+    fn check_settled__paid_then_refunded__is_error() {
+        let menu = create_test_menu();
+        let mut order = create_billed_order(&menu);
+        order.record_payment(PaymentMethod::Cash, eur(1250), None, paid_at()).unwrap();
+        order.record_refund(PaymentId(1), eur(250), None, paid_at()).unwrap();
+
+        assert_eq!(Err(OrderChangeError::OutstandingBalance("123".to_string(), eur(250))), order.check_settled());
+        assert_eq!(vec![Refund { amount: eur(250), gateway_reference: None, refunded_at: paid_at(), status: RefundStatus::Refunded }], order.payments[0].refunds);
+    }
+
+    #[test]
+    fn check_settled__line_taken_off_after_paying__is_error_until_refunded() {
+        let menu = create_test_menu();
+        let mut order = create_billed_order(&menu);
+        order.record_payment(PaymentMethod::Cash, eur(1250), None, paid_at()).unwrap();
+        order.remove_item(&LineId(2)).unwrap();
+        order.issue_bill(&menu, None, paid_at()).unwrap();
+
+        assert_eq!(Err(OrderChangeError::Overpaid("123".to_string(), eur(250))), order.check_settled());
+
+        order.record_refund(PaymentId(1), eur(250), None, paid_at()).unwrap();
+        assert_eq!(Ok(()), order.check_settled());
+    }
+
+    #[test]
+    fn payment_method__to_string__is_parsed_back() {
+        for method in [PaymentMethod::Cash, PaymentMethod::Card] {
+            assert_eq!(Ok(method), method.to_string().parse());
+        }
+        assert!("cheque".parse::<PaymentMethod>().is_err());
+    }
+
+    #[test]
+    fn refund_status__to_string__is_parsed_back() {
+        for status in [RefundStatus::Pending, RefundStatus::Refunded, RefundStatus::Failed] {
+            assert_eq!(Ok(status), status.to_string().parse());
+        }
+        assert!("lost".parse::<RefundStatus>().is_err());
+    }
+
+    #[tokio::test]
+    async fn fake_gateway__charge_then_refund__only_up_to_the_charge() {
+        let sut = FakeGateway::default();
+
+        let charge = sut.charge("tok_visa", eur(1250)).await.unwrap();
+        let refund = sut.refund(&charge, eur(1000)).await.unwrap();
+
+        assert_ne!(charge, refund);
+        assert_eq!(Some(eur(1000)), sut.refunded(&charge));
+        assert!(matches!(sut.refund(&charge, eur(251)).await, Err(PaymentGatewayError::Declined(_))));
+        assert!(matches!(sut.refund("fake_ch_999", eur(1)).await, Err(PaymentGatewayError::Declined(_))));
+    }
+
+    #[tokio::test]
+    async fn fake_gateway__declined_token__is_declined() {
+        let sut = FakeGateway::default();
+
+        let result = sut.charge(FAKE_DECLINED_CARD_TOKEN, eur(1250)).await;
+
+        assert!(matches!(result, Err(PaymentGatewayError::Declined(_))));
+    }
+}
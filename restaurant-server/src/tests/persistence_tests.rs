@@ -0,0 +1,655 @@
+#![allow(non_snake_case)]
+
+// The behavior every Persistence implementation has to share, written once and run against each backend by persistence_tests!.
+// Whatever only one backend has (its locking, migrations, reopening a file) stays in that backend's own test file.
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::{
+    models::{
+        billing::{BillingConfig, ServiceChargeRule, TaxMode, TaxRounding},
+        menu::{Menu, MenuItemId},
+        money::{Currency, Money, Percentage},
+        orders::{TableId, TableOrder, TableOrderItem},
+        payments::{PaymentId, PaymentMethod},
+    },
+    persistence::persistence::Persistence,
+    tests::fixtures::menu_item,
+};
+
+pub fn ordered_at() -> DateTime<Utc> {
+    return Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+}
+
+// Item n takes 10 minutes plus 2 for each extra one
+pub fn create_test_menu() -> Menu {
+    let items = (1..=5).map(|i| menu_item(i, &format!("menu item {}", i)).with_prep_time(10, 2)).collect();
+    return Menu::new(items).unwrap();
+}
+
+pub fn create_billing_config() -> BillingConfig {
+    return BillingConfig {
+        tax_mode: TaxMode::Exclusive,
+        tax_rounding: TaxRounding::PerLine,
+        default_tax_rate: Percentage(8875),
+        tax_rates: Default::default(),
+        service_charge: Some(ServiceChargeRule { min_guests: 2, rate: Percentage(12500) }),
+    };
+}
+
+// Bills the order with tax and a service charge, pays it by card and refunds part of it
+pub async fn bill_and_pay<P: Persistence>(sut: &P, table_id: &TableId) -> TableOrder {
+    let menu = create_test_menu().with_billing(create_billing_config());
+    sut.update_order_with(table_id, |o| o.issue_bill(&menu, Some(2), ordered_at()).map(|_| ()), |_| ())
+        .await
+        .unwrap();
+    sut.update_order_with(
+        table_id,
+        |o| {
+            let total = o.latest_bill().unwrap().total;
+            return o
+                .record_payment(PaymentMethod::Card, total, Some("fake_ch_1".to_string()), ordered_at())
+                .map(|_| ());
+        },
+        |_| (),
+    )
+    .await
+    .unwrap();
+    return sut
+        .update_order_with(
+            table_id,
+            |o| {
+                o.record_refund(PaymentId(1), Money::new(100, Currency::Eur), Some("fake_re_1".to_string()), ordered_at())
+                    .map(|_| ())
+            },
+            |_| (),
+        )
+        .await
+        .unwrap();
+}
+
+// 50 tables each created and then updated 19 times, all at once, so every order should end up with a quantity of 20
+pub async fn run_concurrent_requests<P: Persistence + 'static>(sut: &Arc<P>) {
+    let tasks = (0..50)
+        .map(|i| {
+            let sut = Arc::clone(sut);
+            return tokio::spawn(async move {
+                let table_id = TableId(i);
+                sut.create_order(&table_id, None, &[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())], |_| ())
+                    .await
+                    .unwrap();
+                for quantity in 2..=20 {
+                    sut.update_order(&table_id, &[TableOrderItem::new(MenuItemId(1), quantity, 10, ordered_at())], &create_test_menu(), 100, |_| ())
+                        .await
+                        .unwrap();
+                    sut.find_order(&table_id).await.unwrap();
+                }
+            });
+        })
+        .collect::<Vec<_>>();
+    for task in tasks {
+        task.await.unwrap();
+    }
+}
+
+pub fn all_quantities_are_20(data: &HashMap<TableId, TableOrder>) -> bool {
+    return data.values().all(|o| o.items.values().map(|i| i.quantity).collect::<Vec<i32>>() == vec![20]);
+}
+
+// Expects the module it is used in to have
+// `async fn create_sut(data: HashMap<TableId, TableOrder>) -> P`, which starts a fresh backend holding `data`, and
+// `fn get_underlying_data(sut: P) -> HashMap<TableId, TableOrder>`, which reads back what the backend really stored
+macro_rules! persistence_tests {
+    () => {
+        mod persistence_tests {
+            use std::collections::HashMap;
+
+            use chrono::{TimeDelta, TimeZone, Utc};
+
+            use super::{create_sut, get_underlying_data};
+            use crate::{
+                models::{
+                    billing::{BillingConfig, TaxMode, TaxRounding},
+                    menu::{MenuItemId, ModifierId},
+                    money::{Currency, Money},
+                    orders::{LineId, OrderChangeError, OrderItemStatus, TableId, TableOrder, TableOrderItem},
+                    payments::{PaymentId, PaymentMethod, RefundStatus, Settlement, SettlementId},
+                },
+                persistence::persistence::{CreateOrderError, ModifyOrderError, Persistence, ReadOrderError},
+                tests::persistence_tests::{bill_and_pay, create_billing_config, create_test_menu, ordered_at},
+            };
+
+            #[tokio::test]
+            async fn create_order__no_existing_order__is_created() {
+                let table_id = TableId(123);
+                let items =
+                    vec![TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(2), 1, 11, ordered_at()), TableOrderItem::new(MenuItemId(3), 1, 12, ordered_at())];
+                let sut = create_sut(HashMap::new()).await;
+
+                let result = sut.create_order(&table_id, None, &items, |_| ()).await;
+
+                assert!(result.is_ok());
+                let result = result.unwrap();
+                assert_eq!(TableId(123), result.table_id);
+                assert_eq!(3, result.items.len());
+                assert_eq!(TableOrderItem { line_id: LineId(1), ..items[0].clone() }, *result.items.get(&LineId(1)).unwrap());
+                assert_eq!(TableOrderItem { line_id: LineId(2), ..items[1].clone() }, *result.items.get(&LineId(2)).unwrap());
+                assert_eq!(TableOrderItem { line_id: LineId(3), ..items[2].clone() }, *result.items.get(&LineId(3)).unwrap());
+                assert_eq!(1, get_underlying_data(sut).len());
+            }
+
+            #[tokio::test]
+            async fn create_order__same_menu_item_twice__keeps_both_lines() {
+                let table_id = TableId(123);
+                let items = vec![TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(1), 2, 12, ordered_at())];
+                let sut = create_sut(HashMap::new()).await;
+
+                let result = sut.create_order(&table_id, None, &items, |_| ()).await;
+
+                assert!(result.is_ok());
+                assert_eq!(vec![(LineId(1), 1), (LineId(2), 2)], result.unwrap().items.values().map(|i| (i.line_id, i.quantity)).collect::<Vec<(LineId, i32)>>());
+                assert_eq!(2, get_underlying_data(sut).get(&table_id).unwrap().items.len());
+            }
+
+            #[tokio::test]
+            async fn create_order__notes_and_modifiers__are_kept() {
+                let table_id = TableId(123);
+                let items = vec![
+                    TableOrderItem::new(MenuItemId(4), 1, 18, ordered_at())
+                        .with_notes(Some("cut in half".to_string()))
+                        .with_modifiers(vec![ModifierId("rare".to_string()), ModifierId("no_sauce".to_string())]),
+                    TableOrderItem::new(MenuItemId(4), 1, 18, ordered_at()),
+                ];
+                let sut = create_sut(HashMap::new()).await;
+
+                sut.create_order(&table_id, None, &items, |_| ()).await.unwrap();
+
+                let order = get_underlying_data(sut).remove(&table_id).unwrap();
+                assert_eq!(TableOrderItem { line_id: LineId(1), ..items[0].clone() }, *order.items.get(&LineId(1)).unwrap());
+                assert_eq!(TableOrderItem { line_id: LineId(2), ..items[1].clone() }, *order.items.get(&LineId(2)).unwrap());
+            }
+
+            #[tokio::test]
+            async fn create_order__guest_count_and_seats__are_kept_on_the_order_and_its_bills() {
+                let table_id = TableId(123);
+                let items = vec![TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()).with_seat(Some(2)), TableOrderItem::new(MenuItemId(2), 1, 10, ordered_at())];
+                let sut = create_sut(HashMap::new()).await;
+
+                sut.create_order(&table_id, Some(3), &items, |_| ()).await.unwrap();
+                let order = sut
+                    .update_order_with(&table_id, |o| o.issue_bill(&create_test_menu(), None, ordered_at()).map(|_| ()), |_| ())
+                    .await
+                    .unwrap();
+
+                let underlying_order = get_underlying_data(sut).remove(&table_id).unwrap();
+                assert_eq!(Some(3), underlying_order.guest_count);
+                assert_eq!(vec![Some(2), None], underlying_order.items.values().map(|i| i.seat).collect::<Vec<Option<u32>>>());
+                assert_eq!(vec![Some(2), None], underlying_order.bills[0].lines.iter().map(|l| l.seat).collect::<Vec<Option<u32>>>());
+                assert_eq!(order, underlying_order);
+            }
+
+            #[tokio::test]
+            async fn create_order__table_has_existing_order__is_error() {
+                let table_id = TableId(123);
+                let items =
+                    vec![TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(2), 1, 11, ordered_at()), TableOrderItem::new(MenuItemId(3), 1, 12, ordered_at())];
+                let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+                data.insert(table_id.clone(), TableOrder::new(table_id.clone(), &[]));
+                let sut = create_sut(data).await;
+
+                let result = sut.create_order(&table_id, None, &items, |_| ()).await;
+
+                assert!(result.is_err());
+                assert_eq!(CreateOrderError::OrderAlreadyExistsForTable(table_id.to_string()), result.unwrap_err());
+                assert_eq!(1, get_underlying_data(sut).len());
+            }
+
+            #[tokio::test]
+            async fn update_order__no_existing_order__is_error() {
+                let table_id = TableId(123);
+                let items =
+                    vec![TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(2), 1, 11, ordered_at()), TableOrderItem::new(MenuItemId(3), 1, 12, ordered_at())];
+                let sut = create_sut(HashMap::new()).await;
+
+                let result = sut.update_order(&table_id, &items, &create_test_menu(), 100, |_| ()).await;
+
+                assert!(result.is_err());
+                assert_eq!(ModifyOrderError::OrderNotFound(table_id.to_string()), result.unwrap_err());
+                assert_eq!(0, get_underlying_data(sut).len());
+            }
+
+            #[tokio::test]
+            async fn update_order__existing_order__keeps_unchanged_lines_and_reports_removed() {
+                let table_id = TableId(123);
+                let existing_items =
+                    vec![TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(2), 1, 11, ordered_at()), TableOrderItem::new(MenuItemId(3), 1, 12, ordered_at())];
+                let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+                data.insert(table_id.clone(), TableOrder::new(table_id.clone(), &existing_items));
+
+                let sut = create_sut(data).await;
+
+                let table_id = TableId(123);
+                let new_items = vec![
+                    TableOrderItem::new(MenuItemId(2), 1, 11, ordered_at()),
+                    TableOrderItem::new(MenuItemId(3), 1, 12, ordered_at()),
+                    TableOrderItem::new(MenuItemId(4), 1, 13, ordered_at()),
+                    TableOrderItem::new(MenuItemId(5), 1, 14, ordered_at()),
+                ];
+
+                let result = sut.update_order(&table_id, &new_items, &create_test_menu(), 100, |_| ()).await;
+
+                assert!(result.is_ok());
+                let (result, removed) = result.unwrap();
+                assert_eq!(TableId(123), result.table_id);
+                // Menu items 2 and 3 are unchanged and keep their lines, 1 is removed and 4 and 5 go on new lines
+                assert_eq!(vec![LineId(2), LineId(3), LineId(4), LineId(5)], result.items.keys().copied().collect::<Vec<LineId>>());
+                assert_eq!(TableOrderItem { line_id: LineId(2), ..existing_items[1].clone() }, *result.items.get(&LineId(2)).unwrap());
+                assert_eq!(TableOrderItem { line_id: LineId(4), ..new_items[2].clone() }, *result.items.get(&LineId(4)).unwrap());
+                assert_eq!(TableOrderItem { line_id: LineId(5), ..new_items[3].clone() }, *result.items.get(&LineId(5)).unwrap());
+                assert_eq!(vec![TableOrderItem { line_id: LineId(1), ..existing_items[0].clone() }], removed);
+                assert_eq!(result, *get_underlying_data(sut).get(&table_id).unwrap());
+            }
+
+            #[tokio::test]
+            async fn find_order__unknown_id__is_error() {
+                let table_id = TableId(123);
+                let sut = create_sut(HashMap::new()).await;
+
+                let result = sut.find_order(&table_id).await;
+
+                assert!(result.is_err());
+                assert_eq!(ReadOrderError::OrderNotFound(table_id.to_string()), result.unwrap_err());
+            }
+
+            #[tokio::test]
+            async fn find_order__id_exists__returns_order() {
+                let table_id = TableId(123);
+                let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+
+                let expected_order = TableOrder::new(table_id.clone(), &[TableOrderItem::new(MenuItemId(1), 2, 10, ordered_at())]);
+                data.insert(table_id.clone(), expected_order.clone());
+
+                let sut = create_sut(data).await;
+
+                let result = sut.find_order(&table_id).await;
+
+                assert!(result.is_ok());
+                assert_eq!(expected_order, result.unwrap());
+            }
+
+            #[tokio::test]
+            async fn delete_order__order_does_not_exist__is_error() {
+                let sut = create_sut(HashMap::new()).await;
+
+                let table_id = TableId(123);
+                let result = sut.delete_order(&table_id, ordered_at(), |_| Ok(()), |_, _| ()).await;
+
+                assert!(result.is_err());
+                assert_eq!(ModifyOrderError::OrderNotFound(table_id.to_string()), result.unwrap_err());
+            }
+
+            #[tokio::test]
+            async fn delete_order__order_exists__is_deleted() {
+                let table_id = TableId(123);
+                let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+                data.insert(table_id.clone(), TableOrder::new(table_id.clone(), &[]));
+                let sut = create_sut(data).await;
+
+                let table_id = TableId(123);
+                let result = sut.delete_order(&table_id, ordered_at(), |_| Ok(()), |_, _| ()).await;
+
+                assert!(result.is_ok());
+                assert_eq!(0, get_underlying_data(sut).len());
+            }
+
+            #[tokio::test]
+            async fn delete_order__order_exists__saved_is_called_with_deleted_order() {
+                let table_id = TableId(123);
+                let order = TableOrder::new(table_id.clone(), &[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]);
+                let sut = create_sut(HashMap::from([(table_id.clone(), order.clone())])).await;
+
+                let mut deleted = None;
+                let result = sut.delete_order(&table_id, ordered_at(), |_| Ok(()), |o, _| deleted = Some(o.clone())).await;
+
+                assert!(result.is_ok());
+                assert_eq!(Some(order), deleted);
+            }
+
+            #[tokio::test]
+            async fn delete_order__check_fails__is_error_and_order_is_kept() {
+                let table_id = TableId(123);
+                let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+                data.insert(table_id.clone(), TableOrder::new(table_id.clone(), &[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]));
+                let sut = create_sut(data).await;
+
+                let result = sut.delete_order(&table_id, ordered_at(), |o| o.check_settled(), |_, _| ()).await;
+
+                assert_eq!(ModifyOrderError::Rejected(OrderChangeError::UnbilledItems(table_id.to_string())), result.unwrap_err());
+                assert_eq!(1, get_underlying_data(sut).len());
+            }
+
+            #[tokio::test]
+            async fn delete_order__billed_and_paid__bills_and_payments_are_settled() {
+                let table_id = TableId(123);
+                let closed_at = ordered_at() + TimeDelta::hours(2);
+                let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+                data.insert(table_id.clone(), TableOrder::new(table_id.clone(), &[TableOrderItem::new(MenuItemId(1), 2, 10, ordered_at())]));
+                let sut = create_sut(data).await;
+                let order = bill_and_pay(&sut, &table_id).await;
+
+                let mut saved = None;
+                let result = sut.delete_order(&table_id, closed_at, |_| Ok(()), |_, s| saved = s.cloned()).await;
+
+                let expected = Settlement { settlement_id: SettlementId(1), table_id: table_id.clone(), closed_at: closed_at, bills: order.bills.clone(), payments: order.payments.clone() };
+                assert_eq!(Ok(Some(expected.clone())), result);
+                assert_eq!(Some(expected.clone()), saved);
+                assert_eq!(Ok(vec![expected]), sut.list_settlements(&table_id).await);
+                assert_eq!(0, get_underlying_data(sut).len());
+            }
+
+            #[tokio::test]
+            async fn delete_order__table_closed_again__keeps_a_settlement_for_each_billed_order() {
+                let table_id = TableId(123);
+                let sut = create_sut(HashMap::new()).await;
+                let items = [TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())];
+
+                sut.create_order(&table_id, None, &items, |_| ()).await.unwrap();
+                let first = bill_and_pay(&sut, &table_id).await;
+                sut.delete_order(&table_id, ordered_at(), |_| Ok(()), |_, _| ()).await.unwrap();
+                // Never billed, so nothing to keep
+                sut.create_order(&table_id, None, &items, |_| ()).await.unwrap();
+                let unbilled = sut.delete_order(&table_id, ordered_at(), |_| Ok(()), |_, _| ()).await;
+                sut.create_order(&table_id, None, &items, |_| ()).await.unwrap();
+                let second = bill_and_pay(&sut, &table_id).await;
+                sut.delete_order(&table_id, ordered_at(), |_| Ok(()), |_, _| ()).await.unwrap();
+
+                assert_eq!(Ok(None), unbilled);
+                let settlements = sut.list_settlements(&table_id).await.unwrap();
+                assert_eq!(vec![(SettlementId(1), first.payments), (SettlementId(2), second.payments)], settlements.into_iter().map(|s| (s.settlement_id, s.payments)).collect::<Vec<_>>());
+                assert_eq!(Ok(vec![]), sut.list_settlements(&TableId(7)).await);
+            }
+
+            #[tokio::test]
+            async fn list_orders__no_orders__is_empty() {
+                let sut = create_sut(HashMap::new()).await;
+
+                let result = sut.list_orders().await;
+
+                assert_eq!(Ok(vec![]), result);
+            }
+
+            #[tokio::test]
+            async fn list_orders__several_orders__returns_all() {
+                let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+                for id in [1, 2, 3] {
+                    data.insert(TableId(id), TableOrder::new(TableId(id), &[TableOrderItem::new(MenuItemId(id), 1, 10, ordered_at())]));
+                }
+                let sut = create_sut(data.clone()).await;
+                sut.delete_order(&TableId(2), ordered_at(), |_| Ok(()), |_, _| ()).await.unwrap();
+
+                let result = sut.list_orders().await;
+
+                let mut result = result.unwrap();
+                result.sort_by_key(|o| o.table_id.clone());
+                assert_eq!(vec![data.get(&TableId(1)).unwrap().clone(), data.get(&TableId(3)).unwrap().clone()], result);
+            }
+
+            #[tokio::test]
+            async fn update_order_with__no_existing_order__is_error() {
+                let table_id = TableId(123);
+                let sut = create_sut(HashMap::new()).await;
+
+                let result = sut.update_order_with(&table_id, |_| Ok(()), |_| ()).await;
+
+                assert_eq!(ModifyOrderError::OrderNotFound(table_id.to_string()), result.unwrap_err());
+            }
+
+            #[tokio::test]
+            async fn update_order_with__change_succeeds__is_saved() {
+                let table_id = TableId(123);
+                let at = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+                let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+                data.insert(table_id.clone(), TableOrder::new(table_id.clone(), &[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]));
+                let sut = create_sut(data).await;
+
+                let result = sut
+                    .update_order_with(&table_id, |o| o.advance_item_status(&LineId(1), OrderItemStatus::Preparing, at), |_| ())
+                    .await;
+
+                assert!(result.is_ok());
+                assert_eq!(OrderItemStatus::Preparing, result.unwrap().items.get(&LineId(1)).unwrap().status);
+                let underlying_data = get_underlying_data(sut);
+                let underlying_item = underlying_data.get(&table_id).unwrap().items.get(&LineId(1)).unwrap();
+                assert_eq!(OrderItemStatus::Preparing, underlying_item.status);
+                assert_eq!(Some(at), underlying_item.status_changed_at());
+            }
+
+            #[tokio::test]
+            async fn update_order_with__change_succeeds__saved_is_called_with_saved_order() {
+                let table_id = TableId(123);
+                let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+                data.insert(table_id.clone(), TableOrder::new(table_id.clone(), &[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]));
+                let sut = create_sut(data).await;
+
+                let mut saved = None;
+                let result = sut
+                    .update_order_with(&table_id, |o| o.advance_item_status(&LineId(1), OrderItemStatus::Preparing, ordered_at()), |o| saved = Some(o.clone()))
+                    .await;
+
+                assert_eq!(Some(result.unwrap()), saved);
+                assert_eq!(saved.unwrap(), get_underlying_data(sut).remove(&table_id).unwrap());
+            }
+
+            #[tokio::test]
+            async fn update_order_with__change_is_rejected__nothing_is_saved() {
+                let table_id = TableId(123);
+                let existing_items = vec![TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(2), 1, 11, ordered_at())];
+                let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+                data.insert(table_id.clone(), TableOrder::new(table_id.clone(), &existing_items));
+                let sut = create_sut(data).await;
+
+                // The first change applies cleanly, so this checks the whole change is dropped rather than just the failing part
+                let result = sut
+                    .update_order_with(
+                        &table_id,
+                        |o| {
+                            o.advance_item_status(&LineId(1), OrderItemStatus::Preparing, Utc::now())?;
+                            return o.advance_item_status(&LineId(2), OrderItemStatus::Served, Utc::now());
+                        },
+                        |_| panic!("nothing was saved"),
+                    )
+                    .await;
+
+                assert_eq!(
+                    ModifyOrderError::Rejected(OrderChangeError::IllegalStatusTransition(table_id.to_string(), "2".to_string(), OrderItemStatus::Ordered, OrderItemStatus::Served)),
+                    result.unwrap_err()
+                );
+                let underlying_data = get_underlying_data(sut);
+                assert_eq!(TableOrder::new(table_id.clone(), &existing_items), *underlying_data.get(&table_id).unwrap());
+            }
+
+            #[tokio::test]
+            async fn update_order_with__bills_issued__are_kept_unchanged() {
+                let table_id = TableId(123);
+                let at = Utc.with_ymd_and_hms(2024, 1, 1, 21, 0, 0).unwrap();
+                let per_line_menu = create_test_menu().with_billing(create_billing_config());
+                let per_bill_menu = create_test_menu().with_billing(BillingConfig { tax_mode: TaxMode::Inclusive, tax_rounding: TaxRounding::PerBill, ..create_billing_config() });
+                let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+                data.insert(table_id.clone(), TableOrder::new(table_id.clone(), &[TableOrderItem::new(MenuItemId(1), 2, 10, ordered_at())]));
+                let sut = create_sut(data).await;
+
+                sut.update_order_with(&table_id, |o| o.issue_bill(&per_line_menu, Some(2), at).map(|_| ()), |_| ())
+                    .await
+                    .unwrap();
+                sut.update_order_with(&table_id, |o| o.add_items(&[TableOrderItem::new(MenuItemId(2), 1, 10, ordered_at())], 100), |_| ())
+                    .await
+                    .unwrap();
+                let result = sut
+                    .update_order_with(&table_id, |o| o.issue_bill(&per_bill_menu, None, at).map(|_| ()), |_| ())
+                    .await;
+
+                let order = result.unwrap();
+                assert_eq!(2, order.bills.len());
+                assert_eq!(1, order.bills[0].lines.len());
+                let underlying_data = get_underlying_data(sut);
+                assert_eq!(order.bills, underlying_data.get(&table_id).unwrap().bills);
+            }
+
+            #[tokio::test]
+            async fn update_order_with__payments_and_refunds__are_kept() {
+                let table_id = TableId(123);
+                let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+                data.insert(table_id.clone(), TableOrder::new(table_id.clone(), &[TableOrderItem::new(MenuItemId(1), 2, 10, ordered_at())]));
+                let sut = create_sut(data).await;
+                sut.update_order_with(&table_id, |o| o.issue_bill(&create_test_menu(), None, ordered_at()).map(|_| ()), |_| ())
+                    .await
+                    .unwrap();
+
+                sut.update_order_with(
+                    &table_id,
+                    |o| {
+                        o.record_payment(PaymentMethod::Card, Money::new(1500, Currency::Eur), Some("fake_ch_1".to_string()), ordered_at())
+                            .map(|_| ())
+                    },
+                    |_| (),
+                )
+                .await
+                .unwrap();
+                sut.update_order_with(
+                    &table_id,
+                    |o| {
+                        o.record_payment(PaymentMethod::Cash, Money::new(500, Currency::Eur), None, ordered_at())
+                            .map(|_| ())
+                    },
+                    |_| (),
+                )
+                .await
+                .unwrap();
+                sut.update_order_with(
+                    &table_id,
+                    |o| {
+                        o.record_refund(PaymentId(1), Money::new(200, Currency::Eur), Some("fake_re_2".to_string()), ordered_at())
+                            .map(|_| ())
+                    },
+                    |_| (),
+                )
+                .await
+                .unwrap();
+                let result = sut
+                    .update_order_with(
+                        &table_id,
+                        |o| {
+                            o.record_refund(PaymentId(1), Money::new(100, Currency::Eur), Some("fake_re_3".to_string()), ordered_at())
+                                .map(|_| ())
+                        },
+                        |_| (),
+                    )
+                    .await;
+
+                let order = result.unwrap();
+                assert_eq!(Money::new(300, Currency::Eur), order.balance().unwrap());
+                let underlying_data = get_underlying_data(sut);
+                assert_eq!(order.payments, underlying_data.get(&table_id).unwrap().payments);
+                assert_eq!(2, order.payments[0].refunds.len());
+            }
+
+            #[tokio::test]
+            async fn update_order_with__pending_refund_finished__is_kept_with_the_gateway_reference() {
+                let table_id = TableId(123);
+                let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+                data.insert(table_id.clone(), TableOrder::new(table_id.clone(), &[TableOrderItem::new(MenuItemId(1), 2, 10, ordered_at())]));
+                let sut = create_sut(data).await;
+                bill_and_pay(&sut, &table_id).await;
+                sut.update_order_with(&table_id, |o| o.reserve_refund(PaymentId(1), Money::new(200, Currency::Eur), ordered_at()).map(|_| ()), |_| ())
+                    .await
+                    .unwrap();
+                assert_eq!(RefundStatus::Pending, sut.find_order(&table_id).await.unwrap().payments[0].refunds[1].status);
+
+                let result = sut
+                    .update_order_with(&table_id, |o| o.finish_refund(PaymentId(1), 1, Some("fake_re_2".to_string())).map(|_| ()), |_| ())
+                    .await;
+
+                let order = result.unwrap();
+                assert_eq!((RefundStatus::Refunded, Some("fake_re_2".to_string())), (order.payments[0].refunds[1].status, order.payments[0].refunds[1].gateway_reference.clone()));
+                let underlying_data = get_underlying_data(sut);
+                assert_eq!(order.payments, underlying_data.get(&table_id).unwrap().payments);
+            }
+
+            #[tokio::test]
+            async fn general_persistence_behavior() {
+                let table_id = TableId(123);
+                let items =
+                    vec![TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at()), TableOrderItem::new(MenuItemId(2), 1, 11, ordered_at()), TableOrderItem::new(MenuItemId(3), 1, 12, ordered_at())];
+                let sut = create_sut(HashMap::new()).await;
+
+                // No orders initially
+                assert!(sut.find_order(&table_id).await.is_err());
+
+                // Can add order
+                {
+                    let result = sut.create_order(&table_id, None, &items, |_| ()).await;
+                    assert!(result.is_ok());
+                    let added_order = result.unwrap();
+
+                    let mut added_order_item_ids = added_order.items.values().map(|i| i.item_id.clone()).collect::<Vec<MenuItemId>>();
+                    added_order_item_ids.sort();
+                    assert_eq!(TableId(123), added_order.table_id);
+                    assert_eq!(vec![MenuItemId(1), MenuItemId(2), MenuItemId(3)], added_order_item_ids);
+                }
+
+                // Can find the order after creation
+                {
+                    let found_order = sut.find_order(&table_id).await;
+                    assert!(found_order.is_ok());
+                    let found_order = found_order.unwrap();
+                    let mut found_order_item_ids = found_order.items.values().map(|i| i.item_id.clone()).collect::<Vec<MenuItemId>>();
+                    found_order_item_ids.sort();
+                    assert_eq!(TableId(123), found_order.table_id);
+                    assert_eq!(vec![MenuItemId(1), MenuItemId(2), MenuItemId(3)], found_order_item_ids);
+                }
+
+                // Can update the order with deleted and new items
+                {
+                    let new_items = vec![TableOrderItem::new(MenuItemId(2), 1, 11, ordered_at()), TableOrderItem::new(MenuItemId(4), 1, 14, ordered_at())];
+
+                    let result = sut.update_order(&table_id, &new_items, &create_test_menu(), 100, |_| ()).await;
+                    assert!(result.is_ok());
+                    let (updated_order, _) = result.unwrap();
+
+                    let mut updated_order_item_ids = updated_order.items.values().map(|i| i.item_id.clone()).collect::<Vec<MenuItemId>>();
+                    updated_order_item_ids.sort();
+                    assert_eq!(TableId(123), updated_order.table_id);
+                    assert_eq!(vec![MenuItemId(2), MenuItemId(4)], updated_order_item_ids);
+                }
+
+                // Can delete a single item
+                {
+                    // Menu item 2 was left alone by the update and kept its line
+                    let result = sut.update_order_with(&table_id, |o| o.remove_item(&LineId(2)), |_| ()).await;
+                    assert!(result.is_ok());
+
+                    let order_after_deletion = result.unwrap();
+                    let mut order_after_deletion_item_ids = order_after_deletion.items.values().map(|i| i.item_id.clone()).collect::<Vec<MenuItemId>>();
+                    order_after_deletion_item_ids.sort();
+                    assert_eq!(TableId(123), order_after_deletion.table_id);
+                    assert_eq!(vec![MenuItemId(4)], order_after_deletion_item_ids);
+                }
+
+                // Can delete the order
+                {
+                    let result = sut.delete_order(&table_id, ordered_at(), |_| Ok(()), |_, _| ()).await;
+                    assert!(result.is_ok());
+                }
+
+                // Can no longer find the order
+                {
+                    let result = sut.find_order(&table_id).await;
+                    assert!(result.is_err());
+                }
+            }
+        }
+    };
+}
+
+pub(crate) use persistence_tests;
@@ -0,0 +1,208 @@
+#![allow(non_snake_case)]
+
+// The behavior shared with the other backends is in persistence_tests.rs, this is just what is particular to sqlite
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    use chrono::Utc;
+
+    use crate::{
+        models::{
+            menu::MenuItemId,
+            orders::{LineId, OrderItemStatus, TableId, TableOrder, TableOrderItem},
+        },
+        persistence::{
+            persistence::Persistence,
+            sqlite_persistence::{get_underlying_data, lock_table, SqlitePersistence, MIGRATIONS},
+        },
+        tests::persistence_tests::{all_quantities_are_20, ordered_at, persistence_tests, run_concurrent_requests},
+    };
+
+    async fn create_sut(data: HashMap<TableId, TableOrder>) -> SqlitePersistence {
+        let sut = SqlitePersistence::open_in_memory().unwrap();
+        for order in data.values() {
+            sut.create_order(&order.table_id, None, &order.items.values().cloned().collect::<Vec<TableOrderItem>>(), |_| ())
+                .await
+                .unwrap();
+        }
+
+        return sut;
+    }
+
+    persistence_tests!();
+
+    fn create_temp_db_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("restaurant-server-{}-{}.db", name, std::process::id()));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+        return path;
+    }
+
+    #[tokio::test]
+    async fn open__existing_database_file__keeps_orders() {
+        let path = create_temp_db_path("reopen");
+        let table_id = TableId(123);
+        let items = vec![TableOrderItem::new(MenuItemId(1), 2, 10, ordered_at())];
+
+        {
+            let sut = SqlitePersistence::open(&path).unwrap();
+            sut.create_order(&table_id, None, &items, |_| ()).await.unwrap();
+        }
+
+        let sut = SqlitePersistence::open(&path).unwrap();
+        let result = sut.find_order(&table_id).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_ok());
+        assert_eq!(TableOrderItem { line_id: LineId(1), ..items[0].clone() }, *result.unwrap().items.get(&LineId(1)).unwrap());
+    }
+
+    #[tokio::test]
+    async fn open__items_from_before_order_times_were_stored__are_treated_as_ordered_now() {
+        let path = create_temp_db_path("migrate-order-times");
+        {
+            let connection = rusqlite::Connection::open(&path).unwrap();
+            connection.execute_batch(MIGRATIONS[0]).unwrap();
+            connection.pragma_update(None, "user_version", 1).unwrap();
+            connection.execute("INSERT INTO table_orders (table_id) VALUES (123)", []).unwrap();
+            connection
+                .execute("INSERT INTO table_order_items (table_id, item_id, quantity, total_preparation_time_mins) VALUES (123, 1, 2, 15)", [])
+                .unwrap();
+        }
+        let before = Utc::now();
+
+        let sut = SqlitePersistence::open(&path).unwrap();
+        let result = sut.find_order(&TableId(123)).await;
+        std::fs::remove_file(&path).unwrap();
+
+        let order = result.unwrap();
+        let item = order.items.get(&LineId(1)).unwrap();
+        assert!(item.ordered_at >= before - chrono::TimeDelta::seconds(1) && item.ordered_at <= Utc::now());
+        assert_eq!(chrono::TimeDelta::minutes(15), item.ready_at - item.ordered_at);
+        assert_eq!(OrderItemStatus::Ordered, item.status);
+    }
+
+    #[tokio::test]
+    async fn open__items_from_before_line_ids__keep_menu_item_id_as_line_id() {
+        let path = create_temp_db_path("migrate-line-ids");
+        {
+            let connection = rusqlite::Connection::open(&path).unwrap();
+            for migration in &MIGRATIONS[..3] {
+                connection.execute_batch(migration).unwrap();
+            }
+            connection.pragma_update(None, "user_version", 3).unwrap();
+            connection.execute("INSERT INTO table_orders (table_id) VALUES (123)", []).unwrap();
+            connection
+                .execute(
+                    "INSERT INTO table_order_items (table_id, item_id, quantity, total_preparation_time_mins, status, ordered_at, ready_at) VALUES (123, 2, 1, 10, 'preparing', ?1, ?1), (123, 7, 1, 10, 'ordered', ?1, ?1)",
+                    [ordered_at()],
+                )
+                .unwrap();
+            connection
+                .execute("INSERT INTO table_order_item_status_changes (table_id, item_id, seq, status, at) VALUES (123, 2, 0, 'preparing', ?1)", [ordered_at()])
+                .unwrap();
+        }
+
+        let sut = SqlitePersistence::open(&path).unwrap();
+        let result = sut
+            .update_order_with(
+                &TableId(123),
+                |o| {
+                    o.add_item(TableOrderItem::new(MenuItemId(2), 1, 10, ordered_at()));
+                    return Ok(());
+                },
+                |_| (),
+            )
+            .await;
+        std::fs::remove_file(&path).unwrap();
+
+        let order = result.unwrap();
+        assert_eq!(vec![LineId(2), LineId(7), LineId(8)], order.items.keys().copied().collect::<Vec<LineId>>());
+        assert_eq!(vec![MenuItemId(2), MenuItemId(7), MenuItemId(2)], order.items.values().map(|i| i.item_id.clone()).collect::<Vec<MenuItemId>>());
+        assert_eq!(Some(ordered_at()), order.items.get(&LineId(2)).unwrap().status_changed_at());
+    }
+
+    #[tokio::test]
+    async fn update_order_with__other_table_is_locked__is_not_blocked() {
+        let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+        for table_id in [TableId(1), TableId(2)] {
+            data.insert(table_id.clone(), TableOrder::new(table_id, &[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]));
+        }
+        let sut = create_sut(data).await;
+        let _table_1_guard = lock_table(&sut, &TableId(1)).await;
+
+        let result = tokio::time::timeout(Duration::from_secs(5), sut.update_order_with(&TableId(2), |o| o.set_guest_count(Some(4)), |_| ())).await;
+
+        assert_eq!(Some(4), result.unwrap().unwrap().guest_count);
+    }
+
+    #[tokio::test]
+    async fn update_order_with__same_table_is_locked__waits_for_lock() {
+        let table_id = TableId(1);
+        let mut data: HashMap<TableId, TableOrder> = HashMap::new();
+        data.insert(table_id.clone(), TableOrder::new(table_id.clone(), &[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())]));
+        let sut = Arc::new(create_sut(data).await);
+        let table_1_guard = lock_table(&sut, &table_id).await;
+
+        let update = tokio::spawn({
+            let sut = Arc::clone(&sut);
+            async move { sut.update_order_with(&TableId(1), |o| o.set_guest_count(Some(4)), |_| ()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert!(!update.is_finished());
+        assert_eq!(None, sut.find_order(&table_id).await.unwrap().guest_count);
+        drop(table_1_guard);
+        assert_eq!(Some(4), tokio::time::timeout(Duration::from_secs(5), update).await.unwrap().unwrap().unwrap().guest_count);
+    }
+
+    // Another connection holds SQLite's write lock for a while, so the request can be given up on while its write is still waiting to commit
+    #[tokio::test]
+    async fn update_order_with__request_dropped_before_commit__saved_is_still_called() {
+        let path = create_temp_db_path("dropped-write");
+        let table_id = TableId(123);
+        let sut = SqlitePersistence::open(&path).unwrap();
+        sut.create_order(&table_id, None, &[TableOrderItem::new(MenuItemId(1), 1, 10, ordered_at())], |_| ())
+            .await
+            .unwrap();
+        let (locked_sender, locked) = std::sync::mpsc::channel();
+        let blocker = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                let connection = rusqlite::Connection::open(&path).unwrap();
+                connection.execute_batch("BEGIN IMMEDIATE").unwrap();
+                locked_sender.send(()).unwrap();
+                std::thread::sleep(Duration::from_millis(300));
+                connection.execute_batch("COMMIT").unwrap();
+            }
+        });
+        locked.recv().unwrap();
+
+        let mut saved = None;
+        let result = tokio::time::timeout(Duration::from_millis(50), sut.update_order_with(&table_id, |o| o.set_guest_count(Some(4)), |o| saved = Some(o.guest_count))).await;
+        blocker.join().unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(Some(Some(4)), saved);
+        let order = sut.find_order(&table_id).await.unwrap();
+        drop(sut);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(Some(4), order.guest_count);
+    }
+
+    // A file rather than in memory, so the requests really are spread over several connections
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_requests__many_tables__all_applied() {
+        let path = create_temp_db_path("concurrent");
+        let sut = Arc::new(SqlitePersistence::open(&path).unwrap());
+
+        run_concurrent_requests(&sut).await;
+
+        let data = get_underlying_data(Arc::into_inner(sut).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(50, data.len());
+        assert!(all_quantities_are_20(&data));
+    }
+}
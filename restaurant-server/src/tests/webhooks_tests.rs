@@ -0,0 +1,411 @@
+#![allow(non_snake_case)]
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeSet,
+        net::IpAddr,
+        str::FromStr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use axum::{
+        body::Body,
+        extract::State,
+        http::{self, HeaderMap, Request, Response, StatusCode},
+        routing::post,
+        Router,
+    };
+    use chrono::{TimeZone, Utc};
+    use http_body_util::BodyExt;
+    use hyper_util::client::legacy::connect::dns::Name;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+    use tower_service::Service;
+
+    use crate::{
+        api::v0::{
+            view_models::{DeadLettersViewModel, WebhookViewModel, WebhooksViewModel},
+            webhooks::spawn_webhook_dispatcher,
+        },
+        app::{create_app_with_state, create_router},
+        events::OrderEventType,
+        models::menu::Menu,
+        persistence::memory_persistence::MemoryPersistence,
+        state::AppState,
+        tests::fixtures::menu_item,
+        webhooks::{is_public_address, sign_payload, PublicOnlyResolver, RetryPolicy, WebhookError, WebhookHostAllowList, WebhookRegistry, EVENT_ID_HEADER, EVENT_TYPE_HEADER, SIGNATURE_HEADER},
+    };
+
+    const SECRET: &str = "s3cret";
+
+    #[derive(Debug, Clone)]
+    struct ReceivedRequest {
+        signature: String,
+        event_type: String,
+        event_id: u64,
+        body: String,
+    }
+
+    // Stands in for the loyalty/inventory system, failing the first `failures` requests with a 500
+    #[derive(Clone, Default)]
+    struct StandInReceiver {
+        requests: Arc<Mutex<Vec<ReceivedRequest>>>,
+        failures_left: Arc<AtomicUsize>,
+    }
+
+    impl StandInReceiver {
+        fn received(&self) -> Vec<ReceivedRequest> {
+            return self.requests.lock().unwrap().clone();
+        }
+    }
+
+    async fn receive_webhook(State(receiver): State<StandInReceiver>, headers: HeaderMap, body: String) -> StatusCode {
+        let header = |name: &str| headers.get(name).map(|v| v.to_str().unwrap().to_string()).unwrap_or_default();
+        receiver.requests.lock().unwrap().push(ReceivedRequest {
+            signature: header(SIGNATURE_HEADER),
+            event_type: header(EVENT_TYPE_HEADER),
+            event_id: header(EVENT_ID_HEADER).parse().unwrap(),
+            body: body,
+        });
+
+        let failed = receiver
+            .failures_left
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |f| f.checked_sub(1))
+            .is_ok();
+        return if failed { StatusCode::INTERNAL_SERVER_ERROR } else { StatusCode::OK };
+    }
+
+    async fn serve_receiver(failures: usize) -> (StandInReceiver, String) {
+        let receiver = StandInReceiver { requests: Arc::default(), failures_left: Arc::new(AtomicUsize::new(failures)) };
+        let app = Router::new().route("/hook", post(receive_webhook)).with_state(receiver.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        return (receiver, url);
+    }
+
+    fn create_test_menu() -> Menu {
+        return Menu::new(vec![menu_item(1, "menu item 1")]).unwrap();
+    }
+
+    // Quick retries so failing deliveries don't slow the tests down. The stand-in receivers run on this machine, so it is allowed.
+    fn create_test_state(max_attempts: u32) -> Arc<AppState<MemoryPersistence>> {
+        let retry = RetryPolicy { max_attempts: max_attempts, initial_backoff: Duration::from_millis(10), max_backoff: Duration::from_millis(50), timeout: Duration::from_secs(5) };
+        let state = AppState::new(MemoryPersistence::default(), create_test_menu())
+            .with_webhook_retry(retry)
+            .with_webhook_hosts(WebhookHostAllowList::from_hosts_str("127.0.0.1,localhost"));
+        return Arc::new(state);
+    }
+
+    // With the dispatcher running, which is left to stop when the test's runtime does
+    fn create_test_app(max_attempts: u32) -> Router {
+        let state = create_test_state(max_attempts);
+        spawn_webhook_dispatcher(Arc::clone(&state));
+        return create_router(state);
+    }
+
+    async fn read_dead_letters(app: &Router, count: usize) -> DeadLettersViewModel {
+        let mut dead_letters = DeadLettersViewModel { dead_letters: vec![] };
+        for _ in 0..500 {
+            let response = send_request(app, http::Method::GET, "/v0/webhooks/dead_letters", None).await;
+            dead_letters = serde_json::from_value(get_body_json(response).await).unwrap();
+            if dead_letters.dead_letters.len() >= count {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        return dead_letters;
+    }
+
+    async fn send_request(app: &Router, method: http::Method, uri: &str, body: Option<Value>) -> Response<Body> {
+        let request = match body {
+            Some(body) => Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(serde_json::to_vec(&body).unwrap()))
+                .unwrap(),
+            None => Request::builder().method(method).uri(uri).body(Body::empty()).unwrap(),
+        };
+        return app.clone().oneshot(request).await.unwrap();
+    }
+
+    async fn get_body_json(response: Response<Body>) -> Value {
+        return serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes()).unwrap();
+    }
+
+    async fn wait_until<F: Fn() -> bool>(condition: F) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("condition not met within 5 seconds");
+    }
+
+    #[test]
+    fn sign_payload__rfc_4231_test_case__matches_known_signature() {
+        assert_eq!("sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843", sign_payload("Jefe", b"what do ya want for nothing?"));
+    }
+
+    #[test]
+    fn backoff__each_failed_attempt__doubles_until_capped() {
+        let policy = RetryPolicy { max_attempts: 10, initial_backoff: Duration::from_secs(1), max_backoff: Duration::from_secs(10), timeout: Duration::from_secs(1) };
+
+        let backoffs = (1..=6).map(|attempt| policy.backoff(attempt).as_secs()).collect::<Vec<u64>>();
+
+        assert_eq!(vec![1, 2, 4, 8, 10, 10], backoffs);
+    }
+
+    #[test]
+    fn matching__event_type_filter__only_subscriptions_wanting_it() {
+        let registry = WebhookRegistry::default();
+        let at = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let everything = registry.add("http://localhost/a", BTreeSet::new(), SECRET, at);
+        let created_only = registry.add("http://localhost/b", BTreeSet::from([OrderEventType::OrderCreated]), SECRET, at);
+
+        assert_eq!(vec![everything.id, created_only.id], registry.matching(OrderEventType::OrderCreated).iter().map(|s| s.id).collect::<Vec<u64>>());
+        assert_eq!(vec![everything.id], registry.matching(OrderEventType::OrderDeleted).iter().map(|s| s.id).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn remove__unknown_webhook__is_error() {
+        let registry = WebhookRegistry::default();
+
+        assert_eq!(Err(WebhookError::WebhookNotFound("7".to_string())), registry.remove(7));
+    }
+
+    #[tokio::test]
+    async fn webhook__subscribed_events__are_delivered_signed() {
+        let (receiver, url) = serve_receiver(0).await;
+        let app = create_test_app(3);
+        let response = send_request(&app, http::Method::POST, "/v0/webhooks", Some(json!({ "url": url, "events": ["order_created", "order_deleted"], "secret": SECRET }))).await;
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        assert_eq!(
+            StatusCode::CREATED,
+            send_request(&app, http::Method::POST, "/v0/orders/12", Some(json!({ "items": [{ "item_id": "1", "qty": 1 }] })))
+                .await
+                .status()
+        );
+        assert_eq!(
+            StatusCode::OK,
+            send_request(&app, http::Method::PUT, "/v0/orders/12", Some(json!({ "items": [{ "item_id": "1", "qty": 2 }] })))
+                .await
+                .status()
+        );
+        assert_eq!(StatusCode::CREATED, send_request(&app, http::Method::POST, "/v0/orders/12/bill", Some(json!({}))).await.status());
+        assert_eq!(
+            StatusCode::CREATED,
+            send_request(&app, http::Method::POST, "/v0/orders/12/payments", Some(json!({ "method": "cash" })))
+                .await
+                .status()
+        );
+        assert_eq!(StatusCode::NO_CONTENT, send_request(&app, http::Method::DELETE, "/v0/orders/12", None).await.status());
+        wait_until(|| receiver.received().len() >= 2).await;
+
+        // Deliveries run side by side, so they can arrive in either order
+        let mut received = receiver.received();
+        received.sort_by_key(|r| r.event_id);
+        assert_eq!(vec![(1, "order_created".to_string()), (5, "order_deleted".to_string())], received.iter().map(|r| (r.event_id, r.event_type.clone())).collect::<Vec<(u64, String)>>());
+        for request in &received {
+            assert_eq!(sign_payload(SECRET, request.body.as_bytes()), request.signature);
+            let body: Value = serde_json::from_str(&request.body).unwrap();
+            assert_eq!((request.event_type.as_str(), "12"), (body["type"].as_str().unwrap(), body["table_id"].as_str().unwrap()));
+        }
+    }
+
+    #[tokio::test]
+    async fn webhook__receiver_fails_then_recovers__is_retried_until_delivered() {
+        let (receiver, url) = serve_receiver(2).await;
+        let app = create_test_app(5);
+        assert_eq!(
+            StatusCode::CREATED,
+            send_request(&app, http::Method::POST, "/v0/webhooks", Some(json!({ "url": url, "secret": SECRET })))
+                .await
+                .status()
+        );
+
+        assert_eq!(
+            StatusCode::CREATED,
+            send_request(&app, http::Method::POST, "/v0/orders/12", Some(json!({ "items": [{ "item_id": "1", "qty": 1 }] })))
+                .await
+                .status()
+        );
+        wait_until(|| receiver.received().len() >= 3).await;
+
+        let received = receiver.received();
+        assert!(received.iter().all(|r| r.event_id == 1 && r.body == received[0].body));
+        let response = send_request(&app, http::Method::GET, "/v0/webhooks/dead_letters", None).await;
+        let dead_letters: DeadLettersViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert!(dead_letters.dead_letters.is_empty());
+    }
+
+    #[tokio::test]
+    async fn webhook__receiver_keeps_failing__ends_up_on_dead_letter_list() {
+        let (receiver, url) = serve_receiver(usize::MAX).await;
+        let app = create_test_app(3);
+        let response = send_request(&app, http::Method::POST, "/v0/webhooks", Some(json!({ "url": url, "secret": SECRET }))).await;
+        let webhook: WebhookViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+
+        assert_eq!(
+            StatusCode::CREATED,
+            send_request(&app, http::Method::POST, "/v0/orders/12", Some(json!({ "items": [{ "item_id": "1", "qty": 1 }] })))
+                .await
+                .status()
+        );
+        let dead_letters = read_dead_letters(&app, 1).await;
+
+        assert_eq!(3, receiver.received().len());
+        let dead_letter = &dead_letters.dead_letters[0];
+        assert_eq!(
+            (webhook.webhook_id, "1".to_string(), "order_created".to_string(), 3),
+            (dead_letter.webhook_id.clone(), dead_letter.event_id.clone(), dead_letter.event_type.clone(), dead_letter.attempts)
+        );
+        assert_eq!("receiver responded 500 Internal Server Error", dead_letter.last_error);
+        assert_eq!("12", dead_letter.payload["table_id"]);
+    }
+
+    #[tokio::test]
+    async fn replay_dead_letter__receiver_recovered__is_delivered_and_taken_off_the_list() {
+        let (receiver, url) = serve_receiver(2).await;
+        let app = create_test_app(2);
+        assert_eq!(
+            StatusCode::CREATED,
+            send_request(&app, http::Method::POST, "/v0/webhooks", Some(json!({ "url": url, "secret": SECRET })))
+                .await
+                .status()
+        );
+        assert_eq!(
+            StatusCode::CREATED,
+            send_request(&app, http::Method::POST, "/v0/orders/12", Some(json!({ "items": [{ "item_id": "1", "qty": 1 }] })))
+                .await
+                .status()
+        );
+        let dead_letters = read_dead_letters(&app, 1).await;
+
+        let uri = format!("/v0/webhooks/dead_letters/{}/replay", dead_letters.dead_letters[0].dead_letter_id);
+        assert_eq!(StatusCode::ACCEPTED, send_request(&app, http::Method::POST, &uri, None).await.status());
+        wait_until(|| receiver.received().len() >= 3).await;
+
+        let received = receiver.received();
+        assert_eq!((1, "order_created"), (received[2].event_id, received[2].event_type.as_str()));
+        assert_eq!(sign_payload(SECRET, received[2].body.as_bytes()), received[2].signature);
+        assert!(read_dead_letters(&app, 0).await.dead_letters.is_empty());
+        assert_eq!(StatusCode::NOT_FOUND, send_request(&app, http::Method::POST, &uri, None).await.status());
+    }
+
+    #[tokio::test]
+    async fn replay_dead_letter__invalid_id__is_400() {
+        let app = create_test_app(1);
+
+        let response = send_request(&app, http::Method::POST, "/v0/webhooks/dead_letters/abc/replay", None).await;
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!("invalid_dead_letter_id", get_body_json(response).await["code"]);
+    }
+
+    #[tokio::test]
+    async fn webhook__dispatcher_stopped__nothing_is_delivered() {
+        let (receiver, url) = serve_receiver(0).await;
+        let state = create_test_state(1);
+        let dispatcher = spawn_webhook_dispatcher(Arc::clone(&state));
+        let app = create_router(state);
+        assert_eq!(
+            StatusCode::CREATED,
+            send_request(&app, http::Method::POST, "/v0/webhooks", Some(json!({ "url": url, "secret": SECRET })))
+                .await
+                .status()
+        );
+
+        dispatcher.abort();
+        assert!(dispatcher.await.unwrap_err().is_cancelled());
+        assert_eq!(
+            StatusCode::CREATED,
+            send_request(&app, http::Method::POST, "/v0/orders/12", Some(json!({ "items": [{ "item_id": "1", "qty": 1 }] })))
+                .await
+                .status()
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert!(receiver.received().is_empty());
+    }
+
+    #[tokio::test]
+    async fn create_webhook__host_is_not_public__is_422_unless_allowed() {
+        let app = create_app_with_state(AppState::new(MemoryPersistence::default(), create_test_menu()).with_webhook_hosts(WebhookHostAllowList::from_hosts_str("10.1.2.3")));
+
+        for url in ["http://localhost:8080/hook", "http://127.0.0.1/hook", "http://[::1]/hook", "http://192.168.1.10/hook", "http://169.254.169.254/latest/meta-data", "http://0.0.0.0/hook"] {
+            let response = send_request(&app, http::Method::POST, "/v0/webhooks", Some(json!({ "url": url, "secret": SECRET }))).await;
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status(), "{}", url);
+            assert_eq!("webhook_url_not_allowed", get_body_json(response).await["code"]);
+        }
+        for url in ["http://10.1.2.3/hook", "http://example.com/hook"] {
+            let response = send_request(&app, http::Method::POST, "/v0/webhooks", Some(json!({ "url": url, "secret": SECRET }))).await;
+            assert_eq!(StatusCode::CREATED, response.status(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn is_public_address__private_and_special_ranges__are_not_public() {
+        let not_public = ["127.0.0.1", "10.0.0.1", "172.16.5.4", "192.168.0.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1", "::ffff:10.0.0.1"];
+        let public = ["93.184.216.34", "2606:2800:220:1::1", "::ffff:93.184.216.34"];
+
+        assert!(not_public.iter().all(|ip| !is_public_address(IpAddr::from_str(ip).unwrap())));
+        assert!(public.iter().all(|ip| is_public_address(IpAddr::from_str(ip).unwrap())));
+    }
+
+    #[tokio::test]
+    async fn public_only_resolver__name_resolves_to_loopback__is_refused_unless_allowed() {
+        let refused = PublicOnlyResolver::new(WebhookHostAllowList::default())
+            .call(Name::from_str("localhost").unwrap())
+            .await;
+        let allowed = PublicOnlyResolver::new(WebhookHostAllowList::from_hosts_str("LOCALHOST"))
+            .call(Name::from_str("localhost").unwrap())
+            .await;
+
+        assert_eq!(std::io::ErrorKind::PermissionDenied, refused.unwrap_err().kind());
+        assert!(allowed.unwrap().any(|a| a.ip().is_loopback()));
+    }
+
+    #[tokio::test]
+    async fn create_webhook__invalid_params__are_422() {
+        let app = create_test_app(1);
+
+        for (body, code) in [
+            (json!({ "url": "https://example.com/hook", "secret": SECRET }), "invalid_webhook_url"),
+            (json!({ "url": "not a url", "secret": SECRET }), "invalid_webhook_url"),
+            (json!({ "url": "http://localhost/hook", "secret": "" }), "missing_webhook_secret"),
+            (json!({ "url": "http://localhost/hook", "events": ["order_eaten"], "secret": SECRET }), "unknown_event_type"),
+        ] {
+            let response = send_request(&app, http::Method::POST, "/v0/webhooks", Some(body)).await;
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+            assert_eq!(code, get_body_json(response).await["code"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn delete_webhook__existing_webhook__is_no_longer_listed() {
+        let app = create_test_app(1);
+        let response = send_request(&app, http::Method::POST, "/v0/webhooks", Some(json!({ "url": "http://localhost/hook", "events": ["item_ready"], "secret": SECRET }))).await;
+        let webhook: WebhookViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+
+        let response = send_request(&app, http::Method::GET, "/v0/webhooks", None).await;
+        let listed = get_body_json(response).await;
+        assert_eq!(json!(["item_ready"]), listed["webhooks"][0]["events"]);
+        assert!(listed["webhooks"][0].get("secret").is_none());
+
+        let uri = format!("/v0/webhooks/{}", webhook.webhook_id);
+        assert_eq!(StatusCode::NO_CONTENT, send_request(&app, http::Method::DELETE, &uri, None).await.status());
+        let response = send_request(&app, http::Method::GET, "/v0/webhooks", None).await;
+        let webhooks: WebhooksViewModel = serde_json::from_value(get_body_json(response).await).unwrap();
+        assert!(webhooks.webhooks.is_empty());
+        assert_eq!(StatusCode::NOT_FOUND, send_request(&app, http::Method::DELETE, &uri, None).await.status());
+    }
+}
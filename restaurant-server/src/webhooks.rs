@@ -0,0 +1,370 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    body::Bytes,
+    http::{header, Method, Request},
+};
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use http_body_util::Full;
+use hyper_util::{
+    client::legacy::{
+        connect::{dns::Name, HttpConnector},
+        Client,
+    },
+    rt::TokioExecutor,
+};
+use sha2::Sha256;
+use thiserror::Error;
+use tokio::sync::Notify;
+use tower_service::Service;
+
+use crate::{clock::Clock, events::OrderEventType};
+
+// Oldest are dropped first once there are this many
+pub const MAX_DEAD_LETTERS: usize = 1000;
+
+pub const SIGNATURE_HEADER: &str = "x-restaurant-signature";
+pub const EVENT_TYPE_HEADER: &str = "x-restaurant-event";
+pub const EVENT_ID_HEADER: &str = "x-restaurant-event-id";
+
+pub type WebhookId = u64;
+pub type DeadLetterId = u64;
+pub type WebhookClient = Client<HttpConnector<PublicOnlyResolver>, Full<Bytes>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookSubscription {
+    pub id: WebhookId,
+    pub url: String,
+    pub event_types: BTreeSet<OrderEventType>, // empty for every type
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl WebhookSubscription {
+    pub fn wants(&self, event_type: OrderEventType) -> bool {
+        return self.event_types.is_empty() || self.event_types.contains(&event_type);
+    }
+}
+
+// One event on its way to one subscription
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookDelivery {
+    pub subscription: WebhookSubscription,
+    pub event_id: u64,
+    pub event_type: OrderEventType,
+    pub payload: Arc<str>, // shared between every subscription the event goes to
+}
+
+// A delivery that failed every attempt, kept so it can be looked into and replayed
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub id: DeadLetterId,
+    pub webhook_id: WebhookId,
+    pub url: String,
+    pub event_id: u64,
+    pub event_type: OrderEventType,
+    pub payload: String,
+    pub attempts: u32,
+    pub last_error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub timeout: Duration, // per attempt
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        return Self { max_attempts: 5, initial_backoff: Duration::from_secs(1), max_backoff: Duration::from_secs(60), timeout: Duration::from_secs(10) };
+    }
+}
+
+impl RetryPolicy {
+    // How long to wait after the given failed attempt (1 based) before the next, doubling each time
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        return self.initial_backoff.saturating_mul(factor).min(self.max_backoff);
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum WebhookError {
+    #[error("Webhook id {0} not found.")]
+    WebhookNotFound(String),
+    #[error("Dead letter id {0} not found.")]
+    DeadLetterNotFound(String),
+}
+
+// Hosts webhooks may be sent to even though they are loopback, private or link-local, e.g. a receiver running next to the server.
+// Anywhere else has to be a public address, so webhooks can't be used to reach services that are only meant to be reachable from inside.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WebhookHostAllowList {
+    hosts: BTreeSet<String>,
+}
+
+impl WebhookHostAllowList {
+    // e.g. "localhost,10.0.0.5", names are compared ignoring case
+    pub fn from_hosts_str(hosts: &str) -> Self {
+        return Self { hosts: hosts.split(',').map(normalize_host).filter(|h| !h.is_empty()).collect() };
+    }
+
+    pub fn contains(&self, host: &str) -> bool {
+        return self.hosts.contains(&normalize_host(host));
+    }
+
+    // Only addresses and localhost can be told apart from the URL, any other name is checked once it is resolved, see PublicOnlyResolver
+    pub fn allows_host(&self, host: &str) -> bool {
+        if self.contains(host) {
+            return true;
+        }
+
+        let host = normalize_host(host);
+        return match host.parse::<IpAddr>() {
+            Ok(ip) => is_public_address(ip),
+            Err(_) => host != "localhost" && !host.ends_with(".localhost"),
+        };
+    }
+}
+
+// Without the brackets around IPv6 addresses or a trailing dot, so the same host is always written the same way
+fn normalize_host(host: &str) -> String {
+    return host.trim().trim_start_matches('[').trim_end_matches(']').trim_end_matches('.').to_ascii_lowercase();
+}
+
+// Not loopback, private, link-local (which includes cloud metadata services), shared (carrier-grade NAT) or unspecified
+pub fn is_public_address(ip: IpAddr) -> bool {
+    return match ip {
+        IpAddr::V4(ip) => {
+            let shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64;
+            !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast() || shared || ip.octets()[0] == 0)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_address(IpAddr::V4(ip)),
+            None => {
+                let unique_local = (ip.segments()[0] & 0xfe00) == 0xfc00;
+                let link_local = (ip.segments()[0] & 0xffc0) == 0xfe80;
+                !(ip.is_loopback() || ip.is_unspecified() || unique_local || link_local)
+            }
+        },
+    };
+}
+
+// Resolves like the default resolver, but leaves out any address that isn't public unless the host is allowed.
+// Checked on every connection rather than when the webhook is registered, so a name can't be pointed somewhere private afterwards.
+#[derive(Debug, Clone)]
+pub struct PublicOnlyResolver {
+    allowed: Arc<WebhookHostAllowList>,
+}
+
+impl PublicOnlyResolver {
+    pub fn new(allowed: WebhookHostAllowList) -> Self {
+        return Self { allowed: Arc::new(allowed) };
+    }
+}
+
+impl Service<Name> for PublicOnlyResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        return Poll::Ready(Ok(()));
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let allowed = Arc::clone(&self.allowed);
+        return Box::pin(async move {
+            let host = name.as_str();
+            let addresses = tokio::net::lookup_host((host, 0)).await?.collect::<Vec<SocketAddr>>();
+            if allowed.contains(host) {
+                return Ok(addresses.into_iter());
+            }
+
+            let public = addresses.into_iter().filter(|a| is_public_address(a.ip())).collect::<Vec<SocketAddr>>();
+            if public.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} does not resolve to a public address", host)));
+            }
+            return Ok(public.into_iter());
+        });
+    }
+}
+
+// Subscriptions only live as long as the server, receivers are expected to register again on startup
+#[derive(Debug, Default)]
+pub struct WebhookRegistry {
+    next_id: AtomicU64,
+    subscriptions: Mutex<BTreeMap<WebhookId, WebhookSubscription>>,
+    next_dead_letter_id: AtomicU64,
+    dead_letters: Mutex<VecDeque<DeadLetter>>,
+    replays: Mutex<VecDeque<WebhookDelivery>>, // waiting for the dispatcher to pick them up
+    replay_requested: Notify,
+}
+
+impl WebhookRegistry {
+    pub fn add(&self, url: &str, event_types: BTreeSet<OrderEventType>, secret: &str, created_at: DateTime<Utc>) -> WebhookSubscription {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let subscription = WebhookSubscription { id: id, url: url.to_string(), event_types: event_types, secret: secret.to_string(), created_at: created_at };
+        self.subscriptions.lock().unwrap().insert(id, subscription.clone());
+        return subscription;
+    }
+
+    // Deliveries already under way still finish
+    pub fn remove(&self, id: WebhookId) -> Result<(), WebhookError> {
+        return self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .map(|_| ())
+            .ok_or_else(|| WebhookError::WebhookNotFound(id.to_string()));
+    }
+
+    // Ordered by id
+    pub fn list(&self) -> Vec<WebhookSubscription> {
+        return self.subscriptions.lock().unwrap().values().cloned().collect();
+    }
+
+    pub fn matching(&self, event_type: OrderEventType) -> Vec<WebhookSubscription> {
+        return self.subscriptions.lock().unwrap().values().filter(|s| s.wants(event_type)).cloned().collect();
+    }
+
+    pub fn add_dead_letter(&self, delivery: &WebhookDelivery, attempts: u32, last_error: String, failed_at: DateTime<Utc>) -> DeadLetter {
+        let dead_letter = DeadLetter {
+            id: self.next_dead_letter_id.fetch_add(1, Ordering::Relaxed) + 1,
+            webhook_id: delivery.subscription.id,
+            url: delivery.subscription.url.clone(),
+            event_id: delivery.event_id,
+            event_type: delivery.event_type,
+            payload: delivery.payload.to_string(),
+            attempts: attempts,
+            last_error: last_error,
+            failed_at: failed_at,
+        };
+
+        let mut dead_letters = self.dead_letters.lock().unwrap();
+        if dead_letters.len() == MAX_DEAD_LETTERS {
+            dead_letters.pop_front();
+        }
+        dead_letters.push_back(dead_letter.clone());
+        return dead_letter;
+    }
+
+    // Oldest first
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        return self.dead_letters.lock().unwrap().iter().cloned().collect();
+    }
+
+    // Takes the dead letter off the list and hands it to the dispatcher to go through its retries again, to wherever the webhook points now.
+    // Its webhook has to still be registered. If every attempt fails again it goes back on the list under a new id.
+    pub fn replay_dead_letter(&self, id: DeadLetterId) -> Result<(), WebhookError> {
+        let mut dead_letters = self.dead_letters.lock().unwrap();
+        let index = dead_letters
+            .iter()
+            .position(|d| d.id == id)
+            .ok_or_else(|| WebhookError::DeadLetterNotFound(id.to_string()))?;
+        let dead_letter = &dead_letters[index];
+        let subscription = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .get(&dead_letter.webhook_id)
+            .cloned()
+            .ok_or_else(|| WebhookError::WebhookNotFound(dead_letter.webhook_id.to_string()))?;
+
+        let dead_letter = dead_letters.remove(index).unwrap();
+        self.replays
+            .lock()
+            .unwrap()
+            .push_back(WebhookDelivery { subscription: subscription, event_id: dead_letter.event_id, event_type: dead_letter.event_type, payload: dead_letter.payload.into() });
+        self.replay_requested.notify_one();
+        return Ok(());
+    }
+
+    // Resolves once there is something for take_replays, straight away if anything was asked for since it was last called
+    pub async fn replay_requested(&self) {
+        self.replay_requested.notified().await;
+    }
+
+    pub fn take_replays(&self) -> Vec<WebhookDelivery> {
+        return self.replays.lock().unwrap().drain(..).collect();
+    }
+}
+
+pub fn create_webhook_client(allowed_hosts: &WebhookHostAllowList) -> WebhookClient {
+    return Client::builder(TokioExecutor::new()).build(HttpConnector::new_with_resolver(PublicOnlyResolver::new(allowed_hosts.clone())));
+}
+
+// Lowercase hex HMAC-SHA256 of the exact body sent, prefixed with the algorithm, e.g. sha256=5bdc...
+pub fn sign_payload(secret: &str, payload: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(payload);
+    let signature = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    return format!("sha256={}", signature);
+}
+
+// Anything but a 2xx response within the timeout is a failure
+async fn deliver(client: &WebhookClient, delivery: &WebhookDelivery, timeout: Duration) -> Result<(), String> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(&delivery.subscription.url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, sign_payload(&delivery.subscription.secret, delivery.payload.as_bytes()))
+        .header(EVENT_TYPE_HEADER, delivery.event_type.as_str())
+        .header(EVENT_ID_HEADER, delivery.event_id.to_string())
+        .body(Full::new(Bytes::from(delivery.payload.to_string())))
+        .map_err(|err| err.to_string())?;
+
+    return match tokio::time::timeout(timeout, client.request(request)).await {
+        Ok(Ok(response)) if response.status().is_success() => Ok(()),
+        Ok(Ok(response)) => Err(format!("receiver responded {}", response.status())),
+        Ok(Err(err)) => Err(describe_error(&err)),
+        Err(_) => Err(format!("no response within {}ms", timeout.as_millis())),
+    };
+}
+
+// The client's own errors only say which step failed, the reason is further down, e.g. "client error (Connect): localhost does not resolve to a public address"
+fn describe_error(err: &dyn std::error::Error) -> String {
+    let mut description = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        description = format!("{}: {}", description, err);
+        source = err.source();
+    }
+    return description;
+}
+
+// Tries until the receiver accepts the event or the policy runs out of attempts, then puts it on the dead letter list
+pub async fn deliver_with_retries(client: &WebhookClient, registry: &WebhookRegistry, delivery: &WebhookDelivery, policy: &RetryPolicy, clock: &dyn Clock) {
+    let mut attempt = 1;
+    loop {
+        let last_error = match deliver(client, delivery, policy.timeout).await {
+            Ok(()) => return,
+            Err(err) => err,
+        };
+        tracing::warn!("webhook {} delivery of event {} failed on attempt {}: {}", delivery.subscription.id, delivery.event_id, attempt, last_error);
+
+        if attempt >= policy.max_attempts {
+            registry.add_dead_letter(delivery, attempt, last_error, clock.now());
+            return;
+        }
+
+        tokio::time::sleep(policy.backoff(attempt)).await;
+        attempt += 1;
+    }
+}